@@ -0,0 +1,188 @@
+//! Derive macros for [nginx-rs](https://github.com/dcoles/nginx-rs).
+//!
+//! These are re-exported by `nginx-rs` when its `derive` feature is enabled.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields, Lit, Meta, NestedMeta, Path, Type};
+
+/// Derive `Default` and `Merge` for a module configuration struct.
+///
+/// Together these provide the `create_*_conf` and `merge_*_conf` handlers of an `HTTPModule`.
+/// A freshly created configuration has every field unset, and merging fills unset fields from
+/// the enclosing level, the way `ngx_conf_merge_*_value` does with `NGX_CONF_UNSET`:
+///
+/// - `Option<T>` fields are unset while `None`, and inherited from the enclosing level.
+///   With `#[ngx_conf(default = "expr")]`, fields still unset after inheriting are set to
+///   `Some(expr)`.
+/// - `Vec<T>` fields are unset while empty, and inherited as a whole.
+/// - Fields with `#[ngx_conf(merge = "path")]` are merged by calling `path(&mut self.field,
+///   &prev.field)`.
+/// - Fields with `#[ngx_conf(skip)]` are left alone, keeping their default value or whatever
+///   was set at the current level.
+/// - Any other field must itself implement `Merge`, such as a nested configuration struct.
+///
+/// ```ignore
+/// #[derive(NgxConf)]
+/// struct LocConf {
+///     #[ngx_conf(default = "String::from(\"Hello\")")]
+///     text: Option<String>,
+///     #[ngx_conf(default = "60_000")]
+///     timeout: Option<ngx_msec_t>,
+///     allow: Vec<String>,
+/// }
+/// ```
+#[proc_macro_derive(NgxConf, attributes(ngx_conf))]
+pub fn derive_ngx_conf(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+enum Merge {
+    Option(Option<Expr>),
+    Vec,
+    With(Path),
+    Skip,
+    Nested,
+}
+
+struct Field {
+    ident: syn::Ident,
+    merge: Merge,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?,
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(_) => return Err(Error::new(input.span(), "NgxConf requires named fields")),
+        },
+        _ => return Err(Error::new(input.span(), "NgxConf can only be derived for structs")),
+    };
+
+    let defaults = fields.iter().map(|field| {
+        let ident = &field.ident;
+        quote! { #ident: ::std::default::Default::default() }
+    });
+
+    let merges = fields.iter().map(|field| {
+        let ident = &field.ident;
+        match &field.merge {
+            Merge::Option(default) => {
+                let default = default.as_ref().map(|default| quote! {
+                    if self.#ident.is_none() {
+                        self.#ident = ::std::option::Option::Some(#default);
+                    }
+                });
+                quote! {
+                    if self.#ident.is_none() {
+                        self.#ident = ::std::clone::Clone::clone(&prev.#ident);
+                    }
+                    #default
+                }
+            }
+            Merge::Vec => quote! {
+                if self.#ident.is_empty() {
+                    self.#ident = ::std::clone::Clone::clone(&prev.#ident);
+                }
+            },
+            Merge::With(path) => quote! {
+                #path(&mut self.#ident, &prev.#ident);
+            },
+            Merge::Skip => quote! {},
+            Merge::Nested => quote! {
+                ::nginx_rs::http::Merge::merge(&mut self.#ident, &prev.#ident);
+            },
+        }
+    });
+
+    let body = match &input.data {
+        Data::Struct(data) if matches!(data.fields, Fields::Unit) => quote! { #name },
+        _ => quote! { #name { #( #defaults, )* } },
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::std::default::Default for #name #ty_generics #where_clause {
+            fn default() -> Self {
+                #body
+            }
+        }
+
+        impl #impl_generics ::nginx_rs::http::Merge for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn merge(&mut self, prev: &Self) {
+                #( #merges )*
+            }
+        }
+    })
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<Field> {
+    let ident = field.ident.clone().expect("named field");
+
+    let mut default = None;
+    let mut with = None;
+    let mut skip = false;
+
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("ngx_conf")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected #[ngx_conf(...)]")),
+        };
+
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => skip = true,
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("default") => {
+                    default = Some(parse_str_lit(&nv.lit)?);
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("merge") => {
+                    with = Some(parse_str_lit(&nv.lit)?);
+                }
+                nested => return Err(Error::new(nested.span(), "unknown ngx_conf attribute")),
+            }
+        }
+    }
+
+    let kind = outer_type(&field.ty);
+    let merge = if skip {
+        Merge::Skip
+    } else if let Some(path) = with {
+        Merge::With(path)
+    } else if kind.as_deref() == Some("Option") {
+        Merge::Option(default.take())
+    } else if kind.as_deref() == Some("Vec") {
+        Merge::Vec
+    } else {
+        Merge::Nested
+    };
+
+    if default.is_some() {
+        return Err(Error::new(field.span(), "default is only supported for Option fields"));
+    }
+
+    Ok(Field { ident, merge })
+}
+
+fn parse_str_lit<T: syn::parse::Parse>(lit: &Lit) -> syn::Result<T> {
+    match lit {
+        Lit::Str(s) => s.parse(),
+        lit => Err(Error::new(lit.span(), "expected a string literal")),
+    }
+}
+
+/// Name of the outermost type of a field, such as `Option` for `Option<String>`.
+fn outer_type(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
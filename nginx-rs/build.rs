@@ -13,7 +13,25 @@ fn main() {
     // The bindgen::Builder is the main entry point
     // to bindgen, and lets you build up options for
     // the resulting bindings.
-    let bindings = bindgen::Builder::default()
+    let mut builder = bindgen::Builder::default();
+    if env::var_os("CARGO_FEATURE_STREAM").is_some() {
+        builder = builder
+            .clang_arg("-DNGX_RS_STREAM")
+            .clang_arg(format!("-I{}/src/stream", nginx_dir));
+    }
+    if env::var_os("CARGO_FEATURE_HTTP3").is_some() {
+        builder = builder
+            .clang_arg("-DNGX_RS_HTTP3")
+            .clang_arg(format!("-I{}/src/event/quic", nginx_dir))
+            .clang_arg(format!("-I{}/src/http/v3", nginx_dir));
+    }
+    if env::var_os("CARGO_FEATURE_MAIL").is_some() {
+        builder = builder
+            .clang_arg("-DNGX_RS_MAIL")
+            .clang_arg(format!("-I{}/src/mail", nginx_dir));
+    }
+
+    let bindings = builder
         // The input header we would like to generate
         // bindings for.
         .header("wrapper.h")
@@ -0,0 +1,117 @@
+use crate::bindings::*;
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+/// An atomic integer with the same layout as [`ngx_atomic_t`].
+///
+/// All operations use sequentially consistent ordering, matching the full barriers of Nginx's
+/// own atomic primitives, so they can be freely mixed with C code operating on the same value.
+///
+/// Like anything placed in shared memory, structures containing an `Atomic` should be
+/// `#[repr(C)]`, contain no pointers and have no `Drop` implementation, since they are shared
+/// between processes and outlive any single worker.
+///
+/// [`ngx_atomic_t`]: https://nginx.org/en/docs/dev/development_guide.html#atomic_operations
+#[repr(transparent)]
+#[derive(Default)]
+pub struct Atomic(AtomicUsize);
+
+impl Atomic {
+    /// Create a new atomic with an initial value.
+    pub const fn new(value: ngx_atomic_uint_t) -> Atomic {
+        Atomic(AtomicUsize::new(value as usize))
+    }
+
+    /// Create an [`Atomic`] from a pointer to an [`ngx_atomic_t`].
+    ///
+    /// [`ngx_atomic_t`]: https://nginx.org/en/docs/dev/development_guide.html#atomic_operations
+    pub unsafe fn from_ngx_atomic<'a>(atomic: *mut ngx_atomic_t) -> &'a Atomic {
+        // SAFETY: The caller has provided a valid, suitably aligned pointer which remains valid
+        // for the lifetime of the returned reference. `ngx_atomic_t` is a pointer-sized integer.
+        &*(atomic as *const Atomic)
+    }
+
+    /// Pointer to the underlying [`ngx_atomic_t`].
+    ///
+    /// [`ngx_atomic_t`]: https://nginx.org/en/docs/dev/development_guide.html#atomic_operations
+    pub fn as_ngx_atomic(&self) -> *mut ngx_atomic_t {
+        self as *const Atomic as *mut ngx_atomic_t
+    }
+
+    /// Read the current value.
+    pub fn load(&self) -> ngx_atomic_uint_t {
+        self.0.load(Ordering::SeqCst) as ngx_atomic_uint_t
+    }
+
+    /// Replace the current value.
+    pub fn store(&self, value: ngx_atomic_uint_t) {
+        self.0.store(value as usize, Ordering::SeqCst)
+    }
+
+    /// Add to the value, returning the previous value (`ngx_atomic_fetch_add`).
+    ///
+    /// The addition wraps around on overflow.
+    pub fn fetch_add(&self, add: ngx_atomic_int_t) -> ngx_atomic_uint_t {
+        self.0.fetch_add(add as usize, Ordering::SeqCst) as ngx_atomic_uint_t
+    }
+
+    /// Set the value to `set` if it currently equals `old` (`ngx_atomic_cmp_set`).
+    ///
+    /// Returns `true` if the value was changed.
+    pub fn cmp_set(&self, old: ngx_atomic_uint_t, set: ngx_atomic_uint_t) -> bool {
+        self.0.compare_exchange(old as usize, set as usize, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+}
+
+impl fmt::Debug for Atomic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.load())
+    }
+}
+
+/// Full memory barrier (`ngx_memory_barrier`).
+pub fn memory_barrier() {
+    atomic::fence(Ordering::SeqCst);
+}
+
+/// Hint to the CPU that we are in a spin loop (`ngx_cpu_pause`).
+pub fn cpu_pause() {
+    std::hint::spin_loop();
+}
+
+/// Size of a CPU cache line as detected by Nginx at startup.
+///
+/// Falls back to the compile-time `NGX_CPU_CACHE_LINE` before detection has run.
+pub fn cacheline_size() -> usize {
+    let size = unsafe { ngx_cacheline_size } as usize;
+    if size == 0 {
+        NGX_CPU_CACHE_LINE as usize
+    } else {
+        size
+    }
+}
+
+/// Pads and aligns a value to a cache line, so values updated by different workers don't
+/// share a line (false sharing).
+///
+/// The alignment is fixed at 128 bytes, which covers the cache line size of all platforms
+/// Nginx supports.
+#[repr(C, align(128))]
+#[derive(Default)]
+pub struct CacheAligned<T>(pub T);
+
+impl<T> Deref for CacheAligned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for CacheAligned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
@@ -39,6 +39,13 @@ pub trait Buffer {
             (*buf).set_last_in_chain(if last { 1 } else { 0 });
         }
     }
+
+    fn set_flush(&mut self, flush: bool) {
+        let buf = self.as_ngx_buf_mut();
+        unsafe {
+            (*buf).set_flush(if flush { 1 } else { 0 });
+        }
+    }
 }
 
 pub trait MutableBuffer: Buffer {
@@ -0,0 +1,225 @@
+use crate::bindings::*;
+use crate::core::*;
+
+use std::marker::PhantomData;
+use std::ptr;
+use std::slice;
+
+/// A chain of buffers ([`ngx_chain_t`]), as passed through body filters.
+///
+/// [`ngx_chain_t`]: https://nginx.org/en/docs/dev/development_guide.html#buffer
+pub struct Chain(*mut ngx_chain_t);
+
+impl Chain {
+    /// Wrap an [`ngx_chain_t`], which may be null for an empty chain.
+    ///
+    /// [`ngx_chain_t`]: https://nginx.org/en/docs/dev/development_guide.html#buffer
+    pub unsafe fn from_ngx_chain(cl: *mut ngx_chain_t) -> Chain {
+        Chain(cl)
+    }
+
+    /// An empty chain.
+    pub fn empty() -> Chain {
+        Chain(ptr::null_mut())
+    }
+
+    /// Pointer to the first link of the chain, null if the chain is empty.
+    pub fn as_ngx_chain(&self) -> *mut ngx_chain_t {
+        self.0
+    }
+
+    /// Is the chain empty?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_null()
+    }
+
+    /// Does the chain contain the last buffer of the response?
+    pub fn has_last_buf(&self) -> bool {
+        self.iter().any(|link| link.is_last_buf())
+    }
+
+    /// Iterate over the links of the chain.
+    pub fn iter(&self) -> impl Iterator<Item = &ChainLink> {
+        Links { cl: self.0, _marker: PhantomData::<&ChainLink> }
+    }
+
+    /// Iterate mutably over the links of the chain.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut ChainLink> {
+        Links { cl: self.0, _marker: PhantomData::<&mut ChainLink> }
+    }
+
+    /// Append a buffer to the end of the chain. The link is allocated from `pool`.
+    pub fn push<B: Buffer>(&mut self, pool: &mut Pool, buffer: &mut B) -> bool {
+        unsafe {
+            let cl = ngx_alloc_chain_link(pool.as_ngx_pool());
+            if cl.is_null() {
+                return false;
+            }
+            (*cl).buf = buffer.as_ngx_buf_mut();
+            (*cl).next = ptr::null_mut();
+
+            if self.0.is_null() {
+                self.0 = cl;
+                return true;
+            }
+
+            let mut last = self.0;
+            while !(*last).next.is_null() {
+                last = (*last).next;
+            }
+            (*last).next = cl;
+        }
+        true
+    }
+}
+
+struct Links<T> {
+    cl: *mut ngx_chain_t,
+    _marker: PhantomData<T>,
+}
+
+impl<'a> Iterator for Links<&'a ChainLink> {
+    type Item = &'a ChainLink;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cl = self.cl;
+        if cl.is_null() {
+            return None;
+        }
+        unsafe {
+            self.cl = (*cl).next;
+            Some(&*(cl as *const ChainLink))
+        }
+    }
+}
+
+impl<'a> Iterator for Links<&'a mut ChainLink> {
+    type Item = &'a mut ChainLink;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cl = self.cl;
+        if cl.is_null() {
+            return None;
+        }
+        unsafe {
+            self.cl = (*cl).next;
+            Some(&mut *(cl as *mut ChainLink))
+        }
+    }
+}
+
+/// A link of a [`Chain`], giving access to its buffer.
+#[repr(transparent)]
+pub struct ChainLink(ngx_chain_t);
+
+impl ChainLink {
+    /// Is the data of the buffer in memory (as opposed to only in a file)?
+    pub fn in_memory(&self) -> bool {
+        unsafe {
+            let b = self.0.buf;
+            (*b).temporary() != 0 || (*b).memory() != 0 || (*b).mmap() != 0
+        }
+    }
+
+    /// Can the data of the buffer be modified in place?
+    ///
+    /// Only temporary buffers are owned by the output chain; memory and mmap buffers may be
+    /// shared (such as cached or static data) and must be copied before modification.
+    pub fn is_mutable(&self) -> bool {
+        unsafe { (*self.0.buf).temporary() != 0 }
+    }
+
+    /// Is this the last buffer of the response?
+    pub fn is_last_buf(&self) -> bool {
+        unsafe { (*self.0.buf).last_buf() != 0 }
+    }
+
+    /// Is this a special buffer carrying only flags (flush, sync, last) and no data?
+    pub fn is_special(&self) -> bool {
+        unsafe {
+            let b = self.0.buf;
+            !self.in_memory() && (*b).in_file() == 0
+                && ((*b).flush() != 0 || (*b).last_buf() != 0 || (*b).sync() != 0)
+        }
+    }
+
+    /// Mutable access to the data of the buffer, copying it into `pool` first if the buffer
+    /// is not [mutable](ChainLink::is_mutable).
+    ///
+    /// The copy replaces the buffer in the chain, and the original buffer is marked as
+    /// consumed so its owner can reuse it. Returns `None` if the data is not in memory or
+    /// the allocation fails.
+    pub fn make_mutable(&mut self, pool: &mut Pool) -> Option<&mut [u8]> {
+        if !self.in_memory() {
+            return None;
+        }
+        if !self.is_mutable() {
+            let mut copy = pool.create_buffer_from_slice(self.as_bytes())?;
+            self.replace_with(copy.as_ngx_buf_mut());
+        }
+        Some(self.as_bytes_mut())
+    }
+
+    /// Replace the data of the buffer with a copy of `data` allocated from `pool`.
+    ///
+    /// The flags of the buffer (last buffer, flush, ...) are kept, and the original buffer is
+    /// marked as consumed.
+    pub fn replace(&mut self, pool: &mut Pool, data: &[u8]) -> bool {
+        match pool.create_buffer_from_slice(data) {
+            Some(mut copy) => {
+                self.replace_with(copy.as_ngx_buf_mut());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn replace_with(&mut self, b: *mut ngx_buf_t) {
+        unsafe {
+            let old = self.0.buf;
+            (*b).set_last_buf((*old).last_buf());
+            (*b).set_last_in_chain((*old).last_in_chain());
+            (*b).set_flush((*old).flush());
+            (*b).set_sync((*old).sync());
+
+            (*old).pos = (*old).last;
+            if (*old).in_file() != 0 {
+                (*old).file_pos = (*old).file_last;
+            }
+            self.0.buf = b;
+        }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        if len == 0 {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut((*self.0.buf).pos, len) }
+    }
+}
+
+impl Buffer for ChainLink {
+    fn as_ngx_buf(&self) -> *const ngx_buf_t {
+        self.0.buf
+    }
+
+    fn as_ngx_buf_mut(&mut self) -> *mut ngx_buf_t {
+        self.0.buf
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // Buffers only in a file have no data pointer.
+        if !self.in_memory() || self.len() == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts((*self.0.buf).pos, self.len()) }
+    }
+
+    fn len(&self) -> usize {
+        if !self.in_memory() {
+            return 0;
+        }
+        unsafe { usize::wrapping_sub((*self.0.buf).last as _, (*self.0.buf).pos as _) }
+    }
+}
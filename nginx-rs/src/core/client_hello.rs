@@ -0,0 +1,588 @@
+use crate::bindings::*;
+use crate::core::hmac::Sha256;
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::os::raw::c_void;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+// Maximum length of a plaintext record fragment (RFC 8446, section 5.1).
+const MAX_RECORD_LEN: usize = 1 << 14;
+
+/// `server_name` extension (RFC 6066).
+pub const TLS_EXT_SERVER_NAME: u16 = 0;
+/// `supported_groups` extension (RFC 8422), formerly `elliptic_curves`.
+pub const TLS_EXT_SUPPORTED_GROUPS: u16 = 10;
+/// `ec_point_formats` extension (RFC 8422).
+pub const TLS_EXT_EC_POINT_FORMATS: u16 = 11;
+/// `signature_algorithms` extension (RFC 8446).
+pub const TLS_EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+/// `application_layer_protocol_negotiation` extension (RFC 7301).
+pub const TLS_EXT_ALPN: u16 = 16;
+/// `supported_versions` extension (RFC 8446).
+pub const TLS_EXT_SUPPORTED_VERSIONS: u16 = 43;
+
+/// A parsed TLS ClientHello message.
+///
+/// The parser only depends on the bytes sent by the client, so it can be used on the preread
+/// buffer of a stream session to route or block connections by server name before they are
+/// proxied, without terminating TLS.
+///
+/// ```ignore
+/// stream_preread_handler!(ngx_stream_sni_filter_handler, |session: &mut Session| {
+///     match ClientHello::parse(session.preread_buffer()) {
+///         Ok(hello) if hello.server_name() == Some("blocked.example.com") => Status(NGX_STREAM_FORBIDDEN as ngx_int_t),
+///         Err(ClientHelloError::Incomplete) => AGAIN,
+///         _ => DECLINED,
+///     }
+/// });
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientHello {
+    /// Version of the record layer of the first record.
+    pub record_version: u16,
+    /// Protocol version field of the message (`0x0303` for TLS 1.2 and 1.3).
+    pub version: u16,
+    /// Cipher suites offered by the client, in order.
+    pub cipher_suites: Vec<u16>,
+    /// Compression methods offered by the client.
+    pub compression_methods: Vec<u8>,
+    /// Extensions, as type and data, in order.
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl ClientHello {
+    /// Parse a ClientHello from the start of a TLS connection, which may be split across
+    /// several records.
+    ///
+    /// Returns [`ClientHelloError::Incomplete`] if more data is needed, and
+    /// [`ClientHelloError::NotTls`] if the data is not the start of a TLS handshake.
+    pub fn parse(data: &[u8]) -> Result<ClientHello, ClientHelloError> {
+        let (record_version, message) = reassemble(data)?;
+        ClientHello::parse_body(record_version, &message)
+    }
+
+    /// Parse a ClientHello handshake message, with its 4-byte header but without the record
+    /// layer, as captured during the TLS handshake with the `ssl` feature.
+    ///
+    /// The record version is unknown, and set to the version field of the message.
+    pub fn parse_message(message: &[u8]) -> Result<ClientHello, ClientHelloError> {
+        match message.first() {
+            Some(&HANDSHAKE_CLIENT_HELLO) => {}
+            Some(_) => return Err(ClientHelloError::NotTls),
+            None => return Err(ClientHelloError::Incomplete),
+        }
+        if message.len() < 4 {
+            return Err(ClientHelloError::Incomplete);
+        }
+        let message_len = u32::from_be_bytes([0, message[1], message[2], message[3]]) as usize;
+        let body = message.get(4..4 + message_len).ok_or(ClientHelloError::Incomplete)?;
+        let version = Reader(body).u16()?;
+        ClientHello::parse_body(version, body)
+    }
+
+    fn parse_body(record_version: u16, message: &[u8]) -> Result<ClientHello, ClientHelloError> {
+        let mut hello = Reader(message);
+
+        let version = hello.u16()?;
+        hello.take(32)?; // random
+        let session_id_len = hello.u8()? as usize;
+        hello.take(session_id_len)?;
+
+        let suites_len = hello.u16()? as usize;
+        if suites_len % 2 != 0 {
+            return Err(ClientHelloError::Malformed);
+        }
+        let mut suites = Reader(hello.take(suites_len)?);
+        let mut cipher_suites = Vec::with_capacity(suites_len / 2);
+        while !suites.0.is_empty() {
+            cipher_suites.push(suites.u16()?);
+        }
+
+        let methods_len = hello.u8()? as usize;
+        let compression_methods = hello.take(methods_len)?.to_vec();
+
+        let mut extensions = Vec::new();
+        // Extensions are optional in TLS 1.2 and earlier.
+        if !hello.0.is_empty() {
+            let extensions_len = hello.u16()? as usize;
+            let mut data = Reader(hello.take(extensions_len)?);
+            while !data.0.is_empty() {
+                let extension_type = data.u16()?;
+                let len = data.u16()? as usize;
+                extensions.push((extension_type, data.take(len)?.to_vec()));
+            }
+        }
+
+        Ok(ClientHello { record_version, version, cipher_suites, compression_methods, extensions })
+    }
+
+    /// Data of the first extension of the given type.
+    pub fn extension(&self, extension_type: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(t, _)| *t == extension_type)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Host name requested with the `server_name` extension (SNI), if any.
+    pub fn server_name(&self) -> Option<&str> {
+        let mut data = Reader(self.extension(TLS_EXT_SERVER_NAME)?);
+        let list_len = data.u16().ok()? as usize;
+        let mut list = Reader(data.take(list_len).ok()?);
+        while !list.0.is_empty() {
+            let name_type = list.u8().ok()?;
+            let len = list.u16().ok()? as usize;
+            let name = list.take(len).ok()?;
+            // Type 0 is `host_name`, the only type defined.
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok();
+            }
+        }
+        None
+    }
+
+    /// Protocols offered with the ALPN extension, such as `h2` and `http/1.1`.
+    pub fn alpn_protocols(&self) -> Vec<&[u8]> {
+        let mut protocols = Vec::new();
+        let mut data = match self.extension(TLS_EXT_ALPN) {
+            Some(data) => Reader(data),
+            None => return protocols,
+        };
+        let list_len = match data.u16() {
+            Ok(len) => len as usize,
+            Err(_) => return protocols,
+        };
+        if let Ok(list) = data.take(list_len) {
+            let mut list = Reader(list);
+            while let Ok(len) = list.u8() {
+                match list.take(len as usize) {
+                    Ok(protocol) => protocols.push(protocol),
+                    Err(_) => break,
+                }
+            }
+        }
+        protocols
+    }
+
+    /// Versions offered with the `supported_versions` extension, used by TLS 1.3 clients.
+    pub fn supported_versions(&self) -> Vec<u16> {
+        let mut versions = Vec::new();
+        if let Some(data) = self.extension(TLS_EXT_SUPPORTED_VERSIONS) {
+            let mut data = Reader(data);
+            if let Ok(len) = data.u8() {
+                if let Ok(list) = data.take(len as usize) {
+                    let mut list = Reader(list);
+                    while let Ok(version) = list.u16() {
+                        versions.push(version);
+                    }
+                }
+            }
+        }
+        versions
+    }
+
+    /// [JA3] fingerprint string of the ClientHello: the version, cipher suites, extensions,
+    /// supported groups and point formats, with GREASE values left out.
+    ///
+    /// [JA3]: https://github.com/salesforce/ja3
+    pub fn ja3(&self) -> String {
+        let groups = match self.extension(TLS_EXT_SUPPORTED_GROUPS) {
+            Some(data) if data.len() >= 2 => u16_list(&data[2..]),
+            _ => Vec::new(),
+        };
+        let point_formats = match self.extension(TLS_EXT_EC_POINT_FORMATS) {
+            Some(data) if !data.is_empty() => data[1..].iter().map(|&format| format as u16).collect(),
+            _ => Vec::new(),
+        };
+        let extensions: Vec<u16> = self.extensions.iter().map(|(t, _)| *t).collect();
+
+        let mut ja3 = self.version.to_string();
+        for list in [&self.cipher_suites, &extensions, &groups, &point_formats].iter() {
+            ja3.push(',');
+            let values: Vec<String> = list.iter().filter(|&&v| !is_grease(v)).map(|v| v.to_string()).collect();
+            ja3.push_str(&values.join("-"));
+        }
+        ja3
+    }
+
+    /// JA3 fingerprint hash: the MD5 of [`ClientHello::ja3`], in hexadecimal.
+    pub fn ja3_hash(&self) -> String {
+        let ja3 = self.ja3();
+        let mut digest = [0u8; 16];
+        unsafe {
+            let mut md5: ngx_md5_t = std::mem::zeroed();
+            ngx_md5_init(&mut md5);
+            ngx_md5_update(&mut md5, ja3.as_ptr() as *const c_void, ja3.len());
+            ngx_md5_final(digest.as_mut_ptr(), &mut md5);
+        }
+
+        to_hex(&digest)
+    }
+
+    /// [JA4] fingerprint of the ClientHello, for a connection over TCP, such as
+    /// `t13d1516h2_8daaf6152771_e5627efa2ab1`. Use [`ClientHello::ja4_quic`] for QUIC.
+    ///
+    /// Unlike JA3, it sorts the cipher suites and extensions, so it is stable across clients
+    /// that randomize their order, such as Chrome.
+    ///
+    /// [JA4]: https://github.com/FoxIO-LLC/ja4/blob/main/technical_details/JA4.md
+    pub fn ja4(&self) -> String {
+        self.ja4_with_protocol('t')
+    }
+
+    /// [JA4] fingerprint of the ClientHello, for a QUIC connection such as HTTP/3, starting
+    /// with `q` instead of `t`.
+    ///
+    /// [JA4]: https://github.com/FoxIO-LLC/ja4/blob/main/technical_details/JA4.md
+    pub fn ja4_quic(&self) -> String {
+        self.ja4_with_protocol('q')
+    }
+
+    fn ja4_with_protocol(&self, protocol: char) -> String {
+        let version = self
+            .supported_versions()
+            .into_iter()
+            .filter(|&v| !is_grease(v))
+            .max()
+            .unwrap_or(self.version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let sni = if self.extension(TLS_EXT_SERVER_NAME).is_some() { 'd' } else { 'i' };
+
+        let mut ciphers: Vec<u16> = self.cipher_suites.iter().copied().filter(|&v| !is_grease(v)).collect();
+        let extensions: Vec<u16> = self.extensions.iter().map(|(t, _)| *t).filter(|&t| !is_grease(t)).collect();
+
+        let alpn = match self.alpn_protocols().first() {
+            Some(protocol) if !protocol.is_empty() => {
+                let (first, last) = (protocol[0], protocol[protocol.len() - 1]);
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    // Binary protocols are represented by the outer characters of their hex.
+                    format!("{:x}{:x}", first >> 4, last & 0x0f)
+                }
+            }
+            _ => String::from("00"),
+        };
+
+        let mut ja4 = format!(
+            "{}{}{}{:02}{:02}{}_",
+            protocol,
+            version,
+            sni,
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            alpn
+        );
+
+        ciphers.sort_unstable();
+        ja4.push_str(&truncated_hash(&hex_list(&ciphers)));
+        ja4.push('_');
+
+        // SNI and ALPN are already part of the first section.
+        let mut sorted: Vec<u16> = extensions
+            .into_iter()
+            .filter(|&t| t != TLS_EXT_SERVER_NAME && t != TLS_EXT_ALPN)
+            .collect();
+        sorted.sort_unstable();
+        let mut data = hex_list(&sorted);
+        if let Some(algorithms) = self.extension(TLS_EXT_SIGNATURE_ALGORITHMS) {
+            if algorithms.len() >= 2 {
+                let algorithms: Vec<u16> = u16_list(&algorithms[2..]).into_iter().filter(|&v| !is_grease(v)).collect();
+                if !algorithms.is_empty() {
+                    data.push('_');
+                    data.push_str(&hex_list(&algorithms));
+                }
+            }
+        }
+        ja4.push_str(&truncated_hash(&data));
+        ja4
+    }
+}
+
+// A JA4 hash: the first 12 characters of the SHA-256 in hexadecimal, or zeros for an empty
+// list.
+fn truncated_hash(list: &str) -> String {
+    if list.is_empty() {
+        return String::from("000000000000");
+    }
+    let mut hash = to_hex(&Sha256::digest(list.as_bytes()));
+    hash.truncate(12);
+    hash
+}
+
+fn hex_list(values: &[u16]) -> String {
+    let values: Vec<String> = values.iter().map(|v| format!("{:04x}", v)).collect();
+    values.join(",")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+// Collect the ClientHello message body from the handshake records at the start of `data`.
+fn reassemble(mut data: &[u8]) -> Result<(u16, Vec<u8>), ClientHelloError> {
+    let mut handshake = Vec::new();
+    let mut record_version = None;
+
+    loop {
+        // The type of the first record tells TLS apart from other protocols even before the
+        // whole header is available.
+        match data.first() {
+            Some(&CONTENT_TYPE_HANDSHAKE) => {}
+            Some(_) => return Err(ClientHelloError::NotTls),
+            None => return Err(ClientHelloError::Incomplete),
+        }
+        if data.len() < 5 {
+            return Err(ClientHelloError::Incomplete);
+        }
+        let version = u16::from_be_bytes([data[1], data[2]]);
+        if version >> 8 != 3 {
+            return Err(ClientHelloError::NotTls);
+        }
+        record_version.get_or_insert(version);
+
+        let len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(ClientHelloError::Malformed);
+        }
+        let fragment = data.get(5..5 + len).ok_or(ClientHelloError::Incomplete)?;
+        handshake.extend_from_slice(fragment);
+        data = &data[5 + len..];
+
+        if handshake.len() >= 4 {
+            if handshake[0] != HANDSHAKE_CLIENT_HELLO {
+                return Err(ClientHelloError::NotTls);
+            }
+            let message_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+            if handshake.len() >= 4 + message_len {
+                handshake.truncate(4 + message_len);
+                handshake.drain(..4);
+                return Ok((record_version.unwrap_or(version), handshake));
+            }
+        }
+    }
+}
+
+// GREASE values (RFC 8701) are random and must be ignored by fingerprints.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn u16_list(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ClientHelloError> {
+        if self.0.len() < len {
+            return Err(ClientHelloError::Malformed);
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    fn u8(&mut self) -> Result<u8, ClientHelloError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ClientHelloError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+/// Error parsing a [`ClientHello`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientHelloError {
+    /// The data is a prefix of a ClientHello; more data is needed.
+    Incomplete,
+    /// The data is not the start of a TLS handshake.
+    NotTls,
+    /// The ClientHello is complete but malformed, or is in a record longer than TLS allows.
+    Malformed,
+}
+
+impl fmt::Display for ClientHelloError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientHelloError::Incomplete => write!(f, "incomplete ClientHello"),
+            ClientHelloError::NotTls => write!(f, "not a TLS handshake"),
+            ClientHelloError::Malformed => write!(f, "malformed ClientHello"),
+        }
+    }
+}
+
+impl Error for ClientHelloError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GREASE: u16 = 0x0a0a;
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut out = extension_type.to_be_bytes().to_vec();
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    // A ClientHello message with its handshake header, offering GREASE values in every list.
+    fn message() -> Vec<u8> {
+        let mut sni = vec![0, 14, 0, 0, 11];
+        sni.extend_from_slice(b"example.com");
+        let mut extensions = extension(0x1a1a, &[]);
+        extensions.extend(extension(TLS_EXT_SERVER_NAME, &sni));
+        extensions.extend(extension(TLS_EXT_SUPPORTED_GROUPS, &[0, 6, 0x2a, 0x2a, 0, 29, 0, 23]));
+        extensions.extend(extension(TLS_EXT_EC_POINT_FORMATS, &[1, 0]));
+        extensions.extend(extension(TLS_EXT_ALPN, &[0, 12, 2, b'h', b'2', 8, b'h', b't', b't', b'p', b'/', b'1', b'.', b'1']));
+        message_with(&extensions)
+    }
+
+    // Offsets in the message of the session ID length and the cipher suites length.
+    const SESSION_ID_LEN: usize = 4 + 2 + 32;
+    const SUITES_LEN: usize = SESSION_ID_LEN + 1;
+
+    fn message_with(extensions: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0, 6]);
+        for suite in &[GREASE, 0x1301, 0x1302] {
+            body.extend_from_slice(&suite.to_be_bytes());
+        }
+        body.extend_from_slice(&[1, 0]);
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(extensions);
+
+        let mut message = vec![HANDSHAKE_CLIENT_HELLO, 0, (body.len() >> 8) as u8, body.len() as u8];
+        message.extend(body);
+        message
+    }
+
+    fn record(fragment: &[u8]) -> Vec<u8> {
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 0x03, 0x01];
+        record.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+        record.extend_from_slice(fragment);
+        record
+    }
+
+    #[test]
+    fn parse() {
+        let hello = ClientHello::parse(&record(&message())).unwrap();
+        assert_eq!(hello.record_version, 0x0301);
+        assert_eq!(hello.version, 0x0303);
+        assert_eq!(hello.cipher_suites, vec![GREASE, 0x1301, 0x1302]);
+        assert_eq!(hello.compression_methods, vec![0]);
+        assert_eq!(hello.server_name(), Some("example.com"));
+        assert_eq!(hello.alpn_protocols(), vec![&b"h2"[..], &b"http/1.1"[..]]);
+        assert_eq!(ClientHello::parse_message(&message()).unwrap().extensions, hello.extensions);
+    }
+
+    #[test]
+    fn parse_split_records() {
+        let message = message();
+        let (first, second) = message.split_at(20);
+        let mut data = record(first);
+        data.extend(record(second));
+        assert_eq!(ClientHello::parse(&data).unwrap().server_name(), Some("example.com"));
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let data = record(&message());
+        for len in &[0, 1, 4, 5, 40, data.len() - 1] {
+            assert_eq!(ClientHello::parse(&data[..*len]), Err(ClientHelloError::Incomplete));
+        }
+        let message = message();
+        assert_eq!(ClientHello::parse_message(&message[..message.len() - 1]), Err(ClientHelloError::Incomplete));
+    }
+
+    #[test]
+    fn parse_not_tls() {
+        assert_eq!(ClientHello::parse(b"GET / HTTP/1.1\r\n"), Err(ClientHelloError::NotTls));
+        assert_eq!(ClientHello::parse(&[CONTENT_TYPE_HANDSHAKE, 0x02, 0x00, 0, 0]), Err(ClientHelloError::NotTls));
+        let mut server_hello = message();
+        server_hello[0] = 2;
+        assert_eq!(ClientHello::parse(&record(&server_hello)), Err(ClientHelloError::NotTls));
+    }
+
+    #[test]
+    fn parse_oversized_lengths() {
+        let mut message = message();
+        message[SESSION_ID_LEN] = 0xff;
+        assert_eq!(ClientHello::parse(&record(&message)), Err(ClientHelloError::Malformed));
+
+        let mut message = self::message();
+        message[SUITES_LEN] = 0xff;
+        assert_eq!(ClientHello::parse(&record(&message)), Err(ClientHelloError::Malformed));
+
+        // The extension claims more data than the extensions hold.
+        let message = message_with(&[0, TLS_EXT_SERVER_NAME as u8, 0xff, 0xff, 0]);
+        assert_eq!(ClientHello::parse(&record(&message)), Err(ClientHelloError::Malformed));
+    }
+
+    #[test]
+    fn parse_oversized_record() {
+        // Records are rejected from their header, before the fragment arrives.
+        let header = [CONTENT_TYPE_HANDSHAKE, 0x03, 0x01, 0x40, 0x01];
+        assert_eq!(ClientHello::parse(&header), Err(ClientHelloError::Malformed));
+
+        let mut data = record(&message());
+        data.resize(5 + MAX_RECORD_LEN, 0);
+        data[3..5].copy_from_slice(&(MAX_RECORD_LEN as u16).to_be_bytes());
+        assert!(ClientHello::parse(&data).is_ok());
+    }
+
+    #[test]
+    fn parse_odd_cipher_suites() {
+        let mut message = message();
+        message[SUITES_LEN + 1] = 5;
+        assert_eq!(ClientHello::parse(&record(&message)), Err(ClientHelloError::Malformed));
+    }
+
+    #[test]
+    fn grease() {
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+        assert!(!is_grease(0x1301));
+    }
+
+    #[test]
+    fn ja3() {
+        let hello = ClientHello::parse_message(&message()).unwrap();
+        assert_eq!(hello.ja3(), "771,4865-4866,0-10-11-16,29-23,0");
+    }
+
+    #[test]
+    fn ja4() {
+        let hello = ClientHello::parse_message(&message()).unwrap();
+        assert_eq!(hello.ja4(), "t12d0204h2_62ed6f6ca7ad_33a13ba74d1c");
+
+        // TLS 1.3 without SNI or ALPN: signature algorithms are appended in their order.
+        let mut extensions = extension(TLS_EXT_SUPPORTED_GROUPS, &[0, 2, 0, 29]);
+        extensions.extend(extension(TLS_EXT_SUPPORTED_VERSIONS, &[6, 0x2a, 0x2a, 3, 4, 3, 3]));
+        extensions.extend(extension(TLS_EXT_SIGNATURE_ALGORITHMS, &[0, 4, 4, 3, 8, 4]));
+        let hello = ClientHello::parse_message(&message_with(&extensions)).unwrap();
+        assert_eq!(hello.ja4(), "t13i020300_62ed6f6ca7ad_fbabbea27ee8");
+        assert_eq!(hello.ja4_quic(), "q13i020300_62ed6f6ca7ad_fbabbea27ee8");
+    }
+}
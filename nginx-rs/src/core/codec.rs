@@ -0,0 +1,254 @@
+use std::error::Error;
+use std::fmt;
+
+/// Binary serialization of values stored by the crate's components.
+///
+/// Shared memory dictionaries, session stores, event loggers and admin endpoints all store
+/// or ship values as bytes through this trait, so custom structs can be used with any of
+/// them by implementing it. Implementations are provided for integers, `bool`, `f64`,
+/// strings, `Option`, `Vec` and tuples; with the `serde` feature, [`Json`] wraps any serde
+/// type.
+///
+/// The encoding is compact and not self-describing: integers are little-endian and
+/// variable-length values are prefixed with their length as a `u32`. Values must be
+/// decoded as the same type they were encoded as.
+///
+/// ```ignore
+/// struct Session { user: String, expires: u64 }
+///
+/// impl Codec for Session {
+///     fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+///         self.user.encode(out)?;
+///         self.expires.encode(out)
+///     }
+///
+///     fn decode(input: &mut &[u8]) -> Result<Session, CodecError> {
+///         Ok(Session { user: String::decode(input)?, expires: u64::decode(input)? })
+///     }
+/// }
+/// ```
+pub trait Codec: Sized {
+    /// Append the encoded value to `out`.
+    ///
+    /// On error, `out` may hold part of the value.
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError>;
+
+    /// Decode a value from the start of `input`, advancing it past the value.
+    fn decode(input: &mut &[u8]) -> Result<Self, CodecError>;
+
+    /// Encode the value into a new buffer.
+    fn to_bytes(&self) -> Result<Vec<u8>, CodecError> {
+        let mut out = Vec::new();
+        self.encode(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decode a value that must take up all of `data`.
+    fn from_bytes(mut data: &[u8]) -> Result<Self, CodecError> {
+        let value = Self::decode(&mut data)?;
+        if !data.is_empty() {
+            return Err(CodecError::TrailingData);
+        }
+        Ok(value)
+    }
+}
+
+/// Error encoding or decoding a value with [`Codec`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CodecError {
+    /// The input ends in the middle of a value.
+    Truncated,
+    /// There is data left after the value.
+    TrailingData,
+    /// The input is not a valid encoding of the value.
+    Invalid(String),
+    /// The value can't be encoded, such as a string over 4 GiB.
+    Unencodable(String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Truncated => write!(f, "truncated value"),
+            CodecError::TrailingData => write!(f, "trailing data after value"),
+            CodecError::Invalid(message) => write!(f, "invalid value: {}", message),
+            CodecError::Unencodable(message) => write!(f, "value can't be encoded: {}", message),
+        }
+    }
+}
+
+impl Error for CodecError {}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], CodecError> {
+    if input.len() < len {
+        return Err(CodecError::Truncated);
+    }
+    let (value, rest) = input.split_at(len);
+    *input = rest;
+    Ok(value)
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) -> Result<(), CodecError> {
+    if len > u32::MAX as usize {
+        return Err(CodecError::Unencodable(format!("length {} does not fit in u32", len)));
+    }
+    (len as u32).encode(out)
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize, CodecError> {
+    Ok(u32::decode(input)? as usize)
+}
+
+macro_rules! impl_codec_int {
+    ( $( $t: ty ),* ) => {
+        $(
+            impl Codec for $t {
+                fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+                    out.extend_from_slice(&self.to_le_bytes());
+                    Ok(())
+                }
+
+                fn decode(input: &mut &[u8]) -> Result<$t, CodecError> {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    bytes.copy_from_slice(take(input, std::mem::size_of::<$t>())?);
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_int!(u8, u16, u32, u64, i8, i16, i32, i64, f64);
+
+impl Codec for usize {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u64).encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<usize, CodecError> {
+        let value = u64::decode(input)?;
+        if value > usize::MAX as u64 {
+            return Err(CodecError::Invalid(format!("{} does not fit in usize", value)));
+        }
+        Ok(value as usize)
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        out.push(*self as u8);
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<bool, CodecError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(CodecError::Invalid(format!("{} is not a bool", b))),
+        }
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_len(self.len(), out)?;
+        out.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<String, CodecError> {
+        let len = decode_len(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|err| CodecError::Invalid(err.to_string()))
+    }
+}
+
+impl<T: Codec> Codec for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_len(self.len(), out)?;
+        for item in self {
+            item.encode(out)?;
+        }
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Vec<T>, CodecError> {
+        let len = decode_len(input)?;
+        // Don't trust the length for preallocation beyond what the input could hold.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        match self {
+            Some(value) => {
+                out.push(1);
+                value.encode(out)
+            }
+            None => {
+                out.push(0);
+                Ok(())
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Option<T>, CodecError> {
+        if bool::decode(input)? {
+            Ok(Some(T::decode(input)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! impl_codec_tuple {
+    ( $( ( $( $t: ident ),+ ) ),* ) => {
+        $(
+            #[allow(non_snake_case)]
+            impl<$( $t: Codec ),+> Codec for ( $( $t, )+ ) {
+                fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+                    let ( $( $t, )+ ) = self;
+                    $( $t.encode(out)?; )+
+                    Ok(())
+                }
+
+                fn decode(input: &mut &[u8]) -> Result<Self, CodecError> {
+                    Ok(( $( $t::decode(input)?, )+ ))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_tuple!((A), (A, B), (A, B, C), (A, B, C, D));
+
+/// A serde value, encoded as JSON.
+///
+/// JSON makes values readable as is by admin endpoints and external tools, at the cost of
+/// size.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec for Json<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        // Serializing fails for values that can't be represented in JSON, such as maps with
+        // non-string keys.
+        let json = serde_json::to_vec(&self.0).map_err(|err| CodecError::Unencodable(err.to_string()))?;
+        encode_len(json.len(), out)?;
+        out.extend_from_slice(&json);
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Json<T>, CodecError> {
+        let len = decode_len(input)?;
+        let bytes = take(input, len)?;
+        serde_json::from_slice(bytes).map(Json).map_err(|err| CodecError::Invalid(err.to_string()))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::bindings::*;
+use crate::core::{NgxStr, NgxString, Pool};
+
+use std::os::raw::c_char;
+use std::ptr;
+
+/// Value returned by directive handlers on success (`NGX_CONF_OK`).
+pub const NGX_CONF_OK: *mut c_char = ptr::null_mut();
+
+/// Value returned by directive handlers on failure (`NGX_CONF_ERROR`).
+pub const NGX_CONF_ERROR: *mut c_char = -1isize as *mut c_char;
+
+/// Resolve a path relative to the Nginx prefix (`ngx_conf_full_name`).
+///
+/// Relative paths are resolved against the configuration prefix (the directory of
+/// `nginx.conf`) if `conf_prefix` is `true`, otherwise against the installation prefix
+/// (`-p`). Absolute paths are returned unchanged. The result is allocated from the cycle pool.
+pub unsafe fn conf_full_name<'a>(cf: *mut ngx_conf_t, name: &NgxStr, conf_prefix: bool) -> Option<&'a NgxStr> {
+    // Copy the name so the result never borrows from the argument.
+    let mut pool = Pool::from_ngx_pool((*(*cf).cycle).pool);
+    let mut full = NgxString::new_in(name, &mut pool)?.into_ngx_str();
+    if ngx_conf_full_name((*cf).cycle, &mut full, conf_prefix as ngx_uint_t) != NGX_OK as ngx_int_t {
+        return None;
+    }
+
+    Some(NgxStr::from_ngx_str(full))
+}
+
+/// Read an environment variable allowed by the [`env`] directive.
+///
+/// Nginx removes all other variables from the environment of worker processes, so modules
+/// should only rely on variables that are allowed. Returns the value set by the directive
+/// (`env NAME=value;`) or inherited from the environment (`env NAME;`). Only `env` directives
+/// that appear before the calling directive in the configuration are visible.
+///
+/// [`env`]: https://nginx.org/en/docs/ngx_core_module.html#env
+pub unsafe fn conf_env(cf: *mut ngx_conf_t, name: &str) -> Option<String> {
+    let ccf = ngx_core_conf((*cf).cycle);
+    if ccf.is_null() {
+        return None;
+    }
+
+    // Nginx always keeps TZ, see `ngx_set_environment`.
+    let mut allowed = name == "TZ";
+
+    let env = &(*ccf).env;
+    let vars = env.elts as *const ngx_str_t;
+    for i in 0..env.nelts {
+        let var = NgxStr::from_ngx_str(*vars.add(i)).as_bytes();
+        if var == name.as_bytes() {
+            allowed = true;
+        } else if var.len() > name.len() && var.starts_with(name.as_bytes()) && var[name.len()] == b'=' {
+            return Some(String::from_utf8_lossy(&var[name.len() + 1..]).into_owned());
+        }
+    }
+
+    if allowed {
+        std::env::var(name).ok()
+    } else {
+        None
+    }
+}
+
+/// Configuration of the core module for a cycle (`ngx_get_conf(cycle->conf_ctx, ngx_core_module)`).
+pub(crate) unsafe fn ngx_core_conf(cycle: *mut ngx_cycle_t) -> *mut ngx_core_conf_t {
+    if cycle.is_null() || (*cycle).conf_ctx.is_null() {
+        return ptr::null_mut();
+    }
+    *(*cycle).conf_ctx.add(ngx_core_module.index) as *mut ngx_core_conf_t
+}
@@ -0,0 +1,133 @@
+use serde::de::DeserializeOwned;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Format of a configuration file read with [`load_conf_file`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfFormat {
+    /// JSON, for files ending with `.json`.
+    Json,
+    /// YAML, for files ending with `.yaml` or `.yml`, with the `yaml` feature.
+    Yaml,
+    /// TOML, for files ending with `.toml`, with the `toml` feature.
+    Toml,
+}
+
+impl ConfFormat {
+    /// The format of a file, from its extension.
+    pub fn from_path(path: &Path) -> Option<ConfFormat> {
+        match path.extension()?.to_str()? {
+            "json" => Some(ConfFormat::Json),
+            "yaml" | "yml" => Some(ConfFormat::Yaml),
+            "toml" => Some(ConfFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// Error reading a configuration file, with the position of the error in the file if known.
+///
+/// It is displayed as `path:line:column: message`, like compiler errors, so the position can
+/// be found from the Nginx error log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfFileError {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Line of the error, starting at 1.
+    pub line: Option<usize>,
+    /// Column of the error, starting at 1.
+    pub column: Option<usize>,
+    /// Description of the error.
+    pub message: String,
+}
+
+impl ConfFileError {
+    fn new(path: &Path, message: String) -> ConfFileError {
+        ConfFileError { path: path.to_path_buf(), line: None, column: None, message }
+    }
+}
+
+impl fmt::Display for ConfFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Error for ConfFileError {}
+
+/// Read a configuration file into a serde type, in the format given by its extension (see
+/// [`ConfFormat`]).
+///
+/// This is meant for rules and other structured configuration that doesn't fit the directive
+/// syntax of Nginx. It reads the file synchronously, so it must only be called while parsing
+/// the configuration, typically through the `file` slot of
+/// [`ngx_commands!`](crate::ngx_commands).
+pub fn load_conf_file<T: DeserializeOwned>(path: &Path) -> Result<T, ConfFileError> {
+    let format = ConfFormat::from_path(path)
+        .ok_or_else(|| ConfFileError::new(path, String::from("unknown format, expected a .json, .yaml, .yml or .toml file")))?;
+    let text = fs::read_to_string(path).map_err(|err| ConfFileError::new(path, err.to_string()))?;
+    parse_conf(format, &text, path)
+}
+
+/// Parse configuration text into a serde type. `path` is only used in errors.
+pub fn parse_conf<T: DeserializeOwned>(format: ConfFormat, text: &str, path: &Path) -> Result<T, ConfFileError> {
+    match format {
+        ConfFormat::Json => serde_json::from_str(text).map_err(|err| {
+            let (line, column) = (err.line(), err.column());
+            let mut error = ConfFileError::new(path, without_position(err.to_string()));
+            // serde_json reports line 0 for errors without a position, such as I/O errors.
+            if line > 0 {
+                error.line = Some(line);
+                error.column = Some(column);
+            }
+            error
+        }),
+        #[cfg(feature = "yaml")]
+        ConfFormat::Yaml => serde_yaml::from_str(text).map_err(|err| {
+            let location = err.location();
+            let mut error = ConfFileError::new(path, without_position(err.to_string()));
+            error.line = location.as_ref().map(|location| location.line());
+            error.column = location.as_ref().map(|location| location.column());
+            error
+        }),
+        #[cfg(feature = "toml")]
+        ConfFormat::Toml => toml::from_str(text).map_err(|err| {
+            let mut error = ConfFileError::new(path, err.message().to_string());
+            if let Some(span) = err.span() {
+                let (line, column) = position(text, span.start);
+                error.line = Some(line);
+                error.column = Some(column);
+            }
+            error
+        }),
+        #[allow(unreachable_patterns)]
+        _ => Err(ConfFileError::new(path, String::from("format not supported, enable the \"yaml\" or \"toml\" feature"))),
+    }
+}
+
+// Messages of serde_json and serde_yaml end with the position, which is reported separately.
+fn without_position(mut message: String) -> String {
+    if let Some(i) = message.rfind(" at line ") {
+        message.truncate(i);
+    }
+    message
+}
+
+// Line and column, starting at 1, of a byte offset.
+#[cfg(feature = "toml")]
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text.as_bytes()[..offset.min(text.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let column = before.iter().rev().take_while(|&&b| b != b'\n').count() + 1;
+    (line, column)
+}
@@ -0,0 +1,251 @@
+use crate::bindings::*;
+use crate::core::inet::sockaddr_to_std;
+use crate::core::pool::Pool;
+use crate::core::string::NgxStr;
+use crate::core::units::Sec;
+
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Parameters of TCP keepalive probes, see [`Connection::set_keepalive`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TcpKeepalive {
+    /// Idle time before the first probe (`TCP_KEEPIDLE`).
+    pub idle: Option<Sec>,
+    /// Time between probes (`TCP_KEEPINTVL`).
+    pub interval: Option<Sec>,
+    /// Number of unanswered probes before the connection is closed (`TCP_KEEPCNT`).
+    pub count: Option<u32>,
+}
+
+/// TCP statistics of a connection, from `TCP_INFO`.
+///
+/// Times are in microseconds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TcpInfo {
+    /// Smoothed round-trip time (`$tcpinfo_rtt`).
+    pub rtt: u32,
+    /// Variance of the round-trip time (`$tcpinfo_rttvar`).
+    pub rttvar: u32,
+    /// Congestion window, in segments (`$tcpinfo_snd_cwnd`).
+    pub snd_cwnd: u32,
+    /// Receive space, in bytes (`$tcpinfo_rcv_space`).
+    pub rcv_space: u32,
+    /// Retransmissions of the segment currently unacknowledged.
+    pub retransmits: u8,
+    /// Retransmissions over the life of the connection.
+    pub total_retrans: u32,
+}
+
+/// Wrapper struct for an [`ngx_connection_t`] pointer, a client or upstream connection.
+///
+/// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+#[repr(transparent)]
+pub struct Connection(ngx_connection_t);
+
+impl Connection {
+    /// Create a [`Connection`] from an [`ngx_connection_t`].
+    ///
+    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+    pub unsafe fn from_ngx_connection<'a>(c: *mut ngx_connection_t) -> &'a mut Connection {
+        &mut *c.cast::<Connection>()
+    }
+
+    /// Pointer to the [`ngx_connection_t`].
+    ///
+    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+    pub fn as_ngx_connection(&self) -> *const ngx_connection_t {
+        &self.0
+    }
+
+    /// Mutable pointer to the [`ngx_connection_t`].
+    ///
+    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+    pub fn as_ngx_connection_mut(&mut self) -> *mut ngx_connection_t {
+        &mut self.0
+    }
+
+    /// Connection number, unique in the cycle (`$connection`).
+    pub fn number(&self) -> ngx_atomic_uint_t {
+        self.0.number
+    }
+
+    /// Number of requests made through the connection (`$connection_requests`).
+    pub fn requests(&self) -> ngx_uint_t {
+        self.0.requests
+    }
+
+    /// Number of bytes sent so far.
+    pub fn sent(&self) -> off_t {
+        self.0.sent
+    }
+
+    /// Log of the connection.
+    pub fn log(&self) -> *mut ngx_log_t {
+        self.0.log
+    }
+
+    /// Connection pool.
+    pub fn pool(&self) -> Pool {
+        // SAFETY: Connections always have a pool while in use.
+        unsafe { Pool::from_ngx_pool(self.0.pool) }
+    }
+
+    /// Address of the remote end, such as the client (`$remote_addr` and `$remote_port`).
+    ///
+    /// Returns `None` for UNIX-domain sockets.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        unsafe { sockaddr_to_std(self.0.sockaddr, self.0.socklen) }
+    }
+
+    /// Address of the remote end as text, without the port (`$remote_addr`), such as
+    /// `unix:` for UNIX-domain sockets.
+    pub fn remote_addr_text(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.addr_text) }
+    }
+
+    /// Local address of the connection (`$server_addr` and `$server_port`).
+    ///
+    /// For connections accepted on a wildcard address, the address is looked up with
+    /// `getsockname` the first time. Returns `None` for UNIX-domain sockets, or if the lookup
+    /// fails.
+    pub fn local_addr(&mut self) -> Option<SocketAddr> {
+        unsafe {
+            let c = self.as_ngx_connection_mut();
+            if ngx_connection_local_sockaddr(c, ptr::null_mut(), 0) != NGX_OK as ngx_int_t {
+                return None;
+            }
+            sockaddr_to_std(self.0.local_sockaddr, self.0.local_socklen)
+        }
+    }
+
+    /// Is `TCP_NODELAY` set on the socket?
+    pub fn is_tcp_nodelay(&self) -> bool {
+        self.0.tcp_nodelay() == ngx_connection_tcp_nodelay_e_NGX_TCP_NODELAY_SET as u32
+    }
+
+    /// Set or clear `TCP_NODELAY` on the socket, which disables Nagle's algorithm, as Nginx
+    /// sets it for keepalive connections with `tcp_nodelay on`.
+    ///
+    /// Sockets other than TCP are left alone.
+    pub fn set_tcp_nodelay(&mut self, enable: bool) -> io::Result<()> {
+        if self.0.type_ != libc::SOCK_STREAM || self.remote_addr().is_none() {
+            return Ok(());
+        }
+        // Not with `ngx_tcp_nodelay`, which does nothing once the state is recorded, even if
+        // it was cleared. The state is recorded so Nginx doesn't set it again.
+        self.setsockopt(libc::IPPROTO_TCP, libc::TCP_NODELAY, enable as c_int)?;
+        let state = if enable {
+            ngx_connection_tcp_nodelay_e_NGX_TCP_NODELAY_SET
+        } else {
+            ngx_connection_tcp_nodelay_e_NGX_TCP_NODELAY_DISABLED
+        };
+        self.0.set_tcp_nodelay(state as u32);
+        Ok(())
+    }
+
+    /// Enable TCP keepalive probes on the socket (`SO_KEEPALIVE`), or disable them with
+    /// `None`, like the `so_keepalive` parameter of `listen`.
+    ///
+    /// The parameters of `keepalive` left unset keep the system defaults. They are only
+    /// supported on Linux, and ignored elsewhere.
+    pub fn set_keepalive(&mut self, keepalive: Option<TcpKeepalive>) -> io::Result<()> {
+        self.setsockopt(libc::SOL_SOCKET, libc::SO_KEEPALIVE, keepalive.is_some() as c_int)?;
+
+        #[cfg(target_os = "linux")]
+        if let Some(keepalive) = keepalive {
+            if let Some(idle) = keepalive.idle {
+                self.setsockopt(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle.as_secs() as c_int)?;
+            }
+            if let Some(interval) = keepalive.interval {
+                self.setsockopt(libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, interval.as_secs() as c_int)?;
+            }
+            if let Some(count) = keepalive.count {
+                self.setsockopt(libc::IPPROTO_TCP, libc::TCP_KEEPCNT, count as c_int)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the type of service byte of the packets sent on the socket: `IP_TOS` for IPv4 and
+    /// `IPV6_TCLASS` for IPv6.
+    pub fn set_tos(&mut self, tos: u8) -> io::Result<()> {
+        match self.remote_addr() {
+            Some(SocketAddr::V4(_)) => self.setsockopt(libc::IPPROTO_IP, libc::IP_TOS, tos as c_int),
+            Some(SocketAddr::V6(_)) => self.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos as c_int),
+            None => Err(io::Error::from_raw_os_error(libc::EAFNOSUPPORT)),
+        }
+    }
+
+    /// Mark the packets sent on the socket with a [DSCP] code point (between 0 and 63), such
+    /// as 46 for expedited forwarding. The ECN bits of the type of service byte are cleared.
+    ///
+    /// [DSCP]: https://datatracker.ietf.org/doc/html/rfc2474
+    pub fn set_dscp(&mut self, dscp: u8) -> io::Result<()> {
+        if dscp > 63 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        self.set_tos(dscp << 2)
+    }
+
+    fn setsockopt(&mut self, level: c_int, name: c_int, value: c_int) -> io::Result<()> {
+        let rc = unsafe {
+            let value = &value as *const c_int as *const libc::c_void;
+            libc::setsockopt(self.0.fd, level, name, value, mem::size_of::<c_int>() as libc::socklen_t)
+        };
+        if rc == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// TCP statistics of the connection, such as the round-trip time, for latency-aware
+    /// routing and logging.
+    ///
+    /// Returns `None` for other sockets, if `getsockopt` fails, or on platforms without
+    /// `TCP_INFO` (only Linux is supported).
+    pub fn tcp_info(&self) -> Option<TcpInfo> {
+        if self.0.type_ != libc::SOCK_STREAM || self.remote_addr().is_none() {
+            return None;
+        }
+        tcp_info(self.0.fd)
+    }
+
+    /// File descriptor of the socket.
+    pub fn fd(&self) -> ngx_socket_t {
+        self.0.fd
+    }
+
+    /// Has an error occurred on the connection?
+    pub fn is_error(&self) -> bool {
+        self.0.error() != 0
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn tcp_info(fd: ngx_socket_t) -> Option<TcpInfo> {
+    unsafe {
+        let mut ti: libc::tcp_info = mem::zeroed();
+        let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        let ti_ptr = &mut ti as *mut libc::tcp_info as *mut libc::c_void;
+        if libc::getsockopt(fd, libc::IPPROTO_TCP, libc::TCP_INFO, ti_ptr, &mut len) == -1 {
+            return None;
+        }
+        Some(TcpInfo {
+            rtt: ti.tcpi_rtt,
+            rttvar: ti.tcpi_rttvar,
+            snd_cwnd: ti.tcpi_snd_cwnd,
+            rcv_space: ti.tcpi_rcv_space,
+            retransmits: ti.tcpi_retransmits,
+            total_retrans: ti.tcpi_total_retrans,
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tcp_info(_fd: ngx_socket_t) -> Option<TcpInfo> {
+    None
+}
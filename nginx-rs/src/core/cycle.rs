@@ -0,0 +1,115 @@
+use crate::bindings::*;
+use crate::core::process;
+use crate::core::{NgxStr, Pool};
+
+use std::os::raw::c_void;
+
+/// The configuration cycle (`ngx_cycle_t`) a process runs, as passed to the module lifecycle
+/// handlers.
+///
+/// A cycle holds the configuration parsed from the configuration files, and a reload
+/// creates a new one: the memory of the [`pool`](Cycle::pool) and the configurations live
+/// as long as the cycle.
+#[repr(transparent)]
+pub struct Cycle(ngx_cycle_t);
+
+impl Cycle {
+    /// Create a [`Cycle`] from an [`ngx_cycle_t`].
+    pub unsafe fn from_ngx_cycle<'a>(cycle: *mut ngx_cycle_t) -> &'a Cycle {
+        // SAFETY: The caller has provided a valid non-null pointer to a valid `ngx_cycle_t`
+        // which shares the same representation as `Cycle`.
+        &*cycle.cast::<Cycle>()
+    }
+
+    /// Call `f` with the cycle currently running (`ngx_cycle`).
+    ///
+    /// The cycle is only borrowed for the call, as a reload replaces it and frees the previous
+    /// one once its connections are closed.
+    pub fn with_current<R, F: FnOnce(&Cycle) -> R>(f: F) -> R {
+        // SAFETY: `ngx_cycle` is valid from startup, and is not replaced during the call.
+        f(unsafe { Cycle::from_ngx_cycle(ngx_cycle as *mut ngx_cycle_t) })
+    }
+
+    /// Pointer to the underlying [`ngx_cycle_t`].
+    pub fn as_ngx_cycle(&self) -> *mut ngx_cycle_t {
+        &self.0 as *const ngx_cycle_t as *mut ngx_cycle_t
+    }
+
+    /// The log of the cycle, for [`ngx_log!`](crate::ngx_log).
+    pub fn log(&self) -> *mut ngx_log_t {
+        self.0.log
+    }
+
+    /// The memory pool of the cycle, freed when the cycle is replaced.
+    pub fn pool(&self) -> Pool {
+        unsafe { Pool::from_ngx_pool(self.0.pool) }
+    }
+
+    /// The host name of the machine, as used by `$hostname`.
+    pub fn hostname(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.hostname) }
+    }
+
+    /// The prefix path of the server, as set by `-p` or at build time.
+    pub fn prefix(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.prefix) }
+    }
+
+    /// The path of the main configuration file.
+    pub fn conf_file(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.conf_file) }
+    }
+
+    /// The maximum number of connections of a worker process (`worker_connections`).
+    pub fn connections(&self) -> usize {
+        self.0.connection_n
+    }
+
+    /// The number of connections of the worker process that are free.
+    ///
+    /// Returns `None` outside of worker processes, and before their connections are
+    /// allocated by the `init_process` handler of the event module.
+    pub fn free_connections(&self) -> Option<usize> {
+        if process::worker_number().is_none() || self.0.connections.is_null() {
+            return None;
+        }
+        Some(self.0.free_connection_n)
+    }
+
+    /// The number of connections of the worker process in use, including listening sockets.
+    ///
+    /// Returns `None` where [`Cycle::free_connections`] does.
+    pub fn active_connections(&self) -> Option<usize> {
+        self.free_connections().map(|free| self.0.connection_n.saturating_sub(free))
+    }
+
+    /// The main configuration of an HTTP `module`, if the configuration has an `http` block.
+    pub fn http_main_conf<T>(&self, module: &ngx_module_t) -> Option<&T> {
+        unsafe { self.main_conf(ngx_http_module.index, module) }
+    }
+
+    /// The main configuration of a stream `module`, if the configuration has a `stream` block.
+    #[cfg(feature = "stream")]
+    pub fn stream_main_conf<T>(&self, module: &ngx_module_t) -> Option<&T> {
+        unsafe { self.main_conf(ngx_stream_module.index, module) }
+    }
+
+    /// The main configuration of a mail `module`, if the configuration has a `mail` block.
+    #[cfg(feature = "mail")]
+    pub fn mail_main_conf<T>(&self, module: &ngx_module_t) -> Option<&T> {
+        unsafe { self.main_conf(ngx_mail_module.index, module) }
+    }
+
+    // The HTTP, stream and mail configuration contexts share the layout of their first field.
+    unsafe fn main_conf<T>(&self, index: ngx_uint_t, module: &ngx_module_t) -> Option<&T> {
+        if self.0.conf_ctx.is_null() {
+            return None;
+        }
+        let ctx = *self.0.conf_ctx.add(index) as *mut *mut *mut c_void;
+        if ctx.is_null() {
+            return None;
+        }
+        let conf = *(*ctx).add(module.ctx_index) as *const T;
+        conf.as_ref()
+    }
+}
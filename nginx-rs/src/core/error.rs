@@ -0,0 +1,175 @@
+use crate::bindings::*;
+use crate::core::status::*;
+use crate::ngx_log_error;
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// An error of a handler, with the status to finalize the request with.
+///
+/// Handlers defined with [`http_request_handler!`](crate::http_request_handler) and the other
+/// handler macros may return `Result<Status, NgxError>`, so errors propagate with `?`: the
+/// error is logged and the handler returns its status. Errors without a status become an
+/// internal server error (`500`) in HTTP handlers and [`ERROR`] elsewhere.
+///
+/// ```ignore
+/// http_request_handler!(ngx_http_quota_handler, |request: &mut Request| -> Result<Status, NgxError> {
+///     let key = request.variable("http_x_api_key").ok_or(HTTP_FORBIDDEN)?.to_str()?;
+///     let used = QUOTAS.incr(key, 1, Some(0), Msec::from_secs(60))?;
+///     if used > QUOTA {
+///         return Err(NgxError::with_status(HTTP_TOO_MANY_REQUESTS, format!("quota exceeded for {}", key)));
+///     }
+///     Ok(DECLINED)
+/// });
+/// ```
+#[derive(Debug)]
+pub struct NgxError {
+    status: Option<ngx_int_t>,
+    message: Cow<'static, str>,
+    source: Option<Box<dyn Error + 'static>>,
+}
+
+impl NgxError {
+    /// An error with `message`, and the default status.
+    pub fn new<M: Into<Cow<'static, str>>>(message: M) -> NgxError {
+        NgxError { status: None, message: message.into(), source: None }
+    }
+
+    /// An error with `message` and `status`, such as an [`HTTPStatus`](crate::http::HTTPStatus).
+    pub fn with_status<S: Into<Status>, M: Into<Cow<'static, str>>>(status: S, message: M) -> NgxError {
+        NgxError { status: Some(status.into().0), message: message.into(), source: None }
+    }
+
+    /// An error caused by `source`, with the default status.
+    pub fn from_error<E: Error + 'static>(source: E) -> NgxError {
+        NgxError { status: None, message: Cow::Owned(source.to_string()), source: Some(Box::new(source)) }
+    }
+
+    /// Set the status of the error.
+    pub fn status<S: Into<Status>>(mut self, status: S) -> NgxError {
+        self.status = Some(status.into().0);
+        self
+    }
+
+    /// The status of the error, or `default` if it has none.
+    pub fn status_or(&self, default: Status) -> Status {
+        self.status.map(Status).unwrap_or(default)
+    }
+
+    /// The message of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Log the error to `log`, unless it has no message.
+    ///
+    /// Client errors (HTTP statuses below `500`) are logged at the `info` level, as Nginx
+    /// does, and other errors at the `error` level.
+    pub fn log(&self, log: *mut ngx_log_t) {
+        if self.message.is_empty() || log.is_null() {
+            return;
+        }
+        let level = match self.status {
+            Some(status) if (100..500).contains(&status) => NGX_LOG_INFO,
+            _ => NGX_LOG_ERR,
+        };
+        ngx_log_error!(level, log, "{}", self.message);
+    }
+}
+
+impl fmt::Display for NgxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            match self.status {
+                Some(status) => write!(f, "status {}", status),
+                None => write!(f, "error"),
+            }
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl Error for NgxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref()
+    }
+}
+
+impl From<Status> for NgxError {
+    fn from(status: Status) -> NgxError {
+        NgxError { status: Some(status.0), message: Cow::Borrowed(""), source: None }
+    }
+}
+
+impl From<NgxError> for Status {
+    fn from(error: NgxError) -> Status {
+        error.status_or(ERROR)
+    }
+}
+
+impl From<&'static str> for NgxError {
+    fn from(message: &'static str) -> NgxError {
+        NgxError::new(message)
+    }
+}
+
+impl From<String> for NgxError {
+    fn from(message: String) -> NgxError {
+        NgxError::new(message)
+    }
+}
+
+macro_rules! from_error {
+    ( $( $error: ty ),* $(,)? ) => {
+        $(
+            impl From<$error> for NgxError {
+                fn from(error: $error) -> NgxError {
+                    NgxError::from_error(error)
+                }
+            }
+        )*
+    };
+}
+
+from_error!(
+    std::io::Error,
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::num::ParseIntError,
+    std::num::ParseFloatError,
+    crate::core::AllocError,
+    crate::core::CodecError,
+    crate::core::shm::DictError,
+    crate::core::shm::ChannelError,
+    crate::http::ClientError,
+    crate::http::SignatureError,
+    crate::http::WebSocketError,
+);
+
+/// Value returned by a handler defined with the handler macros: a status, or a `Result` whose
+/// error is logged and returned as its status.
+pub trait HandlerResult {
+    /// The status to return, logging the error, if any, to `log`. Errors without a status
+    /// return `default`.
+    fn into_status(self, log: *mut ngx_log_t, default: Status) -> Status;
+}
+
+impl HandlerResult for Status {
+    fn into_status(self, _log: *mut ngx_log_t, _default: Status) -> Status {
+        self
+    }
+}
+
+impl<T: Into<Status>> HandlerResult for Result<T, NgxError> {
+    fn into_status(self, log: *mut ngx_log_t, default: Status) -> Status {
+        match self {
+            Ok(value) => value.into(),
+            Err(error) => {
+                error.log(log);
+                error.status_or(default)
+            }
+        }
+    }
+}
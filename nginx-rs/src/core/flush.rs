@@ -0,0 +1,78 @@
+use crate::bindings::*;
+use crate::ngx_log;
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+/// Default time budget shared by all exit flushers, see [`set_exit_flush_budget`].
+pub const EXIT_FLUSH_BUDGET: Duration = Duration::from_secs(1);
+
+struct Flusher {
+    name: &'static str,
+    flush: Box<dyn FnOnce(Instant)>,
+}
+
+thread_local! {
+    static FLUSHERS: RefCell<Vec<Flusher>> = RefCell::new(Vec::new());
+    static BUDGET: Cell<Duration> = Cell::new(EXIT_FLUSH_BUDGET);
+}
+
+/// Register a function to flush buffered output when the worker process exits.
+///
+/// Exporters that batch data in memory, such as event sinks or metrics snapshots, register
+/// a flusher so the last batch is not lost on reload or shutdown. Flushers run from the
+/// `exit_process` handler of modules declared with [`http_module!`](crate::http_module), in
+/// the order they were registered, and each runs at most once. A flusher is passed the
+/// deadline of the shared time budget and should give up on anything that would take longer,
+/// as the master process is waiting for the worker to exit. Flushers that have not started
+/// when the budget runs out are skipped, with a warning.
+///
+/// The flushers are local to the worker process, so register them from `init_process` or
+/// while handling requests.
+pub fn register_exit_flusher<F: FnOnce(Instant) + 'static>(name: &'static str, flush: F) {
+    FLUSHERS.with(|flushers| flushers.borrow_mut().push(Flusher { name, flush: Box::new(flush) }));
+}
+
+/// Set the time budget shared by all exit flushers.
+pub fn set_exit_flush_budget(budget: Duration) {
+    BUDGET.with(|b| b.set(budget));
+}
+
+/// Run the registered exit flushers within the time budget.
+///
+/// This is called by the `exit_process` handlers generated by this crate, and only needs to
+/// be called by modules declaring their `ngx_module_t` by hand.
+pub fn run_exit_flushers() {
+    let deadline = Instant::now() + BUDGET.with(Cell::get);
+
+    loop {
+        // Flushers may register further flushers, so don't hold the borrow while running one.
+        let flusher = FLUSHERS.with(|flushers| {
+            let mut flushers = flushers.borrow_mut();
+            if flushers.is_empty() {
+                None
+            } else {
+                Some(flushers.remove(0))
+            }
+        });
+        let flusher = match flusher {
+            Some(flusher) => flusher,
+            None => return,
+        };
+
+        if Instant::now() >= deadline {
+            let mut skipped = vec![flusher.name];
+            FLUSHERS.with(|flushers| skipped.extend(flushers.borrow_mut().drain(..).map(|flusher| flusher.name)));
+            ngx_log!(NGX_LOG_WARN, (*ngx_cycle).log, "exit flush budget exceeded, skipped: {}", skipped.join(", "));
+            return;
+        }
+
+        (flusher.flush)(deadline);
+    }
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn exit_process_flush(_cycle: *mut ngx_cycle_t) {
+    crate::core::process::run_shutdown_handlers();
+    run_exit_flushers();
+}
@@ -0,0 +1,584 @@
+use crate::bindings::*;
+
+use std::hash::Hasher;
+
+/// A 32-bit hash function over byte strings.
+///
+/// Components that hash keys (dictionaries, limiters, fingerprints, ...) take a hasher
+/// implementing this trait, so the hash function can be swapped.
+pub trait Hash32 {
+    /// Hash `data`.
+    fn hash32(&self, data: &[u8]) -> u32;
+}
+
+/// A 64-bit hash function over byte strings, see [`Hash32`].
+pub trait Hash64 {
+    /// Hash `data`.
+    fn hash64(&self, data: &[u8]) -> u64;
+}
+
+/// MurmurHash2 as implemented by Nginx (`ngx_murmur_hash2`).
+///
+/// Nginx uses it for upstream hashing and `split_clients`, so it is the right choice when
+/// results must agree with those.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Murmur2;
+
+impl Hash32 for Murmur2 {
+    fn hash32(&self, data: &[u8]) -> u32 {
+        unsafe { ngx_murmur_hash2(data.as_ptr() as *mut u_char, data.len()) }
+    }
+}
+
+/// The hash of Nginx hash tables (`ngx_hash_key`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NgxHashKey;
+
+impl Hash64 for NgxHashKey {
+    fn hash64(&self, data: &[u8]) -> u64 {
+        hash_key(data) as u64
+    }
+}
+
+/// The hash of Nginx hash tables over lowercased keys (`ngx_hash_key_lc`), such as header
+/// names.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NgxHashKeyLc;
+
+impl Hash64 for NgxHashKeyLc {
+    fn hash64(&self, data: &[u8]) -> u64 {
+        hash_key_lc(data) as u64
+    }
+}
+
+/// Hash a key for lookup in a Nginx hash table (`ngx_hash_key`).
+pub fn hash_key(data: &[u8]) -> ngx_uint_t {
+    unsafe { ngx_hash_key(data.as_ptr() as *mut u_char, data.len()) }
+}
+
+/// Hash a key for lookup in a Nginx hash table, ignoring case (`ngx_hash_key_lc`).
+pub fn hash_key_lc(data: &[u8]) -> ngx_uint_t {
+    unsafe { ngx_hash_key_lc(data.as_ptr() as *mut u_char, data.len()) }
+}
+
+const PRIME64_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME64_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME64_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME64_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME64_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+/// The [xxHash64] hash function.
+///
+/// Much faster than MurmurHash2 on longer keys, with good distribution. Use
+/// [`XxHash64State`] to hash data that arrives in pieces.
+///
+/// [xxHash64]: https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XxHash64 {
+    /// The seed of the hash.
+    pub seed: u64,
+}
+
+impl XxHash64 {
+    /// xxHash64 with the given seed.
+    pub const fn with_seed(seed: u64) -> XxHash64 {
+        XxHash64 { seed }
+    }
+}
+
+impl Hash64 for XxHash64 {
+    fn hash64(&self, data: &[u8]) -> u64 {
+        let mut state = XxHash64State::new(self.seed);
+        state.update(data);
+        state.finish()
+    }
+}
+
+impl Hash32 for XxHash64 {
+    fn hash32(&self, data: &[u8]) -> u32 {
+        self.hash64(data) as u32
+    }
+}
+
+/// Streaming xxHash64.
+///
+/// Also implements [`Hasher`], so it can be used with `std` hash maps.
+#[derive(Clone, Debug)]
+pub struct XxHash64State {
+    seed: u64,
+    acc: [u64; 4],
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl XxHash64State {
+    /// Start hashing with the given seed.
+    pub fn new(seed: u64) -> XxHash64State {
+        XxHash64State {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add data to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.buf_len > 0 {
+            let n = data.len().min(32 - self.buf_len);
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+
+            if self.buf_len < 32 {
+                return;
+            }
+            let buf = self.buf;
+            self.stripe(&buf);
+            self.buf_len = 0;
+        }
+
+        while data.len() >= 32 {
+            self.stripe(&data[..32]);
+            data = &data[32..];
+        }
+
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    /// The hash of all data added so far.
+    pub fn digest(&self) -> u64 {
+        let mut h = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut h = v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for &v in self.acc.iter() {
+                h = merge_round(h, v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h ^= round(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= (read_u32(rest) as u64).wrapping_mul(PRIME64_1);
+            h = h.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            h ^= (byte as u64).wrapping_mul(PRIME64_5);
+            h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^ (h >> 32)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round(*acc, read_u64(&stripe[i * 8..]));
+        }
+    }
+}
+
+impl Default for XxHash64State {
+    fn default() -> XxHash64State {
+        XxHash64State::new(0)
+    }
+}
+
+impl Hasher for XxHash64State {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.digest()
+    }
+}
+
+/// The [XXH3] 64-bit hash function.
+///
+/// Faster than xxHash64, especially on short keys. Use [`Xxh3State`] to hash data that
+/// arrives in pieces.
+///
+/// [XXH3]: https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Xxh3 {
+    /// The seed of the hash.
+    pub seed: u64,
+}
+
+impl Xxh3 {
+    /// XXH3 with the given seed.
+    pub const fn with_seed(seed: u64) -> Xxh3 {
+        Xxh3 { seed }
+    }
+}
+
+impl Hash64 for Xxh3 {
+    fn hash64(&self, data: &[u8]) -> u64 {
+        if data.len() <= XXH3_MIDSIZE_MAX {
+            return xxh3_short(data, &XXH3_SECRET, self.seed);
+        }
+        let secret = xxh3_secret(self.seed);
+        let mut acc = XXH3_ACC_INIT;
+        let stripes_per_block = (XXH3_SECRET.len() - XXH3_STRIPE_LEN) / 8;
+        let block_len = XXH3_STRIPE_LEN * stripes_per_block;
+        let blocks = (data.len() - 1) / block_len;
+        for block in data[..blocks * block_len].chunks(block_len) {
+            xxh3_accumulate(&mut acc, block, &secret, stripes_per_block);
+            xxh3_scramble(&mut acc, &secret[XXH3_SECRET.len() - XXH3_STRIPE_LEN..]);
+        }
+        let stripes = (data.len() - 1 - blocks * block_len) / XXH3_STRIPE_LEN;
+        xxh3_accumulate(&mut acc, &data[blocks * block_len..], &secret, stripes);
+        xxh3_accumulate_stripe(&mut acc, &data[data.len() - XXH3_STRIPE_LEN..], &secret[XXH3_SECRET.len() - XXH3_STRIPE_LEN - 7..]);
+        xxh3_merge(&acc, &secret, (data.len() as u64).wrapping_mul(PRIME64_1))
+    }
+}
+
+impl Hash32 for Xxh3 {
+    fn hash32(&self, data: &[u8]) -> u32 {
+        self.hash64(data) as u32
+    }
+}
+
+/// Streaming XXH3, giving the same hash as [`Xxh3`] over all the data added.
+///
+/// Also implements [`Hasher`], so it can be used with `std` hash maps.
+#[derive(Clone, Debug)]
+pub struct Xxh3State {
+    seed: u64,
+    secret: [u8; 192],
+    acc: [u64; 8],
+    // Data not yet accumulated. Once more than a buffer was added, the last stripe
+    // accumulated is kept at the end, to read the last 64 bytes from.
+    buf: [u8; XXH3_BUFFER_LEN],
+    buf_len: usize,
+    // Stripes accumulated in the current block.
+    stripes: usize,
+    total_len: u64,
+}
+
+impl Xxh3State {
+    /// Start hashing with the given seed.
+    pub fn new(seed: u64) -> Xxh3State {
+        Xxh3State {
+            seed,
+            secret: xxh3_secret(seed),
+            acc: XXH3_ACC_INIT,
+            buf: [0; XXH3_BUFFER_LEN],
+            buf_len: 0,
+            stripes: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add data to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.buf_len + data.len() <= XXH3_BUFFER_LEN {
+            self.buf[self.buf_len..self.buf_len + data.len()].copy_from_slice(data);
+            self.buf_len += data.len();
+            return;
+        }
+
+        if self.buf_len > 0 {
+            let n = XXH3_BUFFER_LEN - self.buf_len;
+            self.buf[self.buf_len..].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            let buf = self.buf;
+            self.consume(&buf, XXH3_BUFFER_LEN / XXH3_STRIPE_LEN);
+            self.buf_len = 0;
+        }
+
+        // Keep at least one byte buffered, as the last stripe is accumulated differently.
+        while data.len() > XXH3_BUFFER_LEN {
+            let (chunk, rest) = data.split_at(XXH3_BUFFER_LEN);
+            self.consume(chunk, XXH3_BUFFER_LEN / XXH3_STRIPE_LEN);
+            self.buf[XXH3_BUFFER_LEN - XXH3_STRIPE_LEN..].copy_from_slice(&chunk[XXH3_BUFFER_LEN - XXH3_STRIPE_LEN..]);
+            data = rest;
+        }
+
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    /// The hash of all data added so far.
+    pub fn digest(&self) -> u64 {
+        if self.total_len as usize <= XXH3_MIDSIZE_MAX {
+            return xxh3_short(&self.buf[..self.buf_len], &XXH3_SECRET, self.seed);
+        }
+
+        let mut state = self.clone();
+        let mut last = [0; XXH3_STRIPE_LEN];
+        if self.buf_len >= XXH3_STRIPE_LEN {
+            let stripes = (self.buf_len - 1) / XXH3_STRIPE_LEN;
+            state.consume(&self.buf, stripes);
+            last.copy_from_slice(&self.buf[self.buf_len - XXH3_STRIPE_LEN..self.buf_len]);
+        } else {
+            let catchup = XXH3_STRIPE_LEN - self.buf_len;
+            last[..catchup].copy_from_slice(&self.buf[XXH3_BUFFER_LEN - catchup..]);
+            last[catchup..].copy_from_slice(&self.buf[..self.buf_len]);
+        }
+        let secret_limit = self.secret.len() - XXH3_STRIPE_LEN;
+        xxh3_accumulate_stripe(&mut state.acc, &last, &self.secret[secret_limit - 7..]);
+        xxh3_merge(&state.acc, &self.secret, self.total_len.wrapping_mul(PRIME64_1))
+    }
+
+    // Accumulate `stripes` stripes of `data`, scrambling at the end of each block.
+    fn consume(&mut self, data: &[u8], stripes: usize) {
+        let stripes_per_block = (self.secret.len() - XXH3_STRIPE_LEN) / 8;
+        let secret_limit = self.secret.len() - XXH3_STRIPE_LEN;
+        let to_block_end = stripes_per_block - self.stripes;
+        if stripes >= to_block_end {
+            xxh3_accumulate(&mut self.acc, data, &self.secret[self.stripes * 8..], to_block_end);
+            xxh3_scramble(&mut self.acc, &self.secret[secret_limit..]);
+            xxh3_accumulate(&mut self.acc, &data[to_block_end * XXH3_STRIPE_LEN..], &self.secret, stripes - to_block_end);
+            self.stripes = stripes - to_block_end;
+        } else {
+            xxh3_accumulate(&mut self.acc, data, &self.secret[self.stripes * 8..], stripes);
+            self.stripes += stripes;
+        }
+    }
+}
+
+impl Default for Xxh3State {
+    fn default() -> Xxh3State {
+        Xxh3State::new(0)
+    }
+}
+
+impl Hasher for Xxh3State {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.digest()
+    }
+}
+
+const PRIME32_1: u64 = 0x9e37_79b1;
+const PRIME32_2: u64 = 0x85eb_ca77;
+const PRIME32_3: u64 = 0xc2b2_ae3d;
+
+const XXH3_STRIPE_LEN: usize = 64;
+const XXH3_MIDSIZE_MAX: usize = 240;
+const XXH3_BUFFER_LEN: usize = 256;
+
+const XXH3_ACC_INIT: [u64; 8] = [PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1];
+
+const XXH3_SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+// The secret of a seed, for inputs over `XXH3_MIDSIZE_MAX` bytes.
+fn xxh3_secret(seed: u64) -> [u8; 192] {
+    let mut secret = XXH3_SECRET;
+    if seed != 0 {
+        for (i, chunk) in secret.chunks_mut(16).enumerate() {
+            let lo = read_u64(&XXH3_SECRET[i * 16..]).wrapping_add(seed);
+            let hi = read_u64(&XXH3_SECRET[i * 16 + 8..]).wrapping_sub(seed);
+            chunk[..8].copy_from_slice(&lo.to_le_bytes());
+            chunk[8..].copy_from_slice(&hi.to_le_bytes());
+        }
+    }
+    secret
+}
+
+// Hash inputs of up to `XXH3_MIDSIZE_MAX` bytes.
+fn xxh3_short(data: &[u8], secret: &[u8], seed: u64) -> u64 {
+    let len = data.len();
+    match len {
+        0 => xxh64_avalanche(seed ^ read_u64(&secret[56..]) ^ read_u64(&secret[64..])),
+        1..=3 => {
+            let combined = (data[0] as u32) << 16 | (data[len >> 1] as u32) << 24 | data[len - 1] as u32 | (len as u32) << 8;
+            let bitflip = ((read_u32(secret) ^ read_u32(&secret[4..])) as u64).wrapping_add(seed);
+            xxh64_avalanche(combined as u64 ^ bitflip)
+        }
+        4..=8 => {
+            let seed = seed ^ ((seed as u32).swap_bytes() as u64) << 32;
+            let bitflip = (read_u64(&secret[8..]) ^ read_u64(&secret[16..])).wrapping_sub(seed);
+            let input = (read_u32(&data[len - 4..]) as u64).wrapping_add((read_u32(data) as u64) << 32);
+            xxh3_rrmxmx(input ^ bitflip, len as u64)
+        }
+        9..=16 => {
+            let bitflip_lo = (read_u64(&secret[24..]) ^ read_u64(&secret[32..])).wrapping_add(seed);
+            let bitflip_hi = (read_u64(&secret[40..]) ^ read_u64(&secret[48..])).wrapping_sub(seed);
+            let lo = read_u64(data) ^ bitflip_lo;
+            let hi = read_u64(&data[len - 8..]) ^ bitflip_hi;
+            let acc = (len as u64).wrapping_add(lo.swap_bytes()).wrapping_add(hi).wrapping_add(mul128_fold64(lo, hi));
+            xxh3_avalanche(acc)
+        }
+        17..=128 => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            let rounds = (len - 1) / 32;
+            for i in (0..=rounds).rev() {
+                acc = acc.wrapping_add(xxh3_mix16(&data[16 * i..], &secret[32 * i..], seed));
+                acc = acc.wrapping_add(xxh3_mix16(&data[len - 16 * (i + 1)..], &secret[32 * i + 16..], seed));
+            }
+            xxh3_avalanche(acc)
+        }
+        _ => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            for i in 0..8 {
+                acc = acc.wrapping_add(xxh3_mix16(&data[16 * i..], &secret[16 * i..], seed));
+            }
+            acc = xxh3_avalanche(acc);
+            for i in 8..len / 16 {
+                acc = acc.wrapping_add(xxh3_mix16(&data[16 * i..], &secret[16 * (i - 8) + 3..], seed));
+            }
+            acc = acc.wrapping_add(xxh3_mix16(&data[len - 16..], &secret[136 - 17..], seed));
+            xxh3_avalanche(acc)
+        }
+    }
+}
+
+fn xxh3_mix16(data: &[u8], secret: &[u8], seed: u64) -> u64 {
+    let lo = read_u64(data) ^ read_u64(secret).wrapping_add(seed);
+    let hi = read_u64(&data[8..]) ^ read_u64(&secret[8..]).wrapping_sub(seed);
+    mul128_fold64(lo, hi)
+}
+
+fn xxh3_accumulate(acc: &mut [u64; 8], data: &[u8], secret: &[u8], stripes: usize) {
+    for n in 0..stripes {
+        xxh3_accumulate_stripe(acc, &data[n * XXH3_STRIPE_LEN..], &secret[n * 8..]);
+    }
+}
+
+fn xxh3_accumulate_stripe(acc: &mut [u64; 8], stripe: &[u8], secret: &[u8]) {
+    for i in 0..8 {
+        let value = read_u64(&stripe[8 * i..]);
+        let key = value ^ read_u64(&secret[8 * i..]);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+        acc[i] = acc[i].wrapping_add((key & 0xffff_ffff).wrapping_mul(key >> 32));
+    }
+}
+
+fn xxh3_scramble(acc: &mut [u64; 8], secret: &[u8]) {
+    for (i, acc) in acc.iter_mut().enumerate() {
+        let mut value = *acc;
+        value ^= value >> 47;
+        value ^= read_u64(&secret[8 * i..]);
+        *acc = value.wrapping_mul(PRIME32_1);
+    }
+}
+
+fn xxh3_merge(acc: &[u64; 8], secret: &[u8], start: u64) -> u64 {
+    let mut result = start;
+    for i in 0..4 {
+        let secret = &secret[11 + 16 * i..];
+        result = result.wrapping_add(mul128_fold64(acc[2 * i] ^ read_u64(secret), acc[2 * i + 1] ^ read_u64(&secret[8..])));
+    }
+    xxh3_avalanche(result)
+}
+
+fn mul128_fold64(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    product as u64 ^ (product >> 64) as u64
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn xxh3_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(0x1656_6791_9e37_79f9);
+    h ^ (h >> 32)
+}
+
+fn xxh3_rrmxmx(mut h: u64, len: u64) -> u64 {
+    h ^= h.rotate_left(49) ^ h.rotate_left(24);
+    h = h.wrapping_mul(0x9fb2_1c65_1e98_df25);
+    h ^= (h >> 35).wrapping_add(len);
+    h = h.wrapping_mul(0x9fb2_1c65_1e98_df25);
+    h ^ (h >> 28)
+}
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn merge_round(acc: u64, val: u64) -> u64 {
+    (acc ^ round(0, val)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xxh3() {
+        assert_eq!(Xxh3::default().hash64(b""), 0x2d06_8005_38d3_94c2);
+        assert_eq!(Xxh3::default().hash64(b"abc"), 0x78af_5f94_892f_3950);
+        assert_eq!(Xxh3::default().hash64(b"The quick brown fox jumps over the lazy dog"), 0xce7d_19a5_418f_b365);
+    }
+
+    #[test]
+    fn xxh3_streaming() {
+        // Cover every input size class, and several blocks of stripes.
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 31 % 251) as u8).collect();
+        for &len in &[0, 3, 8, 16, 100, 200, 240, 241, 256, 257, 1024, 1025, 3000] {
+            for &seed in &[0, 42] {
+                let mut state = Xxh3State::new(seed);
+                for chunk in data[..len].chunks(13) {
+                    state.update(chunk);
+                }
+                assert_eq!(state.digest(), Xxh3::with_seed(seed).hash64(&data[..len]), "len {} seed {}", len, seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::bindings::*;
+use crate::core::pool::Pool;
+use crate::core::string::{NgxStr, NgxString};
+
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::ptr;
+use std::str::FromStr;
+
+/// `INADDR_NONE`, returned by `ngx_inet_addr` for invalid addresses.
+const INADDR_NONE: in_addr_t = 0xffff_ffff;
+
+/// A URL or address as parsed by [`parse_url`], the way directives such as `proxy_pass` and
+/// `server` do.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Url {
+    /// The host name or address, without brackets for IPv6.
+    pub host: String,
+    /// The port, or the default port if the URL has none.
+    pub port: u16,
+    /// Whether the URL has an explicit port.
+    pub has_port: bool,
+    /// The URI part, starting with `/`, if any.
+    pub uri: String,
+    /// The addresses of the host, resolved at configuration time: a single address for a
+    /// numeric host, and those returned by the system resolver otherwise.
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Parse a URL or address such as `backend.example.com:8080/path`, `10.0.0.1`, `[::1]:80` or
+/// `unix:/run/app.sock` with `ngx_parse_url`.
+///
+/// Host names are resolved with the system resolver, which blocks: this is meant for
+/// configuration handlers, with the configuration pool. `default_port` is used for URLs
+/// without a port. On failure, the error is the message Nginx would report, such as
+/// `invalid port`.
+pub fn parse_url(pool: &mut Pool, url: &str, default_port: u16) -> Result<Url, String> {
+    unsafe {
+        let mut u: ngx_url_t = mem::zeroed();
+        u.url = match NgxString::new_in(url, pool) {
+            Some(url) => url.into_ngx_str(),
+            None => return Err(String::from("memory allocation failed")),
+        };
+        u.default_port = default_port;
+        u.set_uri_part(1);
+
+        if ngx_parse_url(pool.as_ngx_pool(), &mut u) != NGX_OK as ngx_int_t {
+            let err = if u.err.is_null() {
+                format!("invalid url \"{}\"", url)
+            } else {
+                CStr::from_ptr(u.err).to_string_lossy().into_owned()
+            };
+            return Err(err);
+        }
+
+        let addrs = (0..u.naddrs)
+            .filter_map(|i| {
+                let addr = &*u.addrs.add(i);
+                sockaddr_to_std(addr.sockaddr, addr.socklen)
+            })
+            .collect();
+
+        Ok(Url {
+            host: NgxStr::from_ngx_str(u.host).to_string_lossy().into_owned(),
+            port: u.port,
+            has_port: u.no_port() == 0,
+            uri: NgxStr::from_ngx_str(u.uri).to_string_lossy().into_owned(),
+            addrs,
+        })
+    }
+}
+
+/// Parse an IPv4 or IPv6 address with `ngx_inet_addr` and `ngx_inet6_addr`, which accept
+/// the same forms as the addresses in Nginx directives.
+pub fn parse_addr(addr: &str) -> Option<IpAddr> {
+    unsafe {
+        let inaddr = ngx_inet_addr(addr.as_ptr() as *mut u_char, addr.len());
+        if inaddr != INADDR_NONE {
+            return Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(inaddr))));
+        }
+
+        let mut octets = [0u8; 16];
+        if ngx_inet6_addr(addr.as_ptr() as *mut u_char, addr.len(), octets.as_mut_ptr()) == NGX_OK as ngx_int_t {
+            return Some(IpAddr::V6(Ipv6Addr::from(octets)));
+        }
+    }
+    None
+}
+
+/// A network in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// A network of the addresses starting with the first `prefix_len` bits of `addr`.
+    ///
+    /// The other bits of `addr` are cleared. Returns `None` if the prefix is longer than the
+    /// address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Cidr> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return None;
+        }
+        let addr = match addr {
+            IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) & (mask(prefix_len, 32) as u32))),
+            IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask(prefix_len, 128))),
+        };
+        Some(Cidr { addr, prefix_len })
+    }
+
+    /// Parse a network with `ngx_ptocidr`, as the `allow` and `set_real_ip_from` directives
+    /// do. A single address is a network of that address alone. Bits of the address beyond
+    /// the prefix are accepted and cleared, for which Nginx warns.
+    pub fn parse(value: &str) -> Result<Cidr, String> {
+        unsafe {
+            let mut text = value.as_bytes().to_vec();
+            let mut text = ngx_str_t { len: text.len(), data: text.as_mut_ptr() };
+            let mut cidr: ngx_cidr_t = mem::zeroed();
+            let rc = ngx_ptocidr(&mut text, &mut cidr);
+            if rc == NGX_ERROR as ngx_int_t {
+                return Err(format!("invalid network \"{}\"", value));
+            }
+
+            match cidr.family as i32 {
+                libc::AF_INET => {
+                    let addr = Ipv4Addr::from(u32::from_be(cidr.u.in_.addr));
+                    let prefix_len = u32::from_be(cidr.u.in_.mask).count_ones() as u8;
+                    Ok(Cidr { addr: IpAddr::V4(addr), prefix_len })
+                }
+                libc::AF_INET6 => {
+                    let addr: [u8; 16] = ptr::read(&cidr.u.in6.addr as *const _ as *const [u8; 16]);
+                    let mask: [u8; 16] = ptr::read(&cidr.u.in6.mask as *const _ as *const [u8; 16]);
+                    let prefix_len = u128::from_be_bytes(mask).count_ones() as u8;
+                    Ok(Cidr { addr: IpAddr::V6(Ipv6Addr::from(addr)), prefix_len })
+                }
+                _ => Err(format!("invalid network \"{}\"", value)),
+            }
+        }
+    }
+
+    /// The first address of the network.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Number of leading bits of the network.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Is `addr` in the network? Addresses of the other family never are, except IPv4-mapped
+    /// IPv6 addresses (`::ffff:10.0.0.1`), which are compared as IPv4 addresses.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, canonical(*addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = mask(self.prefix_len, 32) as u32;
+                u32::from(addr) & mask == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                u128::from(addr) & mask(self.prefix_len, 128) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Cidr, String> {
+        Cidr::parse(value)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+// Mask of the first `prefix_len` bits of a `bits` wide address, in the low bits.
+fn mask(prefix_len: u8, bits: u32) -> u128 {
+    if prefix_len == 0 {
+        return 0;
+    }
+    let mask = u128::MAX << (128 - prefix_len as u32);
+    mask >> (128 - bits)
+}
+
+// IPv4-mapped IPv6 addresses as IPv4 addresses.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => {
+            let octets = v6.octets();
+            if octets[..10].iter().all(|&b| b == 0) && octets[10] == 0xff && octets[11] == 0xff {
+                IpAddr::V4(Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]))
+            } else {
+                addr
+            }
+        }
+        addr => addr,
+    }
+}
+
+/// Convert an IPv4 or IPv6 socket address to a [`SocketAddr`].
+///
+/// Returns `None` for other families, such as UNIX-domain sockets.
+pub unsafe fn sockaddr_to_std(sa: *const sockaddr, socklen: socklen_t) -> Option<SocketAddr> {
+    if sa.is_null() {
+        return None;
+    }
+    match (*sa).sa_family as i32 {
+        libc::AF_INET if socklen as usize >= mem::size_of::<libc::sockaddr_in>() => {
+            let sin = &*(sa as *const libc::sockaddr_in);
+            let addr = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::V4(SocketAddrV4::new(addr, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 if socklen as usize >= mem::size_of::<libc::sockaddr_in6>() => {
+            let sin6 = &*(sa as *const libc::sockaddr_in6);
+            let addr = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(addr, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+        }
+        _ => None,
+    }
+}
+
+/// Convert a [`SocketAddr`] to a socket address, as stored in `ngx_addr_t` and connections.
+pub fn sockaddr_from_std(addr: &SocketAddr) -> (ngx_sockaddr_t, socklen_t) {
+    unsafe {
+        let mut sockaddr: ngx_sockaddr_t = mem::zeroed();
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                let sin = &mut *(&mut sockaddr as *mut ngx_sockaddr_t as *mut libc::sockaddr_in);
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                let sin6 = &mut *(&mut sockaddr as *mut ngx_sockaddr_t as *mut libc::sockaddr_in6);
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (sockaddr, len as socklen_t)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::bindings::*;
+use crate::core::inet::*;
+use crate::core::pool::Pool;
+
+use std::net::IpAddr;
+
+/// `NGX_RADIX_NO_VALUE`, returned by radix tree lookups without a match.
+const NO_VALUE: uintptr_t = uintptr_t::MAX;
+
+/// A set of networks, to check whether client addresses are allowed, trusted proxies and so
+/// on.
+///
+/// The networks are stored in Nginx [radix trees] (`ngx_radix_tree_t`), as the `geo` module
+/// does, so a lookup takes the same time however many networks there are. The trees are
+/// allocated from a pool, normally the configuration pool, which must outlive the matcher.
+///
+/// ```ignore
+/// // At configuration time:
+/// conf.trusted = IpMatcher::from_cidrs(&mut pool, &["10.0.0.0/8", "2001:db8::/32"])?;
+///
+/// // In an access handler:
+/// if !request.remote_addr().map_or(false, |addr| conf.trusted.matches(&addr.ip())) {
+///     return Access::Deny(HTTP_FORBIDDEN);
+/// }
+/// ```
+///
+/// [radix trees]: https://nginx.org/en/docs/dev/development_guide.html#radix_tree
+pub struct IpMatcher {
+    tree4: *mut ngx_radix_tree_t,
+    tree6: *mut ngx_radix_tree_t,
+    len: usize,
+}
+
+impl IpMatcher {
+    /// An empty matcher, matching no address.
+    pub fn new(pool: &mut Pool) -> Option<IpMatcher> {
+        unsafe {
+            let tree4 = ngx_radix_tree_create(pool.as_ngx_pool(), -1);
+            let tree6 = ngx_radix_tree_create(pool.as_ngx_pool(), -1);
+            if tree4.is_null() || tree6.is_null() {
+                return None;
+            }
+            Some(IpMatcher { tree4, tree6, len: 0 })
+        }
+    }
+
+    /// A matcher of networks such as `10.0.0.0/8` or `::1`, parsed as by [`Cidr::parse`].
+    pub fn from_cidrs(pool: &mut Pool, cidrs: &[&str]) -> Result<IpMatcher, String> {
+        let mut matcher = IpMatcher::new(pool).ok_or_else(|| String::from("memory allocation failed"))?;
+        for cidr in cidrs {
+            matcher.insert(&Cidr::parse(cidr)?)?;
+        }
+        Ok(matcher)
+    }
+
+    /// Add a network. Adding a network that is already in the set has no effect.
+    pub fn insert(&mut self, cidr: &Cidr) -> Result<(), String> {
+        let rc = unsafe {
+            match cidr.addr() {
+                IpAddr::V4(addr) => {
+                    let mask = (u32::MAX as u64) << (32 - cidr.prefix_len() as u32);
+                    ngx_radix32tree_insert(self.tree4, u32::from(addr), mask as u32, 1)
+                }
+                IpAddr::V6(addr) => {
+                    let mask = if cidr.prefix_len() == 0 { 0 } else { u128::MAX << (128 - cidr.prefix_len() as u32) };
+                    let mut key = addr.octets();
+                    let mut mask = mask.to_be_bytes();
+                    ngx_radix128tree_insert(self.tree6, key.as_mut_ptr(), mask.as_mut_ptr(), 1)
+                }
+            }
+        };
+
+        if rc == NGX_OK as ngx_int_t {
+            self.len += 1;
+            Ok(())
+        } else if rc == NGX_BUSY as ngx_int_t {
+            Ok(())
+        } else {
+            Err(String::from("memory allocation failed"))
+        }
+    }
+
+    /// Number of networks added.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the set empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Is `addr` in one of the networks? IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) are
+    /// looked up as IPv4 addresses, as the `allow` and `deny` directives do.
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        let value = unsafe {
+            match addr {
+                IpAddr::V4(addr) => ngx_radix32tree_find(self.tree4, u32::from(*addr)),
+                IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+                    Some(addr) => ngx_radix32tree_find(self.tree4, u32::from(addr)),
+                    None => {
+                        let mut key = addr.octets();
+                        ngx_radix128tree_find(self.tree6, key.as_mut_ptr())
+                    }
+                },
+            }
+        };
+        value != NO_VALUE
+    }
+
+    /// Is the address of a socket address, such as the `sockaddr` of a connection, in one of
+    /// the networks? UNIX-domain sockets never match.
+    pub unsafe fn matches_sockaddr(&self, sa: *const sockaddr) -> bool {
+        let socklen = std::mem::size_of::<ngx_sockaddr_t>() as socklen_t;
+        match sockaddr_to_std(sa, socklen) {
+            Some(addr) => self.matches(&addr.ip()),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::bindings::*;
+use crate::core::pool::Pool;
+use crate::core::process;
+use crate::core::random::random_f64;
+use crate::core::units::Msec;
+use crate::event::Timer;
+use crate::ngx_log_debug;
+
+use std::cell::RefCell;
+use std::mem;
+
+/// Worker processes running a background job, see [`register_background_job`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JobWorkers {
+    /// Every worker runs the job, for per-worker state such as a local cache.
+    All,
+    /// Only the worker with this number runs the job, for work done once for the whole
+    /// server, such as rotating keys in shared memory. Worker numbers start at `0`.
+    One(usize),
+}
+
+struct Job {
+    name: &'static str,
+    every: Msec,
+    workers: JobWorkers,
+    run: Box<dyn FnMut()>,
+}
+
+// The jobs registered for a cycle. The master process keeps those of the running cycle
+// while a reload is parsed, and respawned workers keep using them if the reload fails.
+struct CycleJobs {
+    cycle: *mut ngx_cycle_t,
+    jobs: Vec<Job>,
+}
+
+// Allocated from the pool of a cycle, to drop its jobs with it.
+struct CycleJobsGuard(*mut ngx_cycle_t);
+
+impl Drop for CycleJobsGuard {
+    fn drop(&mut self) {
+        let cycle = self.0;
+        let _ = JOBS.try_with(|jobs| jobs.borrow_mut().retain(|jobs| jobs.cycle != cycle));
+    }
+}
+
+thread_local! {
+    static JOBS: RefCell<Vec<CycleJobs>> = RefCell::new(Vec::new());
+}
+
+/// Register a job run every `every` in the background of worker processes, such as
+/// refreshing a threat intelligence feed or flushing buffered telemetry.
+///
+/// Call this while parsing the configuration; [`background_job!`](crate::background_job)
+/// is a shorthand. The jobs start from the `init_process` handler of modules declared with
+/// [`http_module!`](crate::http_module) (or the stream and mail equivalents), with a random
+/// delay of up to one period so workers don't run them in lockstep. Jobs stop when the
+/// worker starts shutting down. On reload, workers run the jobs registered by the new
+/// configuration only once it has been loaded: if the reload fails, workers respawned by
+/// the master process keep running those of the previous configuration.
+///
+/// A job must not block the worker: offload slow work to a
+/// [`ThreadPool`](crate::core::ThreadPool) or an async task.
+pub unsafe fn register_background_job<F>(cf: *mut ngx_conf_t, name: &'static str, every: Msec, workers: JobWorkers, run: F)
+where
+    F: FnMut() + 'static,
+{
+    let cycle = (*cf).cycle;
+    let job = Job { name, every, workers, run: Box::new(run) };
+    let registered = JOBS.with(|jobs| {
+        let mut jobs = jobs.borrow_mut();
+        match jobs.iter_mut().find(|jobs| jobs.cycle == cycle) {
+            Some(jobs) => {
+                jobs.jobs.push(job);
+                true
+            }
+            None => {
+                jobs.push(CycleJobs { cycle, jobs: vec![job] });
+                false
+            }
+        }
+    });
+    // The pool of a cycle is destroyed when a reload fails, or once it is replaced.
+    if !registered {
+        Pool::from_ngx_pool((*cycle).pool).alloc(CycleJobsGuard(cycle));
+    }
+}
+
+/// Register a background job while parsing the configuration.
+///
+/// ```ignore
+/// // In every worker.
+/// background_job!(cf, "refresh_feeds", Msec::from_secs(60), || FEEDS.with(|feeds| feeds.refresh()));
+///
+/// // In worker 0 only.
+/// background_job!(cf, "rotate_keys", Msec::from_secs(3600), worker = 0, || rotate_keys(zone));
+/// ```
+///
+/// See [`register_background_job`](crate::core::register_background_job).
+#[macro_export]
+macro_rules! background_job {
+    ( $cf: expr, $name: expr, $every: expr, worker = $worker: expr, $run: expr $(,)? ) => {
+        $crate::core::register_background_job($cf, $name, $every, $crate::core::JobWorkers::One($worker), $run)
+    };
+    ( $cf: expr, $name: expr, $every: expr, $run: expr $(,)? ) => {
+        $crate::core::register_background_job($cf, $name, $every, $crate::core::JobWorkers::All, $run)
+    };
+}
+
+/// Start the background jobs of the current worker process.
+///
+/// This is called by the `init_process` handlers generated by this crate, and only needs to
+/// be called by modules declaring their `ngx_module_t` by hand.
+pub fn start_background_jobs() {
+    let worker = match process::worker_number() {
+        Some(worker) => worker,
+        None => return,
+    };
+    // Each job is started once, by the first module to get here.
+    let cycle = unsafe { ngx_cycle as *mut ngx_cycle_t };
+    let jobs = JOBS.with(|jobs| {
+        let mut jobs = jobs.borrow_mut();
+        jobs.iter_mut().find(|jobs| jobs.cycle == cycle).map_or_else(Vec::new, |jobs| mem::take(&mut jobs.jobs))
+    });
+
+    for job in jobs {
+        if let JobWorkers::One(n) = job.workers {
+            if n != worker {
+                continue;
+            }
+        }
+
+        let Job { name, every, mut run, .. } = job;
+        let delay = (every.as_msec() as f64 * random_f64()) as ngx_msec_t;
+        Timer::once(Msec::from_msec(delay), move || {
+            run_job(name, &mut run);
+            Timer::interval(every, move || run_job(name, &mut run)).detach();
+        })
+        .detach();
+    }
+}
+
+fn run_job(name: &str, run: &mut dyn FnMut()) {
+    if process::is_exiting() {
+        return;
+    }
+    let log = unsafe { (*ngx_cycle).log };
+    ngx_log_debug!(NGX_LOG_DEBUG_CORE, log, "running background job \"{}\"", name);
+    run();
+}
+
+#[doc(hidden)]
+pub unsafe extern "C" fn init_process_jobs(_cycle: *mut ngx_cycle_t) -> ngx_int_t {
+    start_background_jobs();
+    NGX_OK as ngx_int_t
+}
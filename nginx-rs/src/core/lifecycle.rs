@@ -0,0 +1,129 @@
+/// Define a static `init_module` handler, run once the configuration is parsed.
+///
+/// The handler takes the new [`Cycle`](crate::core::Cycle) and returns a
+/// [`Status`](crate::core::Status), or a `Result` whose error is logged (see
+/// [`NgxError`](crate::core::NgxError)): anything but [`OK`](crate::core::OK) fails the start
+/// or reload. It runs in the master process (or the single process) on start and on each
+/// reload, before the workers are started, so it suits one-time setup such as validating a
+/// license file or binding an admin socket inherited by the workers. Wire it with the
+/// `init_module` parameter of [`http_module!`](crate::http_module) and its stream and mail
+/// equivalents.
+///
+/// ```ignore
+/// ngx_init_module!(ngx_http_license_init_module, |cycle: &Cycle| {
+///     match License::load(cycle.prefix().as_bytes()) {
+///         Ok(_) => OK,
+///         Err(e) => {
+///             ngx_log!(NGX_LOG_EMERG, cycle.log(), "invalid license: {}", e);
+///             ERROR
+///         }
+///     }
+/// });
+/// ```
+#[macro_export]
+macro_rules! ngx_init_module {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(cycle: *mut $crate::__private::bindings::ngx_cycle_t) -> $crate::__private::bindings::ngx_int_t {
+            let status = $handler(unsafe { $crate::core::Cycle::from_ngx_cycle(cycle) });
+            $crate::core::HandlerResult::into_status(status, unsafe { (*cycle).log }, $crate::core::ERROR).0
+        }
+    };
+}
+
+/// Define a static `init_master` handler.
+///
+/// The handler takes a [`Cycle`](crate::core::Cycle) and returns a
+/// [`Status`](crate::core::Status). Nginx declares this hook but current versions never call
+/// it, so setup for the master process belongs in an
+/// [`ngx_init_module!`](crate::ngx_init_module) handler. Wire it with the `init_master`
+/// parameter of the module macros.
+#[macro_export]
+macro_rules! ngx_init_master {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(cycle: *mut $crate::__private::bindings::ngx_cycle_t) -> $crate::__private::bindings::ngx_int_t {
+            let status = $handler(unsafe { $crate::core::Cycle::from_ngx_cycle(cycle) });
+            $crate::core::HandlerResult::into_status(status, unsafe { (*cycle).log }, $crate::core::ERROR).0
+        }
+    };
+}
+
+/// Define a static `init_process` handler, run in each worker process when it starts.
+///
+/// The handler takes a [`Cycle`](crate::core::Cycle) and returns a
+/// [`Status`](crate::core::Status), or a `Result` whose error is logged (see
+/// [`NgxError`](crate::core::NgxError)): anything but [`OK`](crate::core::OK) makes the
+/// worker exit. Per-worker state, such as clients or caches, is set up here. The
+/// [background jobs](crate::core::register_background_job) are started before the handler
+/// runs.
+///
+/// The handler is wired into the module with the `init_process` parameter of
+/// [`http_module!`](crate::http_module) and its stream and mail equivalents:
+///
+/// ```ignore
+/// ngx_init_process!(ngx_http_feeds_init_process, |cycle: &Cycle| {
+///     FEEDS.with(|feeds| feeds.connect(cycle));
+///     OK
+/// });
+///
+/// http_module! {
+///     static ngx_http_feeds_module: Module;
+///     commands = ngx_http_feeds_commands;
+///     init_process = ngx_http_feeds_init_process;
+///     exit_process = ngx_http_feeds_exit_process;
+/// }
+/// ```
+#[macro_export]
+macro_rules! ngx_init_process {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(cycle: *mut $crate::__private::bindings::ngx_cycle_t) -> $crate::__private::bindings::ngx_int_t {
+            $crate::core::start_background_jobs();
+            let status = $handler(unsafe { $crate::core::Cycle::from_ngx_cycle(cycle) });
+            $crate::core::HandlerResult::into_status(status, unsafe { (*cycle).log }, $crate::core::ERROR).0
+        }
+    };
+}
+
+/// Define a static `exit_process` handler, run in each worker process when it exits.
+///
+/// The handler takes a [`Cycle`](crate::core::Cycle), and tears down per-worker state. It
+/// runs after the [shutdown handlers](crate::core::process::on_shutdown) and the
+/// [exit flushers](crate::core::register_exit_flusher), so the clients they use are still
+/// available to them. Wire it with the `exit_process` parameter of the module
+/// macros, see [`ngx_init_process!`](crate::ngx_init_process).
+#[macro_export]
+macro_rules! ngx_exit_process {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(cycle: *mut $crate::__private::bindings::ngx_cycle_t) {
+            $crate::core::process::run_shutdown_handlers();
+            $crate::core::run_exit_flushers();
+            $handler(unsafe { $crate::core::Cycle::from_ngx_cycle(cycle) });
+        }
+    };
+}
+
+/// Define a static `exit_master` handler, run in the master process when it exits.
+///
+/// The handler takes a [`Cycle`](crate::core::Cycle), and typically removes files or other
+/// resources shared by the workers. Wire it with the `exit_master` parameter of the module
+/// macros, see [`ngx_init_process!`](crate::ngx_init_process).
+#[macro_export]
+macro_rules! ngx_exit_master {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(cycle: *mut $crate::__private::bindings::ngx_cycle_t) {
+            $handler(unsafe { $crate::core::Cycle::from_ngx_cycle(cycle) });
+        }
+    };
+}
+
+/// A lifecycle handler of a module macro: the one given, or the default.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_module_hook {
+    ( $hook: ident ; $default: expr ) => { Some($hook) };
+    ( ; $default: expr ) => { $default };
+}
@@ -0,0 +1,187 @@
+#[cfg(feature = "debug-metrics")]
+use crate::bindings::*;
+#[cfg(feature = "debug-metrics")]
+use crate::core::pool::Pool;
+#[cfg(feature = "debug-metrics")]
+use crate::core::shm::{Counter, SharedMetrics};
+
+#[cfg(feature = "debug-metrics")]
+use std::cell::RefCell;
+#[cfg(feature = "debug-metrics")]
+use std::fmt;
+#[cfg(feature = "debug-metrics")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "debug-metrics")]
+static HEADER_SCANS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "debug-metrics")]
+static HEADERS_SCANNED: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "debug-metrics")]
+static POOL_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "debug-metrics")]
+static POOL_BYTES: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "debug-metrics")]
+static LOSSY_CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Internal counters of this crate, to measure the cost of its helpers in real deployments.
+///
+/// Counters are local to the worker process and only maintained with the `debug-metrics`
+/// feature; without it, counting compiles to nothing. [`CrateMetrics::register`] also adds
+/// them up over all workers in a [`SharedMetrics`] zone, to be exported with the metrics of
+/// the module.
+#[cfg(feature = "debug-metrics")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CrateMetrics {
+    /// Linear scans of a request header list, such as by [`Request::get_header`] for headers
+    /// without a dedicated field.
+    ///
+    /// [`Request::get_header`]: crate::http::Request::get_header
+    pub header_scans: usize,
+    /// Headers compared during those scans.
+    pub headers_scanned: usize,
+    /// Allocations from a pool by [`Pool`](crate::core::Pool) helpers.
+    pub pool_allocations: usize,
+    /// Bytes allocated from a pool by [`Pool`](crate::core::Pool) helpers.
+    pub pool_bytes: usize,
+    /// Conversions of invalid UTF-8 with [`NgxStr::to_string_lossy`], which copy the string.
+    ///
+    /// [`NgxStr::to_string_lossy`]: crate::core::NgxStr::to_string_lossy
+    pub lossy_conversions: usize,
+}
+
+#[cfg(feature = "debug-metrics")]
+impl CrateMetrics {
+    /// Current values of the counters of this worker process.
+    pub fn get() -> CrateMetrics {
+        CrateMetrics {
+            header_scans: HEADER_SCANS.load(Ordering::Relaxed),
+            headers_scanned: HEADERS_SCANNED.load(Ordering::Relaxed),
+            pool_allocations: POOL_ALLOCATIONS.load(Ordering::Relaxed),
+            pool_bytes: POOL_BYTES.load(Ordering::Relaxed),
+            lossy_conversions: LOSSY_CONVERSIONS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset the counters of this worker process.
+    pub fn reset() {
+        for counter in [&HEADER_SCANS, &HEADERS_SCANNED, &POOL_ALLOCATIONS, &POOL_BYTES, &LOSSY_CONVERSIONS] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Declare the counters as `nginx_rs_*` counters of `metrics`, and count there too from
+    /// now on, for all workers of this configuration.
+    ///
+    /// Call this while parsing the configuration.
+    ///
+    /// ```ignore
+    /// let metrics = SharedMetrics::register(cf, "my_module_metrics")?;
+    /// CrateMetrics::register(cf, &metrics)?;
+    /// ```
+    pub unsafe fn register(cf: *mut ngx_conf_t, metrics: &SharedMetrics) -> Result<(), String> {
+        let cycle = (*cf).cycle;
+        let counters = SharedCounters {
+            cycle,
+            header_scans: metrics.counter("nginx_rs_header_scans_total")?,
+            headers_scanned: metrics.counter("nginx_rs_headers_scanned_total")?,
+            pool_allocations: metrics.counter("nginx_rs_pool_allocations_total")?,
+            pool_bytes: metrics.counter("nginx_rs_pool_bytes_total")?,
+            lossy_conversions: metrics.counter("nginx_rs_lossy_conversions_total")?,
+        };
+        // The handles live in the configuration pool: forget them with it.
+        Pool::from_ngx_pool((*cycle).pool).alloc(SharedCountersGuard(cycle)).ok_or("no memory")?;
+        SHARED_COUNTERS.with(|shared| {
+            let mut shared = shared.borrow_mut();
+            shared.retain(|counters| counters.cycle != cycle);
+            shared.push(counters);
+        });
+        Ok(())
+    }
+}
+
+// Counters registered with `CrateMetrics::register` for a cycle. The master process keeps
+// those of the running cycle while a reload is parsed, for workers respawned if it fails.
+#[cfg(feature = "debug-metrics")]
+#[derive(Clone, Copy)]
+struct SharedCounters {
+    cycle: *mut ngx_cycle_t,
+    header_scans: Counter,
+    headers_scanned: Counter,
+    pool_allocations: Counter,
+    pool_bytes: Counter,
+    lossy_conversions: Counter,
+}
+
+#[cfg(feature = "debug-metrics")]
+struct SharedCountersGuard(*mut ngx_cycle_t);
+
+#[cfg(feature = "debug-metrics")]
+impl Drop for SharedCountersGuard {
+    fn drop(&mut self) {
+        let cycle = self.0;
+        let _ = SHARED_COUNTERS.try_with(|shared| shared.borrow_mut().retain(|counters| counters.cycle != cycle));
+    }
+}
+
+#[cfg(feature = "debug-metrics")]
+thread_local! {
+    static SHARED_COUNTERS: RefCell<Vec<SharedCounters>> = RefCell::new(Vec::new());
+}
+
+// Count in the shared counters of the running cycle, if any.
+#[cfg(feature = "debug-metrics")]
+fn count_shared<F: FnOnce(&SharedCounters)>(count: F) {
+    let cycle = unsafe { ngx_cycle as *mut ngx_cycle_t };
+    let _ = SHARED_COUNTERS.try_with(|shared| {
+        if let Some(counters) = shared.borrow().iter().find(|counters| counters.cycle == cycle) {
+            count(counters);
+        }
+    });
+}
+
+/// Formats the counters in the Prometheus text format, one `nginx_rs_*` counter per line.
+#[cfg(feature = "debug-metrics")]
+impl fmt::Display for CrateMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "nginx_rs_header_scans_total {}", self.header_scans)?;
+        writeln!(f, "nginx_rs_headers_scanned_total {}", self.headers_scanned)?;
+        writeln!(f, "nginx_rs_pool_allocations_total {}", self.pool_allocations)?;
+        writeln!(f, "nginx_rs_pool_bytes_total {}", self.pool_bytes)?;
+        writeln!(f, "nginx_rs_lossy_conversions_total {}", self.lossy_conversions)
+    }
+}
+
+#[inline]
+pub(crate) fn count_header_scan(_headers: usize) {
+    #[cfg(feature = "debug-metrics")]
+    {
+        HEADER_SCANS.fetch_add(1, Ordering::Relaxed);
+        HEADERS_SCANNED.fetch_add(_headers, Ordering::Relaxed);
+        count_shared(|counters| {
+            counters.header_scans.inc();
+            counters.headers_scanned.add(_headers as u64);
+        });
+    }
+}
+
+#[inline]
+pub(crate) fn count_pool_alloc(_size: usize) {
+    #[cfg(feature = "debug-metrics")]
+    {
+        POOL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        POOL_BYTES.fetch_add(_size, Ordering::Relaxed);
+        count_shared(|counters| {
+            counters.pool_allocations.inc();
+            counters.pool_bytes.add(_size as u64);
+        });
+    }
+}
+
+#[inline]
+pub(crate) fn count_lossy_conversion() {
+    #[cfg(feature = "debug-metrics")]
+    {
+        LOSSY_CONVERSIONS.fetch_add(1, Ordering::Relaxed);
+        count_shared(|counters| counters.lossy_conversions.inc());
+    }
+}
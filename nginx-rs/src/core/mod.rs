@@ -1,12 +1,78 @@
+mod atomic;
 mod buffer;
+mod chain;
+mod client_hello;
+mod codec;
+mod conf;
+#[cfg(feature = "serde")]
+mod conf_file;
+mod connection;
+mod cycle;
+mod error;
+mod flush;
+mod hash;
+mod hmac;
+mod inet;
+mod ip_matcher;
+mod job;
+mod lifecycle;
+mod metrics;
+mod panic;
 mod pool;
+mod proxy_protocol;
+mod pstring;
+mod pvec;
+#[cfg(feature = "http3")]
+mod quic;
+mod random;
+mod sample;
+#[cfg(feature = "ssl")]
+mod ssl;
 mod status;
 mod string;
+#[cfg(feature = "threads")]
+mod thread_pool;
+mod units;
+mod watch;
 
+pub mod process;
+pub mod shm;
+
+pub use atomic::*;
 pub use buffer::*;
+pub use chain::*;
+pub use client_hello::*;
+pub use codec::*;
+pub use conf::*;
+#[cfg(feature = "serde")]
+pub use conf_file::*;
+pub use connection::*;
+pub use cycle::*;
+pub use error::*;
+pub use flush::*;
+pub use hash::*;
+pub use hmac::*;
+pub use inet::*;
+pub use ip_matcher::*;
+pub use job::*;
+pub use metrics::*;
+pub use panic::*;
 pub use pool::*;
+pub use proxy_protocol::*;
+pub use pstring::*;
+pub use pvec::*;
+#[cfg(feature = "http3")]
+pub use quic::*;
+pub use random::*;
+pub use sample::*;
+#[cfg(feature = "ssl")]
+pub use ssl::*;
 pub use status::*;
 pub use string::*;
+#[cfg(feature = "threads")]
+pub use thread_pool::*;
+pub use units::*;
+pub use watch::*;
 
 /// Static empty configuration directive initializer for [`ngx_command_t`].
 ///
@@ -16,7 +82,7 @@ pub use string::*;
 #[macro_export]
 macro_rules! ngx_null_command {
     () => {
-        ngx_command_t {
+        $crate::__private::bindings::ngx_command_t {
             name: $crate::ngx_null_string!(),
             type_: 0,
             set: None,
@@ -0,0 +1,33 @@
+use crate::bindings::*;
+use crate::ngx_log_error;
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Run `f`, the handler `name`, returning `default` if it panics, once the panic is logged to
+/// `log`.
+///
+/// The handler macros of this crate run handlers this way, so a panic fails the request (or
+/// session) rather than unwinding into Nginx, which aborts the worker process along with all
+/// its connections. This relies on the default `panic = "unwind"` strategy: with
+/// `panic = "abort"`, the process still aborts.
+pub fn catch_panic<R, F: FnOnce() -> R>(log: *mut ngx_log_t, name: &str, default: R, f: F) -> R {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            // Formatted without allocating or a `CString`, so logging cannot panic in turn.
+            ngx_log_error!(NGX_LOG_ALERT, log, "panic in {}: {}", name, panic_message(&*payload));
+            default
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
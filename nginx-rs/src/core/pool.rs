@@ -1,8 +1,13 @@
 use crate::bindings::*;
 use crate::core::buffer::{TemporaryBuffer, MemoryBuffer, Buffer};
+use crate::core::metrics::count_pool_alloc;
 
-use std::{ptr, mem};
-use std::os::raw::c_void;
+use std::{ptr, mem, slice};
+use std::any::TypeId;
+use std::error::Error;
+use std::fmt;
+use std::ptr::NonNull;
+use std::os::raw::{c_ulong, c_void};
 
 pub struct Pool(*mut ngx_pool_t);
 
@@ -12,7 +17,13 @@ impl Pool {
         Pool(pool)
     }
 
+    /// Pointer to the underlying `ngx_pool_t`.
+    pub fn as_ngx_pool(&self) -> *mut ngx_pool_t {
+        self.0
+    }
+
     pub fn create_buffer(&mut self, size: usize) -> Option<TemporaryBuffer> {
+        count_pool_alloc(size);
         let buf = unsafe { ngx_create_temp_buf(self.0, size) };
         if buf.is_null() {
             return None;
@@ -32,6 +43,17 @@ impl Pool {
         Some(buffer)
     }
 
+    /// Create a temporary buffer holding a copy of `data`.
+    pub fn create_buffer_from_slice(&mut self, data: &[u8]) -> Option<TemporaryBuffer> {
+        let mut buffer = self.create_buffer(data.len())?;
+        unsafe {
+            let buf = buffer.as_ngx_buf_mut();
+            ptr::copy_nonoverlapping(data.as_ptr(), (*buf).pos, data.len());
+            (*buf).last = (*buf).pos.add(data.len());
+        }
+        Some(buffer)
+    }
+
     pub fn create_buffer_from_str(&mut self, str: &str) -> Option<TemporaryBuffer>
     {
         let mut buffer = self.create_buffer(str.len())?;
@@ -75,35 +97,206 @@ impl Pool {
         Ok(())
     }
 
-    pub fn alloc(&mut self, size: usize) -> *mut c_void {
+    /// Number of bytes allocated from the pool so far.
+    ///
+    /// This is the used part of each pool block. Nginx does not record the size of large
+    /// allocations (those over the `max` of the pool), so each counts as `max` bytes: the
+    /// result is a lower bound.
+    pub fn allocated(&self) -> usize {
+        unsafe {
+            let mut size = 0;
+            let mut p = self.0;
+            let mut start = (p as *mut u8).add(mem::size_of::<ngx_pool_t>());
+            while !p.is_null() {
+                size += ((*p).d.last as usize).saturating_sub(start as usize);
+                p = (*p).d.next;
+                start = (p as *mut u8).wrapping_add(mem::size_of::<ngx_pool_data_t>());
+            }
+
+            let mut large = (*self.0).large;
+            while !large.is_null() {
+                if !(*large).alloc.is_null() {
+                    size += (*self.0).max;
+                }
+                large = (*large).next;
+            }
+            size
+        }
+    }
+
+    /// Allocate `size` bytes, aligned for any primitive type, or return a null pointer if
+    /// allocation fails.
+    pub fn alloc_raw(&mut self, size: usize) -> *mut c_void {
+        count_pool_alloc(size);
         unsafe { ngx_palloc(self.0, size) }
     }
 
     pub fn alloc_type<T: Copy>(&mut self) -> *mut T {
-        self.alloc(mem::size_of::<T>()) as *mut T
+        self.alloc_array::<T>(1)
     }
 
     pub fn calloc(&mut self, size: usize) -> *mut c_void {
+        count_pool_alloc(size);
         unsafe { ngx_pcalloc(self.0, size) }
     }
 
     pub fn calloc_type<T: Copy>(&mut self) -> *mut T {
-        self.calloc(mem::size_of::<T>()) as *mut T
+        let p = self.alloc_array::<T>(1);
+        if !p.is_null() {
+            unsafe { ptr::write_bytes(p, 0, 1) };
+        }
+        p
+    }
+
+    /// Allocate uninitialized memory for a value of type `T`, or return `None` if allocation
+    /// fails.
+    ///
+    /// The memory belongs to the pool, not to this handle, and is valid until the pool is
+    /// destroyed. The value is never dropped by the pool: use [`Pool::alloc`] for types with
+    /// a `Drop` implementation.
+    pub fn alloc_uninit<T>(&mut self) -> Option<NonNull<T>> {
+        NonNull::new(self.alloc_array::<T>(1))
     }
 
-    pub fn allocate<T>(&mut self, value: T) -> *mut T {
+    /// Allocate uninitialized memory for `n` values of type `T`, or return a null pointer if
+    /// allocation fails.
+    pub(crate) fn alloc_array<T>(&mut self, n: usize) -> *mut T {
+        let size = match mem::size_of::<T>().checked_mul(n) {
+            Some(size) => size,
+            None => return ptr::null_mut(),
+        };
+        let align = mem::align_of::<T>();
+        count_pool_alloc(size);
+        // Small allocations are only aligned to NGX_ALIGNMENT, the size of a long.
         unsafe {
-            let p = self.alloc(mem::size_of::<T>()) as *mut T;
-            ptr::write(p, value);
-            if self.add_cleanup_for_value(p).is_err() {
-                ptr::drop_in_place(p);
-                return ptr::null_mut();
-            };
-            p
+            if align <= mem::size_of::<c_ulong>() {
+                ngx_palloc(self.0, size) as *mut T
+            } else {
+                ngx_pmemalign(self.0, size, align) as *mut T
+            }
+        }
+    }
+
+    /// Move `value` into the pool, or return `None` if allocation fails.
+    ///
+    /// The value lives as long as the pool, not this handle. If `T` needs to be dropped, a
+    /// cleanup handler drops the value when the pool is destroyed, such as at the end of the
+    /// request; plain data costs no cleanup.
+    pub fn alloc<T>(&mut self, value: T) -> Option<NonNull<T>> {
+        let p = self.alloc_uninit::<T>()?;
+        unsafe {
+            ptr::write(p.as_ptr(), value);
+            if mem::needs_drop::<T>() && self.add_cleanup_for_value(p.as_ptr()).is_err() {
+                ptr::drop_in_place(p.as_ptr());
+                return None;
+            }
+        }
+        Some(p)
+    }
+
+    /// Copy `data` into the pool, or return `None` if allocation fails.
+    ///
+    /// The copy is not aligned, as for strings and header values. To hand a string to Nginx,
+    /// use [`NgxString`](crate::core::NgxString).
+    pub fn alloc_bytes(&mut self, data: &[u8]) -> Option<&mut [u8]> {
+        count_pool_alloc(data.len());
+        unsafe {
+            let p = ngx_pnalloc(self.0, data.len()) as *mut u8;
+            if p.is_null() {
+                return None;
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), p, data.len());
+            Some(slice::from_raw_parts_mut(p, data.len()))
         }
     }
 }
 
+/// Error of a pool allocation that failed, such as when growing a [`PVec`](crate::core::PVec).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pool allocation failed")
+    }
+}
+
+impl Error for AllocError {}
+
 unsafe extern "C" fn cleanup_type<T>(data: *mut c_void) {
     ptr::drop_in_place(data as *mut T);
 }
+
+/// Header of a value stored with [`Pool::insert_local`].
+///
+/// All local values share the same cleanup handler, which is how they are told apart from
+/// other cleanups registered on the pool.
+#[repr(C)]
+struct LocalHeader {
+    type_id: TypeId,
+    drop: unsafe fn(*mut LocalHeader),
+}
+
+#[repr(C)]
+struct Local<T> {
+    header: LocalHeader,
+    value: T,
+}
+
+unsafe fn drop_local<T>(header: *mut LocalHeader) {
+    ptr::drop_in_place(header as *mut Local<T>);
+}
+
+unsafe extern "C" fn cleanup_local(data: *mut c_void) {
+    let header = data as *mut LocalHeader;
+    ((*header).drop)(header);
+}
+
+impl Pool {
+    /// Store a value in the pool, keyed by its type.
+    ///
+    /// The value is dropped when the pool is destroyed. Returns a null pointer if allocation fails.
+    pub(crate) fn insert_local<T: 'static>(&mut self, value: T) -> *mut T {
+        unsafe {
+            // The data of a cleanup is only aligned to NGX_ALIGNMENT, so the value is allocated
+            // separately. A cleanup without a handler is skipped if the allocation fails.
+            let cln = ngx_pool_cleanup_add(self.0, 0);
+            if cln.is_null() {
+                return ptr::null_mut();
+            }
+            let local = self.alloc_array::<Local<T>>(1);
+            if local.is_null() {
+                return ptr::null_mut();
+            }
+
+            ptr::write(local, Local {
+                header: LocalHeader { type_id: TypeId::of::<T>(), drop: drop_local::<T> },
+                value,
+            });
+            (*cln).data = local as *mut c_void;
+            (*cln).handler = Some(cleanup_local);
+
+            &mut (*local).value
+        }
+    }
+
+    /// Find the most recent value of type `T` stored with [`Pool::insert_local`].
+    ///
+    /// Returns a null pointer if there is no such value.
+    pub(crate) fn get_local<T: 'static>(&self) -> *mut T {
+        unsafe {
+            let mut cln = (*self.0).cleanup;
+            while !cln.is_null() {
+                let is_local = (*cln).handler.map(|h| h as usize) == Some(cleanup_local as usize);
+                if is_local {
+                    let local = (*cln).data as *mut Local<T>;
+                    if (*local).header.type_id == TypeId::of::<T>() {
+                        return &mut (*local).value;
+                    }
+                }
+                cln = (*cln).next;
+            }
+            ptr::null_mut()
+        }
+    }
+}
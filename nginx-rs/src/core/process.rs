@@ -0,0 +1,272 @@
+use crate::bindings::*;
+use crate::core::{ngx_core_conf, Msec};
+use crate::event::Timer;
+
+use std::cell::RefCell;
+
+/// Role of the current Nginx process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    /// Single process mode (`master_process off`).
+    Single,
+    /// The master process.
+    Master,
+    /// A process sending a signal to a running instance (`nginx -s`).
+    Signaller,
+    /// A worker process.
+    Worker,
+    /// A helper process (the cache manager or cache loader).
+    Helper,
+    /// A process type unknown to this crate.
+    Unknown(ngx_uint_t),
+}
+
+/// Role of the current process.
+pub fn role() -> Role {
+    match unsafe { ngx_process } as u32 {
+        NGX_PROCESS_SINGLE => Role::Single,
+        NGX_PROCESS_MASTER => Role::Master,
+        NGX_PROCESS_SIGNALLER => Role::Signaller,
+        NGX_PROCESS_WORKER => Role::Worker,
+        NGX_PROCESS_HELPER => Role::Helper,
+        _ => Role::Unknown(unsafe { ngx_process }),
+    }
+}
+
+/// Process ID of the current process.
+pub fn pid() -> ngx_pid_t {
+    unsafe { ngx_pid }
+}
+
+/// Number of the current worker process, from `0` to [`worker_processes`] - 1.
+///
+/// Returns `None` outside of worker processes. In single process mode the only process counts
+/// as worker `0`.
+pub fn worker_number() -> Option<usize> {
+    match role() {
+        Role::Worker | Role::Single => Some(unsafe { ngx_worker } as usize),
+        _ => None,
+    }
+}
+
+/// Total number of worker processes configured by the `worker_processes` directive.
+///
+/// Returns `None` before the core configuration has been parsed.
+pub fn worker_processes() -> Option<usize> {
+    unsafe {
+        let ccf = core_conf();
+        if ccf.is_null() || (*ccf).worker_processes < 0 {
+            None
+        } else {
+            Some((*ccf).worker_processes as usize)
+        }
+    }
+}
+
+unsafe fn core_conf() -> *mut ngx_core_conf_t {
+    ngx_core_conf(ngx_cycle)
+}
+
+/// Scheduling priority (nice value) of worker processes set by the `worker_priority` directive.
+///
+/// Returns `None` before the core configuration has been parsed.
+pub fn worker_priority() -> Option<ngx_int_t> {
+    unsafe {
+        let ccf = core_conf();
+        if ccf.is_null() {
+            None
+        } else {
+            Some((*ccf).priority)
+        }
+    }
+}
+
+/// Is the process shutting down, gracefully or not?
+///
+/// During a graceful shutdown (`nginx -s quit`, or the old workers of a reload) the worker
+/// stops accepting connections and exits once the requests in progress are done, or when
+/// `worker_shutdown_timeout` expires. Long-running work, such as streaming responses or
+/// background jobs, polls this to wrap up rather than be cut off when the worker exits.
+pub fn is_exiting() -> bool {
+    unsafe { ngx_exiting != 0 || ngx_quit != 0 || ngx_terminate != 0 }
+}
+
+/// Is the process terminating (`nginx -s stop`), closing connections without waiting for
+/// requests in progress?
+pub fn is_terminating() -> bool {
+    unsafe { ngx_terminate != 0 }
+}
+
+/// Interval at which a worker checks for the shutdown, when [`on_shutdown`] handlers are
+/// registered.
+pub const SHUTDOWN_POLL_INTERVAL: Msec = Msec::from_msec(100);
+
+thread_local! {
+    static SHUTDOWN_HANDLERS: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new());
+    static SHUTDOWN_WATCH: RefCell<Option<Timer>> = RefCell::new(None);
+}
+
+/// Call `f` when the current process starts shutting down, see [`is_exiting`].
+///
+/// Handlers run in the order they were registered, within [`SHUTDOWN_POLL_INTERVAL`] of the
+/// shutdown while the worker still has requests in progress, and at the latest from the
+/// `exit_process` handlers generated by this crate, before the
+/// [exit flushers](crate::core::register_exit_flusher). If the shutdown already started,
+/// `f` is called right away.
+///
+/// ```ignore
+/// process::on_shutdown(move || STREAMS.with(|streams| streams.borrow_mut().finish_all()));
+/// ```
+pub fn on_shutdown<F: FnOnce() + 'static>(f: F) {
+    if is_exiting() {
+        f();
+        return;
+    }
+
+    SHUTDOWN_HANDLERS.with(|handlers| handlers.borrow_mut().push(Box::new(f)));
+    SHUTDOWN_WATCH.with(|watch| {
+        watch.borrow_mut().get_or_insert_with(|| {
+            Timer::interval(SHUTDOWN_POLL_INTERVAL, || {
+                if is_exiting() {
+                    run_shutdown_handlers();
+                }
+            })
+        });
+    });
+}
+
+/// Run the handlers registered with [`on_shutdown`].
+///
+/// This is called by the `exit_process` handlers generated by this crate, and only needs to
+/// be called by modules declaring their `ngx_module_t` by hand.
+pub fn run_shutdown_handlers() {
+    // Stop watching. This may run from the closure of the timer, which is supported.
+    let watch = SHUTDOWN_WATCH.with(|watch| watch.borrow_mut().take());
+    drop(watch);
+
+    loop {
+        // Handlers may register further handlers, so don't hold the borrow while running one.
+        let handler = SHUTDOWN_HANDLERS.with(|handlers| {
+            let mut handlers = handlers.borrow_mut();
+            if handlers.is_empty() {
+                None
+            } else {
+                Some(handlers.remove(0))
+            }
+        });
+        match handler {
+            Some(handler) => handler(),
+            None => return,
+        }
+    }
+}
+
+/// Number of CPUs available to Nginx.
+pub fn ncpu() -> usize {
+    unsafe { ngx_ncpu.max(1) as usize }
+}
+
+/// A set of CPUs, as used by the `worker_cpu_affinity` directive.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+#[derive(Clone, Copy)]
+pub struct CpuSet(ngx_cpuset_t);
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+impl CpuSet {
+    const WORD_BITS: usize = 8 * std::mem::size_of::<std::os::raw::c_ulong>();
+
+    /// An empty set.
+    pub fn empty() -> CpuSet {
+        // SAFETY: A zeroed CPU set is a valid empty set.
+        CpuSet(unsafe { std::mem::zeroed() })
+    }
+
+    /// Maximum number of CPUs a set can hold.
+    pub fn capacity(&self) -> usize {
+        self.0.__bits.len() * Self::WORD_BITS
+    }
+
+    /// Is `cpu` part of the set?
+    pub fn contains(&self, cpu: usize) -> bool {
+        cpu < self.capacity() && self.0.__bits[cpu / Self::WORD_BITS] as u64 & (1 << (cpu % Self::WORD_BITS)) != 0
+    }
+
+    /// Add `cpu` to the set. CPUs beyond [`CpuSet::capacity`] are ignored.
+    pub fn insert(&mut self, cpu: usize) {
+        if cpu < self.capacity() {
+            self.0.__bits[cpu / Self::WORD_BITS] |= 1 << (cpu % Self::WORD_BITS);
+        }
+    }
+
+    /// Remove `cpu` from the set.
+    pub fn remove(&mut self, cpu: usize) {
+        if cpu < self.capacity() {
+            self.0.__bits[cpu / Self::WORD_BITS] &= !(1 << (cpu % Self::WORD_BITS));
+        }
+    }
+
+    /// Iterate over the CPUs in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.capacity()).filter(move |&cpu| self.contains(cpu))
+    }
+
+    /// Number of CPUs in the set.
+    pub fn count(&self) -> usize {
+        self.0.__bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Is the set empty?
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// The available CPUs (see [`ncpu`]) that are *not* in this set.
+    ///
+    /// Applied to the worker's own affinity, this gives the CPUs auxiliary threads can use
+    /// without competing with the event loop.
+    pub fn complement(&self) -> CpuSet {
+        let mut set = CpuSet::empty();
+        for cpu in (0..ncpu()).filter(|&cpu| !self.contains(cpu)) {
+            set.insert(cpu);
+        }
+        set
+    }
+
+    /// Pointer to the underlying `ngx_cpuset_t`.
+    pub fn as_ngx_cpuset(&self) -> *const ngx_cpuset_t {
+        &self.0
+    }
+}
+
+/// CPU affinity of the current worker process, as configured by `worker_cpu_affinity`.
+///
+/// Returns `None` outside of worker processes, or if no affinity is configured.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn cpu_affinity() -> Option<CpuSet> {
+    let worker = worker_number()?;
+    unsafe {
+        let set = ngx_get_cpu_affinity(worker as ngx_uint_t);
+        if set.is_null() {
+            None
+        } else {
+            Some(CpuSet(*set))
+        }
+    }
+}
+
+/// Bind the calling thread to a set of CPUs.
+///
+/// This is meant for auxiliary threads started by a module (thread pools, runtimes), for
+/// example pinning them to the [complement](CpuSet::complement) of the worker's
+/// [`cpu_affinity`]. Failures are logged to the cycle log.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn set_thread_affinity(set: &CpuSet) {
+    if set.is_empty() {
+        return;
+    }
+
+    unsafe {
+        // `ngx_setaffinity` applies to the calling thread only.
+        ngx_setaffinity(set.as_ngx_cpuset() as *mut ngx_cpuset_t, (*ngx_cycle).log);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::bindings::*;
+use crate::core::inet::parse_addr;
+use crate::core::string::NgxStr;
+
+use std::net::{IpAddr, SocketAddr};
+use std::slice;
+
+/// Type of the TLV carrying the AWS VPC endpoint ID, behind AWS Network Load Balancers.
+pub const PP2_TYPE_AWS: u8 = 0xea;
+
+/// Subtype of [`PP2_TYPE_AWS`] for the VPC endpoint ID.
+const PP2_SUBTYPE_AWS_VPCE_ID: u8 = 0x01;
+
+/// The [PROXY protocol] header received on a connection, as enabled by the `proxy_protocol`
+/// parameter of `listen`.
+///
+/// It carries the address of the client as seen by the load balancer in front of Nginx, and,
+/// for version 2, TLVs (type-length-value fields) with details such as the TLS session of the
+/// balancer or provider specific IDs.
+///
+/// [PROXY protocol]: https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+pub struct ProxyProtocol<'a> {
+    connection: *mut ngx_connection_t,
+    pp: &'a ngx_proxy_protocol_t,
+}
+
+impl<'a> ProxyProtocol<'a> {
+    /// The PROXY protocol header of a connection, if one was received.
+    ///
+    /// # Safety
+    ///
+    /// `c` must be a valid connection, which outlives the returned value.
+    pub unsafe fn from_connection(c: *mut ngx_connection_t) -> Option<ProxyProtocol<'a>> {
+        let pp = (*c).proxy_protocol;
+        if pp.is_null() {
+            None
+        } else {
+            Some(ProxyProtocol { connection: c, pp: &*pp })
+        }
+    }
+
+    /// Address of the client, as text (`$proxy_protocol_addr`).
+    pub fn src_addr_text(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.pp.src_addr) }
+    }
+
+    /// Address of the client, if it is an IP address.
+    pub fn src_addr(&self) -> Option<IpAddr> {
+        parse_addr(self.src_addr_text().to_str().ok()?)
+    }
+
+    /// Port of the client (`$proxy_protocol_port`).
+    pub fn src_port(&self) -> u16 {
+        self.pp.src_port
+    }
+
+    /// Address and port of the client.
+    pub fn source(&self) -> Option<SocketAddr> {
+        Some(SocketAddr::new(self.src_addr()?, self.src_port()))
+    }
+
+    /// Address the client connected to, as text (`$proxy_protocol_server_addr`).
+    pub fn dst_addr_text(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.pp.dst_addr) }
+    }
+
+    /// Address the client connected to, if it is an IP address.
+    pub fn dst_addr(&self) -> Option<IpAddr> {
+        parse_addr(self.dst_addr_text().to_str().ok()?)
+    }
+
+    /// Port the client connected to (`$proxy_protocol_server_port`).
+    pub fn dst_port(&self) -> u16 {
+        self.pp.dst_port
+    }
+
+    /// Address and port the client connected to.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        Some(SocketAddr::new(self.dst_addr()?, self.dst_port()))
+    }
+
+    /// Value of a TLV by name, with `ngx_proxy_protocol_get_tlv`, as for the
+    /// `$proxy_protocol_tlv_<name>` variables.
+    ///
+    /// `name` is either a known name such as `alpn`, `authority`, `unique_id`, `ssl_version`,
+    /// `ssl_cn` or `ssl_verify`, or a type in hexadecimal such as `0xea`. Returns `None` if
+    /// the header has no such TLV.
+    pub fn tlv(&self, name: &str) -> Option<&NgxStr> {
+        unsafe {
+            let mut name = ngx_str_t { len: name.len(), data: name.as_ptr() as *mut u_char };
+            let mut value = ngx_str_t { len: 0, data: std::ptr::null_mut() };
+            if ngx_proxy_protocol_get_tlv(self.connection, &mut name, &mut value) != NGX_OK as ngx_int_t {
+                return None;
+            }
+            Some(NgxStr::from_ngx_str(value))
+        }
+    }
+
+    /// The raw TLVs of a version 2 header, as `(type, value)` pairs in the order received.
+    ///
+    /// Values of the SSL TLV (`0x20`) include its sub-TLVs unparsed.
+    pub fn tlvs(&self) -> Tlvs<'a> {
+        let tlvs = self.pp.tlvs;
+        let data = if tlvs.len == 0 {
+            &[][..]
+        } else {
+            unsafe { slice::from_raw_parts(tlvs.data, tlvs.len) }
+        };
+        Tlvs { data }
+    }
+
+    /// The AWS VPC endpoint ID (such as `vpce-0123456789abcdef0`) of a connection through an
+    /// AWS PrivateLink endpoint.
+    pub fn aws_vpce_id(&self) -> Option<&'a str> {
+        self.tlvs()
+            .find(|&(kind, value)| kind == PP2_TYPE_AWS && value.first() == Some(&PP2_SUBTYPE_AWS_VPCE_ID))
+            .and_then(|(_, value)| std::str::from_utf8(&value[1..]).ok())
+    }
+}
+
+/// Iterator over the TLVs of a PROXY protocol header, see [`ProxyProtocol::tlvs`].
+pub struct Tlvs<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Tlvs<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        if self.data.len() < 3 {
+            return None;
+        }
+        let kind = self.data[0];
+        let len = u16::from_be_bytes([self.data[1], self.data[2]]) as usize;
+        // Nginx validates the TLVs when reading the header, so this only guards against a
+        // truncated buffer.
+        let value = self.data.get(3..3 + len)?;
+        self.data = &self.data[3 + len..];
+        Some((kind, value))
+    }
+}
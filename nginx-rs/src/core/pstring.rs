@@ -0,0 +1,168 @@
+use crate::bindings::*;
+use crate::core::pool::{AllocError, Pool};
+use crate::core::pvec::PVec;
+use crate::core::string::NgxStr;
+
+use std::fmt;
+use std::ops::Deref;
+use std::str;
+
+/// A growable UTF-8 string whose storage is allocated from a [`Pool`], such as the pool of
+/// the request, instead of the global heap.
+///
+/// This is the pool counterpart of [`String`], to build header values, log lines or
+/// variable values that live exactly as long as the request. See [`PVec`] for how the
+/// storage grows.
+///
+/// ```ignore
+/// let pool = request.pool();
+/// let mut value = PString::new_in(&pool);
+/// write!(value, "score={:.2}; rules={}", score, rules).ok();
+/// request.set_header("X-Risk", &value);
+/// ```
+pub struct PString<'a> {
+    vec: PVec<'a, u8>,
+}
+
+impl<'a> PString<'a> {
+    /// An empty string allocating from `pool`.
+    pub fn new_in(pool: &'a Pool) -> PString<'a> {
+        PString { vec: PVec::new_in(pool) }
+    }
+
+    /// An empty string allocating from `pool`, with room for `capacity` bytes.
+    pub fn with_capacity_in(capacity: usize, pool: &'a Pool) -> PString<'a> {
+        PString { vec: PVec::with_capacity_in(capacity, pool) }
+    }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    /// Is the string empty?
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    /// Number of bytes the string can hold without allocating.
+    pub fn capacity(&self) -> usize {
+        self.vec.capacity()
+    }
+
+    /// Make room for at least `additional` more bytes.
+    pub fn reserve(&mut self, additional: usize) {
+        self.vec.reserve(additional);
+    }
+
+    /// Make room for at least `additional` more bytes, or return an error if the pool can't
+    /// allocate.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        self.vec.try_reserve(additional)
+    }
+
+    /// Append `s`.
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.extend_from_slice(s.as_bytes());
+    }
+
+    /// Append `c`.
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]));
+    }
+
+    /// Shorten the string to `len` bytes.
+    ///
+    /// Panics if `len` is not on a character boundary.
+    pub fn truncate(&mut self, len: usize) {
+        assert!(self.as_str().is_char_boundary(len), "truncated within a character");
+        self.vec.truncate(len);
+    }
+
+    /// Empty the string, keeping the storage.
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+
+    /// The string as a `&str`.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: Only whole strings are appended.
+        unsafe { str::from_utf8_unchecked(self.vec.as_slice()) }
+    }
+
+    /// The string as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.vec.as_slice()
+    }
+
+    /// The string as an [`NgxStr`].
+    pub fn as_ngx_str(&self) -> &NgxStr {
+        self.as_bytes().into()
+    }
+
+    /// Keep the string in the pool for as long as it lives.
+    pub fn leak(self) -> &'a mut str {
+        // SAFETY: Only whole strings are appended.
+        unsafe { str::from_utf8_unchecked_mut(self.vec.leak()) }
+    }
+
+    /// Keep the string in the pool for as long as it lives, as an [`ngx_str_t`] to hand to
+    /// Nginx, such as the value of a header or a variable.
+    pub fn into_ngx_str(self) -> ngx_str_t {
+        let s = self.leak();
+        ngx_str_t { len: s.len(), data: s.as_mut_ptr() }
+    }
+}
+
+impl<'a> Deref for PString<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> AsRef<str> for PString<'a> {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a> AsRef<[u8]> for PString<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> fmt::Write for PString<'a> {
+    // Fails rather than panics if the pool can't allocate.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.try_reserve(s.len()).map_err(|_| fmt::Error)?;
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for PString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> fmt::Debug for PString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<'a> PartialEq<str> for PString<'a> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for PString<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        self.as_str() == *other
+    }
+}
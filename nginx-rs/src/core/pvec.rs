@@ -0,0 +1,215 @@
+use crate::bindings::*;
+use crate::core::pool::{AllocError, Pool};
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::c_void;
+use std::ptr::{self, NonNull};
+use std::slice;
+
+/// A growable array whose storage is allocated from a [`Pool`], such as the pool of the
+/// request, instead of the global heap.
+///
+/// Memory from a pool is only released with the pool, except for large allocations, so the
+/// storage left behind when the vector grows stays allocated until then: reserve the final
+/// capacity up front when it is known. The elements are dropped with the vector.
+///
+/// Methods that grow the vector panic if the pool can't allocate, as [`Vec`] does; use
+/// [`PVec::try_reserve`] to handle the failure instead.
+///
+/// ```ignore
+/// let pool = request.pool();
+/// let mut scores = PVec::with_capacity_in(rules.len(), &pool);
+/// for rule in rules {
+///     scores.push(rule.score(request));
+/// }
+/// ```
+pub struct PVec<'a, T> {
+    pool: *mut ngx_pool_t,
+    ptr: NonNull<T>,
+    len: usize,
+    cap: usize,
+    _marker: PhantomData<(&'a Pool, T)>,
+}
+
+impl<'a, T> PVec<'a, T> {
+    /// An empty vector allocating from `pool`. Nothing is allocated until the first element
+    /// is pushed.
+    pub fn new_in(pool: &'a Pool) -> PVec<'a, T> {
+        let cap = if mem::size_of::<T>() == 0 { usize::MAX } else { 0 };
+        PVec { pool: pool.as_ngx_pool(), ptr: NonNull::dangling(), len: 0, cap, _marker: PhantomData }
+    }
+
+    /// An empty vector allocating from `pool`, with room for `capacity` elements.
+    pub fn with_capacity_in(capacity: usize, pool: &'a Pool) -> PVec<'a, T> {
+        let mut vec = PVec::new_in(pool);
+        vec.reserve(capacity);
+        vec
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the vector empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of elements the vector can hold without allocating.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Make room for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        if self.try_reserve(additional).is_err() {
+            panic!("pool allocation of {} elements failed", self.len.saturating_add(additional));
+        }
+    }
+
+    /// Make room for at least `additional` more elements, or return an error if the pool
+    /// can't allocate.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        let needed = self.len.checked_add(additional).ok_or(AllocError)?;
+        if needed <= self.cap {
+            return Ok(());
+        }
+        let cap = needed.max(self.cap.saturating_mul(2)).max(4);
+
+        let mut pool = unsafe { Pool::from_ngx_pool(self.pool) };
+        let p = pool.alloc_array::<T>(cap);
+        if p.is_null() {
+            return Err(AllocError);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), p, self.len);
+            self.free_storage();
+            self.ptr = NonNull::new_unchecked(p);
+        }
+        self.cap = cap;
+        Ok(())
+    }
+
+    /// Append an element.
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+    }
+
+    /// Remove the last element and return it, or `None` if the vector is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+    }
+
+    /// Drop the elements after the first `len`.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = ptr::slice_from_raw_parts_mut(unsafe { self.ptr.as_ptr().add(len) }, self.len - len);
+        // Shortened first, so a panicking destructor doesn't drop elements twice.
+        self.len = len;
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    /// Drop all the elements, keeping the storage.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// The elements as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// The elements as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Keep the elements in the pool for as long as it lives, such as to hand them to Nginx.
+    ///
+    /// The elements are never dropped.
+    pub fn leak(self) -> &'a mut [T] {
+        let elts = unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) };
+        mem::forget(self);
+        elts
+    }
+
+    // Large allocations are given back to the system; smaller ones stay in the pool.
+    unsafe fn free_storage(&mut self) {
+        if self.cap > 0 && mem::size_of::<T>() > 0 {
+            ngx_pfree(self.pool, self.ptr.as_ptr() as *mut c_void);
+        }
+    }
+}
+
+impl<'a, T: Clone> PVec<'a, T> {
+    /// Append clones of the elements of `other`.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        for value in other {
+            self.push(value.clone());
+        }
+    }
+}
+
+impl<'a, T> Drop for PVec<'a, T> {
+    fn drop(&mut self) {
+        self.clear();
+        unsafe { self.free_storage() };
+    }
+}
+
+impl<'a, T> Deref for PVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<'a, T> DerefMut for PVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<'a, T> AsRef<[T]> for PVec<'a, T> {
+    fn as_ref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<'a, T> Extend<T> for PVec<'a, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for PVec<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+impl<'a, T: PartialEq> PartialEq<[T]> for PVec<'a, T> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.as_slice() == other
+    }
+}
@@ -0,0 +1,135 @@
+use crate::bindings::*;
+use crate::core::connection::Connection;
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+
+/// Transport statistics of a QUIC connection, see [`Quic::stats`].
+///
+/// Times are in milliseconds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QuicStats {
+    /// Smoothed round-trip time.
+    pub rtt: ngx_msec_t,
+    /// Minimum round-trip time.
+    pub min_rtt: ngx_msec_t,
+    /// Variance of the round-trip time.
+    pub rttvar: ngx_msec_t,
+    /// Number of consecutive probe timeouts, a sign of packet loss.
+    pub pto_count: ngx_uint_t,
+    /// Congestion window, in bytes.
+    pub congestion_window: usize,
+    /// Bytes sent and not yet acknowledged.
+    pub bytes_in_flight: usize,
+    /// Bytes received, before the client address was validated.
+    pub received: off_t,
+}
+
+/// Error closing a QUIC connection, see [`Quic::error`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuicError {
+    /// Error code, such as `0x0a` for `PROTOCOL_VIOLATION`.
+    pub code: ngx_uint_t,
+    /// Is this an application error (HTTP/3) rather than a transport error?
+    pub application: bool,
+    /// Type of the frame that caused the error, for transport errors.
+    pub frame_type: ngx_uint_t,
+    /// Reason for the error, if any.
+    pub reason: Option<String>,
+}
+
+/// The QUIC connection of an HTTP/3 request, see [`Connection::quic`].
+///
+/// With the `http3` feature, which requires Nginx to be built with
+/// `--with-http_v3_module`. Fields are read from the internal structures of Nginx, so they
+/// follow its version.
+pub struct Quic<'a> {
+    stream: *mut ngx_quic_stream_t,
+    qc: *mut ngx_quic_connection_t,
+    _marker: PhantomData<&'a Connection>,
+}
+
+impl<'a> Quic<'a> {
+    /// QUIC version, such as `1` ([RFC 9000]).
+    ///
+    /// [RFC 9000]: https://datatracker.ietf.org/doc/html/rfc9000
+    pub fn version(&self) -> u32 {
+        unsafe { (*self.qc).version }
+    }
+
+    /// Identifier of the QUIC stream.
+    pub fn stream_id(&self) -> u64 {
+        unsafe { (*self.stream).id }
+    }
+
+    /// Transport statistics of the connection.
+    pub fn stats(&self) -> QuicStats {
+        unsafe {
+            let qc = &*self.qc;
+            QuicStats {
+                rtt: qc.avg_rtt,
+                min_rtt: qc.min_rtt,
+                rttvar: qc.rttvar,
+                pto_count: qc.pto_count,
+                congestion_window: qc.congestion.window,
+                bytes_in_flight: qc.congestion.in_flight,
+                received: qc.received,
+            }
+        }
+    }
+
+    /// The error closing the connection, once one occurred.
+    pub fn error(&self) -> Option<QuicError> {
+        unsafe {
+            let qc = &*self.qc;
+            if qc.error == 0 && qc.error_reason.is_null() {
+                return None;
+            }
+            let reason = if qc.error_reason.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(qc.error_reason).to_string_lossy().into_owned())
+            };
+            Some(QuicError { code: qc.error, application: qc.error_app() != 0, frame_type: qc.error_ftype, reason })
+        }
+    }
+
+    /// Is the connection closing?
+    pub fn is_closing(&self) -> bool {
+        unsafe { (*self.qc).closing() != 0 }
+    }
+}
+
+impl Connection {
+    /// Is this a QUIC stream?
+    pub fn is_quic(&self) -> bool {
+        !self.as_ngx_connection_quic().is_null()
+    }
+
+    /// The QUIC connection of the stream, or `None` for other connections.
+    ///
+    /// Whether the request was sent with 0-RTT is given by
+    /// [`Ssl::early_data`](crate::core::Ssl::early_data).
+    pub fn quic(&self) -> Option<Quic> {
+        let stream = self.as_ngx_connection_quic();
+        if stream.is_null() {
+            return None;
+        }
+        unsafe {
+            // The QUIC connection hangs off the socket of the main connection.
+            let parent = (*stream).parent;
+            if parent.is_null() || (*parent).udp.is_null() {
+                return None;
+            }
+            let qc = (*((*parent).udp as *mut ngx_quic_socket_t)).quic;
+            if qc.is_null() {
+                return None;
+            }
+            Some(Quic { stream, qc, _marker: PhantomData })
+        }
+    }
+
+    fn as_ngx_connection_quic(&self) -> *mut ngx_quic_stream_t {
+        unsafe { (*self.as_ngx_connection()).quic }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::bindings::*;
+
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(0);
+}
+
+/// A fast, non-cryptographic pseudo-random number (xorshift64*).
+///
+/// The generator is local to the thread and seeded on first use from the process ID and the
+/// current time, so every worker process produces a different sequence. It is suitable for
+/// sampling and load balancing decisions, not for anything security sensitive.
+pub fn random_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            x = seed();
+        }
+
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// A pseudo-random number uniformly distributed in `[0, 1)`, see [`random_u64`].
+pub fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    let pid = unsafe { ngx_pid } as u64;
+    // splitmix64 of the inputs, which is never zero for practical purposes.
+    let mut z = nanos ^ pid.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) | 1
+}
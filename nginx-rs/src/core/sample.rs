@@ -0,0 +1,274 @@
+use crate::bindings::*;
+use crate::core::random::*;
+use crate::core::units::*;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Uniform reservoir sampler ([Algorithm R]).
+///
+/// Keeps a uniformly random sample of at most `capacity` of the items offered, using memory
+/// proportional to the capacity only.
+///
+/// [Algorithm R]: https://en.wikipedia.org/wiki/Reservoir_sampling#Simple:_Algorithm_R
+#[derive(Clone, Debug)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    /// Create an empty reservoir keeping up to `capacity` items.
+    pub fn new(capacity: usize) -> Reservoir<T> {
+        Reservoir { capacity, seen: 0, items: Vec::with_capacity(capacity) }
+    }
+
+    /// Offer an item to the sample. Returns `true` if the item was kept.
+    pub fn offer(&mut self, item: T) -> bool {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return true;
+        }
+
+        let slot = random_u64() % self.seen;
+        if (slot as usize) < self.capacity {
+            self.items[slot as usize] = item;
+            return true;
+        }
+        false
+    }
+
+    /// Items currently in the sample, in no particular order.
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Number of items offered since the reservoir was created or cleared.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Empty the reservoir.
+    pub fn clear(&mut self) {
+        self.seen = 0;
+        self.items.clear();
+    }
+
+    /// Take the sample, leaving the reservoir empty.
+    pub fn take(&mut self) -> Vec<T> {
+        self.seen = 0;
+        std::mem::replace(&mut self.items, Vec::with_capacity(self.capacity))
+    }
+}
+
+/// Weighted reservoir sampler ([A-Res]).
+///
+/// Items are kept with a probability proportional to their weight, such as the size of a
+/// response or the cost of a request.
+///
+/// [A-Res]: https://en.wikipedia.org/wiki/Reservoir_sampling#Algorithm_A-Res
+#[derive(Clone, Debug)]
+pub struct WeightedReservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<(f64, T)>,
+}
+
+impl<T> WeightedReservoir<T> {
+    /// Create an empty reservoir keeping up to `capacity` items.
+    pub fn new(capacity: usize) -> WeightedReservoir<T> {
+        WeightedReservoir { capacity, seen: 0, items: Vec::with_capacity(capacity) }
+    }
+
+    /// Offer an item with a weight. Items with a weight that is not positive are ignored.
+    /// Returns `true` if the item was kept.
+    pub fn offer(&mut self, item: T, weight: f64) -> bool {
+        if weight.is_nan() || weight <= 0.0 || self.capacity == 0 {
+            return false;
+        }
+        self.seen += 1;
+
+        let key = random_f64().powf(1.0 / weight);
+        if self.items.len() < self.capacity {
+            self.items.push((key, item));
+            return true;
+        }
+
+        // The capacity is expected to be small, so a linear scan beats maintaining a heap.
+        let mut min = 0;
+        for (i, (key, _)) in self.items.iter().enumerate() {
+            if *key < self.items[min].0 {
+                min = i;
+            }
+        }
+        if key > self.items[min].0 {
+            self.items[min] = (key, item);
+            return true;
+        }
+        false
+    }
+
+    /// Items currently in the sample, in no particular order.
+    pub fn items(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    /// Number of items offered since the reservoir was created or cleared.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Empty the reservoir.
+    pub fn clear(&mut self) {
+        self.seen = 0;
+        self.items.clear();
+    }
+
+    /// Take the sample, leaving the reservoir empty.
+    pub fn take(&mut self) -> Vec<T> {
+        self.seen = 0;
+        self.items.drain(..).map(|(_, item)| item).collect()
+    }
+}
+
+/// Reservoir samples per key over a period of time.
+///
+/// For example, keep 10 URIs per blocked client per hour, or per tenant of the request
+/// [classification](crate::http::Classification). All samples are dropped at the end
+/// of each period. To bound memory, at most `max_keys` keys are sampled per period; items for
+/// further keys are counted in [`KeyedSampler::dropped`] but not kept. The sampler is local
+/// to the worker process.
+#[derive(Debug)]
+pub struct KeyedSampler<K, T> {
+    per_key: usize,
+    max_keys: usize,
+    period: ngx_msec_t,
+    started: ngx_msec_t,
+    dropped: u64,
+    samples: HashMap<K, Reservoir<T>>,
+}
+
+impl<K: Hash + Eq, T> KeyedSampler<K, T> {
+    /// Sample up to `per_key` items for each of up to `max_keys` keys, over periods of
+    /// `period`.
+    pub fn new(per_key: usize, max_keys: usize, period: Msec) -> KeyedSampler<K, T> {
+        KeyedSampler {
+            per_key,
+            max_keys,
+            period: period.as_msec(),
+            started: unsafe { ngx_current_msec },
+            dropped: 0,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Offer an item for a key. Returns `true` if the item was kept.
+    pub fn offer(&mut self, key: K, item: T) -> bool {
+        self.expire();
+
+        let len = self.samples.len();
+        let per_key = self.per_key;
+        match self.samples.get_mut(&key) {
+            Some(reservoir) => reservoir.offer(item),
+            None if len < self.max_keys => {
+                let mut reservoir = Reservoir::new(per_key);
+                let kept = reservoir.offer(item);
+                self.samples.insert(key, reservoir);
+                kept
+            }
+            None => {
+                self.dropped += 1;
+                false
+            }
+        }
+    }
+
+    /// Sample of a key in the current period.
+    pub fn get(&mut self, key: &K) -> Option<&Reservoir<T>> {
+        self.expire();
+        self.samples.get(key)
+    }
+
+    /// Samples of all keys in the current period.
+    pub fn iter(&mut self) -> impl Iterator<Item = (&K, &Reservoir<T>)> {
+        self.expire();
+        self.samples.iter()
+    }
+
+    /// Number of items not sampled in the current period because too many keys were seen.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn expire(&mut self) {
+        let now = unsafe { ngx_current_msec };
+        if now.wrapping_sub(self.started) >= self.period {
+            self.started = now;
+            self.dropped = 0;
+            self.samples.clear();
+        }
+    }
+}
+
+/// Fixed-size reservoir of byte strings, suitable for shared memory.
+///
+/// Holds up to `N` samples of at most `L` bytes each (longer samples are truncated). The
+/// structure contains no pointers and zeroed memory is a valid empty reservoir, so it can be
+/// placed in a shared memory zone, where it must be protected by the zone's lock.
+#[repr(C)]
+pub struct FixedReservoir<const N: usize, const L: usize> {
+    seen: u64,
+    lens: [u32; N],
+    data: [[u8; L]; N],
+}
+
+impl<const N: usize, const L: usize> FixedReservoir<N, L> {
+    /// Create an empty reservoir.
+    pub const fn new() -> Self {
+        FixedReservoir { seen: 0, lens: [0; N], data: [[0; L]; N] }
+    }
+
+    /// Offer a sample. Returns `true` if the sample was kept.
+    pub fn offer(&mut self, sample: &[u8]) -> bool {
+        self.seen += 1;
+
+        let slot = if self.seen <= N as u64 {
+            (self.seen - 1) as usize
+        } else {
+            let slot = random_u64() % self.seen;
+            if slot >= N as u64 {
+                return false;
+            }
+            slot as usize
+        };
+
+        let len = sample.len().min(L);
+        self.data[slot][..len].copy_from_slice(&sample[..len]);
+        self.lens[slot] = len as u32;
+        true
+    }
+
+    /// Samples currently in the reservoir, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let len = (self.seen.min(N as u64)) as usize;
+        self.data[..len].iter().zip(self.lens.iter()).map(|(data, &len)| &data[..len as usize])
+    }
+
+    /// Number of samples offered since the reservoir was created or cleared.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Empty the reservoir.
+    pub fn clear(&mut self) {
+        self.seen = 0;
+    }
+}
+
+impl<const N: usize, const L: usize> Default for FixedReservoir<N, L> {
+    fn default() -> Self {
+        FixedReservoir::new()
+    }
+}
@@ -0,0 +1,234 @@
+use crate::bindings::*;
+use crate::core::process;
+use crate::core::shm::zone::{Zone, CHANNEL_ZONE_TAG};
+use crate::core::units::Msec;
+use crate::event::ngx_add_timer;
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+use std::slice;
+
+/// Largest message of a [`WorkerChannel`], in bytes.
+pub const CHANNEL_MESSAGE_MAX: usize = 1024;
+
+/// Error sending a message on a [`WorkerChannel`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChannelError {
+    /// The message is larger than [`CHANNEL_MESSAGE_MAX`].
+    TooLarge,
+    /// The channel is not mapped yet: it is only usable once the configuration is parsed.
+    NotMapped,
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelError::TooLarge => write!(f, "message too large"),
+            ChannelError::NotMapped => write!(f, "not mapped"),
+        }
+    }
+}
+
+impl Error for ChannelError {}
+
+#[repr(C)]
+struct Slot {
+    seq: u64,
+    sender: ngx_pid_t,
+    len: u32,
+    data: [u8; CHANNEL_MESSAGE_MAX],
+}
+
+// State of the zone: a ring of the most recent messages, followed by the slots.
+#[repr(C)]
+struct ChannelShared {
+    // Sequence number of the next message, starting at 1.
+    next: u64,
+    capacity: usize,
+}
+
+impl ChannelShared {
+    unsafe fn slot(sh: *mut ChannelShared, seq: u64) -> *mut Slot {
+        let slots = (sh as *mut u8).add(mem::size_of::<ChannelShared>()) as *mut Slot;
+        slots.add((seq % (*sh).capacity as u64) as usize)
+    }
+}
+
+/// A message received from a [`WorkerChannel`].
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelMessage<'a> {
+    /// Process ID of the worker which sent the message.
+    pub sender: ngx_pid_t,
+    /// Content of the message.
+    pub data: &'a [u8],
+}
+
+// State of a subscribed worker, for the life of the process.
+struct Subscriber {
+    channel: WorkerChannel,
+    // Sequence number of the next message to receive.
+    next: u64,
+    missed: u64,
+    interval: ngx_msec_t,
+    callback: Box<dyn FnMut(ChannelMessage)>,
+    timer: ngx_event_t,
+}
+
+/// A channel broadcasting small messages, such as cache invalidations or configuration
+/// updates, from one worker process to all the others.
+///
+/// Messages are written to a ring buffer in a shared memory zone, which each subscribed
+/// worker polls with a timer, so delivery takes up to the polling interval. A worker that
+/// falls more than the capacity of the ring behind misses the oldest messages, which are
+/// counted in [`ChannelSubscription::missed`]. Workers don't receive their own messages.
+///
+/// ```ignore
+/// // While parsing the configuration.
+/// conf.invalidations = WorkerChannel::register(cf, "cache_invalidations", 256)?;
+///
+/// // In init_process.
+/// conf.invalidations.subscribe(Msec::from_msec(100), |message| {
+///     CACHE.with(|cache| cache.borrow_mut().remove(message.data));
+/// });
+///
+/// // In the worker removing an entry.
+/// conf.invalidations.send(key.as_bytes())?;
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct WorkerChannel {
+    zone: Zone,
+}
+
+impl WorkerChannel {
+    /// Add a channel in a new zone `name`, keeping the last `capacity` messages.
+    ///
+    /// Call this while parsing the configuration.
+    pub unsafe fn register(cf: *mut ngx_conf_t, name: &str, capacity: usize) -> Result<WorkerChannel, String> {
+        let capacity = capacity.max(1);
+        let needed = mem::size_of::<ChannelShared>() + capacity * mem::size_of::<Slot>();
+        // Room for the slab allocator's own pages and headers.
+        let size = needed + needed / 8 + 8 * ngx_pagesize as usize;
+
+        let zone = Zone::register_with_tag(cf, name, size, &CHANNEL_ZONE_TAG, move |zone: &Zone, reused: bool| {
+            if reused && !zone.data::<ChannelShared>().is_null() {
+                return Ok(());
+            }
+            let slab = zone.slab().ok_or("not mapped")?;
+            let sh = slab.calloc(needed) as *mut ChannelShared;
+            if sh.is_null() {
+                return Err(String::from("no memory"));
+            }
+            (*sh).next = 1;
+            (*sh).capacity = capacity;
+            zone.set_data(sh);
+            Ok(())
+        })?;
+        Ok(WorkerChannel { zone })
+    }
+
+    /// Send a message to the other workers.
+    pub fn send(&self, data: &[u8]) -> Result<(), ChannelError> {
+        if data.len() > CHANNEL_MESSAGE_MAX {
+            return Err(ChannelError::TooLarge);
+        }
+        let slab = self.zone.slab().ok_or(ChannelError::NotMapped)?;
+        let sh = self.zone.data::<ChannelShared>();
+
+        let _guard = slab.lock();
+        unsafe {
+            let seq = (*sh).next;
+            let slot = ChannelShared::slot(sh, seq);
+            (*slot).seq = seq;
+            (*slot).sender = process::pid();
+            (*slot).len = data.len() as u32;
+            (*slot).data[..data.len()].copy_from_slice(data);
+            (*sh).next = seq + 1;
+        }
+        Ok(())
+    }
+
+    /// Call `callback` with the messages sent by other workers after now, checking for new
+    /// messages every `interval`.
+    ///
+    /// Call this from the `init_process` handler of a module. The timer is cancelable, so it
+    /// doesn't delay the graceful shutdown of the worker.
+    pub unsafe fn subscribe<F>(&self, interval: Msec, callback: F) -> Result<ChannelSubscription, ChannelError>
+    where
+        F: FnMut(ChannelMessage) + 'static,
+    {
+        let slab = self.zone.slab().ok_or(ChannelError::NotMapped)?;
+        let next = {
+            let _guard = slab.lock();
+            (*self.zone.data::<ChannelShared>()).next
+        };
+
+        // The subscriber lives as long as the worker process.
+        let subscriber: &'static mut Subscriber = Box::leak(Box::new(Subscriber {
+            channel: *self,
+            next,
+            missed: 0,
+            interval: interval.as_msec().max(1),
+            callback: Box::new(callback),
+            timer: mem::zeroed(),
+        }));
+        let interval = subscriber.interval;
+        let subscriber = subscriber as *mut Subscriber;
+        let ev = &mut (*subscriber).timer;
+        ev.handler = Some(ngx_rs_worker_channel_handler);
+        ev.data = subscriber as *mut c_void;
+        ev.log = (*ngx_cycle).log;
+        ev.set_cancelable(1);
+        ngx_add_timer(ev, interval);
+        Ok(ChannelSubscription(subscriber))
+    }
+}
+
+/// Subscription of the current worker to a [`WorkerChannel`], see
+/// [`WorkerChannel::subscribe`].
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelSubscription(*mut Subscriber);
+
+impl ChannelSubscription {
+    /// Number of messages missed because the worker fell behind.
+    pub fn missed(&self) -> u64 {
+        unsafe { (*self.0).missed }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_worker_channel_handler(ev: *mut ngx_event_t) {
+    if ngx_exiting != 0 || ngx_terminate != 0 || ngx_quit != 0 {
+        return;
+    }
+    let subscriber = &mut *((*ev).data as *mut Subscriber);
+
+    // Copy the new messages under the lock, then deliver them without it.
+    let mut messages = Vec::new();
+    if let Some(slab) = subscriber.channel.zone.slab() {
+        let sh = subscriber.channel.zone.data::<ChannelShared>();
+        let pid = process::pid();
+
+        let _guard = slab.lock();
+        let next = (*sh).next;
+        let oldest = next.saturating_sub((*sh).capacity as u64).max(1);
+        if subscriber.next < oldest {
+            subscriber.missed += oldest - subscriber.next;
+            subscriber.next = oldest;
+        }
+        for seq in subscriber.next..next {
+            let slot = ChannelShared::slot(sh, seq);
+            if (*slot).sender != pid {
+                let data = slice::from_raw_parts((*slot).data.as_ptr(), (*slot).len as usize);
+                messages.push(((*slot).sender, data.to_vec()));
+            }
+        }
+        subscriber.next = next;
+    }
+
+    for (sender, data) in &messages {
+        (subscriber.callback)(ChannelMessage { sender: *sender, data });
+    }
+
+    ngx_add_timer(ev, subscriber.interval);
+}
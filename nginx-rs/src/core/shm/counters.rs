@@ -0,0 +1,300 @@
+use crate::bindings::*;
+use crate::core::atomic::{Atomic, CacheAligned};
+use crate::core::pool::Pool;
+use crate::core::shm::zone::{Zone, METRICS_ZONE_TAG};
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+/// Longest name of a metric, in bytes.
+pub const METRIC_NAME_MAX: usize = 96;
+
+/// Most metrics a zone of [`SharedMetrics`] holds, over all the configurations it is used
+/// with.
+pub const METRICS_MAX: usize = 256;
+
+// Position of a metric not yet found in the zone.
+const UNRESOLVED: usize = usize::MAX;
+
+/// Kind of a metric of [`SharedMetrics`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetricKind {
+    /// Only goes up, such as a number of requests.
+    Counter,
+    /// Goes up and down, such as a number of requests in progress.
+    Gauge,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+// A metric in shared memory. Each is on its own cache line, so workers updating different
+// metrics don't contend.
+#[repr(C)]
+struct Slot {
+    value: Atomic,
+    kind: u8,
+    name_len: u8,
+    name: [u8; METRIC_NAME_MAX],
+}
+
+impl Slot {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+}
+
+// State of the zone: the metrics, in the order they were first declared. Slots are only
+// appended, and never moved or freed, as workers of a previous configuration keep updating
+// theirs until they exit.
+#[repr(C)]
+struct MetricsShared {
+    len: usize,
+    slots: *mut CacheAligned<Slot>,
+}
+
+struct Declared {
+    name: String,
+    kind: MetricKind,
+    // Position of the metric in the zone, set when the zone is initialized. Allocated from
+    // the configuration pool, so it lives as long as the handles of this configuration.
+    slot: *const Cell<usize>,
+}
+
+/// Named counters and gauges in shared memory, updated without locks from all worker
+/// processes.
+///
+/// Metrics are declared while parsing the configuration, and the handles returned are then
+/// used from any worker. Values are kept across reloads for metrics declared again with the
+/// same name and kind. A metric keeps its place in the zone once declared, even if a later
+/// configuration drops it, so a zone holds at most [`METRICS_MAX`] distinct metrics until
+/// Nginx is restarted.
+///
+/// ```ignore
+/// // While parsing the configuration.
+/// let metrics = SharedMetrics::register(cf, "my_module_metrics")?;
+/// conf.requests = metrics.counter("my_module_requests_total")?;
+/// conf.blocked = metrics.counter("my_module_blocked_total")?;
+/// conf.metrics = Some(metrics);
+///
+/// // In a handler.
+/// conf.requests.inc();
+///
+/// // In the content handler of a metrics location.
+/// let body = conf.metrics.as_ref().unwrap().to_string();
+/// request.send_response(HTTP_OK, "text/plain; version=0.0.4", body.as_bytes())
+/// ```
+///
+/// This is not meant to replace a metrics framework: there are no labels or histograms, and
+/// the value of a gauge is shared by all workers rather than kept per worker.
+#[derive(Clone)]
+pub struct SharedMetrics {
+    zone: Zone,
+    pool: *mut ngx_pool_t,
+    declared: Rc<RefCell<Vec<Declared>>>,
+}
+
+impl SharedMetrics {
+    /// Add a zone `name` to hold metrics.
+    ///
+    /// Call this while parsing the configuration.
+    pub unsafe fn register(cf: *mut ngx_conf_t, name: &str) -> Result<SharedMetrics, String> {
+        let declared: Rc<RefCell<Vec<Declared>>> = Rc::new(RefCell::new(Vec::new()));
+        let init_declared = declared.clone();
+        let size = 8 * ngx_pagesize as usize + METRICS_MAX * mem::size_of::<CacheAligned<Slot>>();
+
+        let zone = Zone::register_with_tag(cf, name, size, &METRICS_ZONE_TAG, move |zone: &Zone, reused: bool| {
+            let declared = init_declared.borrow();
+            let slab = zone.slab().ok_or("not mapped")?;
+            let mut guard = slab.lock();
+
+            let mut sh = zone.data::<MetricsShared>();
+            if !reused || sh.is_null() {
+                sh = guard.calloc(mem::size_of::<MetricsShared>()) as *mut MetricsShared;
+                if sh.is_null() {
+                    return Err(String::from("no memory"));
+                }
+                let slots = guard.calloc(METRICS_MAX * mem::size_of::<CacheAligned<Slot>>()) as *mut CacheAligned<Slot>;
+                if slots.is_null() {
+                    return Err(String::from("no memory"));
+                }
+                (*sh).slots = slots;
+                zone.set_data(sh);
+            }
+
+            // Metrics declared by a previous configuration keep their slot, and so their value.
+            for metric in declared.iter() {
+                let slots = slice::from_raw_parts((*sh).slots, (*sh).len);
+                let found = slots.iter().position(|slot| slot.name() == metric.name.as_bytes() && slot.kind == metric.kind as u8);
+                let index = match found {
+                    Some(index) => index,
+                    None if (*sh).len < METRICS_MAX => {
+                        let slot = &mut *(*sh).slots.add((*sh).len);
+                        slot.kind = metric.kind as u8;
+                        slot.name_len = metric.name.len() as u8;
+                        slot.name[..metric.name.len()].copy_from_slice(metric.name.as_bytes());
+                        (*sh).len += 1;
+                        (*sh).len - 1
+                    }
+                    None => return Err(format!("too many metrics, the maximum is {}", METRICS_MAX)),
+                };
+                (*metric.slot).set(index);
+            }
+            Ok(())
+        })?;
+
+        Ok(SharedMetrics { zone, pool: (*cf).pool, declared })
+    }
+
+    /// Declare a counter, or get the counter already declared with this name.
+    pub fn counter(&self, name: &str) -> Result<Counter, String> {
+        self.declare(name, MetricKind::Counter).map(|slot| Counter(Metric { zone: self.zone, slot }))
+    }
+
+    /// Declare a gauge, or get the gauge already declared with this name.
+    pub fn gauge(&self, name: &str) -> Result<Gauge, String> {
+        self.declare(name, MetricKind::Gauge).map(|slot| Gauge(Metric { zone: self.zone, slot }))
+    }
+
+    fn declare(&self, name: &str, kind: MetricKind) -> Result<*const Cell<usize>, String> {
+        if name.is_empty() || name.len() > METRIC_NAME_MAX {
+            return Err(format!("invalid metric name \"{}\"", name));
+        }
+        let mut declared = self.declared.borrow_mut();
+        if let Some(metric) = declared.iter().find(|metric| metric.name == name) {
+            if metric.kind != kind {
+                return Err(format!("metric \"{}\" is already declared as a {}", name, metric.kind.as_str()));
+            }
+            return Ok(metric.slot);
+        }
+
+        let mut pool = unsafe { Pool::from_ngx_pool(self.pool) };
+        let slot: *const Cell<usize> = pool.alloc(Cell::new(UNRESOLVED)).ok_or("no memory")?.as_ptr();
+        declared.push(Declared { name: name.to_string(), kind, slot });
+        Ok(slot)
+    }
+
+    /// Current values of all metrics, in the order they were declared.
+    ///
+    /// The values of gauges are signed, and those of counters may be cast to `u64`.
+    pub fn snapshot(&self) -> Vec<(String, MetricKind, i64)> {
+        let declared = self.declared.borrow();
+        declared
+            .iter()
+            .map(|metric| {
+                let value = Metric { zone: self.zone, slot: metric.slot }.load();
+                (metric.name.clone(), metric.kind, value as i64)
+            })
+            .collect()
+    }
+}
+
+/// Formats the metrics in the Prometheus text format, with a `# TYPE` line for each.
+impl fmt::Display for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, kind, value) in self.snapshot() {
+            writeln!(f, "# TYPE {} {}", name, kind.as_str())?;
+            match kind {
+                MetricKind::Counter => writeln!(f, "{} {}", name, value as u64)?,
+                MetricKind::Gauge => writeln!(f, "{} {}", name, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Metric {
+    zone: Zone,
+    slot: *const Cell<usize>,
+}
+
+impl Metric {
+    fn atomic(&self) -> Option<&Atomic> {
+        let sh = self.zone.data::<MetricsShared>();
+        unsafe {
+            let index = (*self.slot).get();
+            if sh.is_null() || index >= METRICS_MAX {
+                return None;
+            }
+            Some(&(*(*sh).slots.add(index)).value)
+        }
+    }
+
+    fn load(&self) -> ngx_atomic_uint_t {
+        self.atomic().map_or(0, Atomic::load)
+    }
+
+    fn add(&self, delta: ngx_atomic_int_t) {
+        if let Some(atomic) = self.atomic() {
+            atomic.fetch_add(delta);
+        }
+    }
+}
+
+/// A counter of [`SharedMetrics`].
+///
+/// Updates before the zone is mapped, while parsing the configuration, are ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Counter(Metric);
+
+impl Counter {
+    /// Add one.
+    pub fn inc(&self) {
+        self.0.add(1);
+    }
+
+    /// Add `n`.
+    pub fn add(&self, n: u64) {
+        self.0.add(n as ngx_atomic_int_t);
+    }
+
+    /// Current value, summed over all workers.
+    pub fn get(&self) -> u64 {
+        self.0.load() as u64
+    }
+}
+
+/// A gauge of [`SharedMetrics`].
+///
+/// Updates before the zone is mapped, while parsing the configuration, are ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Gauge(Metric);
+
+impl Gauge {
+    /// Add one.
+    pub fn inc(&self) {
+        self.0.add(1);
+    }
+
+    /// Subtract one.
+    pub fn dec(&self) {
+        self.0.add(-1);
+    }
+
+    /// Add `delta`, which may be negative.
+    pub fn add(&self, delta: i64) {
+        self.0.add(delta as ngx_atomic_int_t);
+    }
+
+    /// Set the value, overwriting the updates of other workers.
+    pub fn set(&self, value: i64) {
+        if let Some(atomic) = self.0.atomic() {
+            atomic.store(value as ngx_atomic_uint_t);
+        }
+    }
+
+    /// Current value.
+    pub fn get(&self) -> i64 {
+        self.0.load() as i64
+    }
+}
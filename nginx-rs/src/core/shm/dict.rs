@@ -0,0 +1,558 @@
+use crate::bindings::*;
+use crate::core::codec::{Codec, CodecError};
+use crate::core::hash::{Hash64, XxHash64};
+use crate::core::shm::slab::{SlabGuard, SlabPool};
+use crate::core::shm::zone::{Zone, DICT_ZONE_TAG};
+use crate::core::units::Msec;
+
+use std::error::Error;
+use std::fmt;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::slice;
+
+// Kind of the value of an entry.
+const KIND_BYTES: u8 = 0;
+const KIND_INTEGER: u8 = 1;
+
+// Least recently used entries looked at for expiry on each write, and on each attempt to make
+// room, as `limit_req` does, so writes take bounded time however many entries there are.
+const EXPIRE_SCAN: usize = 3;
+
+// Attempts to make room for a new entry, each removing the least recently used entry and the
+// expired ones after it, before giving up, as for `lua_shared_dict`. A value that can't fit
+// must not empty the dictionary.
+const EVICT_MAX: usize = 30;
+
+/// Error of a [`SharedDict`] operation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DictError {
+    /// The zone is full, and evicting entries didn't free enough memory.
+    NoMemory,
+    /// The value to increment is not an integer.
+    NotAnInteger,
+    /// The dictionary is not mapped yet: it is only usable once the configuration is parsed.
+    NotMapped,
+    /// The value given to [`SharedDict::set_as`] can't be encoded.
+    Encode(CodecError),
+}
+
+impl fmt::Display for DictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DictError::NoMemory => write!(f, "no memory"),
+            DictError::NotAnInteger => write!(f, "not an integer"),
+            DictError::NotMapped => write!(f, "not mapped"),
+            DictError::Encode(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for DictError {}
+
+// State of the dictionary, at the start of the zone.
+#[repr(C)]
+struct DictShared {
+    rbtree: ngx_rbtree_t,
+    sentinel: ngx_rbtree_node_t,
+    // Least recently used entries last.
+    lru: ngx_queue_t,
+    len: usize,
+}
+
+// An entry, followed by its key and value. The tree is keyed by the hash of the key.
+#[repr(C)]
+struct Entry {
+    node: ngx_rbtree_node_t,
+    queue: ngx_queue_t,
+    // Expiry, in `ngx_current_msec` time, or 0 for none.
+    expires: ngx_msec_t,
+    kind: u8,
+    key_len: u32,
+    value_len: u32,
+}
+
+impl Entry {
+    fn size(key_len: usize, value_len: usize) -> usize {
+        mem::size_of::<Entry>() + key_len + value_len
+    }
+
+    unsafe fn key<'a>(entry: *const Entry) -> &'a [u8] {
+        slice::from_raw_parts((entry as *const u8).add(mem::size_of::<Entry>()), (*entry).key_len as usize)
+    }
+
+    unsafe fn value<'a>(entry: *mut Entry) -> &'a mut [u8] {
+        let data = (entry as *mut u8).add(mem::size_of::<Entry>() + (*entry).key_len as usize);
+        slice::from_raw_parts_mut(data, (*entry).value_len as usize)
+    }
+
+    unsafe fn is_expired(entry: *const Entry, now: ngx_msec_t) -> bool {
+        (*entry).expires != 0 && (*entry).expires.wrapping_sub(now) as ngx_msec_int_t <= 0
+    }
+
+    unsafe fn from_queue(q: *mut ngx_queue_t) -> *mut Entry {
+        (q as *mut u8).sub(queue_offset()) as *mut Entry
+    }
+}
+
+fn queue_offset() -> usize {
+    let entry = MaybeUninit::<Entry>::uninit();
+    let base = entry.as_ptr();
+    unsafe { ptr::addr_of!((*base).queue) as usize - base as usize }
+}
+
+// The queue macros of Nginx.
+unsafe fn queue_init(q: *mut ngx_queue_t) {
+    (*q).prev = q;
+    (*q).next = q;
+}
+
+unsafe fn queue_insert_head(h: *mut ngx_queue_t, x: *mut ngx_queue_t) {
+    (*x).next = (*h).next;
+    (*(*x).next).prev = x;
+    (*x).prev = h;
+    (*h).next = x;
+}
+
+unsafe fn queue_remove(x: *mut ngx_queue_t) {
+    (*(*x).next).prev = (*x).prev;
+    (*(*x).prev).next = (*x).next;
+}
+
+/// A key/value store in shared memory, visible to all worker processes, in the spirit of
+/// `lua_shared_dict`.
+///
+/// Entries may expire, and when the zone is full, the least recently used entries are
+/// evicted to make room. Values are bytes, or integers for [`SharedDict::incr`]; any type
+/// implementing [`Codec`] can be stored with [`SharedDict::set_as`].
+///
+/// Operations take the mutex of the zone, so they are cheap but serialized across workers.
+///
+/// ```ignore
+/// // While parsing the configuration.
+/// let seen = SharedDict::register(cf, "dedup", 10 * 1024 * 1024)?;
+///
+/// // In a request handler.
+/// if !seen.add(request_id.as_bytes(), b"", Some(Msec::from_secs(60)))? {
+///     return Access::Deny(HTTP_FORBIDDEN);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SharedDict<H: Hash64 = XxHash64> {
+    zone: Zone,
+    hasher: H,
+}
+
+impl SharedDict<XxHash64> {
+    /// Add a dictionary in a new zone `name` of `size` bytes, hashing keys with
+    /// [`XxHash64`].
+    ///
+    /// Call this while parsing the configuration. Entries are kept on reload, unless the
+    /// size changes.
+    pub unsafe fn register(cf: *mut ngx_conf_t, name: &str, size: usize) -> Result<SharedDict, String> {
+        SharedDict::register_with_hasher(cf, name, size, XxHash64::default())
+    }
+}
+
+impl<H: Hash64> SharedDict<H> {
+    /// Add a dictionary hashing keys with `hasher`, see [`SharedDict::register`].
+    pub unsafe fn register_with_hasher(cf: *mut ngx_conf_t, name: &str, size: usize, hasher: H) -> Result<SharedDict<H>, String> {
+        let zone = Zone::register_with_tag(cf, name, size, &DICT_ZONE_TAG, |zone: &Zone, reused: bool| {
+            if reused {
+                let sh = zone.data::<DictShared>();
+                if !sh.is_null() {
+                    // The insert function may have moved if the module was reloaded.
+                    (*sh).rbtree.insert = Some(ngx_rs_shared_dict_insert_value);
+                    return Ok(());
+                }
+            }
+
+            let slab = zone.slab().ok_or("not mapped")?;
+            let sh = slab.calloc(mem::size_of::<DictShared>()) as *mut DictShared;
+            if sh.is_null() {
+                return Err(String::from("no memory"));
+            }
+            // ngx_rbtree_init()
+            (*sh).sentinel.color = 0;
+            (*sh).rbtree.root = &mut (*sh).sentinel;
+            (*sh).rbtree.sentinel = &mut (*sh).sentinel;
+            (*sh).rbtree.insert = Some(ngx_rs_shared_dict_insert_value);
+            queue_init(&mut (*sh).lru);
+            zone.set_data(sh);
+            Ok(())
+        })?;
+        Ok(SharedDict { zone, hasher })
+    }
+
+    /// The zone of the dictionary.
+    pub fn zone(&self) -> &Zone {
+        &self.zone
+    }
+
+    /// The value of `key`, if it is set and not expired. Integers are returned in decimal.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.with_entry(key, |entry| unsafe {
+            match (*entry).kind {
+                KIND_INTEGER => read_integer(entry).to_string().into_bytes(),
+                _ => Entry::value(entry).to_vec(),
+            }
+        })
+    }
+
+    /// The integer value of `key`, if it is set to an integer and not expired.
+    pub fn get_integer(&self, key: &[u8]) -> Option<i64> {
+        self.with_entry(key, |entry| unsafe {
+            match (*entry).kind {
+                KIND_INTEGER => Some(read_integer(entry)),
+                _ => None,
+            }
+        })
+        .flatten()
+    }
+
+    /// The value of `key`, decoded with [`Codec`].
+    pub fn get_as<T: Codec>(&self, key: &[u8]) -> Option<Result<T, CodecError>> {
+        self.get(key).map(|value| T::from_bytes(&value))
+    }
+
+    /// Set `key` to `value`, expiring after `ttl` if given.
+    pub fn set(&self, key: &[u8], value: &[u8], ttl: Option<Msec>) -> Result<(), DictError> {
+        self.write(key, KIND_BYTES, value, ttl, true).map(|_| ())
+    }
+
+    /// Set `key` to `value`, encoded with [`Codec`].
+    pub fn set_as<T: Codec>(&self, key: &[u8], value: &T, ttl: Option<Msec>) -> Result<(), DictError> {
+        let value = value.to_bytes().map_err(DictError::Encode)?;
+        self.set(key, &value, ttl)
+    }
+
+    /// Set `key` to `value` only if it is not set, returning whether it was set.
+    pub fn add(&self, key: &[u8], value: &[u8], ttl: Option<Msec>) -> Result<bool, DictError> {
+        self.write(key, KIND_BYTES, value, ttl, false)
+    }
+
+    /// Add `delta` to the integer value of `key`, returning the new value.
+    ///
+    /// If `key` is not set, it is first set to `init` with the expiry `ttl`, or the
+    /// increment fails with [`DictError::NotAnInteger`] without `init`. The expiry of
+    /// existing entries is kept.
+    pub fn incr(&self, key: &[u8], delta: i64, init: Option<i64>, ttl: Option<Msec>) -> Result<i64, DictError> {
+        let slab = self.zone.slab().ok_or(DictError::NotMapped)?;
+        let sh = self.zone.data::<DictShared>();
+        let hash = self.hasher.hash64(key) as ngx_rbtree_key_t;
+
+        let mut guard = slab.lock();
+        unsafe {
+            let now = ngx_current_msec;
+            let entry = lookup(&mut guard, sh, hash, key, now);
+            if !entry.is_null() {
+                if (*entry).kind != KIND_INTEGER {
+                    return Err(DictError::NotAnInteger);
+                }
+                let value = read_integer(entry).wrapping_add(delta);
+                Entry::value(entry).copy_from_slice(&value.to_le_bytes());
+                touch(sh, entry);
+                return Ok(value);
+            }
+
+            let value = init.ok_or(DictError::NotAnInteger)?.wrapping_add(delta);
+            insert(&mut guard, sh, hash, key, KIND_INTEGER, &value.to_le_bytes(), ttl, now)?;
+            Ok(value)
+        }
+    }
+
+    /// Remove `key`, returning whether it was set.
+    pub fn delete(&self, key: &[u8]) -> bool {
+        let (slab, sh) = match self.zone.slab() {
+            Some(slab) => (slab, self.zone.data::<DictShared>()),
+            None => return false,
+        };
+        let hash = self.hasher.hash64(key) as ngx_rbtree_key_t;
+
+        let mut guard = slab.lock();
+        unsafe {
+            let entry = lookup(&mut guard, sh, hash, key, ngx_current_msec);
+            if entry.is_null() {
+                return false;
+            }
+            remove(&mut guard, sh, entry);
+            true
+        }
+    }
+
+    /// Number of entries, including expired entries not removed yet.
+    pub fn len(&self) -> usize {
+        let slab = match self.zone.slab() {
+            Some(slab) => slab,
+            None => return 0,
+        };
+        let sh = self.zone.data::<DictShared>();
+        let _guard = slab.lock();
+        unsafe { (*sh).len }
+    }
+
+    /// Is the dictionary empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove the expired entries, returning how many were removed.
+    ///
+    /// Expired entries are otherwise removed as they are found, or to make room.
+    pub fn flush_expired(&self) -> usize {
+        let slab = match self.zone.slab() {
+            Some(slab) => slab,
+            None => return 0,
+        };
+        let sh = self.zone.data::<DictShared>();
+        let mut guard = slab.lock();
+        unsafe { expire_all(&mut guard, sh, ngx_current_msec) }
+    }
+
+    // Call `f` with the entry of `key`, marking it as recently used.
+    fn with_entry<R>(&self, key: &[u8], f: impl FnOnce(*mut Entry) -> R) -> Option<R> {
+        let slab = self.zone.slab()?;
+        let sh = self.zone.data::<DictShared>();
+        let hash = self.hasher.hash64(key) as ngx_rbtree_key_t;
+
+        let mut guard = slab.lock();
+        unsafe {
+            let entry = lookup(&mut guard, sh, hash, key, ngx_current_msec);
+            if entry.is_null() {
+                return None;
+            }
+            touch(sh, entry);
+            Some(f(entry))
+        }
+    }
+
+    fn write(&self, key: &[u8], kind: u8, value: &[u8], ttl: Option<Msec>, replace: bool) -> Result<bool, DictError> {
+        let slab = self.zone.slab().ok_or(DictError::NotMapped)?;
+        if !fits(&slab, Entry::size(key.len(), value.len())) {
+            return Err(DictError::NoMemory);
+        }
+        let sh = self.zone.data::<DictShared>();
+        let hash = self.hasher.hash64(key) as ngx_rbtree_key_t;
+
+        let mut guard = slab.lock();
+        unsafe {
+            let now = ngx_current_msec;
+            let entry = lookup(&mut guard, sh, hash, key, now);
+            if !entry.is_null() {
+                if !replace {
+                    return Ok(false);
+                }
+                if (*entry).value_len as usize == value.len() {
+                    // Same size: update in place.
+                    (*entry).kind = kind;
+                    (*entry).expires = expiry(ttl, now);
+                    Entry::value(entry).copy_from_slice(value);
+                    touch(sh, entry);
+                    return Ok(true);
+                }
+                remove(&mut guard, sh, entry);
+            }
+
+            expire(&mut guard, sh, now, false);
+            insert(&mut guard, sh, hash, key, kind, value, ttl, now)?;
+            Ok(true)
+        }
+    }
+
+    // Update the value of `key` in place under the lock, for state such as that of a rate
+    // limiter. `f` is called with a zeroed value of `size` bytes and `true` if the key was
+    // not set, or set to a value of another size. The expiry is reset to `ttl`.
+    pub(crate) fn update<R>(&self, key: &[u8], size: usize, ttl: Option<Msec>, f: impl FnOnce(&mut [u8], bool) -> R) -> Result<R, DictError> {
+        let slab = self.zone.slab().ok_or(DictError::NotMapped)?;
+        if !fits(&slab, Entry::size(key.len(), size)) {
+            return Err(DictError::NoMemory);
+        }
+        let sh = self.zone.data::<DictShared>();
+        let hash = self.hasher.hash64(key) as ngx_rbtree_key_t;
+
+        let mut guard = slab.lock();
+        unsafe {
+            let now = ngx_current_msec;
+            let mut entry = lookup(&mut guard, sh, hash, key, now);
+            if !entry.is_null() && ((*entry).kind != KIND_BYTES || (*entry).value_len as usize != size) {
+                remove(&mut guard, sh, entry);
+                entry = ptr::null_mut();
+            }
+
+            let created = entry.is_null();
+            if created {
+                expire(&mut guard, sh, now, false);
+                insert(&mut guard, sh, hash, key, KIND_BYTES, &vec![0; size], ttl, now)?;
+                entry = lookup(&mut guard, sh, hash, key, now);
+            }
+            (*entry).expires = expiry(ttl, now);
+            touch(sh, entry);
+            Ok(f(Entry::value(entry), created))
+        }
+    }
+}
+
+// Can an entry of `size` bytes fit in the zone at all?
+fn fits(slab: &SlabPool, size: usize) -> bool {
+    let (_, pages) = slab.pages();
+    size <= pages * unsafe { ngx_pagesize } as usize
+}
+
+fn expiry(ttl: Option<Msec>, now: ngx_msec_t) -> ngx_msec_t {
+    match ttl {
+        // 0 means no expiry.
+        Some(ttl) => now.wrapping_add(ttl.as_msec()).max(1),
+        None => 0,
+    }
+}
+
+unsafe fn read_integer(entry: *mut Entry) -> i64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(Entry::value(entry));
+    i64::from_le_bytes(bytes)
+}
+
+// Find the entry of `key`, removing it if it expired, so a new entry for the key can't be
+// hidden behind it in the tree.
+unsafe fn lookup(guard: &mut SlabGuard, sh: *mut DictShared, hash: ngx_rbtree_key_t, key: &[u8], now: ngx_msec_t) -> *mut Entry {
+    let sentinel = (*sh).rbtree.sentinel;
+    let mut node = (*sh).rbtree.root;
+
+    while node != sentinel {
+        if hash != (*node).key {
+            node = if hash < (*node).key { (*node).left } else { (*node).right };
+            continue;
+        }
+        let entry = node as *mut Entry;
+        let entry_key = Entry::key(entry);
+        if key == entry_key {
+            if Entry::is_expired(entry, now) {
+                remove(guard, sh, entry);
+                return ptr::null_mut();
+            }
+            return entry;
+        }
+        node = if key < entry_key { (*node).left } else { (*node).right };
+    }
+    ptr::null_mut()
+}
+
+// Mark an entry as the most recently used.
+unsafe fn touch(sh: *mut DictShared, entry: *mut Entry) {
+    queue_remove(&mut (*entry).queue);
+    queue_insert_head(&mut (*sh).lru, &mut (*entry).queue);
+}
+
+unsafe fn remove(guard: &mut SlabGuard, sh: *mut DictShared, entry: *mut Entry) {
+    queue_remove(&mut (*entry).queue);
+    ngx_rbtree_delete(&mut (*sh).rbtree, &mut (*entry).node);
+    (*sh).len -= 1;
+    guard.free(entry);
+}
+
+// Remove expired entries among the `EXPIRE_SCAN` least recently used, stopping at the first
+// one that is not expired. With `force`, the least recently used entry is removed even if it
+// is not expired, to make room.
+unsafe fn expire(guard: &mut SlabGuard, sh: *mut DictShared, now: ngx_msec_t, force: bool) -> usize {
+    let head: *mut ngx_queue_t = &mut (*sh).lru;
+    let mut removed = 0;
+    while removed < EXPIRE_SCAN && (*head).prev != head {
+        let entry = Entry::from_queue((*head).prev);
+        if !(force && removed == 0) && !Entry::is_expired(entry, now) {
+            break;
+        }
+        remove(guard, sh, entry);
+        removed += 1;
+    }
+    removed
+}
+
+// Remove all expired entries.
+unsafe fn expire_all(guard: &mut SlabGuard, sh: *mut DictShared, now: ngx_msec_t) -> usize {
+    let head: *mut ngx_queue_t = &mut (*sh).lru;
+    let mut removed = 0;
+    let mut q = (*head).prev;
+    while q != head {
+        let entry = Entry::from_queue(q);
+        q = (*q).prev;
+        if Entry::is_expired(entry, now) {
+            remove(guard, sh, entry);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn insert(
+    guard: &mut SlabGuard,
+    sh: *mut DictShared,
+    hash: ngx_rbtree_key_t,
+    key: &[u8],
+    kind: u8,
+    value: &[u8],
+    ttl: Option<Msec>,
+    now: ngx_msec_t,
+) -> Result<(), DictError> {
+    let size = Entry::size(key.len(), value.len());
+    let mut entry = guard.alloc(size) as *mut Entry;
+    let mut attempts = 0;
+    while entry.is_null() {
+        if attempts == EVICT_MAX {
+            return Err(DictError::NoMemory);
+        }
+        attempts += 1;
+
+        // Make room: the least recently used entry, and the expired ones after it.
+        if expire(guard, sh, now, true) == 0 {
+            return Err(DictError::NoMemory);
+        }
+        entry = guard.alloc(size) as *mut Entry;
+    }
+
+    (*entry).node.key = hash;
+    (*entry).expires = expiry(ttl, now);
+    (*entry).kind = kind;
+    (*entry).key_len = key.len() as u32;
+    (*entry).value_len = value.len() as u32;
+    let data = (entry as *mut u8).add(mem::size_of::<Entry>());
+    ptr::copy_nonoverlapping(key.as_ptr(), data, key.len());
+    ptr::copy_nonoverlapping(value.as_ptr(), data.add(key.len()), value.len());
+
+    ngx_rbtree_insert(&mut (*sh).rbtree, &mut (*entry).node);
+    queue_insert_head(&mut (*sh).lru, &mut (*entry).queue);
+    (*sh).len += 1;
+    Ok(())
+}
+
+// Insert function of the tree, ordering entries with the same hash by key.
+unsafe extern "C" fn ngx_rs_shared_dict_insert_value(
+    mut temp: *mut ngx_rbtree_node_t,
+    node: *mut ngx_rbtree_node_t,
+    sentinel: *mut ngx_rbtree_node_t,
+) {
+    let p = loop {
+        let p = if (*node).key != (*temp).key {
+            if (*node).key < (*temp).key {
+                &mut (*temp).left
+            } else {
+                &mut (*temp).right
+            }
+        } else if Entry::key(node as *const Entry) < Entry::key(temp as *const Entry) {
+            &mut (*temp).left
+        } else {
+            &mut (*temp).right
+        };
+        if *p == sentinel {
+            break p;
+        }
+        temp = *p;
+    };
+
+    *p = node;
+    (*node).parent = temp;
+    (*node).left = sentinel;
+    (*node).right = sentinel;
+    // ngx_rbt_red()
+    (*node).color = 1;
+}
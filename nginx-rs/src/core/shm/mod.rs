@@ -0,0 +1,23 @@
+//! Shared memory, visible to all worker processes.
+//!
+//! A [`Zone`] is a named shared memory segment created while parsing the configuration. Its
+//! content survives reloads as long as its size is unchanged.
+//!
+//! [`SharedDict`] builds a key/value store with expiry on top of a zone, and [`SharedMetrics`]
+//! counters and gauges updated by all workers.
+
+mod channel;
+mod counters;
+mod dict;
+mod mutex;
+mod rate_limit;
+mod slab;
+mod zone;
+
+pub use channel::*;
+pub use counters::*;
+pub use dict::*;
+pub use mutex::*;
+pub use rate_limit::*;
+pub use slab::*;
+pub use zone::*;
@@ -0,0 +1,131 @@
+use crate::bindings::*;
+use crate::core::shm::slab::SlabPool;
+
+use std::cell::UnsafeCell;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+/// A mutex in shared memory (`ngx_shmtx_t`), protecting a value of type `T` shared by all
+/// worker processes.
+///
+/// The mutex refers to its own lock word, so it is initialized in place, in a zone, with
+/// [`ShmMutex::init`] or [`SlabPool::allocate_mutex`], and never moved. Like anything in
+/// shared memory, `T` must not own memory from the process heap.
+///
+/// ```ignore
+/// let state = slab.allocate_mutex(Totals::default()).ok_or("no memory")?;
+/// zone.set_data(state);
+///
+/// // In any worker.
+/// let state = unsafe { &*zone.data::<ShmMutex<Totals>>() };
+/// state.lock().requests += 1;
+/// ```
+///
+/// Locks are held across processes, so keep them short: Nginx spins, then yields to other
+/// processes, while waiting.
+#[repr(C)]
+pub struct ShmMutex<T> {
+    mutex: ngx_shmtx_t,
+    sh: ngx_shmtx_sh_t,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: The value is only reached through the lock.
+unsafe impl<T: Send> Send for ShmMutex<T> {}
+unsafe impl<T: Send> Sync for ShmMutex<T> {}
+
+impl<T> ShmMutex<T> {
+    /// Initialize a mutex holding `value` at `p`, in shared memory.
+    ///
+    /// Call this from the initialization of the zone, before worker processes start.
+    pub unsafe fn init(p: *mut ShmMutex<T>, value: T) -> Result<(), String> {
+        ptr::write(ptr::addr_of_mut!((*p).data), UnsafeCell::new(value));
+        ptr::write_bytes(ptr::addr_of_mut!((*p).mutex), 0, 1);
+        ptr::write_bytes(ptr::addr_of_mut!((*p).sh), 0, 1);
+        // The name is the lock file, only used without atomic operations.
+        let name = (*ngx_cycle).lock_file.data;
+        if ngx_shmtx_create(&mut (*p).mutex, &mut (*p).sh, name) != NGX_OK as ngx_int_t {
+            return Err(String::from("failed to create mutex"));
+        }
+        Ok(())
+    }
+
+    /// Lock the mutex, waiting for other processes to unlock it.
+    pub fn lock(&self) -> ShmMutexGuard<T> {
+        unsafe { ngx_shmtx_lock(self.as_ngx_shmtx()) };
+        ShmMutexGuard { mutex: self }
+    }
+
+    /// Lock the mutex if it is not locked.
+    pub fn try_lock(&self) -> Option<ShmMutexGuard<T>> {
+        if unsafe { ngx_shmtx_trylock(self.as_ngx_shmtx()) } == 0 {
+            return None;
+        }
+        Some(ShmMutexGuard { mutex: self })
+    }
+
+    /// Unlock the mutex if it is held by the process `pid`, such as a worker which exited
+    /// abnormally. Returns `true` if it was held.
+    ///
+    /// Nginx does this itself for the mutexes of slab pools. The value may be left in an
+    /// inconsistent state.
+    pub unsafe fn force_unlock(&self, pid: ngx_pid_t) -> bool {
+        ngx_shmtx_force_unlock(self.as_ngx_shmtx(), pid) != 0
+    }
+
+    /// Pointer to the [`ngx_shmtx_t`].
+    pub fn as_ngx_shmtx(&self) -> *mut ngx_shmtx_t {
+        &self.mutex as *const ngx_shmtx_t as *mut ngx_shmtx_t
+    }
+
+    /// The value, without locking, through exclusive access to the mutex.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+/// Lock on a [`ShmMutex`], giving access to its value. The mutex is unlocked when the guard
+/// is dropped, including while unwinding from a panic, so other workers are not left
+/// waiting forever.
+pub struct ShmMutexGuard<'a, T> {
+    mutex: &'a ShmMutex<T>,
+}
+
+impl<'a, T> Deref for ShmMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for ShmMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for ShmMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe { ngx_shmtx_unlock(self.mutex.as_ngx_shmtx()) };
+    }
+}
+
+impl SlabPool {
+    /// Allocate a [`ShmMutex`] holding `value`, or return `None` if the zone is full or the
+    /// mutex can't be created.
+    pub fn allocate_mutex<T>(&self, value: T) -> Option<*mut ShmMutex<T>> {
+        let p = self.calloc(mem::size_of::<ShmMutex<T>>()) as *mut ShmMutex<T>;
+        if p.is_null() {
+            return None;
+        }
+        unsafe {
+            if ShmMutex::init(p, value).is_err() {
+                self.free(p);
+                return None;
+            }
+        }
+        Some(p)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::bindings::*;
+use crate::core::shm::dict::SharedDict;
+use crate::core::units::Msec;
+
+/// Algorithm and limit of a [`RateLimiter`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimit {
+    /// A leaky bucket, as `limit_req`: requests drain at `rate` per second, and up to `burst`
+    /// requests above the rate are accepted. Of those, the first `delay` pass right away
+    /// and the others are delayed to match the rate; `delay` set to `burst` is the `nodelay`
+    /// parameter.
+    LeakyBucket { rate: f64, burst: u32, delay: u32 },
+    /// At most `limit` requests in any `window`, estimated from the counts of the current
+    /// and previous fixed windows.
+    SlidingWindow { limit: u32, window: Msec },
+}
+
+/// Decision of a [`RateLimiter`] for a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// Within the limit.
+    Allow,
+    /// Within the burst of a leaky bucket: process the request after this delay.
+    Delay(Msec),
+    /// Over the limit. The request is not counted.
+    Reject,
+}
+
+/// Rate limiting by an arbitrary key, such as a client address or an API key, counted in a
+/// shared memory zone so the limit applies to all worker processes together.
+///
+/// This is the engine of `limit_req` for use in Rust handlers, with a sliding window
+/// variant. The state of each key is kept in a [`SharedDict`], so keys that were not seen
+/// for a while are forgotten, and the least recently seen keys are evicted when the zone is
+/// full.
+///
+/// ```ignore
+/// // While parsing the configuration.
+/// let limiter = RateLimiter::register(cf, "api_keys", 10 * 1024 * 1024, RateLimit::SlidingWindow {
+///     limit: 100,
+///     window: Msec::from_secs(60),
+/// })?;
+///
+/// // In an access phase handler.
+/// match limiter.check(api_key.as_bytes()) {
+///     Decision::Reject => HTTP_TOO_MANY_REQUESTS.into(),
+///     _ => DECLINED,
+/// }
+/// ```
+///
+/// Requests are allowed if the zone can't be used, such as when it is full of keys that
+/// can't be evicted because their state is larger than the free space.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimiter {
+    dict: SharedDict,
+    limit: RateLimit,
+}
+
+impl RateLimiter {
+    /// Add a limiter keeping its state in a new zone `name` of `size` bytes.
+    ///
+    /// Call this while parsing the configuration.
+    pub unsafe fn register(cf: *mut ngx_conf_t, name: &str, size: usize, limit: RateLimit) -> Result<RateLimiter, String> {
+        if let RateLimit::LeakyBucket { rate, .. } = limit {
+            if rate.is_nan() || rate <= 0.0 {
+                return Err(format!("invalid rate {}", rate));
+            }
+        }
+        let dict = SharedDict::register(cf, name, size)?;
+        Ok(RateLimiter { dict, limit })
+    }
+
+    /// The limit of the limiter.
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Count a request for `key`, unless it is rejected.
+    pub fn check(&self, key: &[u8]) -> Decision {
+        let now = unsafe { ngx_current_msec } as u64;
+        let decision = match self.limit {
+            RateLimit::LeakyBucket { rate, burst, delay } => {
+                // State is drained after (burst + 1) / rate seconds.
+                let ttl = Msec::from_msec(((burst as f64 + 1.0) * 1000.0 / rate) as ngx_msec_t + 1000);
+                self.dict.update(key, 16, Some(ttl), |state, created| leaky_bucket(state, created, now, rate, burst, delay))
+            }
+            RateLimit::SlidingWindow { limit, window } => {
+                let window = window.as_msec().max(1) as u64;
+                let ttl = Msec::from_msec((2 * window) as ngx_msec_t);
+                self.dict.update(key, 24, Some(ttl), |state, _| sliding_window(state, now, limit, window))
+            }
+        };
+        decision.unwrap_or(Decision::Allow)
+    }
+
+    /// Forget the state of `key`.
+    pub fn reset(&self, key: &[u8]) {
+        self.dict.delete(key);
+    }
+}
+
+// The state is stored as little-endian integers: the dictionary doesn't align values.
+fn read_u64(state: &[u8], i: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&state[i * 8..i * 8 + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn write_u64(state: &mut [u8], i: usize, value: u64) {
+    state[i * 8..i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+// State: the excess in thousandths of a request, and the time of the last request.
+fn leaky_bucket(state: &mut [u8], created: bool, now: u64, rate: f64, burst: u32, delay: u32) -> Decision {
+    // The first request of a key leaves the bucket empty, as with `limit_req`.
+    let mut excess = 0;
+    if !created {
+        let elapsed = now.saturating_sub(read_u64(state, 1)) as f64;
+        excess = (read_u64(state, 0) as f64 - rate * elapsed + 1000.0).max(0.0) as i64;
+        if excess > burst as i64 * 1000 {
+            return Decision::Reject;
+        }
+    }
+
+    write_u64(state, 0, excess as u64);
+    write_u64(state, 1, now);
+
+    let delayed = excess - delay as i64 * 1000;
+    if delayed <= 0 {
+        return Decision::Allow;
+    }
+    Decision::Delay(Msec::from_msec((delayed as f64 / rate) as ngx_msec_t))
+}
+
+// State: the index of the current window, and the counts of the current and previous windows.
+fn sliding_window(state: &mut [u8], now: u64, limit: u32, window: u64) -> Decision {
+    let index = now / window;
+    let mut current = read_u64(state, 1);
+    let mut previous = read_u64(state, 2);
+
+    let last = read_u64(state, 0);
+    if last != index {
+        previous = if last + 1 == index { current } else { 0 };
+        current = 0;
+        write_u64(state, 0, index);
+        write_u64(state, 2, previous);
+    }
+
+    // Weight the previous window by how much of it is still within the sliding window.
+    let elapsed = now % window;
+    let estimate = previous * (window - elapsed) / window + current;
+    if estimate >= limit as u64 {
+        write_u64(state, 1, current);
+        return Decision::Reject;
+    }
+
+    current += 1;
+    write_u64(state, 1, current);
+    Decision::Allow
+}
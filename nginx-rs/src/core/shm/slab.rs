@@ -0,0 +1,143 @@
+use crate::bindings::*;
+use crate::core::shm::zone::Zone;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// The slab allocator of a shared memory [`Zone`] (`ngx_slab_pool_t`).
+///
+/// Allocations are visible to all worker processes, at the same address in each, so data
+/// allocated in a zone may point to other data in the same zone. Allocations take the
+/// mutex of the pool; use [`SlabPool::lock`] to make several under one lock.
+///
+/// The pool implements [`GlobalAlloc`], for containers generic over an allocator. Rust
+/// values in shared memory must not own memory from the process heap, such as a `String`
+/// or a `Box`, as other processes can't read it.
+#[derive(Clone, Copy, Debug)]
+pub struct SlabPool {
+    shpool: *mut ngx_slab_pool_t,
+}
+
+// SAFETY: The pool is in shared memory and protected by its own mutex.
+unsafe impl Send for SlabPool {}
+unsafe impl Sync for SlabPool {}
+
+impl SlabPool {
+    /// Create a [`SlabPool`] from an [`ngx_slab_pool_t`].
+    pub unsafe fn from_ngx_slab_pool(shpool: *mut ngx_slab_pool_t) -> SlabPool {
+        SlabPool { shpool }
+    }
+
+    /// Pointer to the [`ngx_slab_pool_t`].
+    pub fn as_ngx_slab_pool(&self) -> *mut ngx_slab_pool_t {
+        self.shpool
+    }
+
+    /// Allocate `size` bytes, or return a null pointer if the zone is full.
+    pub fn alloc(&self, size: usize) -> *mut c_void {
+        unsafe { ngx_slab_alloc(self.shpool, size) }
+    }
+
+    /// Allocate `size` zeroed bytes, or return a null pointer if the zone is full.
+    pub fn calloc(&self, size: usize) -> *mut c_void {
+        unsafe { ngx_slab_calloc(self.shpool, size) }
+    }
+
+    /// Allocate a value of type `T`, or return `None` if the zone is full.
+    pub fn allocate<T>(&self, value: T) -> Option<*mut T> {
+        let p = self.alloc(mem::size_of::<T>().max(mem::align_of::<T>())) as *mut T;
+        if p.is_null() {
+            return None;
+        }
+        unsafe { ptr::write(p, value) };
+        Some(p)
+    }
+
+    /// Free memory allocated from the pool. Values are not dropped.
+    pub unsafe fn free<T>(&self, p: *mut T) {
+        ngx_slab_free(self.shpool, p as *mut c_void);
+    }
+
+    /// Lock the pool, to make several allocations under one lock.
+    pub fn lock(&self) -> SlabGuard {
+        unsafe { ngx_shmtx_lock(&mut (*self.shpool).mutex) };
+        SlabGuard { shpool: self.shpool, _marker: PhantomData }
+    }
+
+    /// Number of free pages, and the total number of pages of the zone.
+    ///
+    /// Small allocations share pages, so a zone can be unable to allocate some sizes while
+    /// it still has free space.
+    pub fn pages(&self) -> (usize, usize) {
+        unsafe {
+            let total = ((*self.shpool).end as usize - (*self.shpool).start as usize) / ngx_pagesize as usize;
+            ((*self.shpool).pfree as usize, total)
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for SlabPool {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Slots are aligned to their size, which is a power of two, and pages to the page
+        // size.
+        if layout.align() > ngx_pagesize as usize {
+            return ptr::null_mut();
+        }
+        SlabPool::alloc(self, layout.size().max(layout.align())) as *mut u8
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > ngx_pagesize as usize {
+            return ptr::null_mut();
+        }
+        SlabPool::calloc(self, layout.size().max(layout.align())) as *mut u8
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, _layout: Layout) {
+        SlabPool::free(self, p);
+    }
+}
+
+/// Lock on a [`SlabPool`], see [`SlabPool::lock`]. The pool is unlocked when the guard is
+/// dropped.
+pub struct SlabGuard<'a> {
+    shpool: *mut ngx_slab_pool_t,
+    _marker: PhantomData<&'a SlabPool>,
+}
+
+impl<'a> SlabGuard<'a> {
+    /// Allocate `size` bytes, or return a null pointer if the zone is full.
+    pub fn alloc(&mut self, size: usize) -> *mut c_void {
+        unsafe { ngx_slab_alloc_locked(self.shpool, size) }
+    }
+
+    /// Allocate `size` zeroed bytes, or return a null pointer if the zone is full.
+    pub fn calloc(&mut self, size: usize) -> *mut c_void {
+        unsafe { ngx_slab_calloc_locked(self.shpool, size) }
+    }
+
+    /// Free memory allocated from the pool. Values are not dropped.
+    pub unsafe fn free<T>(&mut self, p: *mut T) {
+        ngx_slab_free_locked(self.shpool, p as *mut c_void);
+    }
+}
+
+impl<'a> Drop for SlabGuard<'a> {
+    fn drop(&mut self) {
+        unsafe { ngx_shmtx_unlock(&mut (*self.shpool).mutex) };
+    }
+}
+
+impl Zone {
+    /// The slab allocator of the zone, once it is mapped.
+    pub fn slab(&self) -> Option<SlabPool> {
+        let shpool = self.slab_pool();
+        if shpool.is_null() {
+            return None;
+        }
+        Some(SlabPool { shpool })
+    }
+}
@@ -0,0 +1,181 @@
+use crate::bindings::*;
+use crate::core::pool::Pool;
+use crate::core::string::{NgxStr, NgxString};
+
+use std::os::raw::c_void;
+use std::ptr;
+
+// Tags of the zones of the crate, one per kind of zone, telling them apart from each other
+// and from zones of other modules with the same name: Nginx refuses a name already used with
+// another tag, and only keeps the content of a zone on reload if its tag is unchanged. The
+// values differ so that the statics can't be merged.
+static ZONE_TAG: u8 = 0;
+pub(crate) static DICT_ZONE_TAG: u8 = 1;
+pub(crate) static CHANNEL_ZONE_TAG: u8 = 2;
+pub(crate) static METRICS_ZONE_TAG: u8 = 3;
+pub(crate) static DYNAMIC_UPSTREAM_ZONE_TAG: u8 = 4;
+pub(crate) static OUTLIER_ZONE_TAG: u8 = 5;
+
+type ZoneInit = Box<dyn FnMut(&Zone, bool) -> Result<(), String>>;
+
+struct ZoneCtx {
+    init: ZoneInit,
+}
+
+/// A shared memory zone (`ngx_shm_zone_t`).
+///
+/// The zone starts with a slab pool (`ngx_slab_pool_t`) allocating the rest of the zone,
+/// whose `data` field conventionally points to the state of the zone.
+///
+/// ```ignore
+/// let zone = Zone::register(cf, "counters", 1024 * 1024, |zone: &Zone, reused: bool| {
+///     if !reused {
+///         let slab = zone.slab().ok_or("not mapped")?;
+///         let table = slab.allocate(Table::default()).ok_or("out of memory")?;
+///         zone.set_data(table);
+///     }
+///     Ok(())
+/// })?;
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Zone {
+    zone: *mut ngx_shm_zone_t,
+}
+
+impl Zone {
+    /// Add a zone `name` of `size` bytes, calling `init` once it is mapped, before the worker
+    /// processes start.
+    ///
+    /// `init` is called with `reused` set if the zone kept its content from the previous
+    /// configuration, in which case its state is already there. It may be called again on
+    /// each configuration reload.
+    ///
+    /// Call this while parsing the configuration, such as from the handler of a directive
+    /// declaring the zone. Names are shared by all zones registered with this function, and
+    /// must be unique. Zones of other kinds, such as those of a
+    /// [`SharedDict`](crate::core::shm::SharedDict), can't have the same name.
+    pub unsafe fn register<F>(cf: *mut ngx_conf_t, name: &str, size: usize, init: F) -> Result<Zone, String>
+    where
+        F: FnMut(&Zone, bool) -> Result<(), String> + 'static,
+    {
+        Zone::register_with_tag(cf, name, size, &ZONE_TAG, init)
+    }
+
+    // Add a zone of the kind of `tag`, one of the tags above.
+    pub(crate) unsafe fn register_with_tag<F>(cf: *mut ngx_conf_t, name: &str, size: usize, tag: &'static u8, init: F) -> Result<Zone, String>
+    where
+        F: FnMut(&Zone, bool) -> Result<(), String> + 'static,
+    {
+        let min_size = 8 * ngx_pagesize as usize;
+        if size < min_size {
+            return Err(format!("zone \"{}\" is too small, the minimum is {} bytes", name, min_size));
+        }
+
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        let mut zone_name = match NgxString::new_in(name, &mut pool) {
+            Some(name) => name.into_ngx_str(),
+            None => return Err(String::from("allocation failed")),
+        };
+
+        let zone = ngx_shared_memory_add(cf, &mut zone_name, size, tag as *const u8 as *mut c_void);
+        if zone.is_null() {
+            return Err(format!("failed to add shared memory zone \"{}\"", name));
+        }
+        if !(*zone).data.is_null() {
+            return Err(format!("duplicate zone \"{}\"", name));
+        }
+
+        let ctx: *mut ZoneCtx = match pool.alloc(ZoneCtx { init: Box::new(init) }) {
+            Some(ctx) => ctx.as_ptr(),
+            None => return Err(String::from("allocation failed")),
+        };
+        (*zone).init = Some(ngx_rs_shm_zone_init);
+        (*zone).data = ctx as *mut c_void;
+
+        Ok(Zone { zone })
+    }
+
+    /// Find a zone registered with [`Zone::register`] in the current configuration, such as
+    /// from a request handler.
+    pub fn find(name: &str) -> Option<Zone> {
+        unsafe {
+            let mut part: *mut ngx_list_part_t = &mut (*ngx_cycle).shared_memory.part;
+            while !part.is_null() {
+                let elts = (*part).elts as *mut ngx_shm_zone_t;
+                for i in 0..(*part).nelts {
+                    let zone = elts.add(i);
+                    if (*zone).tag == &ZONE_TAG as *const u8 as *mut c_void
+                        && NgxStr::from_ngx_str((*zone).shm.name).as_bytes() == name.as_bytes()
+                    {
+                        return Some(Zone { zone });
+                    }
+                }
+                part = (*part).next;
+            }
+        }
+        None
+    }
+
+    /// Create a [`Zone`] from an [`ngx_shm_zone_t`], such as one added by another module.
+    pub unsafe fn from_ngx_shm_zone(zone: *mut ngx_shm_zone_t) -> Zone {
+        Zone { zone }
+    }
+
+    /// Pointer to the [`ngx_shm_zone_t`].
+    pub fn as_ngx_shm_zone(&self) -> *mut ngx_shm_zone_t {
+        self.zone
+    }
+
+    /// Name of the zone.
+    pub fn name(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str((*self.zone).shm.name) }
+    }
+
+    /// Size of the zone, in bytes.
+    pub fn size(&self) -> usize {
+        unsafe { (*self.zone).shm.size }
+    }
+
+    /// Is the zone mapped? Zones are mapped once the configuration is parsed.
+    pub fn is_mapped(&self) -> bool {
+        unsafe { !(*self.zone).shm.addr.is_null() }
+    }
+
+    /// The slab pool allocating the zone, once it is mapped.
+    pub fn slab_pool(&self) -> *mut ngx_slab_pool_t {
+        unsafe { (*self.zone).shm.addr as *mut ngx_slab_pool_t }
+    }
+
+    /// The state of the zone, as set by [`Zone::set_data`], or a null pointer.
+    pub fn data<T>(&self) -> *mut T {
+        let shpool = self.slab_pool();
+        if shpool.is_null() {
+            return ptr::null_mut();
+        }
+        unsafe { (*shpool).data as *mut T }
+    }
+
+    /// Set the state of the zone, which must be allocated in the zone.
+    pub fn set_data<T>(&self, data: *mut T) {
+        let shpool = self.slab_pool();
+        if !shpool.is_null() {
+            unsafe { (*shpool).data = data as *mut c_void };
+        }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_shm_zone_init(zone: *mut ngx_shm_zone_t, data: *mut c_void) -> ngx_int_t {
+    let ctx = &mut *((*zone).data as *mut ZoneCtx);
+    // With the previous configuration's data, the zone was kept on reload.
+    let reused = !data.is_null() || (*zone).shm.exists != 0;
+
+    let handle = Zone { zone };
+    match (ctx.init)(&handle, reused) {
+        Ok(()) => NGX_OK as ngx_int_t,
+        Err(err) => {
+            let name = handle.name().to_string_lossy().into_owned();
+            ngx_log!(NGX_LOG_EMERG, (*ngx_cycle).log, "shared memory zone \"{}\": {}", name, err);
+            NGX_ERROR as ngx_int_t
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use crate::bindings::*;
+use crate::core::client_hello::{ClientHello, ClientHelloError};
+use crate::core::connection::Connection;
+use crate::core::pool::Pool;
+use crate::core::status::*;
+use crate::core::string::NgxStr;
+
+use std::marker::PhantomData;
+use std::os::raw::{c_int, c_long, c_void};
+use std::ptr;
+use std::slice;
+
+const SSL3_RT_HANDSHAKE: c_int = 22;
+const SSL_CTRL_SET_MSG_CALLBACK_ARG: c_int = 16;
+
+type CertCallback = unsafe extern "C" fn(*mut ngx_ssl_conn_t, *mut c_void) -> c_int;
+
+// OpenSSL functions used by Nginx are not part of the bindings.
+extern "C" {
+    fn SSL_CTX_set_msg_callback(ctx: *mut c_void, cb: Option<MsgCallback>);
+    fn SSL_CTX_set_cert_cb(ctx: *mut c_void, cb: Option<CertCallback>, arg: *mut c_void);
+    fn SSL_CTX_ctrl(ctx: *mut c_void, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_get_ex_data(ssl: *const ngx_ssl_conn_t, idx: c_int) -> *mut c_void;
+}
+
+type SslGetter = unsafe extern "C" fn(*mut ngx_connection_t, *mut ngx_pool_t, *mut ngx_str_t) -> ngx_int_t;
+
+/// The TLS session of a connection, see [`Connection::ssl`] and
+/// [`Request::ssl`](crate::http::Request::ssl).
+///
+/// Values are read with the `ngx_ssl_get_*` functions behind the `$ssl_*` variables, and
+/// allocated from the pool of the request, or of the connection outside of requests.
+pub struct Ssl<'a> {
+    c: *mut ngx_connection_t,
+    pool: *mut ngx_pool_t,
+    _marker: PhantomData<&'a Connection>,
+}
+
+impl<'a> Ssl<'a> {
+    // The session of `c`, if it has one, allocating values from `pool`.
+    pub(crate) unsafe fn new(c: *mut ngx_connection_t, pool: *mut ngx_pool_t) -> Option<Ssl<'a>> {
+        if (*c).ssl.is_null() || (*(*c).ssl).connection.is_null() {
+            return None;
+        }
+        Some(Ssl { c, pool, _marker: PhantomData })
+    }
+
+    /// Negotiated protocol, such as `TLSv1.3` (`$ssl_protocol`).
+    pub fn protocol(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_protocol)
+    }
+
+    /// Negotiated cipher, such as `TLS_AES_128_GCM_SHA256` (`$ssl_cipher`).
+    pub fn cipher(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_cipher_name)
+    }
+
+    /// Server name requested by the client with SNI (`$ssl_server_name`).
+    pub fn server_name(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_server_name)
+    }
+
+    /// Protocol selected with ALPN, such as `h2` or `http/1.1` (`$ssl_alpn_protocol`).
+    pub fn alpn_protocol(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_alpn_protocol)
+    }
+
+    /// Elliptic curve used for the key exchange, such as `X25519` (`$ssl_curve`).
+    pub fn curve(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_curve)
+    }
+
+    /// Session identifier, in hexadecimal (`$ssl_session_id`).
+    pub fn session_id(&self) -> Option<&'a NgxStr> {
+        self.get(ngx_ssl_get_session_id)
+    }
+
+    /// Was the session resumed (`$ssl_session_reused`)?
+    pub fn session_reused(&self) -> bool {
+        self.get(ngx_ssl_get_session_reused).map_or(false, |reused| reused.as_bytes() == b"r")
+    }
+
+    /// Was the request sent in TLS 1.3 early data, or QUIC 0-RTT (`$ssl_early_data`)? Such
+    /// requests may be replayed.
+    pub fn early_data(&self) -> bool {
+        self.get(ngx_ssl_get_early_data).map_or(false, |early| early.as_bytes() == b"1")
+    }
+
+    /// Has the handshake completed? Until then, only the server name is known.
+    pub fn handshaked(&self) -> bool {
+        unsafe { (*(*self.c).ssl).handshaked() != 0 }
+    }
+
+    /// Result of the verification of the client certificate (`$ssl_client_verify`), with
+    /// `ssl_verify_client` enabled.
+    pub fn client_verify(&self) -> ClientVerify {
+        match self.get(ngx_ssl_get_client_verify) {
+            Some(result) if result.as_bytes() == b"SUCCESS" => ClientVerify::Success,
+            Some(result) if result.as_bytes().starts_with(b"FAILED:") => {
+                ClientVerify::Failed(String::from_utf8_lossy(&result.as_bytes()[7..]).into_owned())
+            }
+            Some(result) if result.as_bytes() == b"FAILED" => ClientVerify::Failed(String::new()),
+            _ => ClientVerify::None,
+        }
+    }
+
+    /// The certificate presented by the client, whether it was verified or not.
+    pub fn client_certificate(&self) -> Option<ClientCertificate<'a>> {
+        let pem = self.get(ngx_ssl_get_raw_certificate)?;
+        Some(ClientCertificate { ssl: Ssl { c: self.c, pool: self.pool, _marker: PhantomData }, pem })
+    }
+
+    /// The client certificate, only if it was verified.
+    pub fn verified_client_certificate(&self) -> Option<ClientCertificate<'a>> {
+        match self.client_verify() {
+            ClientVerify::Success => self.client_certificate(),
+            _ => None,
+        }
+    }
+
+    /// Pointer to the OpenSSL `SSL` object, for anything not covered here.
+    pub fn as_ptr(&self) -> *mut ngx_ssl_conn_t {
+        unsafe { (*(*self.c).ssl).connection }
+    }
+
+    // Values are not found when empty, as with the variables.
+    fn get(&self, getter: SslGetter) -> Option<&'a NgxStr> {
+        unsafe {
+            let mut s = ngx_str_t { len: 0, data: std::ptr::null_mut() };
+            if getter(self.c, self.pool, &mut s) != NGX_OK as ngx_int_t || s.len == 0 {
+                return None;
+            }
+            Some(NgxStr::from_ngx_str(s))
+        }
+    }
+}
+
+/// Result of the verification of a client certificate, see [`Ssl::client_verify`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ClientVerify {
+    /// The client sent no certificate.
+    None,
+    /// The certificate was verified.
+    Success,
+    /// The verification failed, for the given reason, such as `certificate has expired`.
+    Failed(String),
+}
+
+impl ClientVerify {
+    /// Was the certificate verified?
+    pub fn is_success(&self) -> bool {
+        *self == ClientVerify::Success
+    }
+}
+
+/// A client certificate, see [`Ssl::client_certificate`].
+///
+/// Names are formatted per RFC 2253, as in the `$ssl_client_s_dn` variable.
+pub struct ClientCertificate<'a> {
+    ssl: Ssl<'a>,
+    pem: &'a NgxStr,
+}
+
+impl<'a> ClientCertificate<'a> {
+    /// The certificate in PEM format (`$ssl_client_raw_cert`).
+    pub fn pem(&self) -> &'a NgxStr {
+        self.pem
+    }
+
+    /// The certificate in DER format, decoded from PEM.
+    pub fn der(&self) -> Option<Vec<u8>> {
+        let pem = self.pem.as_bytes();
+        let begin = b"-----BEGIN CERTIFICATE-----";
+        let start = pem.windows(begin.len()).position(|w| w == begin)? + begin.len();
+        let end = start + pem[start..].iter().position(|&b| b == b'-')?;
+        let base64: Vec<u8> = pem[start..end].iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+
+        let mut der = vec![0u8; base64.len() / 4 * 3 + 3];
+        unsafe {
+            let mut src = ngx_str_t { len: base64.len(), data: base64.as_ptr() as *mut u_char };
+            let mut dst = ngx_str_t { len: 0, data: der.as_mut_ptr() };
+            if ngx_decode_base64(&mut dst, &mut src) != NGX_OK as ngx_int_t {
+                return None;
+            }
+            der.truncate(dst.len);
+        }
+        Some(der)
+    }
+
+    /// Subject name (`$ssl_client_s_dn`), such as `CN=client,O=Example`.
+    pub fn subject(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_subject_dn)
+    }
+
+    /// Issuer name (`$ssl_client_i_dn`).
+    pub fn issuer(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_issuer_dn)
+    }
+
+    /// Serial number, in hexadecimal (`$ssl_client_serial`).
+    pub fn serial(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_serial_number)
+    }
+
+    /// SHA-1 fingerprint, in hexadecimal (`$ssl_client_fingerprint`).
+    pub fn fingerprint(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_fingerprint)
+    }
+
+    /// Start of the validity period (`$ssl_client_v_start`), such as
+    /// `Jan  1 00:00:00 2025 GMT`.
+    pub fn not_before(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_client_v_start)
+    }
+
+    /// End of the validity period (`$ssl_client_v_end`).
+    pub fn not_after(&self) -> Option<&'a NgxStr> {
+        self.ssl.get(ngx_ssl_get_client_v_end)
+    }
+
+    /// Days until the certificate expires (`$ssl_client_v_remain`), `0` once it has.
+    pub fn remaining_days(&self) -> Option<u32> {
+        self.ssl.get(ngx_ssl_get_client_v_remain)?.to_str().ok()?.parse().ok()
+    }
+
+    /// The value of the first attribute `name` of the subject, such as `CN` or `O`.
+    pub fn subject_attribute(&self, name: &str) -> Option<String> {
+        let subject = self.subject()?.to_string_lossy().into_owned();
+        let mut attribute = String::new();
+        let mut escaped = false;
+        // Split at unescaped commas, per RFC 2253.
+        for c in subject.chars().chain(std::iter::once(',')) {
+            match c {
+                '\\' if !escaped => escaped = true,
+                ',' if !escaped => {
+                    if let Some(value) = attribute.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+                        return Some(value.to_string());
+                    }
+                    attribute.clear();
+                }
+                c => {
+                    attribute.push(c);
+                    escaped = false;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Handler of ClientHello messages, see [`capture_client_hello`].
+///
+/// It is called with the client connection and the raw message from the message callback of
+/// OpenSSL, in the middle of the handshake: it must not close or finalize the connection.
+/// To reject a client, record the decision, such as in the pool of the connection, and act
+/// on it from a request handler.
+pub type ClientHelloHandler = fn(&mut Connection, &[u8]);
+
+/// Message callback of OpenSSL, as set with `SSL_CTX_set_msg_callback`.
+pub type MsgCallback = unsafe extern "C" fn(c_int, c_int, c_int, *const c_void, usize, *mut ngx_ssl_conn_t, *mut c_void);
+
+/// A message callback and its argument, to be called by [`capture_client_hello`] for each
+/// message.
+#[derive(Clone, Copy, Debug)]
+pub struct MessageCallback {
+    pub callback: MsgCallback,
+    pub arg: *mut c_void,
+}
+
+// The first ClientHello of a connection, stored in its pool.
+struct CapturedClientHello(Vec<u8>);
+
+// Argument of the message callback, allocated from the configuration pool.
+struct CaptureContext {
+    handler: Option<ClientHelloHandler>,
+    previous: Option<MessageCallback>,
+}
+
+/// Capture the ClientHello of connections using the SSL context `ssl`, for TLS
+/// fingerprinting with [`Connection::client_hello`], optionally calling `handler` with it.
+///
+/// The message is captured with the message callback of OpenSSL, before the server name is
+/// known, so this must be set on the context of the default server of each listen socket.
+/// OpenSSL keeps a single message callback per context and cannot return the current one:
+/// pass a callback already set on the context as `previous`, and it is called first with
+/// each message.
+///
+/// The context must outlive its connections: call this while parsing the configuration.
+pub unsafe fn capture_client_hello(
+    cf: *mut ngx_conf_t,
+    ssl: *mut ngx_ssl_t,
+    handler: Option<ClientHelloHandler>,
+    previous: Option<MessageCallback>,
+) -> Status {
+    let mut pool = Pool::from_ngx_pool((*cf).pool);
+    let arg = match pool.alloc(CaptureContext { handler, previous }) {
+        Some(arg) => arg.as_ptr(),
+        None => return ERROR,
+    };
+    let ctx = (*ssl).ctx as *mut c_void;
+    SSL_CTX_set_msg_callback(ctx, Some(ngx_rs_ssl_msg_callback));
+    SSL_CTX_ctrl(ctx, SSL_CTRL_SET_MSG_CALLBACK_ARG, 0, arg as *mut c_void);
+    OK
+}
+
+unsafe extern "C" fn ngx_rs_ssl_msg_callback(
+    write_p: c_int,
+    version: c_int,
+    content_type: c_int,
+    buf: *const c_void,
+    len: usize,
+    ssl: *mut ngx_ssl_conn_t,
+    arg: *mut c_void,
+) {
+    let context = &*(arg as *const CaptureContext);
+    if let Some(previous) = context.previous {
+        (previous.callback)(write_p, version, content_type, buf, len, ssl, previous.arg);
+    }
+
+    if write_p != 0 || content_type != SSL3_RT_HANDSHAKE || len == 0 {
+        return;
+    }
+    let message = slice::from_raw_parts(buf as *const u8, len);
+    if message[0] != 1 {
+        return;
+    }
+
+    let c = SSL_get_ex_data(ssl, ngx_ssl_connection_index) as *mut ngx_connection_t;
+    if c.is_null() {
+        return;
+    }
+    // Keep the first message: after a HelloRetryRequest, the client sends another one.
+    let mut pool = Pool::from_ngx_pool((*c).pool);
+    if !pool.get_local::<CapturedClientHello>().is_null() {
+        return;
+    }
+    pool.insert_local(CapturedClientHello(message.to_vec()));
+
+    if let Some(handler) = context.handler {
+        handler(Connection::from_ngx_connection(c), message);
+    }
+}
+
+/// A certificate and its private key, as selected by a [`CertificateSelector`].
+///
+/// They are loaded with `ngx_ssl_connection_certificate`, as with variables in
+/// `ssl_certificate`: this happens on each handshake, so keep them in memory rather than in
+/// files for servers with many handshakes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SslCertificate {
+    certificate: String,
+    key: String,
+}
+
+impl SslCertificate {
+    /// A certificate chain and key in PEM files, relative to the configuration prefix.
+    pub fn from_files(certificate: &str, key: &str) -> SslCertificate {
+        SslCertificate { certificate: String::from(certificate), key: String::from(key) }
+    }
+
+    /// A certificate chain and key in PEM format, such as from a local certificate store.
+    pub fn from_pem(certificate: &str, key: &str) -> SslCertificate {
+        SslCertificate { certificate: format!("data:{}", certificate), key: format!("data:{}", key) }
+    }
+}
+
+/// Choice of a [`CertificateSelector`] for a handshake.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CertificateSelection {
+    /// Use the certificate configured with `ssl_certificate`.
+    Default,
+    /// Use this certificate instead.
+    Use(SslCertificate),
+    /// Fail the handshake.
+    Reject,
+}
+
+/// Selection of the server certificate for each TLS handshake, such as by server name from
+/// a local store, see [`set_certificate_selector`].
+///
+/// Closures with the signature of [`CertificateSelector::select`] are selectors.
+///
+/// ```ignore
+/// let selector = |_: &mut Connection, server_name: Option<&str>| match server_name.and_then(|name| STORE.get(name)) {
+///     Some(entry) => CertificateSelection::Use(SslCertificate::from_pem(&entry.chain, &entry.key)),
+///     None => CertificateSelection::Default,
+/// };
+/// ```
+pub trait CertificateSelector: 'static {
+    /// Select the certificate of a handshake, given the server name requested by the client.
+    ///
+    /// This is called during the handshake, before the certificate is sent: it must not
+    /// block.
+    fn select(&self, connection: &mut Connection, server_name: Option<&str>) -> CertificateSelection;
+}
+
+impl<F> CertificateSelector for F
+where
+    F: Fn(&mut Connection, Option<&str>) -> CertificateSelection + 'static,
+{
+    fn select(&self, connection: &mut Connection, server_name: Option<&str>) -> CertificateSelection {
+        self(connection, server_name)
+    }
+}
+
+/// Select the certificate of each handshake on the SSL context `ssl` with `selector`.
+///
+/// The selector is kept in the configuration pool. It replaces the callback Nginx sets for
+/// variables in `ssl_certificate`, so don't use both. A certificate must still be configured
+/// with `ssl_certificate`, for [`CertificateSelection::Default`].
+pub unsafe fn set_certificate_selector(
+    cf: *mut ngx_conf_t,
+    ssl: *mut ngx_ssl_t,
+    selector: Box<dyn CertificateSelector>,
+) -> Status {
+    let selector: *mut Box<dyn CertificateSelector> = match Pool::from_ngx_pool((*cf).pool).alloc(selector) {
+        Some(selector) => selector.as_ptr(),
+        None => return ERROR,
+    };
+    set_cert_callback(ssl, selector);
+    OK
+}
+
+pub(crate) unsafe fn set_cert_callback(ssl: *mut ngx_ssl_t, selector: *mut Box<dyn CertificateSelector>) {
+    SSL_CTX_set_cert_cb((*ssl).ctx as *mut c_void, Some(ngx_rs_ssl_cert_callback), selector as *mut c_void);
+}
+
+unsafe extern "C" fn ngx_rs_ssl_cert_callback(ssl: *mut ngx_ssl_conn_t, arg: *mut c_void) -> c_int {
+    let c = SSL_get_ex_data(ssl, ngx_ssl_connection_index) as *mut ngx_connection_t;
+    if c.is_null() {
+        return 0;
+    }
+    let selector = &*(arg as *const Box<dyn CertificateSelector>);
+    let connection = Connection::from_ngx_connection(c);
+    let server_name = connection.ssl().and_then(|ssl| ssl.server_name()).and_then(|name| name.to_str().ok());
+    // The name is in the connection pool, which outlives the call.
+    let server_name = server_name.map(|name| &*(name as *const str));
+
+    let certificate = match selector.select(connection, server_name) {
+        CertificateSelection::Default => return 1,
+        CertificateSelection::Use(certificate) => certificate,
+        CertificateSelection::Reject => return 0,
+    };
+
+    let mut pool = Pool::from_ngx_pool((*c).pool);
+    let (mut cert, mut key) = match (c_string(&mut pool, &certificate.certificate), c_string(&mut pool, &certificate.key)) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return 0,
+    };
+    // Without a cache, the certificate is loaded on each handshake, and without passwords
+    // the key must not be encrypted.
+    let rc = ngx_ssl_connection_certificate(c, (*c).pool, &mut cert, &mut key, ptr::null_mut(), ptr::null_mut());
+    if rc != NGX_OK as ngx_int_t {
+        return 0;
+    }
+    1
+}
+
+// Nginx opens certificate files by name, so strings are null-terminated.
+fn c_string(pool: &mut Pool, s: &str) -> Option<ngx_str_t> {
+    let data = pool.alloc_raw(s.len() + 1) as *mut u_char;
+    if data.is_null() {
+        return None;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(s.as_ptr(), data, s.len());
+        *data.add(s.len()) = 0;
+    }
+    Some(ngx_str_t { len: s.len(), data })
+}
+
+impl Connection {
+    /// The raw ClientHello message sent by the client, with its handshake header, if it was
+    /// captured with [`capture_client_hello`].
+    pub fn client_hello_message(&self) -> Option<&[u8]> {
+        unsafe {
+            let mut c = self.as_ngx_connection() as *mut ngx_connection_t;
+            // The connections of HTTP/2 streams share the TLS session of the client connection.
+            if !(*c).ssl.is_null() && !(*(*c).ssl).connection.is_null() {
+                let ssl_c = SSL_get_ex_data((*(*c).ssl).connection, ngx_ssl_connection_index) as *mut ngx_connection_t;
+                if !ssl_c.is_null() {
+                    c = ssl_c;
+                }
+            }
+            let captured = Pool::from_ngx_pool((*c).pool).get_local::<CapturedClientHello>();
+            if captured.is_null() {
+                return None;
+            }
+            Some(&(*captured).0)
+        }
+    }
+
+    /// The ClientHello sent by the client, if it was captured with [`capture_client_hello`].
+    pub fn client_hello(&self) -> Option<Result<ClientHello, ClientHelloError>> {
+        self.client_hello_message().map(ClientHello::parse_message)
+    }
+}
+
+impl Connection {
+    /// The TLS session of the connection, or `None` for connections without TLS.
+    ///
+    /// Values are allocated from the connection pool, which is only freed with the
+    /// connection: while processing HTTP requests, which may be many on a keepalive
+    /// connection, use [`Request::ssl`](crate::http::Request::ssl) instead.
+    ///
+    /// With the `ssl` feature, which requires Nginx to be built with OpenSSL.
+    pub fn ssl(&self) -> Option<Ssl> {
+        let c = self.as_ngx_connection() as *mut ngx_connection_t;
+        unsafe { Ssl::new(c, (*c).pool) }
+    }
+}
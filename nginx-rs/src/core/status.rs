@@ -8,6 +8,37 @@ impl Status {
     pub fn is_ok(&self) -> bool {
         self == &OK
     }
+
+    /// Is this [`ERROR`]?
+    pub fn is_error(&self) -> bool {
+        self == &ERROR
+    }
+
+    /// Is this [`AGAIN`], an operation to resume later?
+    pub fn is_again(&self) -> bool {
+        self == &AGAIN
+    }
+
+    /// Is this [`DONE`], an operation completed elsewhere?
+    pub fn is_done(&self) -> bool {
+        self == &DONE
+    }
+
+    /// Is this [`DECLINED`], passing on to the next handler?
+    pub fn is_declined(&self) -> bool {
+        self == &DECLINED
+    }
+
+    /// Is this an HTTP status (`100` and above) rather than a core status?
+    pub fn is_http(&self) -> bool {
+        self.0 >= NGX_HTTP_CONTINUE as ngx_int_t
+    }
+
+    /// Is this an HTTP error status (`400` and above), to finalize the request with an error
+    /// page?
+    pub fn is_http_error(&self) -> bool {
+        self.0 >= NGX_HTTP_BAD_REQUEST as ngx_int_t
+    }
 }
 
 impl Into<ngx_int_t> for Status {
@@ -26,3 +57,7 @@ impl fmt::Debug for Status {
 pub const OK: Status = Status(NGX_OK as ngx_int_t);
 pub const ERROR: Status = Status(NGX_ERROR as ngx_int_t);
 pub const AGAIN: Status = Status(NGX_AGAIN as ngx_int_t);
+pub const DONE: Status = Status(NGX_DONE as ngx_int_t);
+pub const DECLINED: Status = Status(NGX_DECLINED as ngx_int_t);
+pub const BUSY: Status = Status(NGX_BUSY as ngx_int_t);
+pub const ABORT: Status = Status(NGX_ABORT as ngx_int_t);
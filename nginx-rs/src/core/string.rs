@@ -1,5 +1,10 @@
 use crate::bindings::*;
+use crate::core::metrics::count_lossy_conversion;
+use crate::core::pool::Pool;
 
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
 use std::slice;
 use std::str::{self, Utf8Error};
 use std::borrow::Cow;
@@ -13,7 +18,7 @@ use std::borrow::Cow;
 macro_rules! ngx_string {
     ($s:expr) => {
         {
-            ngx_str_t { len: $s.len(), data: concat!($s, "\0").as_ptr() as *mut u8 }
+            $crate::__private::bindings::ngx_str_t { len: $s.len(), data: concat!($s, "\0").as_ptr() as *mut u8 }
         }
     };
 }
@@ -24,7 +29,19 @@ macro_rules! ngx_string {
 #[macro_export]
 macro_rules! ngx_null_string {
     () => {
-        ngx_str_t { len: 0, data: ::std::ptr::null_mut() }
+        $crate::__private::bindings::ngx_str_t { len: 0, data: ::std::ptr::null_mut() }
+    };
+}
+
+/// Static [`NgxStr`] initializer, usable in constants.
+///
+/// ```ignore
+/// const HEADER: &NgxStr = ngx_str!("X-Request-Score");
+/// ```
+#[macro_export]
+macro_rules! ngx_str {
+    ($s:expr) => {
+        $crate::core::NgxStr::from_static_bytes($s.as_bytes())
     };
 }
 
@@ -44,6 +61,12 @@ impl NgxStr {
         slice::from_raw_parts(str.data, str.len as usize).into()
     }
 
+    /// Create an [`NgxStr`] from a static byte slice, such as with [`ngx_str!`].
+    pub const fn from_static_bytes(bytes: &'static [u8]) -> &'static NgxStr {
+        // SAFETY: An `NgxStr` is identical to a `[u8]` slice, given `u_char` is an alias for `u8`.
+        unsafe { &*(bytes as *const [u8] as *const NgxStr) }
+    }
+
     /// Access the [`NgxStr`] as a byte slice.
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
@@ -58,13 +81,66 @@ impl NgxStr {
     ///
     /// See [`String::from_utf8_lossy`].
     pub fn to_string_lossy(&self) -> Cow<str> {
-        String::from_utf8_lossy(self.as_bytes())
+        let s = String::from_utf8_lossy(self.as_bytes());
+        if let Cow::Owned(_) = s {
+            count_lossy_conversion();
+        }
+        s
     }
 
     /// Returns `true` if the [`NgxStr`] is empty, otherwise `false`.
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Compare with `other`, ignoring ASCII case, as for header names.
+    pub fn eq_ignore_ascii_case<S: AsRef<[u8]> + ?Sized>(&self, other: &S) -> bool {
+        self.0.eq_ignore_ascii_case(other.as_ref())
+    }
+
+    /// Does the string start with `prefix`?
+    pub fn starts_with<S: AsRef<[u8]> + ?Sized>(&self, prefix: &S) -> bool {
+        self.0.starts_with(prefix.as_ref())
+    }
+
+    /// Does the string end with `suffix`?
+    pub fn ends_with<S: AsRef<[u8]> + ?Sized>(&self, suffix: &S) -> bool {
+        self.0.ends_with(suffix.as_ref())
+    }
+
+    /// Does the string start with `prefix`, ignoring ASCII case?
+    pub fn starts_with_ignore_ascii_case<S: AsRef<[u8]> + ?Sized>(&self, prefix: &S) -> bool {
+        let prefix = prefix.as_ref();
+        self.0.len() >= prefix.len() && self.0[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }
+
+    /// The string without `prefix`, or `None` if it doesn't start with it.
+    pub fn strip_prefix<S: AsRef<[u8]> + ?Sized>(&self, prefix: &S) -> Option<&NgxStr> {
+        self.0.strip_prefix(prefix.as_ref()).map(Into::into)
+    }
+
+    /// The string without leading and trailing ASCII whitespace.
+    pub fn trim(&self) -> &NgxStr {
+        let start = self.0.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(self.0.len());
+        let end = self.0.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(start, |end| end + 1);
+        self.0[start..end].into()
+    }
+
+    /// Split the string on each `separator` byte, such as the `,` of a header list.
+    pub fn split(&self, separator: u8) -> impl Iterator<Item = &NgxStr> {
+        self.0.split(move |&b| b == separator).map(Into::into)
+    }
+
+    /// Split the string on the first `separator` byte, such as the `=` of a parameter.
+    pub fn split_once(&self, separator: u8) -> Option<(&NgxStr, &NgxStr)> {
+        let i = self.0.iter().position(|&b| b == separator)?;
+        Some((self.0[..i].into(), self.0[i + 1..].into()))
+    }
 }
 
 impl From<&[u8]> for &NgxStr {
@@ -96,3 +172,169 @@ impl Default for &NgxStr {
         }
     }
 }
+
+impl AsRef<NgxStr> for NgxStr {
+    fn as_ref(&self) -> &NgxStr {
+        self
+    }
+}
+
+impl PartialEq for NgxStr {
+    fn eq(&self, other: &NgxStr) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for NgxStr {}
+
+impl PartialEq<str> for NgxStr {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == *other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for NgxStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other.as_bytes()
+    }
+}
+
+impl PartialEq<[u8]> for NgxStr {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<&[u8]> for NgxStr {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.0 == **other
+    }
+}
+
+/// Invalid UTF-8 sequences are replaced, as with [`NgxStr::to_string_lossy`].
+impl fmt::Display for NgxStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Debug for NgxStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&String::from_utf8_lossy(self.as_bytes()), f)
+    }
+}
+
+/// An [Nginx string] owning a copy of Rust data, allocated from a [`Pool`].
+///
+/// This is the owned counterpart of [`NgxStr`], for values Nginx keeps after the call that
+/// sets them, such as headers, variables and complex values: the copy lives as long as the
+/// pool, such as until the end of the request, whatever becomes of the Rust data. It is the
+/// way to copy Rust data into a pool for Nginx; build longer values with a
+/// [`PString`](crate::core::PString) instead.
+///
+/// ```ignore
+/// let mut pool = request.pool();
+/// let value = NgxString::new_in(format!("{:.2}", score), &mut pool).ok_or(HTTP_INTERNAL_SERVER_ERROR)?;
+/// (*h).value = value.into_ngx_str();
+/// ```
+///
+/// [Nginx string]: https://nginx.org/en/docs/dev/development_guide.html#string_overview
+pub struct NgxString<'a> {
+    str: ngx_str_t,
+    _marker: PhantomData<&'a Pool>,
+}
+
+impl<'a> NgxString<'a> {
+    /// Copy `data` into `pool`, or return `None` if allocation fails.
+    pub fn new_in<T: AsRef<[u8]>>(data: T, pool: &'a mut Pool) -> Option<NgxString<'a>> {
+        let data = data.as_ref();
+        let copy = pool.alloc_bytes(data)?;
+        Some(NgxString { str: ngx_str_t { len: copy.len(), data: copy.as_mut_ptr() }, _marker: PhantomData })
+    }
+
+    /// The string as an [`ngx_str_t`], valid for as long as the pool.
+    pub fn as_ngx_str(&self) -> ngx_str_t {
+        self.str
+    }
+
+    /// Give the string to Nginx, as an [`ngx_str_t`] valid for as long as the pool.
+    pub fn into_ngx_str(self) -> ngx_str_t {
+        self.str
+    }
+}
+
+impl<'a> Deref for NgxString<'a> {
+    type Target = NgxStr;
+
+    fn deref(&self) -> &NgxStr {
+        // SAFETY: The data was copied into the pool, which outlives the string.
+        unsafe { NgxStr::from_ngx_str(self.str) }
+    }
+}
+
+impl<'a> AsRef<[u8]> for NgxString<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> AsRef<NgxStr> for NgxString<'a> {
+    fn as_ref(&self) -> &NgxStr {
+        self
+    }
+}
+
+impl<'a> fmt::Display for NgxString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<'a> fmt::Debug for NgxString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(s: &str) -> &NgxStr {
+        s.into()
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(s("  gzip, br\t").trim(), "gzip, br");
+        assert_eq!(s("gzip").trim(), "gzip");
+        assert_eq!(s(" \r\n ").trim(), "");
+        assert_eq!(s("").trim(), "");
+    }
+
+    #[test]
+    fn split_once() {
+        let (name, value) = s("q=0.5").split_once(b'=').unwrap();
+        assert_eq!((name, value), (s("q"), s("0.5")));
+        let (name, value) = s("a=b=c").split_once(b'=').unwrap();
+        assert_eq!((name, value), (s("a"), s("b=c")));
+        let (name, value) = s("q=").split_once(b'=').unwrap();
+        assert_eq!((name, value), (s("q"), s("")));
+        assert!(s("gzip").split_once(b'=').is_none());
+    }
+
+    #[test]
+    fn starts_with_ignore_ascii_case() {
+        assert!(s("Bearer abc").starts_with_ignore_ascii_case("bearer "));
+        assert!(s("BEARER").starts_with_ignore_ascii_case("bearer"));
+        assert!(s("abc").starts_with_ignore_ascii_case(""));
+        assert!(!s("Bear").starts_with_ignore_ascii_case("bearer"));
+        assert!(!s("Basic abc").starts_with_ignore_ascii_case("bearer"));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(s("gzip").to_string(), "gzip");
+        assert_eq!(<&NgxStr>::from(&b"a\xffb"[..]).to_string(), "a\u{fffd}b");
+    }
+}
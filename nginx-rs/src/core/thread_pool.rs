@@ -0,0 +1,129 @@
+use crate::bindings::*;
+use crate::core::status::*;
+use crate::event::oneshot;
+
+use std::future::Future;
+use std::mem;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+
+/// A pool of threads declared by the `thread_pool` directive (`ngx_thread_pool_t`), to run
+/// blocking work, such as expensive cryptography or model scoring, without stalling the
+/// event loop of the worker.
+///
+/// Requires Nginx built with `--with-threads`.
+#[derive(Clone, Copy, Debug)]
+pub struct ThreadPool(*mut ngx_thread_pool_t);
+
+impl ThreadPool {
+    /// Declare the use of the pool `name` while parsing the configuration, such as from the
+    /// handler of a directive naming it.
+    ///
+    /// A pool that is not declared by a `thread_pool` directive is created with the default
+    /// settings, as for `aio threads=name`.
+    pub unsafe fn add(cf: *mut ngx_conf_t, name: &str) -> Option<ThreadPool> {
+        let mut name = ngx_str_t { len: name.len(), data: name.as_ptr() as *mut u_char };
+        let tp = ngx_thread_pool_add(cf, &mut name);
+        if tp.is_null() {
+            return None;
+        }
+        Some(ThreadPool(tp))
+    }
+
+    /// The pool `name` of the current cycle, if it was declared.
+    pub fn get(name: &str) -> Option<ThreadPool> {
+        let mut name = ngx_str_t { len: name.len(), data: name.as_ptr() as *mut u_char };
+        let tp = unsafe { ngx_thread_pool_get(ngx_cycle as *mut ngx_cycle_t, &mut name) };
+        if tp.is_null() {
+            return None;
+        }
+        Some(ThreadPool(tp))
+    }
+
+    /// Create a [`ThreadPool`] from an [`ngx_thread_pool_t`].
+    pub unsafe fn from_ngx_thread_pool(tp: *mut ngx_thread_pool_t) -> ThreadPool {
+        ThreadPool(tp)
+    }
+
+    /// Pointer to the [`ngx_thread_pool_t`].
+    pub fn as_ngx_thread_pool(&self) -> *mut ngx_thread_pool_t {
+        self.0
+    }
+
+    /// Run `task` on a thread of the pool, then `completion` with its result on the event
+    /// loop, or with `None` if the task panicked.
+    ///
+    /// `task` must not call into Nginx, which is not thread-safe: only the data moved into
+    /// it is safe to use. Returns [`ERROR`] if the queue of the pool is full, as set by the
+    /// `max_queue` parameter of `thread_pool`, in which case neither closure is called.
+    pub fn spawn<R, T, C>(&self, task: T, completion: C) -> Status
+    where
+        R: Send + 'static,
+        T: FnOnce() -> R + Send + 'static,
+        C: FnOnce(Option<R>) + 'static,
+    {
+        unsafe {
+            let ctx = Box::into_raw(Box::new(TaskCtx {
+                task: mem::zeroed(),
+                work: Some(Box::new(task)),
+                result: None,
+                completion: Some(Box::new(completion)),
+            }));
+            (*ctx).task.ctx = ctx as *mut c_void;
+            (*ctx).task.handler = Some(ngx_rs_thread_task_handler::<R>);
+            (*ctx).task.event.data = ctx as *mut c_void;
+            (*ctx).task.event.handler = Some(ngx_rs_thread_task_completion::<R>);
+            (*ctx).task.event.log = (*ngx_cycle).log;
+
+            if ngx_thread_task_post(self.0, &mut (*ctx).task) != NGX_OK as ngx_int_t {
+                drop(Box::from_raw(ctx));
+                return ERROR;
+            }
+        }
+        OK
+    }
+
+    /// Run `task` on a thread of the pool, resolving to its result, or to `None` if it
+    /// panicked or the queue of the pool is full. See [`ThreadPool::spawn`].
+    pub fn run<R, T>(&self, task: T) -> impl Future<Output = Option<R>>
+    where
+        R: Send + 'static,
+        T: FnOnce() -> R + Send + 'static,
+    {
+        let (tx, rx) = oneshot();
+        // On failure the sender is dropped, which resolves the receiver.
+        self.spawn(task, move |result| {
+            if let Some(result) = result {
+                tx.send(result);
+            }
+        });
+        async move { rx.await }
+    }
+}
+
+// Heap-allocated, as the task outlives the caller.
+struct TaskCtx<R> {
+    task: ngx_thread_task_t,
+    // Only touched by the pool thread, until the completion event.
+    work: Option<Box<dyn FnOnce() -> R + Send>>,
+    result: Option<R>,
+    completion: Option<Box<dyn FnOnce(Option<R>)>>,
+}
+
+// Runs on a thread of the pool.
+unsafe extern "C" fn ngx_rs_thread_task_handler<R>(data: *mut c_void, _log: *mut ngx_log_t) {
+    let ctx = data as *mut TaskCtx<R>;
+    if let Some(work) = (*ctx).work.take() {
+        // A panic must not unwind into the thread pool.
+        (*ctx).result = panic::catch_unwind(AssertUnwindSafe(work)).ok();
+    }
+}
+
+// Runs on the event loop once the task is done.
+unsafe extern "C" fn ngx_rs_thread_task_completion<R>(ev: *mut ngx_event_t) {
+    let ctx = Box::from_raw((*ev).data as *mut TaskCtx<R>);
+    let TaskCtx { result, completion, .. } = *ctx;
+    if let Some(completion) = completion {
+        completion(result);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::bindings::*;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A time interval in milliseconds, such as a timeout.
+///
+/// Parsed from the Nginx [time syntax] (`500ms`, `10s`, `1h 30m`; a bare number counts as
+/// seconds), as used by directives like `proxy_read_timeout`. Directive fields of this type
+/// are set by the `msec` slot of [`ngx_commands!`](crate::ngx_commands).
+///
+/// [time syntax]: https://nginx.org/en/docs/syntax.html
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Msec(ngx_msec_t);
+
+impl Msec {
+    /// An interval of `msec` milliseconds.
+    pub const fn from_msec(msec: ngx_msec_t) -> Msec {
+        Msec(msec)
+    }
+
+    /// An interval of `secs` seconds.
+    /// An interval of `secs` seconds, saturating at the longest interval.
+    pub const fn from_secs(secs: ngx_msec_t) -> Msec {
+        Msec(secs.saturating_mul(1000))
+    }
+
+    /// The interval in milliseconds, as taken by timers.
+    pub const fn as_msec(self) -> ngx_msec_t {
+        self.0
+    }
+
+    /// The interval as a [`Duration`].
+    pub fn as_duration(self) -> Duration {
+        Duration::from_millis(self.0 as u64)
+    }
+
+    /// Parse an interval, as `ngx_parse_time` does.
+    pub fn parse(value: &str) -> Result<Msec, String> {
+        parse_time(value, false).map(|msec| Msec(msec as ngx_msec_t))
+    }
+}
+
+impl From<Msec> for ngx_msec_t {
+    fn from(msec: Msec) -> ngx_msec_t {
+        msec.0
+    }
+}
+
+impl From<Msec> for Duration {
+    fn from(msec: Msec) -> Duration {
+        msec.as_duration()
+    }
+}
+
+impl FromStr for Msec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Msec, String> {
+        Msec::parse(value)
+    }
+}
+
+impl fmt::Display for Msec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 != 0 && self.0 % 1000 == 0 {
+            write!(f, "{}s", self.0 / 1000)
+        } else {
+            write!(f, "{}ms", self.0)
+        }
+    }
+}
+
+/// A time interval in seconds, such as an expiry time.
+///
+/// Parsed from the Nginx [time syntax] (`30s`, `1h`, `7d`; a bare number counts as seconds)
+/// and rejecting millisecond precision. Directive fields of this type are set by the `sec`
+/// slot of [`ngx_commands!`](crate::ngx_commands).
+///
+/// [time syntax]: https://nginx.org/en/docs/syntax.html
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Sec(time_t);
+
+impl Sec {
+    /// An interval of `secs` seconds.
+    pub const fn from_secs(secs: time_t) -> Sec {
+        Sec(secs)
+    }
+
+    /// The interval in seconds.
+    pub const fn as_secs(self) -> time_t {
+        self.0
+    }
+
+    /// The interval in milliseconds, saturating at the longest interval. Negative intervals
+    /// are empty.
+    pub const fn as_msec(self) -> Msec {
+        if self.0 < 0 {
+            return Msec(0);
+        }
+        Msec((self.0 as ngx_msec_t).saturating_mul(1000))
+    }
+
+    /// The interval as a [`Duration`].
+    pub fn as_duration(self) -> Duration {
+        Duration::from_secs(self.0.max(0) as u64)
+    }
+
+    /// Parse an interval, as `ngx_parse_time` does.
+    pub fn parse(value: &str) -> Result<Sec, String> {
+        parse_time(value, true).map(|secs| Sec(secs as time_t))
+    }
+}
+
+impl From<Sec> for time_t {
+    fn from(sec: Sec) -> time_t {
+        sec.0
+    }
+}
+
+impl From<Sec> for Msec {
+    fn from(sec: Sec) -> Msec {
+        sec.as_msec()
+    }
+}
+
+impl FromStr for Sec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Sec, String> {
+        Sec::parse(value)
+    }
+}
+
+impl fmt::Display for Sec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}s", self.0)
+    }
+}
+
+/// A size in bytes, such as a buffer size or a body limit.
+///
+/// Parsed from the Nginx [size syntax] (`512`, `16k`, `1m`), as used by directives like
+/// `client_max_body_size`. Directive fields of this type are set by the `size` slot of
+/// [`ngx_commands!`](crate::ngx_commands).
+///
+/// [size syntax]: https://nginx.org/en/docs/syntax.html
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ByteSize(usize);
+
+impl ByteSize {
+    /// A size of `bytes` bytes.
+    pub const fn from_bytes(bytes: usize) -> ByteSize {
+        ByteSize(bytes)
+    }
+
+    /// A size of `kib` kilobytes (1024 bytes), as `k` in the size syntax.
+    pub const fn from_kib(kib: usize) -> ByteSize {
+        ByteSize(kib * 1024)
+    }
+
+    /// A size of `mib` megabytes (1024 kilobytes), as `m` in the size syntax.
+    pub const fn from_mib(mib: usize) -> ByteSize {
+        ByteSize(mib * 1024 * 1024)
+    }
+
+    /// The size in bytes.
+    pub const fn as_bytes(self) -> usize {
+        self.0
+    }
+
+    /// Parse a size, as `ngx_parse_size` does.
+    pub fn parse(value: &str) -> Result<ByteSize, String> {
+        let invalid = || format!("invalid size \"{}\"", value);
+        let (digits, scale) = match value.as_bytes().last() {
+            Some(b'k') | Some(b'K') => (&value[..value.len() - 1], 1 << 10),
+            Some(b'm') | Some(b'M') => (&value[..value.len() - 1], 1 << 20),
+            _ => (value, 1),
+        };
+        let size = parse_digits(digits.as_bytes()).ok_or_else(invalid)?;
+        // Sizes are signed in Nginx.
+        match size.checked_mul(scale) {
+            Some(size) if size <= isize::MAX as u64 => Ok(ByteSize(size as usize)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl From<ByteSize> for usize {
+    fn from(size: ByteSize) -> usize {
+        size.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ByteSize, String> {
+        ByteSize::parse(value)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: usize = 1024 * 1024;
+        if self.0 != 0 && self.0 % MIB == 0 {
+            write!(f, "{}m", self.0 / MIB)
+        } else if self.0 != 0 && self.0 % 1024 == 0 {
+            write!(f, "{}k", self.0 / 1024)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+// A non-empty run of decimal digits.
+fn parse_digits(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, &c| {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        value.checked_mul(10)?.checked_add(u64::from(c - b'0'))
+    })
+}
+
+// Steps of the time parser, in the order units must appear.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Step {
+    Start,
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Min,
+    Sec,
+    Msec,
+    Last,
+}
+
+// The time syntax of Nginx, parsed as `ngx_parse_time` does: numbers with units from years
+// down to milliseconds, each used at most once and in decreasing order, optionally
+// separated by spaces. A number without a unit counts as seconds, whether it is last or
+// followed by a space. With `is_sec`, the result is in seconds and milliseconds are not
+// allowed; otherwise it is in milliseconds and years and months are not allowed.
+fn parse_time(value: &str, is_sec: bool) -> Result<i64, String> {
+    let invalid = || format!("invalid time interval \"{}\"", value);
+
+    let mut rest = value.as_bytes();
+    let mut step = if is_sec { Step::Start } else { Step::Month };
+    let mut valid = false;
+    let mut number: i64 = 0;
+    let mut total: i64 = 0;
+    while let Some((&c, tail)) = rest.split_first() {
+        rest = tail;
+        if c.is_ascii_digit() {
+            number = number.checked_mul(10).and_then(|n| n.checked_add(i64::from(c - b'0'))).ok_or_else(invalid)?;
+            valid = true;
+            continue;
+        }
+
+        // Units and their length in seconds.
+        let (unit, scale) = match c {
+            b'y' if step == Step::Start => (Step::Year, 365 * 24 * 60 * 60),
+            b'M' if step < Step::Month => (Step::Month, 30 * 24 * 60 * 60),
+            b'w' if step < Step::Week => (Step::Week, 7 * 24 * 60 * 60),
+            b'd' if step < Step::Day => (Step::Day, 24 * 60 * 60),
+            b'h' if step < Step::Hour => (Step::Hour, 60 * 60),
+            b'm' if rest.first() == Some(&b's') => {
+                if is_sec || step >= Step::Msec {
+                    return Err(invalid());
+                }
+                rest = &rest[1..];
+                (Step::Msec, 1)
+            }
+            b'm' if step < Step::Min => (Step::Min, 60),
+            b's' if step < Step::Sec => (Step::Sec, 1),
+            b' ' if step < Step::Sec => (Step::Last, 1),
+            _ => return Err(invalid()),
+        };
+        step = unit;
+
+        let scale = if is_sec || step == Step::Msec { scale } else { scale * 1000 };
+        total = number.checked_mul(scale).and_then(|value| total.checked_add(value)).ok_or_else(invalid)?;
+        number = 0;
+        while let Some((b' ', tail)) = rest.split_first() {
+            rest = tail;
+        }
+    }
+    if !valid {
+        return Err(invalid());
+    }
+
+    // A number left at the end counts as seconds.
+    let scale = if is_sec { 1 } else { 1000 };
+    number.checked_mul(scale).and_then(|value| total.checked_add(value)).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msec() {
+        assert_eq!(Msec::parse("500ms"), Ok(Msec::from_msec(500)));
+        assert_eq!(Msec::parse("10"), Ok(Msec::from_secs(10)));
+        assert_eq!(Msec::parse("10s"), Ok(Msec::from_secs(10)));
+        assert_eq!(Msec::parse("2m"), Ok(Msec::from_secs(120)));
+        assert_eq!(Msec::parse("1h 30m"), Ok(Msec::from_secs(5400)));
+        assert_eq!(Msec::parse("1h30m"), Ok(Msec::from_secs(5400)));
+        assert_eq!(Msec::parse("1d"), Ok(Msec::from_secs(86400)));
+        assert_eq!(Msec::parse("1w"), Ok(Msec::from_secs(7 * 86400)));
+        assert_eq!(Msec::parse("1s 500ms"), Ok(Msec::from_msec(1500)));
+        assert_eq!(Msec::parse("1m 5"), Ok(Msec::from_secs(65)));
+        assert_eq!(Msec::parse("1m5"), Ok(Msec::from_secs(65)));
+        assert_eq!(Msec::parse("1s5"), Ok(Msec::from_secs(6)));
+        assert_eq!(Msec::parse("1 2"), Ok(Msec::from_secs(3)));
+        assert_eq!(Msec::parse("500ms 1"), Ok(Msec::from_msec(1500)));
+        assert_eq!(Msec::parse("1h "), Ok(Msec::from_secs(3600)));
+    }
+
+    #[test]
+    fn sec() {
+        assert_eq!(Sec::parse("30"), Ok(Sec::from_secs(30)));
+        assert_eq!(Sec::parse("30s"), Ok(Sec::from_secs(30)));
+        assert_eq!(Sec::parse("1h"), Ok(Sec::from_secs(3600)));
+        assert_eq!(Sec::parse("7d"), Ok(Sec::from_secs(7 * 86400)));
+        assert_eq!(Sec::parse("1M"), Ok(Sec::from_secs(30 * 86400)));
+        assert_eq!(Sec::parse("1y"), Ok(Sec::from_secs(365 * 86400)));
+        assert!(Sec::parse("500ms").is_err());
+        assert!(Sec::parse("1m 1ms").is_err());
+    }
+
+    #[test]
+    fn time_invalid() {
+        for value in &["", " ", "s", "10x", "1.5s", "-1s", " 1s", "1s 1h", "1h 1h", "1s 2s", "1 2s", "1ms 1ms", "1y", "1M"] {
+            assert!(Msec::parse(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn time_overflow() {
+        assert!(Msec::parse("9223372036854775807ms").is_ok());
+        assert!(Msec::parse("9223372036854775808ms").is_err());
+        assert!(Msec::parse("9223372036854775807s").is_err());
+        assert!(Msec::parse("99999999999999999999999").is_err());
+        assert!(Sec::parse("9223372036854775807").is_ok());
+        assert!(Sec::parse("9223372036854775807s 1s").is_err());
+    }
+
+    #[test]
+    fn byte_size() {
+        assert_eq!(ByteSize::parse("512"), Ok(ByteSize::from_bytes(512)));
+        assert_eq!(ByteSize::parse("16k"), Ok(ByteSize::from_kib(16)));
+        assert_eq!(ByteSize::parse("16K"), Ok(ByteSize::from_kib(16)));
+        assert_eq!(ByteSize::parse("1m"), Ok(ByteSize::from_mib(1)));
+        for value in &["", "k", "1.5m", "-1", "1 k", "1g", "1t", "16kb"] {
+            assert!(ByteSize::parse(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn byte_size_overflow() {
+        assert!(ByteSize::parse("99999999999999999999999").is_err());
+        assert!(ByteSize::parse("9223372036854775807k").is_err());
+        assert!(ByteSize::parse(&(isize::MAX as usize).to_string()).is_ok());
+    }
+
+    #[test]
+    fn to_msec_saturates() {
+        assert_eq!(Msec::from_secs(ngx_msec_t::MAX).as_msec(), ngx_msec_t::MAX);
+        assert_eq!(Sec::from_secs(time_t::MAX).as_msec().as_msec(), ngx_msec_t::MAX);
+        assert_eq!(Sec::from_secs(-1).as_msec(), Msec::from_msec(0));
+        assert_eq!(Sec::from_secs(2).as_msec(), Msec::from_secs(2));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Msec::from_msec(1500).to_string(), "1500ms");
+        assert_eq!(Msec::from_secs(2).to_string(), "2s");
+        assert_eq!(ByteSize::from_kib(16).to_string(), "16k");
+        assert_eq!(ByteSize::from_mib(1).to_string(), "1m");
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Watches a data file (blocklist, keys, ...) for changes by comparing its metadata.
+///
+/// [`FileWatch::changed`] is cheap enough to be called from a timer or periodically from
+/// request handlers. Where the platform supports it, a [`FileNotifier`] can be used to be told
+/// about changes as soon as they happen instead.
+pub struct FileWatch {
+    path: PathBuf,
+    state: Option<FileState>,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct FileState {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileWatch {
+    /// Watch the file at `path`. The current state of the file is recorded immediately.
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileWatch {
+        let path = path.into();
+        let state = Self::stat(&path);
+        FileWatch { path, state }
+    }
+
+    /// Path of the watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Has the file changed (been modified, replaced, created or removed) since the last call?
+    pub fn changed(&mut self) -> bool {
+        let state = Self::stat(&self.path);
+        if state == self.state {
+            return false;
+        }
+
+        self.state = state;
+        true
+    }
+
+    /// Does the watched file currently exist?
+    pub fn exists(&self) -> bool {
+        self.state.is_some()
+    }
+
+    fn stat(path: &Path) -> Option<FileState> {
+        let meta = fs::metadata(path).ok()?;
+        Some(FileState {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::inotify::FileNotifier;
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use crate::bindings::*;
+
+    use std::ffi::{CString, OsStr, OsString};
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    struct NotifierState {
+        path: PathBuf,
+        name: OsString,
+        callback: Box<dyn FnMut(&Path)>,
+    }
+
+    /// Native change notification for a file, driven by the Nginx event loop (Linux `inotify`).
+    ///
+    /// The parent directory is watched, so files replaced by renaming over them (the usual way
+    /// of atomically updating data files) are detected. The callback is called at most once per
+    /// batch of events. Notifiers must be created in worker processes, typically from
+    /// `init_process`, with the event loop running. Dropping the notifier stops watching.
+    pub struct FileNotifier {
+        connection: *mut ngx_connection_t,
+        state: *mut NotifierState,
+    }
+
+    impl FileNotifier {
+        /// Call `callback` whenever the file at `path` changes.
+        pub fn new<P, F>(path: P, callback: F) -> io::Result<FileNotifier>
+        where
+            P: Into<PathBuf>,
+            F: FnMut(&Path) + 'static,
+        {
+            let path = path.into();
+            let name = path.file_name().map(OsStr::to_os_string)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let dir = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))?;
+
+            unsafe {
+                let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+                if fd == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM;
+                if libc::inotify_add_watch(fd, dir.as_ptr(), mask) == -1 {
+                    let err = io::Error::last_os_error();
+                    libc::close(fd);
+                    return Err(err);
+                }
+
+                let log = (*ngx_cycle).log;
+                let c = ngx_get_connection(fd, log);
+                if c.is_null() {
+                    libc::close(fd);
+                    return Err(io::Error::new(io::ErrorKind::Other, "no free connections"));
+                }
+
+                let state = Box::into_raw(Box::new(NotifierState { path, name, callback: Box::new(callback) }));
+
+                (*c).data = state as *mut _;
+                (*(*c).read).handler = Some(ngx_rs_file_notifier_handler);
+                (*(*c).read).log = log;
+
+                if ngx_handle_read_event((*c).read, 0) != NGX_OK as ngx_int_t {
+                    ngx_close_connection(c);
+                    drop(Box::from_raw(state));
+                    return Err(io::Error::new(io::ErrorKind::Other, "failed to add inotify read event"));
+                }
+
+                Ok(FileNotifier { connection: c, state })
+            }
+        }
+    }
+
+    impl Drop for FileNotifier {
+        fn drop(&mut self) {
+            unsafe {
+                // Closes the inotify descriptor as well.
+                ngx_close_connection(self.connection);
+                drop(Box::from_raw(self.state));
+            }
+        }
+    }
+
+    unsafe extern "C" fn ngx_rs_file_notifier_handler(rev: *mut ngx_event_t) {
+        let c = (*rev).data as *mut ngx_connection_t;
+        let state = &mut *((*c).data as *mut NotifierState);
+
+        // Drain all pending events; the descriptor is registered edge-triggered where possible.
+        let mut changed = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = libc::read((*c).fd, buf.as_mut_ptr() as *mut _, buf.len());
+            if n <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= n as usize {
+                let event = std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event);
+                let name_start = offset + mem::size_of::<libc::inotify_event>();
+                let name = &buf[name_start..name_start + event.len as usize];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                if name == state.name.as_bytes() {
+                    changed = true;
+                }
+                offset = name_start + event.len as usize;
+            }
+        }
+
+        if changed {
+            (state.callback)(&state.path);
+        }
+
+        if ngx_handle_read_event(rev, 0) != NGX_OK as ngx_int_t {
+            ngx_log_error_core(NGX_LOG_ALERT as ngx_uint_t, (*c).log, 0,
+                b"failed to re-arm file notification event\0".as_ptr() as *const _);
+        }
+    }
+}
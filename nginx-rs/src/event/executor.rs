@@ -0,0 +1,341 @@
+use crate::bindings::*;
+use crate::core::units::Msec;
+use crate::event::posted::ngx_post_event;
+use crate::event::timer::{ngx_add_timer, ngx_del_timer};
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::mem;
+use std::os::raw::c_void;
+use std::pin::Pin;
+use std::ptr;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+struct Task {
+    // Taken out while the task is polled, so it can spawn or wake tasks.
+    future: RefCell<Option<LocalFuture>>,
+    queued: Cell<bool>,
+}
+
+struct Executor {
+    queue: RefCell<VecDeque<Rc<Task>>>,
+    // Posted to run the queue, for the life of the worker process.
+    event: Cell<*mut ngx_event_t>,
+    running: Cell<bool>,
+}
+
+thread_local! {
+    static EXECUTOR: Executor = Executor {
+        queue: RefCell::new(VecDeque::new()),
+        event: Cell::new(ptr::null_mut()),
+        running: Cell::new(false),
+    };
+}
+
+/// Run `future` on the event loop of the worker process, returning a handle to await its
+/// output.
+///
+/// This is a single-threaded executor driven by Nginx events: tasks are polled from a posted
+/// event when woken, by timers, I/O callbacks or other tasks. A task that yields, with
+/// [`yield_now`], runs again after pending I/O is handled, so long computations split at
+/// yield points don't starve other connections.
+///
+/// Futures must not block, and wakers must only be used on the thread of the event loop.
+/// Awaitable adapters are provided for timers ([`sleep`]), thread pools
+/// (`ThreadPool::run`), DNS lookups (`Request::resolve_name_async`) and HTTP requests
+/// (`Request::fetch_async`), and [`oneshot`] turns other callback APIs into futures.
+///
+/// ```ignore
+/// spawn_local(async move {
+///     loop {
+///         refresh_blocklist().await;
+///         sleep(Msec::from_secs(60)).await;
+///     }
+/// });
+/// ```
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    let state = Rc::new(RefCell::new(JoinState { output: None, waker: None }));
+    let task_state = state.clone();
+    let task = Rc::new(Task {
+        future: RefCell::new(Some(Box::pin(async move {
+            let output = future.await;
+            let waker = {
+                let mut state = task_state.borrow_mut();
+                state.output = Some(output);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }))),
+        queued: Cell::new(false),
+    });
+    schedule(task);
+    JoinHandle { state }
+}
+
+fn schedule(task: Rc<Task>) {
+    if task.queued.replace(true) {
+        return;
+    }
+    EXECUTOR.with(|executor| {
+        executor.queue.borrow_mut().push_back(task);
+        unsafe {
+            let mut ev = executor.event.get();
+            if ev.is_null() {
+                ev = Box::leak(Box::new(mem::zeroed()));
+                (*ev).handler = Some(ngx_rs_executor_handler);
+                (*ev).log = (*ngx_cycle).log;
+                executor.event.set(ev);
+            }
+            // Tasks woken while running, such as by yielding, run on the next iteration of
+            // the event loop.
+            if executor.running.get() {
+                ngx_post_event(ev, ptr::addr_of_mut!(ngx_posted_next_events));
+            } else {
+                ngx_post_event(ev, ptr::addr_of_mut!(ngx_posted_events));
+            }
+        }
+    });
+}
+
+unsafe extern "C" fn ngx_rs_executor_handler(_ev: *mut ngx_event_t) {
+    // Only run the tasks queued so far; tasks woken meanwhile are posted again.
+    let tasks = EXECUTOR.with(|executor| {
+        executor.running.set(true);
+        mem::take(&mut *executor.queue.borrow_mut())
+    });
+
+    for task in tasks {
+        task.queued.set(false);
+        let future = task.future.borrow_mut().take();
+        if let Some(mut future) = future {
+            let waker = waker(task.clone());
+            let mut cx = Context::from_waker(&waker);
+            if future.as_mut().poll(&mut cx).is_pending() {
+                *task.future.borrow_mut() = Some(future);
+            }
+        }
+    }
+
+    EXECUTOR.with(|executor| executor.running.set(false));
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+fn waker(task: Rc<Task>) -> Waker {
+    let raw = RawWaker::new(Rc::into_raw(task) as *const (), &VTABLE);
+    // SAFETY: The waker is only used on the thread of the event loop.
+    unsafe { Waker::from_raw(raw) }
+}
+
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+    Rc::increment_strong_count(data as *const Task);
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn waker_wake(data: *const ()) {
+    schedule(Rc::from_raw(data as *const Task));
+}
+
+unsafe fn waker_wake_by_ref(data: *const ()) {
+    Rc::increment_strong_count(data as *const Task);
+    schedule(Rc::from_raw(data as *const Task));
+}
+
+unsafe fn waker_drop(data: *const ()) {
+    drop(Rc::from_raw(data as *const Task));
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Handle of a task started with [`spawn_local`], resolving to its output.
+///
+/// Dropping the handle doesn't cancel the task.
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Has the task completed?
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().output.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Yield to the event loop, letting pending I/O and other tasks run before the task
+/// continues.
+pub fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/// Future of [`yield_now`].
+pub struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+struct SleepState {
+    event: ngx_event_t,
+    waker: Option<Waker>,
+    fired: bool,
+}
+
+/// Wait for `duration`, with an Nginx timer.
+///
+/// The timer is cancelable, so a pending sleep doesn't delay the graceful shutdown of the
+/// worker, but it then never completes.
+pub fn sleep(duration: Msec) -> Sleep {
+    Sleep { duration, state: None }
+}
+
+/// Future of [`sleep`]. Dropping it cancels the timer.
+pub struct Sleep {
+    duration: Msec,
+    // Boxed, as the timer refers to it.
+    state: Option<Box<SleepState>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let duration = self.duration;
+        let state = self.state.get_or_insert_with(|| unsafe {
+            let mut state = Box::new(SleepState { event: mem::zeroed(), waker: None, fired: false });
+            let data = &mut *state as *mut SleepState as *mut c_void;
+            state.event.handler = Some(ngx_rs_sleep_handler);
+            state.event.data = data;
+            state.event.log = (*ngx_cycle).log;
+            state.event.set_cancelable(1);
+            ngx_add_timer(&mut state.event, duration.as_msec());
+            state
+        });
+        if state.fired {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.as_mut() {
+            if state.event.timer_set() != 0 {
+                unsafe { ngx_del_timer(&mut state.event) };
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_sleep_handler(ev: *mut ngx_event_t) {
+    let state = &mut *((*ev).data as *mut SleepState);
+    state.fired = true;
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+struct OneshotState<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// A channel carrying a single value from a callback to a task, to await callback-based
+/// APIs.
+///
+/// The receiver resolves to the value, or to `None` if the sender is dropped without
+/// sending.
+///
+/// ```ignore
+/// let (tx, rx) = oneshot();
+/// watcher.on_change(move |path| tx.send(path.to_path_buf()));
+/// let path = rx.await;
+/// ```
+pub fn oneshot<T>() -> (OneshotSender<T>, OneshotReceiver<T>) {
+    let state = Rc::new(RefCell::new(OneshotState { value: None, waker: None, closed: false }));
+    (OneshotSender { state: state.clone() }, OneshotReceiver { state })
+}
+
+/// Sending side of a [`oneshot`] channel.
+pub struct OneshotSender<T> {
+    state: Rc<RefCell<OneshotState<T>>>,
+}
+
+impl<T> OneshotSender<T> {
+    /// Send the value, waking the task awaiting the receiver.
+    pub fn send(self, value: T) {
+        self.state.borrow_mut().value = Some(value);
+    }
+}
+
+impl<T> Drop for OneshotSender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.borrow_mut();
+            state.closed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Receiving side of a [`oneshot`] channel.
+pub struct OneshotReceiver<T> {
+    state: Rc<RefCell<OneshotState<T>>>,
+}
+
+impl<T> Future for OneshotReceiver<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut state = self.state.borrow_mut();
+        if let Some(value) = state.value.take() {
+            return Poll::Ready(Some(value));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
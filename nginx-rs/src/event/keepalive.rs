@@ -0,0 +1,246 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::timer::*;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::slice;
+
+/// `MSG_PEEK`, which has the same value on all platforms Nginx supports.
+const MSG_PEEK: c_int = 2;
+
+/// A cache of idle outbound connections, to reuse connections to a peer instead of opening a
+/// new one (and doing a new TLS handshake) for each request.
+///
+/// This is the mechanism of the `keepalive` directive of upstream blocks: once a request is
+/// done with a connection that can be reused, it is [released](ConnectionPool::release) to
+/// the pool, and the next [`ConnectionPool::get`] for the same address takes it. Idle
+/// connections are closed after `timeout`, when the peer closes them or sends unexpected
+/// data, and when there are more than `max_idle` of them, oldest first.
+///
+/// A pool is local to the worker process. [`Balancer`](crate::http::upstream::Balancer)s use
+/// one by returning it from [`Balancer::keepalive`](crate::http::upstream::Balancer::keepalive),
+/// and [`ClientRequest`](crate::http::ClientRequest)s with
+/// [`ClientRequest::set_keepalive`](crate::http::ClientRequest::set_keepalive).
+pub struct ConnectionPool {
+    max_idle: usize,
+    timeout: Msec,
+    idle: RefCell<Vec<Box<Idle>>>,
+}
+
+struct Idle {
+    pool: *const ConnectionPool,
+    connection: *mut ngx_connection_t,
+    sockaddr: ngx_sockaddr_t,
+    socklen: socklen_t,
+}
+
+impl Idle {
+    fn address(&self) -> &[u8] {
+        let len = (self.socklen as usize).min(mem::size_of::<ngx_sockaddr_t>());
+        unsafe { slice::from_raw_parts(&self.sockaddr as *const ngx_sockaddr_t as *const u8, len) }
+    }
+}
+
+unsafe fn peer_address<'a>(pc: *const ngx_peer_connection_t) -> &'a [u8] {
+    if (*pc).sockaddr.is_null() {
+        return &[];
+    }
+    slice::from_raw_parts((*pc).sockaddr as *const u8, (*pc).socklen as usize)
+}
+
+impl ConnectionPool {
+    /// Create a pool keeping up to `max_idle` idle connections for up to `timeout` each.
+    ///
+    /// The pool is boxed as cached connections refer to it: it must outlive them, and closes
+    /// them when dropped.
+    pub fn new(max_idle: usize, timeout: Msec) -> Box<ConnectionPool> {
+        Box::new(ConnectionPool { max_idle, timeout, idle: RefCell::new(Vec::new()) })
+    }
+
+    /// Number of idle connections.
+    pub fn len(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Is the pool empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take an idle connection to the address of `pc` (`pc.sockaddr`), if any.
+    ///
+    /// On success, the connection is set as `pc.connection` with `pc.cached` set, and the
+    /// caller sets its event handlers. An upstream `get` handler then returns `NGX_DONE`.
+    pub unsafe fn get(&self, pc: *mut ngx_peer_connection_t) -> bool {
+        let item = {
+            let mut idle = self.idle.borrow_mut();
+            let address = peer_address(pc);
+            match idle.iter().rposition(|item| item.address() == address) {
+                Some(index) => idle.remove(index),
+                None => return false,
+            }
+        };
+
+        let c = item.connection;
+        if (*(*c).read).timer_set() != 0 {
+            ngx_del_timer((*c).read);
+        }
+        (*c).set_idle(0);
+        (*c).sent = 0;
+        (*c).data = std::ptr::null_mut();
+        (*c).log = (*pc).log;
+        (*(*c).read).log = (*pc).log;
+        (*(*c).write).log = (*pc).log;
+        if !(*c).pool.is_null() {
+            (*(*c).pool).log = (*pc).log;
+        }
+
+        (*pc).connection = c;
+        (*pc).set_cached(1);
+        true
+    }
+
+    /// Cache the connection of `pc` once a request is done with it.
+    ///
+    /// `reusable` tells if the protocol allows reusing the connection, such as when the
+    /// response was read in full without `Connection: close`. Returns `true` if the
+    /// connection was cached, in which case `pc.connection` is cleared; otherwise the caller
+    /// closes it as usual.
+    pub unsafe fn release(&self, pc: *mut ngx_peer_connection_t, state: ngx_uint_t, reusable: bool) -> bool {
+        let c = (*pc).connection;
+        if c.is_null() || !reusable || state & NGX_PEER_FAILED as ngx_uint_t != 0 || self.max_idle == 0 {
+            return false;
+        }
+        if ngx_terminate != 0 || ngx_exiting != 0 {
+            return false;
+        }
+
+        let (rev, wev) = ((*c).read, (*c).write);
+        if (*rev).eof() != 0 || (*rev).error() != 0 || (*rev).timedout() != 0 || (*wev).error() != 0 || (*wev).timedout() != 0 {
+            return false;
+        }
+        if ngx_handle_read_event(rev, 0) != NGX_OK as ngx_int_t {
+            return false;
+        }
+
+        if (*rev).timer_set() != 0 {
+            ngx_del_timer(rev);
+        }
+        if (*wev).timer_set() != 0 {
+            ngx_del_timer(wev);
+        }
+
+        let evicted = {
+            let idle = self.idle.borrow();
+            if idle.len() >= self.max_idle {
+                Some(&*idle[0] as *const Idle as *mut Idle)
+            } else {
+                None
+            }
+        };
+        if let Some(item) = evicted {
+            self.close(item);
+        }
+
+        let mut item = Box::new(Idle { pool: self, connection: c, sockaddr: mem::zeroed(), socklen: 0 });
+        let address = peer_address(pc);
+        let len = address.len().min(mem::size_of::<ngx_sockaddr_t>());
+        std::ptr::copy_nonoverlapping(address.as_ptr(), &mut item.sockaddr as *mut ngx_sockaddr_t as *mut u8, len);
+        item.socklen = len as socklen_t;
+
+        (*wev).handler = Some(ngx_rs_keepalive_dummy_handler);
+        (*rev).handler = Some(ngx_rs_keepalive_close_handler);
+        (*c).data = &mut *item as *mut Idle as *mut c_void;
+        (*c).set_idle(1);
+        (*c).log = (*ngx_cycle).log;
+        (*rev).log = (*ngx_cycle).log;
+        (*wev).log = (*ngx_cycle).log;
+        if !(*c).pool.is_null() {
+            (*(*c).pool).log = (*ngx_cycle).log;
+        }
+        ngx_add_timer(rev, self.timeout.as_msec());
+
+        self.idle.borrow_mut().push(item);
+        (*pc).connection = std::ptr::null_mut();
+
+        // The peer may already have closed the connection, or sent data.
+        if (*rev).ready() != 0 {
+            ngx_rs_keepalive_close_handler(rev);
+        }
+        true
+    }
+
+    unsafe fn close(&self, item: *mut Idle) {
+        let item = {
+            let mut idle = self.idle.borrow_mut();
+            match idle.iter().position(|i| &**i as *const Idle == item as *const Idle) {
+                Some(index) => idle.remove(index),
+                None => return,
+            }
+        };
+
+        close_connection(item.connection);
+    }
+}
+
+// Close an idle connection, as `ngx_http_upstream_keepalive_close` does: TLS is shut down
+// without waiting for the peer, so its session can be reused.
+unsafe extern "C" fn close_connection(c: *mut ngx_connection_t) {
+    #[cfg(feature = "ssl")]
+    if !(*c).ssl.is_null() {
+        (*(*c).ssl).set_no_wait_shutdown(1);
+        (*(*c).ssl).set_no_send_shutdown(1);
+        if ngx_ssl_shutdown(c) == NGX_AGAIN as ngx_int_t {
+            (*(*c).ssl).handler = Some(close_connection);
+            return;
+        }
+    }
+
+    if !(*c).pool.is_null() {
+        ngx_destroy_pool((*c).pool);
+    }
+    ngx_close_connection(c);
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("max_idle", &self.max_idle)
+            .field("timeout", &self.timeout)
+            .field("idle", &self.len())
+            .finish()
+    }
+}
+
+impl Drop for ConnectionPool {
+    fn drop(&mut self) {
+        let items: Vec<*mut Idle> = self.idle.borrow_mut().iter_mut().map(|item| &mut **item as *mut Idle).collect();
+        for item in items {
+            unsafe { self.close(item) };
+        }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_keepalive_dummy_handler(_ev: *mut ngx_event_t) {}
+
+unsafe extern "C" fn ngx_rs_keepalive_close_handler(ev: *mut ngx_event_t) {
+    let c = (*ev).data as *mut ngx_connection_t;
+
+    if (*c).close() == 0 && (*ev).timedout() == 0 {
+        let mut buf = 0u8;
+        let n = recv((*c).fd, &mut buf as *mut u8 as *mut c_void, 1, MSG_PEEK);
+        if n == -1 && io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock {
+            (*ev).set_ready(0);
+            if ngx_handle_read_event(ev, 0) == NGX_OK as ngx_int_t {
+                return;
+            }
+        }
+    }
+
+    let item = (*c).data as *mut Idle;
+    (*(*item).pool).close(item);
+}
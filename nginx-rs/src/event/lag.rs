@@ -0,0 +1,112 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::timer::*;
+
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static INTERVAL: AtomicUsize = AtomicUsize::new(0);
+static EXPECTED: AtomicUsize = AtomicUsize::new(0);
+static LAST: AtomicUsize = AtomicUsize::new(0);
+static SMOOTHED: AtomicUsize = AtomicUsize::new(0);
+static MAX: AtomicUsize = AtomicUsize::new(0);
+static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Event loop lag of the current worker process.
+///
+/// A recurring timer measures how late it fires compared to when it was scheduled. As timers
+/// are run by the event loop between handling events, the delay grows when a worker is
+/// saturated or blocked by slow handlers, making it a good proxy for worker load. All values
+/// are in milliseconds and limited by the resolution of the cached Nginx time.
+///
+/// [`EventLoopLag::smoothed`] can be used directly as the signal of a
+/// [`LoadShedder`](crate::http::LoadShedder).
+pub struct EventLoopLag;
+
+impl EventLoopLag {
+    /// Start measuring with a timer every `interval`.
+    ///
+    /// Call this from the `init_process` handler of a module. Starting an already running
+    /// measurement does nothing. The timer is cancelable, so it doesn't delay the graceful
+    /// shutdown of the worker.
+    pub unsafe fn start(interval: Msec) {
+        if RUNNING.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let interval = interval.as_msec().max(1);
+        INTERVAL.store(interval as usize, Ordering::Relaxed);
+
+        // The event lives as long as the worker process.
+        let ev: &'static mut ngx_event_t = Box::leak(Box::new(mem::zeroed()));
+        ev.handler = Some(ngx_rs_event_loop_lag_handler);
+        ev.log = (*ngx_cycle).log;
+        ev.set_cancelable(1);
+
+        schedule(ev, interval);
+    }
+
+    /// Is the measurement running?
+    pub fn is_running() -> bool {
+        RUNNING.load(Ordering::Relaxed)
+    }
+
+    /// Lag of the most recent measurement, or `None` before the first measurement.
+    pub fn last() -> Option<ngx_msec_t> {
+        Self::value(&LAST)
+    }
+
+    /// Exponentially weighted moving average of the lag, or `None` before the first
+    /// measurement.
+    pub fn smoothed() -> Option<ngx_msec_t> {
+        Self::value(&SMOOTHED)
+    }
+
+    /// Maximum lag since the previous call, which resets it. Useful for periodic reporting.
+    pub fn take_max() -> Option<ngx_msec_t> {
+        if SAMPLES.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(MAX.swap(0, Ordering::Relaxed) as ngx_msec_t)
+    }
+
+    /// Number of measurements so far.
+    pub fn samples() -> usize {
+        SAMPLES.load(Ordering::Relaxed)
+    }
+
+    fn value(gauge: &AtomicUsize) -> Option<ngx_msec_t> {
+        if SAMPLES.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(gauge.load(Ordering::Relaxed) as ngx_msec_t)
+    }
+}
+
+unsafe fn schedule(ev: *mut ngx_event_t, interval: ngx_msec_t) {
+    EXPECTED.store(ngx_current_msec.wrapping_add(interval) as usize, Ordering::Relaxed);
+    ngx_add_timer(ev, interval);
+}
+
+unsafe extern "C" fn ngx_rs_event_loop_lag_handler(ev: *mut ngx_event_t) {
+    if ngx_exiting != 0 || ngx_terminate != 0 || ngx_quit != 0 {
+        return;
+    }
+
+    let lag = (ngx_current_msec as usize).saturating_sub(EXPECTED.load(Ordering::Relaxed));
+
+    let smoothed = if SAMPLES.fetch_add(1, Ordering::Relaxed) == 0 {
+        lag
+    } else {
+        // Weight of 1/8, like the TCP round trip time estimate.
+        let previous = SMOOTHED.load(Ordering::Relaxed);
+        (previous * 7 + lag) / 8
+    };
+
+    LAST.store(lag, Ordering::Relaxed);
+    SMOOTHED.store(smoothed, Ordering::Relaxed);
+    MAX.fetch_max(lag, Ordering::Relaxed);
+
+    schedule(ev, INTERVAL.load(Ordering::Relaxed) as ngx_msec_t);
+}
@@ -0,0 +1,13 @@
+mod executor;
+mod keepalive;
+mod lag;
+mod peer;
+mod posted;
+mod timer;
+
+pub use executor::*;
+pub use keepalive::*;
+pub use lag::*;
+pub use peer::*;
+pub use posted::*;
+pub use timer::*;
@@ -0,0 +1,467 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::keepalive::ConnectionPool;
+use crate::event::posted::*;
+use crate::event::timer::*;
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+/// Size of the buffer data is read with.
+const READ_SIZE: usize = 4096;
+
+/// Size of the temporary pool addresses are parsed with.
+const PARSE_POOL_SIZE: usize = 256;
+
+/// Event of a [`PeerConnection`], passed to its handler.
+#[derive(Debug)]
+pub enum PeerEvent<'a> {
+    /// The connection is established.
+    Connected,
+    /// Data was received.
+    Data(&'a [u8]),
+    /// The connection is closed: by the peer (`Ok`), or because of an error. This is the last
+    /// event of a connection, which is not open anymore.
+    Closed(Result<(), PeerError>),
+}
+
+/// Error of a [`PeerConnection`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PeerError {
+    /// The address is not a numeric address with a port.
+    Address,
+    /// Connecting failed.
+    Connect,
+    /// Sending or receiving failed.
+    Io,
+    /// Nothing was sent or received for longer than the timeout of the connection.
+    Timeout,
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerError::Address => write!(f, "invalid address"),
+            PeerError::Connect => write!(f, "connect failed"),
+            PeerError::Io => write!(f, "connection error"),
+            PeerError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl Error for PeerError {}
+
+type Handler = Box<dyn FnMut(&mut PeerConnection, PeerEvent)>;
+
+/// An outbound TCP connection (`ngx_peer_connection_t`) driven by the event loop, to talk
+/// custom protocols such as statsd or redis from a worker without blocking it.
+///
+/// The connection is driven by a handler receiving its [`PeerEvent`]s with the connection, on
+/// which it writes data or closes the connection. Data written is buffered and sent as soon
+/// as the connection is writable, including before it is established. The connection
+/// outlives the request or handler that opened it: it is freed once closed, by either side.
+///
+/// ```ignore
+/// let handle = PeerConnection::connect("127.0.0.1:6379", Msec::from_secs(5), |pc: &mut PeerConnection, event| {
+///     match event {
+///         PeerEvent::Data(data) => replies.extend_from_slice(data),
+///         PeerEvent::Closed(Err(err)) => ngx_log!(NGX_LOG_WARN, log, "redis: {}", err),
+///         _ => {}
+///     }
+/// })?;
+/// handle.with(|pc| pc.write(b"PING\r\n"));
+/// ```
+pub struct PeerConnection {
+    pc: ngx_peer_connection_t,
+    sockaddr: ngx_sockaddr_t,
+    name: ngx_str_t,
+    address: Vec<u8>,
+    timeout: Msec,
+    connected: bool,
+    out: Vec<u8>,
+    closing: bool,
+    closed: bool,
+    dispatching: bool,
+    handle: Rc<Cell<*mut PeerConnection>>,
+    handler: Option<Handler>,
+}
+
+/// Handle to a [`PeerConnection`], to use it outside of its handler.
+///
+/// The handle does not keep the connection open: once the connection is closed, the handle
+/// is empty.
+#[derive(Clone)]
+pub struct PeerHandle(Rc<Cell<*mut PeerConnection>>);
+
+impl PeerHandle {
+    /// Call `f` with the connection, or return `None` if it is closed.
+    ///
+    /// `None` is also returned while the handler of the connection runs, such as when it is
+    /// called from the handler, which has the connection already.
+    pub fn with<R>(&self, f: impl FnOnce(&mut PeerConnection) -> R) -> Option<R> {
+        let pc = self.0.get();
+        if pc.is_null() || unsafe { (*pc).dispatching } {
+            return None;
+        }
+
+        unsafe {
+            let result = f(&mut *pc);
+            PeerConnection::release(pc);
+            Some(result)
+        }
+    }
+
+    /// Is the connection still open?
+    pub fn is_open(&self) -> bool {
+        !self.0.get().is_null()
+    }
+}
+
+impl PeerConnection {
+    /// Connect to a numeric `address`, such as `10.0.0.1:8125` or `[::1]:6379`, calling
+    /// `handler` with the events of the connection.
+    ///
+    /// The connection is closed with [`PeerError::Timeout`] when nothing is sent or received
+    /// within `timeout`, which includes connecting.
+    pub fn connect<F>(address: &str, timeout: Msec, handler: F) -> Result<PeerHandle, PeerError>
+    where
+        F: FnMut(&mut PeerConnection, PeerEvent) + 'static,
+    {
+        PeerConnection::connect_with(address, timeout, None, handler)
+    }
+
+    /// Connect to `address` as [`PeerConnection::connect`] does, taking an idle connection
+    /// to the address from `pool` if there is one.
+    ///
+    /// Once done with the connection, [`PeerConnection::release_to`] gives it back to the
+    /// pool, if the protocol allows reusing it.
+    pub fn connect_pooled<F>(address: &str, timeout: Msec, pool: &ConnectionPool, handler: F) -> Result<PeerHandle, PeerError>
+    where
+        F: FnMut(&mut PeerConnection, PeerEvent) + 'static,
+    {
+        PeerConnection::connect_with(address, timeout, Some(pool), handler)
+    }
+
+    fn connect_with<F>(address: &str, timeout: Msec, pool: Option<&ConnectionPool>, handler: F) -> Result<PeerHandle, PeerError>
+    where
+        F: FnMut(&mut PeerConnection, PeerEvent) + 'static,
+    {
+        unsafe {
+            let log = (*ngx_cycle).log;
+            let (sockaddr, socklen) = parse_address(address, log)?;
+
+            let pc = Box::into_raw(Box::new(PeerConnection {
+                pc: mem::zeroed(),
+                sockaddr,
+                name: ngx_str_t { len: 0, data: ptr::null_mut() },
+                address: address.as_bytes().to_vec(),
+                timeout,
+                connected: false,
+                out: Vec::new(),
+                closing: false,
+                closed: false,
+                dispatching: false,
+                handle: Rc::new(Cell::new(ptr::null_mut())),
+                handler: Some(Box::new(handler)),
+            }));
+            let p = &mut *pc;
+
+            p.name = ngx_str_t { len: p.address.len(), data: p.address.as_mut_ptr() };
+            p.pc.sockaddr = &mut p.sockaddr.sockaddr;
+            p.pc.socklen = socklen;
+            p.pc.name = &mut p.name;
+            p.pc.get = Some(ngx_event_get_peer);
+            p.pc.log = log;
+            p.pc.set_log_error(ngx_connection_log_error_e_NGX_ERROR_ERR as u32);
+
+            // A cached connection is established already, as one that connected immediately.
+            let rc = match pool {
+                Some(pool) if pool.get(&mut p.pc) => NGX_OK as ngx_int_t,
+                _ => ngx_event_connect_peer(&mut p.pc),
+            };
+            if rc == NGX_ERROR as ngx_int_t || rc == NGX_BUSY as ngx_int_t || rc == NGX_DECLINED as ngx_int_t {
+                if !p.pc.connection.is_null() {
+                    ngx_close_connection(p.pc.connection);
+                }
+                drop(Box::from_raw(pc));
+                return Err(PeerError::Connect);
+            }
+
+            let c = p.pc.connection;
+            (*c).data = pc as *mut c_void;
+            (*c).log = log;
+            (*(*c).read).handler = Some(ngx_rs_peer_read_handler);
+            (*(*c).read).log = log;
+            (*(*c).write).handler = Some(ngx_rs_peer_write_handler);
+            (*(*c).write).log = log;
+            ngx_add_timer((*c).write, timeout.as_msec());
+
+            p.handle.set(pc);
+            let handle = PeerHandle(p.handle.clone());
+
+            if rc == NGX_OK as ngx_int_t {
+                // Events are not dispatched before `connect` returns, as the caller does not
+                // expect its handler to run yet; they are posted instead.
+                ngx_post_write(c);
+            }
+            Ok(handle)
+        }
+    }
+
+    /// Address of the peer, as given to [`PeerConnection::connect`].
+    pub fn address(&self) -> &str {
+        std::str::from_utf8(&self.address).unwrap_or("")
+    }
+
+    /// Is the connection established?
+    pub fn is_connected(&self) -> bool {
+        self.connected && !self.closed
+    }
+
+    /// Number of bytes written but not sent yet.
+    pub fn pending(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Write data to the connection.
+    ///
+    /// The data is buffered, and sent as soon as the connection is writable. Nothing is
+    /// written once the connection is closed or [shut down](PeerConnection::shutdown).
+    pub fn write(&mut self, data: &[u8]) {
+        if self.closed || self.closing {
+            return;
+        }
+        self.out.extend_from_slice(data);
+        if self.connected {
+            unsafe { self.send() };
+        }
+    }
+
+    /// Close the connection once all data written is sent.
+    pub fn shutdown(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closing = true;
+        if self.connected && self.out.is_empty() {
+            self.close();
+        }
+    }
+
+    /// Give the connection to `pool` for reuse, such as once a response is read in full, or
+    /// close it if it can't be reused. The handler is not called.
+    ///
+    /// Only connections that are established and have sent all data written are reused.
+    /// Returns whether the connection was cached.
+    pub fn release_to(&mut self, pool: &ConnectionPool) -> bool {
+        if self.closed {
+            return false;
+        }
+        let reusable = self.connected && !self.closing && self.out.is_empty();
+        // On success, the pool takes the connection, which is not closed then.
+        let released = unsafe { pool.release(&mut self.pc, 0, reusable) };
+        self.close();
+        released
+    }
+
+    /// Close the connection now, discarding data not sent yet. The handler is not called.
+    pub fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        self.handle.set(ptr::null_mut());
+        if !self.pc.connection.is_null() {
+            unsafe { ngx_close_connection(self.pc.connection) };
+            self.pc.connection = ptr::null_mut();
+        }
+    }
+
+    // Free a closed connection, unless one of its events is being handled.
+    unsafe fn release(pc: *mut PeerConnection) {
+        if (*pc).closed && !(*pc).dispatching {
+            drop(Box::from_raw(pc));
+        }
+    }
+
+    unsafe fn dispatch(&mut self, event: PeerEvent) {
+        if let Some(mut handler) = self.handler.take() {
+            let dispatching = mem::replace(&mut self.dispatching, true);
+            handler(self, event);
+            self.dispatching = dispatching;
+            self.handler = Some(handler);
+        }
+    }
+
+    // Close with a final event for the handler.
+    unsafe fn fail(&mut self, result: Result<(), PeerError>) {
+        if self.closed {
+            return;
+        }
+        self.close();
+        self.dispatch(PeerEvent::Closed(result));
+    }
+
+    unsafe fn on_write(&mut self) {
+        let c = self.pc.connection;
+
+        if (*(*c).write).timedout() != 0 {
+            return self.fail(Err(PeerError::Timeout));
+        }
+        if !self.connected {
+            if !self.test_connect() {
+                return self.fail(Err(PeerError::Connect));
+            }
+            self.connected = true;
+            ngx_add_timer((*c).read, self.timeout.as_msec());
+            self.dispatch(PeerEvent::Connected);
+            if self.closed {
+                return;
+            }
+            // Data may have arrived along with the connection.
+            self.on_read();
+            if self.closed {
+                return;
+            }
+        }
+        self.send();
+    }
+
+    unsafe fn send(&mut self) {
+        let c = self.pc.connection;
+        let mut sent = 0;
+
+        while sent < self.out.len() {
+            let out = &mut self.out[sent..];
+            let n = (*c).send.unwrap()(c, out.as_mut_ptr(), out.len());
+            if n == NGX_ERROR as isize {
+                return self.fail(Err(PeerError::Io));
+            }
+            if n == NGX_AGAIN as isize || n == 0 {
+                break;
+            }
+            sent += n as usize;
+        }
+        self.out.drain(..sent);
+
+        if sent > 0 {
+            ngx_add_timer((*c).read, self.timeout.as_msec());
+        }
+        if self.out.is_empty() {
+            if (*(*c).write).timer_set() != 0 {
+                ngx_del_timer((*c).write);
+            }
+            if self.closing {
+                return self.close();
+            }
+        } else if (*(*c).write).timer_set() == 0 {
+            ngx_add_timer((*c).write, self.timeout.as_msec());
+        }
+
+        if ngx_handle_write_event((*c).write, 0) != NGX_OK as ngx_int_t {
+            self.fail(Err(PeerError::Io));
+        }
+    }
+
+    unsafe fn on_read(&mut self) {
+        let c = self.pc.connection;
+        if (*(*c).read).timedout() != 0 {
+            return self.fail(Err(PeerError::Timeout));
+        }
+
+        let mut buf = [0u8; READ_SIZE];
+        loop {
+            let n = (*c).recv.unwrap()(c, buf.as_mut_ptr(), buf.len());
+            if n == NGX_AGAIN as isize {
+                break;
+            }
+            if n == NGX_ERROR as isize {
+                return self.fail(Err(PeerError::Io));
+            }
+            if n == 0 {
+                return self.fail(Ok(()));
+            }
+
+            ngx_add_timer((*c).read, self.timeout.as_msec());
+            self.dispatch(PeerEvent::Data(&buf[..n as usize]));
+            if self.closed {
+                return;
+            }
+        }
+
+        if ngx_handle_read_event((*c).read, 0) != NGX_OK as ngx_int_t {
+            self.fail(Err(PeerError::Io));
+        }
+    }
+
+    // Check that a non-blocking connect succeeded, as done by `ngx_http_upstream_test_connect`.
+    unsafe fn test_connect(&self) -> bool {
+        let mut err: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let rc = getsockopt(
+            (*self.pc.connection).fd,
+            SOL_SOCKET as c_int,
+            SO_ERROR as c_int,
+            &mut err as *mut c_int as *mut c_void,
+            &mut len,
+        );
+        rc == 0 && err == 0
+    }
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// Parse a numeric address with a port into a socket address.
+unsafe fn parse_address(address: &str, log: *mut ngx_log_t) -> Result<(ngx_sockaddr_t, socklen_t), PeerError> {
+    let pool = ngx_create_pool(PARSE_POOL_SIZE, log);
+    if pool.is_null() {
+        return Err(PeerError::Address);
+    }
+
+    let mut addr: ngx_addr_t = mem::zeroed();
+    let rc = ngx_parse_addr_port(pool, &mut addr, address.as_ptr() as *mut u_char, address.len());
+    let result = if rc != NGX_OK as ngx_int_t || ngx_inet_get_port(addr.sockaddr) == 0 {
+        Err(PeerError::Address)
+    } else {
+        let mut sockaddr: ngx_sockaddr_t = mem::zeroed();
+        let len = (addr.socklen as usize).min(mem::size_of::<ngx_sockaddr_t>());
+        ptr::copy_nonoverlapping(addr.sockaddr as *const u8, &mut sockaddr as *mut ngx_sockaddr_t as *mut u8, len);
+        Ok((sockaddr, len as socklen_t))
+    };
+
+    ngx_destroy_pool(pool);
+    result
+}
+
+// Run the write handler of a connection that connected immediately, from the posted events.
+unsafe fn ngx_post_write(c: *mut ngx_connection_t) {
+    (*(*c).write).set_ready(1);
+    ngx_post_event((*c).write, ptr::addr_of_mut!(ngx_posted_events));
+}
+
+unsafe extern "C" fn ngx_rs_peer_write_handler(wev: *mut ngx_event_t) {
+    let c = (*wev).data as *mut ngx_connection_t;
+    let pc = (*c).data as *mut PeerConnection;
+    (*pc).on_write();
+    PeerConnection::release(pc);
+}
+
+unsafe extern "C" fn ngx_rs_peer_read_handler(rev: *mut ngx_event_t) {
+    let c = (*rev).data as *mut ngx_connection_t;
+    let pc = (*c).data as *mut PeerConnection;
+    if (*pc).connected {
+        (*pc).on_read();
+    } else if (*rev).timedout() != 0 {
+        (*pc).fail(Err(PeerError::Timeout));
+    }
+    PeerConnection::release(pc);
+}
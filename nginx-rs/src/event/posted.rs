@@ -0,0 +1,38 @@
+use crate::bindings::*;
+
+/// Post an [event] to a queue, normally `ngx_posted_events`, for deferred processing.
+///
+/// Rust implementation of the `ngx_post_event` macro. Posting an event that is already posted
+/// has no effect.
+///
+/// [event]: https://nginx.org/en/docs/dev/development_guide.html#events
+pub unsafe fn ngx_post_event(ev: *mut ngx_event_t, q: *mut ngx_queue_t) {
+    if (*ev).posted() != 0 {
+        return;
+    }
+
+    (*ev).set_posted(1);
+
+    // ngx_queue_insert_tail
+    let x = &mut (*ev).queue as *mut ngx_queue_t;
+    (*x).prev = (*q).prev;
+    (*(*x).prev).next = x;
+    (*x).next = q;
+    (*q).prev = x;
+}
+
+/// Remove a posted [event] from its queue.
+///
+/// Rust implementation of the `ngx_delete_posted_event` macro.
+///
+/// [event]: https://nginx.org/en/docs/dev/development_guide.html#events
+pub unsafe fn ngx_delete_posted_event(ev: *mut ngx_event_t) {
+    (*ev).set_posted(0);
+
+    // ngx_queue_remove
+    let x = &mut (*ev).queue as *mut ngx_queue_t;
+    (*(*x).next).prev = (*x).prev;
+    (*(*x).prev).next = (*x).next;
+    (*x).prev = std::ptr::null_mut();
+    (*x).next = std::ptr::null_mut();
+}
@@ -0,0 +1,185 @@
+use crate::bindings::*;
+use crate::core::{AllocError, Msec, Pool};
+
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// Add (or reschedule) the timer of an [event].
+///
+/// Rust implementation of the `ngx_add_timer` macro, which bindgen is unable to translate.
+///
+/// [event]: https://nginx.org/en/docs/dev/development_guide.html#events
+pub unsafe fn ngx_add_timer(ev: *mut ngx_event_t, timer: ngx_msec_t) {
+    let key = ngx_current_msec.wrapping_add(timer);
+
+    if (*ev).timer_set() != 0 {
+        // Keep the existing timer if the difference is small enough, to avoid
+        // rebalancing the timer tree on every call.
+        let diff = key.wrapping_sub((*ev).timer.key) as ngx_msec_int_t;
+        if diff.abs() < NGX_TIMER_LAZY_DELAY as ngx_msec_int_t {
+            return;
+        }
+
+        ngx_del_timer(ev);
+    }
+
+    (*ev).timer.key = key;
+    ngx_rbtree_insert(ptr::addr_of_mut!(ngx_event_timer_rbtree), &mut (*ev).timer);
+    (*ev).set_timer_set(1);
+}
+
+/// Remove the timer of an [event].
+///
+/// Rust implementation of the `ngx_del_timer` macro.
+///
+/// [event]: https://nginx.org/en/docs/dev/development_guide.html#events
+pub unsafe fn ngx_del_timer(ev: *mut ngx_event_t) {
+    ngx_rbtree_delete(ptr::addr_of_mut!(ngx_event_timer_rbtree), &mut (*ev).timer);
+    (*ev).set_timer_set(0);
+}
+
+enum TimerCallback {
+    Once(Option<Box<dyn FnOnce()>>),
+    Interval(Box<dyn FnMut()>),
+}
+
+struct TimerState {
+    event: ngx_event_t,
+    callback: TimerCallback,
+    period: ngx_msec_t,
+    // Is there a `Timer` handle for the state? If not, the timer frees it once done.
+    owned: bool,
+    dispatching: bool,
+    done: bool,
+}
+
+/// A timer calling a Rust closure, once or at an interval, on the event loop of the worker.
+///
+/// Dropping the handle cancels the timer, so a timer lives as long as the value holding it,
+/// such as a module context. [`Timer::bind`] ties the timer to a pool instead, for work that
+/// must not outlive a request or connection, and [`Timer::detach`] lets it run on its own.
+///
+/// ```ignore
+/// // Give up on a slow operation of the request.
+/// Timer::once(Msec::from_secs(5), move || abort(r)).bind(&mut request.pool())?;
+///
+/// // Refresh a cache for the life of the worker.
+/// Timer::interval(Msec::from_secs(30), || CACHE.with(|cache| cache.borrow_mut().refresh())).detach();
+/// ```
+///
+/// Timers are cancelable: pending timers don't delay the graceful shutdown of the worker.
+/// They keep firing while it waits for other work, such as open connections, to finish, and
+/// are dropped without firing when the worker exits.
+pub struct Timer(*mut TimerState);
+
+impl Timer {
+    /// Call `f` once after `delay`.
+    pub fn once<F: FnOnce() + 'static>(delay: Msec, f: F) -> Timer {
+        Timer::start(delay.as_msec(), TimerCallback::Once(Some(Box::new(f))), 0)
+    }
+
+    /// Call `f` every `period`, starting after one period.
+    ///
+    /// The next period starts once `f` returns, so calls don't pile up when the worker is
+    /// busy.
+    pub fn interval<F: FnMut() + 'static>(period: Msec, f: F) -> Timer {
+        let period = period.as_msec().max(1);
+        Timer::start(period, TimerCallback::Interval(Box::new(f)), period)
+    }
+
+    fn start(delay: ngx_msec_t, callback: TimerCallback, period: ngx_msec_t) -> Timer {
+        unsafe {
+            let state = Box::into_raw(Box::new(TimerState {
+                event: mem::zeroed(),
+                callback,
+                period,
+                owned: true,
+                dispatching: false,
+                done: false,
+            }));
+            let ev = &mut (*state).event;
+            ev.handler = Some(ngx_rs_timer_handler);
+            ev.data = state as *mut c_void;
+            ev.log = (*ngx_cycle).log;
+            ev.set_cancelable(1);
+            ngx_add_timer(ev, delay);
+            Timer(state)
+        }
+    }
+
+    /// Is the timer still to fire?
+    pub fn is_pending(&self) -> bool {
+        unsafe { !(*self.0).done }
+    }
+
+    /// Cancel the timer. The closure is not called again, but may be running, if the timer
+    /// is cancelled from it.
+    pub fn cancel(&self) {
+        unsafe {
+            let state = &mut *self.0;
+            state.done = true;
+            if state.event.timer_set() != 0 {
+                ngx_del_timer(&mut state.event);
+            }
+        }
+    }
+
+    /// Cancel the timer when `pool` is destroyed, such as the pool of a request, if it is
+    /// still pending.
+    ///
+    /// If the pool can't allocate, the timer is cancelled right away, rather than risk
+    /// running after the pool is gone, and an error is returned.
+    pub fn bind(self, pool: &mut Pool) -> Result<(), AllocError> {
+        // Dropped, and so cancelled, with the pool.
+        pool.alloc(self).map(|_| ()).ok_or(AllocError)
+    }
+
+    /// Let the timer run without a handle, until it is done, or for the life of the worker
+    /// for an interval.
+    pub fn detach(self) {
+        unsafe {
+            let state = self.0;
+            mem::forget(self);
+            (*state).owned = false;
+            if (*state).done && !(*state).dispatching {
+                drop(Box::from_raw(state));
+            }
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel();
+        unsafe {
+            (*self.0).owned = false;
+            // Freed by the handler once the closure returns.
+            if !(*self.0).dispatching {
+                drop(Box::from_raw(self.0));
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_timer_handler(ev: *mut ngx_event_t) {
+    let state = (*ev).data as *mut TimerState;
+
+    (*state).dispatching = true;
+    match &mut (*state).callback {
+        TimerCallback::Once(f) => {
+            (*state).done = true;
+            if let Some(f) = f.take() {
+                f();
+            }
+        }
+        TimerCallback::Interval(f) => f(),
+    }
+    (*state).dispatching = false;
+
+    if !(*state).done {
+        ngx_add_timer(ev, (*state).period);
+    } else if !(*state).owned {
+        drop(Box::from_raw(state));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::spawn_local;
+
+use std::future::Future;
+
+/// Define a static request handler running an async function.
+///
+/// The handler takes a single [`Request`](crate::http::Request) argument and returns a
+/// future of a [`Status`], typically an `async fn(&mut Request) -> Status`. The request is
+/// held while the future runs on the executor of the worker (see
+/// [`spawn_local`](crate::event::spawn_local)), so it can await timers, thread pools, DNS
+/// lookups and HTTP requests, and is finalized with the status the future resolves to.
+///
+/// This is meant for content handlers, which send the response from the future and return
+/// its status:
+///
+/// ```ignore
+/// async fn lookup_handler(request: &mut Request) -> Status {
+///     let addrs = match request.resolve_name_async("backend.internal").await {
+///         Ok(addrs) => addrs,
+///         Err(_) => return HTTP_SERVICE_UNAVAILABLE.into(),
+///     };
+///     let body = format!("{:?}", addrs);
+///     request.send_response(HTTP_OK, "text/plain", body.as_bytes())
+/// }
+///
+/// http_async_request_handler!(ngx_http_lookup_handler, lookup_handler);
+/// ```
+///
+/// The request stays valid while the future runs even if the client goes away meanwhile, as
+/// its termination waits for the future to complete, so long futures should check [`Request::connection`](crate::http::Request::connection) for
+/// errors at their await points.
+#[macro_export]
+macro_rules! http_async_request_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            let internal_error = $crate::__private::bindings::NGX_HTTP_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_int_t;
+            $crate::core::catch_panic(log, stringify!($name), internal_error, || unsafe {
+                // The request is held until the future completes.
+                let future = $handler($crate::http::Request::from_ngx_http_request(r));
+                $crate::http::spawn_request_future(r, future)
+            })
+        }
+    };
+}
+
+/// Run the future of an [`http_async_request_handler!`], holding the request until it
+/// completes, then finalize the request with its status.
+#[doc(hidden)]
+pub unsafe fn spawn_request_future<F>(r: *mut ngx_http_request_t, future: F) -> ngx_int_t
+where
+    F: Future<Output = Status> + 'static,
+{
+    // Keep the request until the future completes, and hold off its termination, as
+    // `Request::spawn_blocking` does: the future still refers to the request.
+    let main = (*r).main;
+    (*main).set_count((*main).count() + 1);
+    (*main).set_blocked((*main).blocked() + 1);
+
+    spawn_local(async move {
+        let status = future.await;
+        let c = (*r).connection;
+        (*main).set_blocked((*main).blocked() - 1);
+        ngx_http_finalize_request(r, status.0);
+        ngx_http_run_posted_requests(c);
+    });
+    NGX_DONE as ngx_int_t
+}
@@ -0,0 +1,176 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::variable::*;
+
+use std::fmt::Write;
+
+/// Hash function of a [`BodyHasher`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BodyHashAlgorithm {
+    /// SHA-256, for content integrity: 64 hexadecimal digits.
+    Sha256,
+    /// 64-bit XXH3 with a zero seed, for cheap deduplication: 16 hexadecimal digits.
+    Xxh3,
+}
+
+enum State {
+    Sha256(Sha256),
+    Xxh3(Xxh3State),
+}
+
+/// Hashing state of a response, stored in the request pool.
+struct BodyHashState {
+    state: State,
+    // Part of the body was only in a file and could not be hashed.
+    incomplete: bool,
+    digest: Option<String>,
+}
+
+/// Streaming hash of response bodies, computed as the body passes through a body filter,
+/// without buffering it.
+///
+/// The hash is finalized with the last buffer of the response and is then available with
+/// [`Request::body_hash`], in the `$rs_body_hash` variable (see
+/// [`BodyHasher::add_variable`]), typically for the access log, and in a trailer if one is
+/// set with [`BodyHasher::set_trailer`].
+///
+/// Only data in memory can be hashed: a response with buffers only in a file (such as static
+/// files sent with `sendfile`) gets no hash. Subrequests are not hashed.
+///
+/// ```ignore
+/// http_header_filter!(header_filter, |request: &mut Request| {
+///     HASHER.start(request);
+///     unsafe { NEXT_HEADER_FILTER.call(request) }
+/// });
+///
+/// http_body_filter!(body_filter, |request: &mut Request, chain: &mut Chain| {
+///     HASHER.update(request, chain);
+///     unsafe { NEXT_BODY_FILTER.call(request, chain) }
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct BodyHasher {
+    algorithm: BodyHashAlgorithm,
+    trailer: Option<String>,
+}
+
+impl BodyHasher {
+    /// Hash response bodies with `algorithm`.
+    pub fn new(algorithm: BodyHashAlgorithm) -> BodyHasher {
+        BodyHasher { algorithm, trailer: None }
+    }
+
+    /// The hash function.
+    pub fn algorithm(&self) -> BodyHashAlgorithm {
+        self.algorithm
+    }
+
+    /// Also send the hash in the trailer `name`, such as `Digest-SHA256`.
+    ///
+    /// The trailer is declared by [`BodyHasher::start`], which must then run before the
+    /// response header is sent.
+    pub fn set_trailer(&mut self, name: &str) {
+        self.trailer = Some(String::from(name));
+    }
+
+    /// Start hashing the response body of a request, from a header filter.
+    ///
+    /// Returns `false` if the request is not hashed: for subrequests, or if the allocation
+    /// of the state fails.
+    pub fn start(&self, request: &mut Request) -> bool {
+        if !request.is_main() {
+            return false;
+        }
+        let mut pool = request.pool();
+        if !pool.get_local::<BodyHashState>().is_null() {
+            return true;
+        }
+
+        let state = match self.algorithm {
+            BodyHashAlgorithm::Sha256 => State::Sha256(Sha256::new()),
+            BodyHashAlgorithm::Xxh3 => State::Xxh3(Xxh3State::new(0)),
+        };
+        if pool.insert_local(BodyHashState { state, incomplete: false, digest: None }).is_null() {
+            return false;
+        }
+
+        if let Some(trailer) = &self.trailer {
+            return request.declare_trailers(&[trailer.as_str()]);
+        }
+        true
+    }
+
+    /// Hash the buffers of `chain`, from a body filter, before passing it on.
+    ///
+    /// The hash is finalized when the chain has the last buffer. Returns `false` if the body
+    /// of the request is not hashed.
+    pub fn update(&self, request: &mut Request, chain: &Chain) -> bool {
+        let state = request.pool().get_local::<BodyHashState>();
+        if state.is_null() {
+            return false;
+        }
+        let state = unsafe { &mut *state };
+        if state.digest.is_some() {
+            return true;
+        }
+
+        for link in chain.iter() {
+            if link.in_memory() {
+                match &mut state.state {
+                    State::Sha256(sha) => sha.update(link.as_bytes()),
+                    State::Xxh3(xxh) => xxh.update(link.as_bytes()),
+                }
+            } else if !link.is_special() {
+                state.incomplete = true;
+            }
+        }
+
+        if !chain.has_last_buf() {
+            return true;
+        }
+        if state.incomplete {
+            return false;
+        }
+
+        let digest = match &state.state {
+            State::Sha256(sha) => to_hex(&sha.clone().finish()),
+            State::Xxh3(xxh) => to_hex(&xxh.digest().to_be_bytes()),
+        };
+        if let Some(trailer) = &self.trailer {
+            if request.expect_trailers() {
+                request.add_trailer(trailer, &digest);
+            }
+        }
+        state.digest = Some(digest);
+        true
+    }
+
+    /// Register the `$rs_body_hash` variable, the hash of the response body in hexadecimal.
+    ///
+    /// The variable is not found until the last buffer of the response went through
+    /// [`BodyHasher::update`]. Call this from the `preconfiguration` handler of a module.
+    pub unsafe fn add_variable(cf: *mut ngx_conf_t) -> Status {
+        Variables::add(cf, "rs_body_hash", VariableFlags::NOCACHEABLE, |request: &mut Request| request.body_hash())
+    }
+}
+
+impl Request {
+    /// Hash of the response body computed by a [`BodyHasher`], in hexadecimal, once the last
+    /// buffer of the response was hashed.
+    pub fn body_hash(&self) -> Option<String> {
+        let state = self.pool().get_local::<BodyHashState>();
+        if state.is_null() {
+            return None;
+        }
+        unsafe { (*state).digest.clone() }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
@@ -0,0 +1,44 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::conf::*;
+
+/// Select the certificate of TLS handshakes to all servers of the `http` block being
+/// configured with `selector`, see [`CertificateSelector`].
+///
+/// Call this from the `postconfiguration` handler of the module, once the SSL contexts of
+/// the servers have been created.
+///
+/// Servers whose `ssl_certificate` contains variables are left out: Nginx selects their
+/// certificates with its own callback, which the selector would replace.
+///
+/// ```ignore
+/// unsafe extern "C" fn ngx_http_certs_postconfiguration(cf: *mut ngx_conf_t) -> ngx_int_t {
+///     select_certificates(cf, |_: &mut Connection, server_name: Option<&str>| {
+///         match server_name.and_then(|name| STORE.get(name)) {
+///             Some(entry) => CertificateSelection::Use(SslCertificate::from_pem(&entry.chain, &entry.key)),
+///             None => CertificateSelection::Default,
+///         }
+///     })
+///     .into()
+/// }
+/// ```
+pub unsafe fn select_certificates<S: CertificateSelector>(cf: *mut ngx_conf_t, selector: S) -> Status {
+    let selector: Box<dyn CertificateSelector> = Box::new(selector);
+    let selector: *mut Box<dyn CertificateSelector> = match Pool::from_ngx_pool((*cf).pool).alloc(selector) {
+        Some(selector) => selector.as_ptr(),
+        None => return ERROR,
+    };
+
+    let cmcf = ngx_http_conf_get_module_main_conf(cf, &ngx_http_core_module) as *mut ngx_http_core_main_conf_t;
+    let servers = std::slice::from_raw_parts(
+        (*cmcf).servers.elts as *const *mut ngx_http_core_srv_conf_t,
+        (*cmcf).servers.nelts,
+    );
+    for &cscf in servers {
+        let sscf = *(*(*cscf).ctx).srv_conf.add(ngx_http_ssl_module.ctx_index) as *mut ngx_http_ssl_srv_conf_t;
+        if !sscf.is_null() && !(*sscf).ssl.ctx.is_null() && (*sscf).certificate_values.is_null() {
+            set_cert_callback(&mut (*sscf).ssl, selector);
+        }
+    }
+    OK
+}
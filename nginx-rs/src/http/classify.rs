@@ -0,0 +1,137 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::variable::*;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Priority of a request, as assigned by its [`Classification`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub enum Priority {
+    /// Shed first.
+    Low,
+    /// Shed once all low priority traffic is being shed.
+    Normal,
+    /// Never shed.
+    Critical,
+}
+
+impl Priority {
+    /// Name of the priority: `low`, `normal` or `critical`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::Normal
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a request is segmented by the crate's components.
+///
+/// The classification is computed once per request by the classifier registered with
+/// [`set_classifier`], so the load shedder, rate limiters, samplers and loggers all see the
+/// same priority, traffic class and tenant for a request.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Classification {
+    /// Priority of the request.
+    pub priority: Priority,
+    /// Traffic class, such as `api`, `static` or `bot`.
+    pub class: Option<String>,
+    /// Tenant (customer, account or API key) the request is made on behalf of.
+    pub tenant: Option<String>,
+}
+
+type Classifier = Rc<dyn Fn(&mut Request) -> Classification>;
+
+thread_local! {
+    static CLASSIFIER: RefCell<Option<Classifier>> = RefCell::new(None);
+}
+
+/// Register the classifier of the worker process, replacing any previous one.
+///
+/// Without a classifier, all requests have the default classification: normal priority and
+/// no class or tenant. Register it from `init_process`, or while parsing the configuration
+/// in single process mode.
+///
+/// ```ignore
+/// set_classifier(|request: &mut Request| Classification {
+///     priority: if request.user_agent().as_bytes().starts_with(b"health") { Priority::Critical } else { Priority::Normal },
+///     class: request.uri().filter(|uri| uri.starts_with("/api/")).map(|_| "api".to_string()),
+///     tenant: request.get_header("x-tenant-id"),
+/// });
+/// ```
+pub fn set_classifier<F: Fn(&mut Request) -> Classification + 'static>(classifier: F) {
+    CLASSIFIER.with(|c| *c.borrow_mut() = Some(Rc::new(classifier)));
+}
+
+impl Request {
+    /// Classification of the request, computed by the registered classifier on first use.
+    pub fn classification(&mut self) -> Classification {
+        if let Some(classification) = self.cached_classification() {
+            return classification;
+        }
+
+        // The classifier is cloned out so it may itself use the request freely.
+        let classifier = CLASSIFIER.with(|c| c.borrow().clone());
+        let classification = classifier.map(|classify| classify(self)).unwrap_or_default();
+        self.set_classification(classification.clone());
+        classification
+    }
+
+    /// Override the classification of the request, such as once a handler has authenticated
+    /// the tenant. Components that already used the previous classification are not updated.
+    pub fn set_classification(&mut self, classification: Classification) {
+        let mut pool = self.pool();
+        let cached = pool.get_local::<Classification>();
+        if cached.is_null() {
+            pool.insert_local(classification);
+        } else {
+            unsafe { *cached = classification };
+        }
+    }
+
+    /// Classification of the request if it has already been computed, without running the
+    /// classifier. Used where the request can't be borrowed mutably, such as when logging.
+    pub fn cached_classification(&self) -> Option<Classification> {
+        unsafe { self.pool().get_local::<Classification>().as_ref().cloned() }
+    }
+}
+
+/// Register the `<prefix>priority`, `<prefix>class` and `<prefix>tenant` variables, with the
+/// classification of the request, for use in `log_format` and other directives.
+///
+/// Call this from the `preconfiguration` handler of a module.
+pub unsafe fn add_classification_variables(cf: *mut ngx_conf_t, prefix: &str) -> Status {
+    let variables: [(&str, fn(Classification) -> Option<String>); 3] = [
+        ("priority", |classification| Some(classification.priority.as_str().to_string())),
+        ("class", |classification| classification.class),
+        ("tenant", |classification| classification.tenant),
+    ];
+
+    for &(name, field) in variables.iter() {
+        let name = format!("{}{}", prefix, name);
+        let status = Variables::add(cf, &name, VariableFlags::NOCACHEABLE, move |request: &mut Request| {
+            field(request.classification())
+        });
+        if status != OK {
+            return status;
+        }
+    }
+
+    OK
+}
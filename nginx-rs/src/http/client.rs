@@ -0,0 +1,614 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::timer::*;
+use crate::event::{oneshot, ConnectionPool, PeerConnection, PeerError, PeerEvent, PeerHandle};
+use crate::http::parse::*;
+use crate::http::request::Request;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::os::raw::c_void;
+
+/// An outbound HTTP/1.1 request, sent with [`Request::fetch`].
+///
+/// The request is sent to a numeric address with a port, such as `10.0.0.1:8080` or
+/// `[::1]:8080`, with the `Host` header set separately. It is sent with `Connection: close`,
+/// unless connections are kept alive with [`ClientRequest::set_keepalive`].
+#[derive(Clone, Debug)]
+pub struct ClientRequest {
+    method: String,
+    host: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    timeout: Msec,
+    max_response: ByteSize,
+    keepalive: Option<&'static ConnectionPool>,
+}
+
+impl ClientRequest {
+    /// Create a request for `path` on `host`, the value of the `Host` header.
+    ///
+    /// The request times out after 1 second, and fails if the response is over 1 megabyte.
+    /// Returns [`ClientError::InvalidRequest`] if the method is not a token, or the host or
+    /// path is empty or has spaces or control characters, which would split the request line.
+    pub fn new(method: &str, host: &str, path: &str) -> Result<ClientRequest, ClientError> {
+        if !is_token(method) || host.is_empty() || !is_field(host, false) || path.is_empty() || !is_field(path, false) {
+            return Err(ClientError::InvalidRequest);
+        }
+        Ok(ClientRequest {
+            method: method.to_string(),
+            host: host.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout: Msec::from_secs(1),
+            max_response: ByteSize::from_mib(1),
+            keepalive: None,
+        })
+    }
+
+    /// Create a `GET` request.
+    pub fn get(host: &str, path: &str) -> Result<ClientRequest, ClientError> {
+        ClientRequest::new("GET", host, path)
+    }
+
+    /// Create a `POST` request with a body.
+    pub fn post(host: &str, path: &str, body: impl Into<Vec<u8>>) -> Result<ClientRequest, ClientError> {
+        let mut request = ClientRequest::new("POST", host, path)?;
+        request.set_body(body);
+        Ok(request)
+    }
+
+    /// Add a request header. `Host`, `Connection` and `Content-Length` are set automatically.
+    ///
+    /// Returns [`ClientError::InvalidRequest`] if the name is not a token or the value has
+    /// control characters such as CR or LF, which would inject headers.
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<(), ClientError> {
+        if !is_token(name) || !is_field(value, true) {
+            return Err(ClientError::InvalidRequest);
+        }
+        self.headers.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Set the request body.
+    pub fn set_body(&mut self, body: impl Into<Vec<u8>>) {
+        self.body = body.into();
+    }
+
+    /// Set the time allowed for the whole exchange, from connecting to reading the response.
+    pub fn set_timeout(&mut self, timeout: Msec) {
+        self.timeout = timeout;
+    }
+
+    /// Set the maximum size of the response, headers included.
+    pub fn set_max_response(&mut self, size: ByteSize) {
+        self.max_response = size;
+    }
+
+    /// Reuse connections from `pool`, and give the connection back to it once the response
+    /// is read, if the server allows it.
+    ///
+    /// The pool lives as long as the worker process, for example created in an
+    /// `init_process` handler and leaked with [`Box::leak`].
+    pub fn set_keepalive(&mut self, pool: &'static ConnectionPool) {
+        self.keepalive = Some(pool);
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut out = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", self.method, self.path, self.host);
+        if self.keepalive.is_none() {
+            out.push_str("Connection: close\r\n");
+        }
+        for (name, value) in self.headers.iter() {
+            out.push_str(name);
+            out.push_str(": ");
+            out.push_str(value);
+            out.push_str("\r\n");
+        }
+        if !self.body.is_empty() || self.method == "POST" || self.method == "PUT" {
+            out.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        out.push_str("\r\n");
+
+        let mut out = out.into_bytes();
+        out.extend_from_slice(&self.body);
+        out
+    }
+}
+
+/// Response to a [`ClientRequest`].
+#[derive(Clone, Debug)]
+pub struct ClientResponse {
+    /// The response status code.
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    /// The response body, with chunked transfer encoding removed.
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    /// Value of a response header. Names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Response headers, as names and values.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// Error of a [`ClientRequest`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientError {
+    /// The method, host, path or a header of the request is invalid.
+    InvalidRequest,
+    /// The address is not a numeric address with a port.
+    Address,
+    /// Connecting failed.
+    Connect,
+    /// Sending the request or reading the response failed, or the connection was closed
+    /// before the end of the response.
+    Io,
+    /// The exchange took longer than the timeout of the request.
+    Timeout,
+    /// The response is not valid HTTP.
+    Malformed,
+    /// The response is larger than allowed by the request.
+    TooLarge,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::InvalidRequest => write!(f, "invalid request"),
+            ClientError::Address => write!(f, "invalid address"),
+            ClientError::Connect => write!(f, "connect failed"),
+            ClientError::Io => write!(f, "connection error"),
+            ClientError::Timeout => write!(f, "timed out"),
+            ClientError::Malformed => write!(f, "malformed response"),
+            ClientError::TooLarge => write!(f, "response too large"),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+type Callback = Box<dyn FnOnce(&mut Request, Result<ClientResponse, ClientError>) -> Status>;
+
+impl Request {
+    /// Send an HTTP request to `address` without blocking the worker, and call `callback`
+    /// with the request and the response once it is complete.
+    ///
+    /// The request is held until then. The status returned by `callback` finalizes the
+    /// request:
+    /// - from a content handler, return [`DONE`] after `fetch` succeeds, and send the
+    ///   response from `callback`, returning its status,
+    /// - from a phase handler such as an access handler, return
+    ///   [`Access::Pending`](crate::http::Access::Pending) after `fetch` succeeds. `callback`
+    ///   saves the response for the handler, for example in a variable with
+    ///   [`Request::set_variable`], resumes the phases with [`Request::run_phases`] and
+    ///   returns [`DONE`]. The handler then runs again and decides based on the saved
+    ///   response.
+    ///
+    /// `address` is numeric, with a port: names are not resolved, as this would block the
+    /// worker. Resolve them first with [`Request::resolve_name`]. If the connection can't be
+    /// started, the error is returned and `callback` is never called; otherwise `callback`
+    /// runs from the event loop, never from `fetch` itself.
+    ///
+    /// If the request is freed first, for example because the client closed the connection,
+    /// the outbound request is aborted and `callback` is not called.
+    ///
+    /// With [`ClientRequest::set_keepalive`], the connection is taken from the pool if one to
+    /// `address` is idle. A connection the server closed meanwhile fails the request with
+    /// [`ClientError::Io`]; it is not retried.
+    ///
+    /// ```ignore
+    /// let score = ClientRequest::post("scoring.internal", "/v1/score", payload)?;
+    /// request.fetch("10.0.0.5:8080", score, |request: &mut Request, response| {
+    ///     let score = response.ok().filter(|response| response.status == 200).map(|response| response.body);
+    ///     request.set_variable(SCORE_VARIABLE, &score.unwrap_or_default());
+    ///     request.run_phases();
+    ///     DONE
+    /// })?;
+    /// Access::Pending
+    /// ```
+    pub fn fetch<F>(&mut self, address: &str, request: ClientRequest, callback: F) -> Result<(), ClientError>
+    where
+        F: FnOnce(&mut Request, Result<ClientResponse, ClientError>) -> Status + 'static,
+    {
+        unsafe {
+            let r = self.as_ngx_http_request_mut();
+            let cln = ngx_http_cleanup_add(r, 0);
+            if cln.is_null() {
+                return Err(ClientError::Connect);
+            }
+
+            let log = (*(*r).connection).log;
+            let fetch = Box::into_raw(Box::new(Fetch {
+                request: r,
+                cleanup: cln,
+                peer: None,
+                timer: mem::zeroed(),
+                keepalive: request.keepalive,
+                response: ResponseParser::new(request.method == "HEAD", request.max_response.as_bytes()),
+                write_event_handler: (*r).write_event_handler,
+                callback: Some(Box::new(callback)),
+            }));
+            let f = &mut *fetch;
+
+            // The handler is not called before `connect` returns, and not after the
+            // connection is closed, which the fetch does before it is freed.
+            let handler = move |pc: &mut PeerConnection, event| (*fetch).on_event(pc, event);
+            let connected = match request.keepalive {
+                Some(pool) => PeerConnection::connect_pooled(address, request.timeout, pool, handler),
+                None => PeerConnection::connect(address, request.timeout, handler),
+            };
+            let peer = match connected {
+                Ok(peer) => peer,
+                Err(err) => {
+                    drop(Box::from_raw(fetch));
+                    return Err(err.into());
+                }
+            };
+            peer.with(|pc| pc.write(&request.serialize()));
+            f.peer = Some(peer);
+
+            (*cln).handler = Some(ngx_http_rs_client_cleanup);
+            (*cln).data = fetch as *mut c_void;
+
+            f.timer.handler = Some(ngx_http_rs_client_timeout_handler);
+            f.timer.data = fetch as *mut c_void;
+            f.timer.log = log;
+            ngx_add_timer(&mut f.timer, request.timeout.as_msec());
+
+            // Events of the client connection must not run the phases again while waiting.
+            (*r).write_event_handler = Some(ngx_http_request_empty_handler);
+            (*(*r).main).count += 1;
+        }
+        Ok(())
+    }
+
+    /// Send an HTTP request to `address`, resolving to the response, for use from a task of
+    /// the async executor.
+    ///
+    /// The exchange starts right away. See [`Request::fetch`]; if the request is freed first,
+    /// the future resolves to [`ClientError::Io`].
+    pub fn fetch_async(&mut self, address: &str, request: ClientRequest) -> impl Future<Output = Result<ClientResponse, ClientError>> {
+        let (tx, rx) = oneshot();
+        let started = self.fetch(address, request, move |_: &mut Request, response| {
+            tx.send(response);
+            DONE
+        });
+        async move {
+            started?;
+            rx.await.unwrap_or(Err(ClientError::Io))
+        }
+    }
+}
+
+struct Fetch {
+    request: *mut ngx_http_request_t,
+    cleanup: *mut ngx_http_cleanup_t,
+    peer: Option<PeerHandle>,
+    timer: ngx_event_t,
+    keepalive: Option<&'static ConnectionPool>,
+    response: ResponseParser,
+    write_event_handler: ngx_http_event_handler_pt,
+    callback: Option<Callback>,
+}
+
+impl Fetch {
+    // Handle an event of the connection, from its handler.
+    unsafe fn on_event(&mut self, pc: &mut PeerConnection, event: PeerEvent) {
+        let result = match event {
+            PeerEvent::Connected => return,
+            PeerEvent::Data(data) => match self.response.parse(data) {
+                Ok(None) => return,
+                Ok(Some(response)) => Ok(response),
+                Err(err) => Err(err),
+            },
+            PeerEvent::Closed(Ok(())) => self.response.finish(),
+            PeerEvent::Closed(Err(err)) => Err(err.into()),
+        };
+        match (self.keepalive, &result) {
+            (Some(pool), Ok(response)) if self.response.reusable(response) => {
+                pc.release_to(pool);
+            }
+            _ => pc.close(),
+        }
+        self.finish(result);
+    }
+
+    // Release the connection and timer. The fetch must not be used afterwards.
+    unsafe fn close(&mut self) -> Box<Fetch> {
+        if let Some(peer) = self.peer.take() {
+            peer.with(|pc| pc.close());
+        }
+        if self.timer.timer_set() != 0 {
+            ngx_del_timer(&mut self.timer);
+        }
+        (*self.cleanup).handler = None;
+        Box::from_raw(self)
+    }
+
+    unsafe fn finish(&mut self, result: Result<ClientResponse, ClientError>) {
+        let mut fetch = self.close();
+        let r = fetch.request;
+        let c = (*r).connection;
+        (*r).write_event_handler = fetch.write_event_handler;
+
+        let callback = fetch.callback.take();
+        drop(fetch);
+
+        let rc = match callback {
+            Some(callback) => callback(Request::from_ngx_http_request(r), result),
+            None => ERROR,
+        };
+        ngx_http_finalize_request(r, rc.0);
+        ngx_http_run_posted_requests(c);
+    }
+}
+
+unsafe extern "C" fn ngx_http_rs_client_cleanup(data: *mut c_void) {
+    let fetch = &mut *(data as *mut Fetch);
+    drop(fetch.close());
+}
+
+unsafe extern "C" fn ngx_http_rs_client_timeout_handler(ev: *mut ngx_event_t) {
+    let fetch = &mut *((*ev).data as *mut Fetch);
+    fetch.finish(Err(ClientError::Timeout));
+}
+
+impl From<PeerError> for ClientError {
+    fn from(err: PeerError) -> ClientError {
+        match err {
+            PeerError::Address => ClientError::Address,
+            PeerError::Connect => ClientError::Connect,
+            PeerError::Io => ClientError::Io,
+            PeerError::Timeout => ClientError::Timeout,
+        }
+    }
+}
+
+impl From<HttpParseError> for ClientError {
+    fn from(_: HttpParseError) -> ClientError {
+        ClientError::Malformed
+    }
+}
+
+enum ResponseState {
+    StatusLine,
+    Headers,
+    Length(usize),
+    Chunked(ChunkedDecoder),
+    UntilClose,
+}
+
+// Incremental parser of a response, with the parsers of Nginx. Interim (1xx) responses are
+// skipped, and responses over `max_size` are rejected.
+struct ResponseParser {
+    state: ResponseState,
+    head: bool,
+    max_size: usize,
+    received: usize,
+    // Input not parsed yet, such as an incomplete line.
+    input: Vec<u8>,
+    header_parser: HeaderParser,
+    http_version: ngx_uint_t,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl ResponseParser {
+    // Responses to `HEAD` requests (`head`) have no body, whatever their headers say.
+    fn new(head: bool, max_size: usize) -> ResponseParser {
+        ResponseParser {
+            state: ResponseState::StatusLine,
+            head,
+            max_size,
+            received: 0,
+            input: Vec::new(),
+            header_parser: HeaderParser::new(true),
+            http_version: 0,
+            status: 0,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    // Parse the next data received, returning the response once complete.
+    fn parse(&mut self, data: &[u8]) -> Result<Option<ClientResponse>, ClientError> {
+        self.received += data.len();
+        if self.received > self.max_size {
+            return Err(ClientError::TooLarge);
+        }
+        self.input.extend_from_slice(data);
+
+        let mut pos = 0;
+        let result = self.parse_input(&mut pos);
+        self.input.drain(..pos);
+        result
+    }
+
+    // The connection was closed: without `Content-Length` or chunked encoding, this ends the
+    // response.
+    fn finish(&mut self) -> Result<ClientResponse, ClientError> {
+        match self.state {
+            ResponseState::UntilClose => Ok(self.response()),
+            _ => Err(ClientError::Io),
+        }
+    }
+
+    fn parse_input(&mut self, pos: &mut usize) -> Result<Option<ClientResponse>, ClientError> {
+        loop {
+            let input = &self.input[*pos..];
+            match self.state {
+                ResponseState::StatusLine => match parse_status_line(input)? {
+                    Some((len, line)) => {
+                        self.status = u16::try_from(line.code).map_err(|_| ClientError::Malformed)?;
+                        self.http_version = line.http_version;
+                        self.state = ResponseState::Headers;
+                        *pos += len;
+                    }
+                    None => return Ok(None),
+                },
+                ResponseState::Headers => match self.header_parser.parse(input)? {
+                    Some((len, HeaderLine::Header { name, value })) => {
+                        let header = (String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned());
+                        self.headers.push(header);
+                        *pos += len;
+                    }
+                    Some((len, HeaderLine::Done)) => {
+                        *pos += len;
+                        if let Some(response) = self.start_body()? {
+                            return Ok(Some(response));
+                        }
+                    }
+                    None => return Ok(None),
+                },
+                ResponseState::Length(length) => {
+                    let len = input.len().min(length - self.body.len());
+                    self.body.extend_from_slice(&input[..len]);
+                    *pos += len;
+                    if self.body.len() < length {
+                        return Ok(None);
+                    }
+                    return Ok(Some(self.response()));
+                }
+                ResponseState::Chunked(ref mut decoder) => {
+                    let (len, chunk) = decoder.decode(input)?;
+                    *pos += len;
+                    match chunk {
+                        Chunk::Data(data) => {
+                            if data.len() > self.max_size - self.body.len() {
+                                return Err(ClientError::TooLarge);
+                            }
+                            self.body.extend_from_slice(data);
+                        }
+                        Chunk::Again => return Ok(None),
+                        Chunk::Done => return Ok(Some(self.response())),
+                    }
+                }
+                ResponseState::UntilClose => {
+                    self.body.extend_from_slice(input);
+                    *pos += input.len();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    // Headers are complete: find how the body is framed, returning the response if it has
+    // none.
+    fn start_body(&mut self) -> Result<Option<ClientResponse>, ClientError> {
+        // Interim responses, such as `100 Continue`, are followed by the final one.
+        if self.status / 100 == 1 && self.status != 101 {
+            self.headers.clear();
+            self.state = ResponseState::StatusLine;
+            return Ok(None);
+        }
+        if self.head || self.status == 101 || self.status == 204 || self.status == 304 {
+            return Ok(Some(self.response()));
+        }
+
+        let response = ClientResponse { status: self.status, headers: mem::take(&mut self.headers), body: Vec::new() };
+        let chunked = response.header("Transfer-Encoding").map_or(false, |value| value.eq_ignore_ascii_case("chunked"));
+        self.state = if chunked {
+            ResponseState::Chunked(ChunkedDecoder::new())
+        } else if let Some(length) = response.header("Content-Length") {
+            let length: usize = length.parse().map_err(|_| ClientError::Malformed)?;
+            if length > self.max_size {
+                return Err(ClientError::TooLarge);
+            }
+            ResponseState::Length(length)
+        } else {
+            ResponseState::UntilClose
+        };
+        self.headers = response.headers;
+        Ok(None)
+    }
+
+    // Can the connection be reused after `response`, as parsed last? The response must be
+    // HTTP/1.1 without `Connection: close`, delimited by its headers, and not followed by
+    // unexpected data.
+    fn reusable(&self, response: &ClientResponse) -> bool {
+        let close = response
+            .header("Connection")
+            .map_or(false, |value| value.split(',').any(|token| token.trim().eq_ignore_ascii_case("close")));
+        self.http_version >= NGX_HTTP_VERSION_11 as ngx_uint_t
+            && response.status != 101
+            && !close
+            && !matches!(self.state, ResponseState::UntilClose)
+            && self.input.is_empty()
+    }
+
+    fn response(&mut self) -> ClientResponse {
+        ClientResponse { status: self.status, headers: mem::take(&mut self.headers), body: mem::take(&mut self.body) }
+    }
+}
+
+// Is `value` a token, as HTTP methods and header names are?
+fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Can `value` be sent on a request or header line without ending it early? Spaces are
+// allowed in header values (`allow_space`), but not in the host or path.
+fn is_field(value: &str, allow_space: bool) -> bool {
+    value.bytes().all(|b| ((b == b' ' || b == b'\t') && allow_space) || (b > b' ' && b != 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let mut request = ClientRequest::post("scoring.internal", "/v1/score?x=1", "{}").unwrap();
+        request.add_header("X-Tenant", "a b\tc").unwrap();
+        assert_eq!(
+            request.serialize(),
+            b"POST /v1/score?x=1 HTTP/1.1\r\nHost: scoring.internal\r\nConnection: close\r\n\
+              X-Tenant: a b\tc\r\nContent-Length: 2\r\n\r\n{}"
+                .to_vec()
+        );
+
+        let request = ClientRequest::get("scoring.internal", "/").unwrap();
+        assert_eq!(request.serialize(), b"GET / HTTP/1.1\r\nHost: scoring.internal\r\nConnection: close\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn invalid_request_line() {
+        assert_eq!(ClientRequest::new("GET /x HTTP/1.1\r\n", "a", "/").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::new("", "a", "/").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::get("a\r\nX-Injected: 1", "/").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::get("", "/").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::get("a", "/x HTTP/1.0").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::get("a", "/\n").unwrap_err(), ClientError::InvalidRequest);
+        assert_eq!(ClientRequest::get("a", "").unwrap_err(), ClientError::InvalidRequest);
+        assert!(ClientRequest::new("M-SEARCH", "[::1]:8080", "*").is_ok());
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut request = ClientRequest::get("a", "/").unwrap();
+        assert_eq!(request.add_header("X-A", "1\r\nX-Injected: 1"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("X-A", "1\n"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("X-A", "1\0"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("X-A:", "1"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("X A", "1"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("", "1"), Err(ClientError::InvalidRequest));
+        assert_eq!(request.add_header("X-Empty", ""), Ok(()));
+        assert_eq!(request.headers.len(), 1);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::conf::*;
+use crate::http::request::Request;
+
+/// Capture the ClientHello of TLS connections to all servers of the `http` block being
+/// configured, for TLS fingerprinting with [`Request::client_hello`], [`Request::ja3`] and
+/// [`Request::ja4`]. `handler` is called with each message during the handshake, see
+/// [`ClientHelloHandler`] for what it may do.
+///
+/// Call this from the `postconfiguration` handler of the module, once the SSL contexts of
+/// the servers have been created.
+///
+/// ```ignore
+/// unsafe extern "C" fn ngx_http_fingerprint_postconfiguration(cf: *mut ngx_conf_t) -> ngx_int_t {
+///     capture_client_hellos(cf, None).into()
+/// }
+///
+/// http_access_handler!(ngx_http_fingerprint_access_handler, |request: &mut Request| {
+///     match request.ja4() {
+///         Some(ja4) if BLOCKED.contains(&ja4.as_str()) => Access::Deny(HTTP_FORBIDDEN),
+///         _ => Access::Decline,
+///     }
+/// });
+/// ```
+pub unsafe fn capture_client_hellos(cf: *mut ngx_conf_t, handler: Option<ClientHelloHandler>) -> Status {
+    let cmcf = ngx_http_conf_get_module_main_conf(cf, &ngx_http_core_module) as *mut ngx_http_core_main_conf_t;
+    let servers = std::slice::from_raw_parts(
+        (*cmcf).servers.elts as *const *mut ngx_http_core_srv_conf_t,
+        (*cmcf).servers.nelts,
+    );
+    for &cscf in servers {
+        let sscf = *(*(*cscf).ctx).srv_conf.add(ngx_http_ssl_module.ctx_index) as *mut ngx_http_ssl_srv_conf_t;
+        if !sscf.is_null() && !(*sscf).ssl.ctx.is_null() {
+            if capture_client_hello(cf, &mut (*sscf).ssl, handler, None) != OK {
+                return ERROR;
+            }
+        }
+    }
+    OK
+}
+
+impl Request {
+    /// The ClientHello of the TLS connection, if it was captured with
+    /// [`capture_client_hellos`].
+    ///
+    /// With HTTP/2, this is the ClientHello of the client connection shared by the streams.
+    pub fn client_hello(&self) -> Option<ClientHello> {
+        self.connection().client_hello()?.ok()
+    }
+
+    /// The raw ClientHello message, see [`Request::client_hello`].
+    pub fn client_hello_message(&self) -> Option<&[u8]> {
+        self.connection().client_hello_message()
+    }
+
+    /// [JA3] fingerprint hash of the TLS client, see [`ClientHello::ja3_hash`].
+    ///
+    /// [JA3]: https://github.com/salesforce/ja3
+    pub fn ja3(&self) -> Option<String> {
+        self.client_hello().map(|hello| hello.ja3_hash())
+    }
+
+    /// [JA4] fingerprint of the TLS client, see [`ClientHello::ja4`], or
+    /// [`ClientHello::ja4_quic`] with HTTP/3.
+    ///
+    /// [JA4]: https://github.com/FoxIO-LLC/ja4
+    pub fn ja4(&self) -> Option<String> {
+        self.client_hello().map(|hello| if self.is_http3() { hello.ja4_quic() } else { hello.ja4() })
+    }
+}
@@ -0,0 +1,423 @@
+use crate::bindings::*;
+use crate::core::*;
+
+use std::collections::HashSet;
+use std::ffi::{CString, OsStr};
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::slice;
+
+/// Define a static table of HTTP module [configuration directives].
+///
+/// Each directive is given as its name, a list of flags and a setter. Flags combine the
+/// contexts the directive is allowed in (`main`, `srv`, `loc`, `sif`, `lif`, `lmt`, `ups`)
+/// with its number of arguments (`noargs`, `take1` ... `take7`, `take12`, `take13`, `take23`,
+/// `take123`, `take1234`, `flag`, `more1`, `more2`, `any`, `block`).
+///
+/// The setter names the configuration the directive applies to (`main`, `srv` or `loc`, the
+/// configuration types of the given [`HTTPModule`]) and either a field name with the slot type
+/// used to parse the argument, or a custom handler:
+///
+/// - `str(loc, field)`: the argument as a `String`
+/// - `flag(loc, field)`: `on` or `off` as a `bool`
+/// - `num(loc, field)`: a non-negative integer as a `usize`
+/// - `msec(loc, field)`: a time interval (`10s`, `500ms`) as [`Msec`](crate::core::Msec)
+/// - `sec(loc, field)`: a time interval in seconds as [`Sec`](crate::core::Sec)
+/// - `size(loc, field)`: a size (`16k`, `1m`) as [`ByteSize`](crate::core::ByteSize)
+/// - `path(loc, field)`: a file path, resolved relative to the configuration prefix
+/// - `file(loc, field)`: a JSON, YAML or TOML file, resolved like `path`, read into any serde
+///   type with [`load_conf_file`](crate::core::load_conf_file) (with the `serde` feature)
+/// - `content(loc, handler)`: no argument; makes a handler defined with
+///   [`http_request_handler!`](crate::http_request_handler) the content handler of the
+///   location, as `proxy_pass` does
+/// - `handler(loc, function)`: any `ngx_command_t` set handler
+///
+/// Directives of [stream modules](crate::stream::StreamModule) use the `stream_main`,
+/// `stream_srv` and `stream_ups` contexts, and `stream_main` or `stream_srv` configurations.
+/// Likewise, directives of [mail modules](crate::mail::MailModule) use `mail_main` and
+/// `mail_srv`.
+///
+/// Fields may be of the parsed type or any type that converts from it (such as an `Option`).
+/// A field can only be set once per configuration block: a second directive setting it is
+/// rejected as a duplicate, as for the slots of Nginx. The table is terminated with
+/// [`ngx_null_command!`].
+///
+/// Several names separated by `|` are aliases setting the same field. A directive followed by
+/// `deprecated "replacement"` still works but logs a warning pointing to its replacement, so
+/// directives can be renamed without breaking existing configurations.
+///
+/// ```ignore
+/// ngx_commands! {
+///     static ngx_http_hello_world_commands: Module = [
+///         "hello_world" [loc, noargs] => content(loc, ngx_http_hello_world_handler),
+///         "hello_text" | "hello_message" [loc, take1] => str(loc, text),
+///         "hello_world_text" [loc, take1] => str(loc, text) deprecated "hello_text",
+///     ];
+/// }
+/// ```
+///
+/// [configuration directives]: https://nginx.org/en/docs/dev/development_guide.html#config_directives
+/// [`HTTPModule`]: crate::http::HTTPModule
+#[macro_export]
+macro_rules! ngx_commands {
+    ( static $name: ident : $module: ty = [ $( $entries: tt )* ]; ) => {
+        $crate::__ngx_commands!(@munch $name, $module; []; []; $( $entries )*);
+    };
+}
+
+// Munches the directive table one name at a time, collecting the aliases of an entry until
+// its flags are reached, and accumulating the commands.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_commands {
+    (@munch $name: ident, $module: ty; [ $( $command: tt )* ]; [];) => {
+        #[no_mangle]
+        static mut $name: [$crate::__private::bindings::ngx_command_t; $crate::count!($( $command, )*) + 1] = [
+            $( $command, )*
+            $crate::ngx_null_command!(),
+        ];
+    };
+    (@munch $name: ident, $module: ty; [ $( $command: tt )* ]; [ $( $alias: literal )* ]; $directive: literal | $( $rest: tt )*) => {
+        $crate::__ngx_commands!(@munch $name, $module; [ $( $command )* ]; [ $( $alias )* $directive ]; $( $rest )*);
+    };
+    (
+        @munch $name: ident, $module: ty; [ $( $command: tt )* ]; [ $( $alias: literal )* ];
+        $directive: literal $flags: tt => $slot: ident $args: tt deprecated $replacement: literal $(, $( $rest: tt )* )?
+    ) => {
+        $crate::__ngx_commands!(@munch $name, $module;
+            [
+                $( $command )*
+                $( { $crate::__ngx_command!($module, $alias, $flags, $slot $args, [ $replacement ]) } )*
+                { $crate::__ngx_command!($module, $directive, $flags, $slot $args, [ $replacement ]) }
+            ];
+            [];
+            $( $( $rest )* )?
+        );
+    };
+    (
+        @munch $name: ident, $module: ty; [ $( $command: tt )* ]; [ $( $alias: literal )* ];
+        $directive: literal $flags: tt => $slot: ident $args: tt $(, $( $rest: tt )* )?
+    ) => {
+        $crate::__ngx_commands!(@munch $name, $module;
+            [
+                $( $command )*
+                $( { $crate::__ngx_command!($module, $alias, $flags, $slot $args, []) } )*
+                { $crate::__ngx_command!($module, $directive, $flags, $slot $args, []) }
+            ];
+            [];
+            $( $( $rest )* )?
+        );
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_command {
+    ( $module: ty, $directive: literal, [ $( $flag: ident ),+ ], handler ( $conf: ident, $handler: ident ), [] ) => {
+        $crate::__private::bindings::ngx_command_t {
+            name: $crate::ngx_string!($directive),
+            type_: ($( $crate::__ngx_command_flag!($flag) )|+) as $crate::__private::bindings::ngx_uint_t,
+            set: Some($handler),
+            conf: $crate::__ngx_command_conf_offset!($conf),
+            offset: 0,
+            post: ::std::ptr::null_mut(),
+        }
+    };
+    ( $module: ty, $directive: literal, [ $( $flag: ident ),+ ], handler ( $conf: ident, $handler: ident ), [ $replacement: literal ] ) => {
+        $crate::__private::bindings::ngx_command_t {
+            name: $crate::ngx_string!($directive),
+            type_: ($( $crate::__ngx_command_flag!($flag) )|+) as $crate::__private::bindings::ngx_uint_t,
+            set: Some({
+                unsafe extern "C" fn set(
+                    cf: *mut $crate::__private::bindings::ngx_conf_t,
+                    cmd: *mut $crate::__private::bindings::ngx_command_t,
+                    conf: *mut ::std::os::raw::c_void,
+                ) -> *mut ::std::os::raw::c_char {
+                    $crate::http::conf_warn_deprecated(cf, cmd, $replacement);
+                    $handler(cf, cmd, conf)
+                }
+                set
+            }),
+            conf: $crate::__ngx_command_conf_offset!($conf),
+            offset: 0,
+            post: ::std::ptr::null_mut(),
+        }
+    };
+    ( $module: ty, $directive: literal, [ $( $flag: ident ),+ ], content ( $conf: ident, $handler: ident ), [ $( $replacement: literal )? ] ) => {
+        $crate::__private::bindings::ngx_command_t {
+            name: $crate::ngx_string!($directive),
+            type_: ($( $crate::__ngx_command_flag!($flag) )|+) as $crate::__private::bindings::ngx_uint_t,
+            set: Some({
+                unsafe extern "C" fn set(
+                    cf: *mut $crate::__private::bindings::ngx_conf_t,
+                    _cmd: *mut $crate::__private::bindings::ngx_command_t,
+                    _conf: *mut ::std::os::raw::c_void,
+                ) -> *mut ::std::os::raw::c_char {
+                    $( $crate::http::conf_warn_deprecated(cf, _cmd, $replacement); )?
+                    $crate::http::ngx_http_conf_set_content_handler(cf, $handler);
+                    ::std::ptr::null_mut()
+                }
+                set
+            }),
+            conf: $crate::__ngx_command_conf_offset!($conf),
+            offset: 0,
+            post: ::std::ptr::null_mut(),
+        }
+    };
+    ( $module: ty, $directive: literal, [ $( $flag: ident ),+ ], $slot: ident ( $conf: ident, $field: ident ), [ $( $replacement: literal )? ] ) => {
+        $crate::__private::bindings::ngx_command_t {
+            name: $crate::ngx_string!($directive),
+            type_: ($( $crate::__ngx_command_flag!($flag) )|+) as $crate::__private::bindings::ngx_uint_t,
+            set: Some({
+                unsafe extern "C" fn set(
+                    cf: *mut $crate::__private::bindings::ngx_conf_t,
+                    cmd: *mut $crate::__private::bindings::ngx_command_t,
+                    conf: *mut ::std::os::raw::c_void,
+                ) -> *mut ::std::os::raw::c_char {
+                    $( $crate::http::conf_warn_deprecated(cf, cmd, $replacement); )?
+                    let conf = &mut *(conf as *mut $crate::__ngx_command_conf_type!($module, $conf));
+                    $crate::http::conf_slot_result(cf, cmd, ($crate::__ngx_command_slot!($slot))(cf, &mut conf.$field))
+                }
+                set
+            }),
+            conf: $crate::__ngx_command_conf_offset!($conf),
+            offset: 0,
+            post: ::std::ptr::null_mut(),
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_command_flag {
+    (main) => { $crate::__private::bindings::NGX_HTTP_MAIN_CONF };
+    (srv) => { $crate::__private::bindings::NGX_HTTP_SRV_CONF };
+    (loc) => { $crate::__private::bindings::NGX_HTTP_LOC_CONF };
+    (sif) => { $crate::__private::bindings::NGX_HTTP_SIF_CONF };
+    (lif) => { $crate::__private::bindings::NGX_HTTP_LIF_CONF };
+    (lmt) => { $crate::__private::bindings::NGX_HTTP_LMT_CONF };
+    (ups) => { $crate::__private::bindings::NGX_HTTP_UPS_CONF };
+    (stream_main) => { $crate::__private::bindings::NGX_STREAM_MAIN_CONF };
+    (stream_srv) => { $crate::__private::bindings::NGX_STREAM_SRV_CONF };
+    (stream_ups) => { $crate::__private::bindings::NGX_STREAM_UPS_CONF };
+    (mail_main) => { $crate::__private::bindings::NGX_MAIL_MAIN_CONF };
+    (mail_srv) => { $crate::__private::bindings::NGX_MAIL_SRV_CONF };
+    (noargs) => { $crate::__private::bindings::NGX_CONF_NOARGS };
+    (take1) => { $crate::__private::bindings::NGX_CONF_TAKE1 };
+    (take2) => { $crate::__private::bindings::NGX_CONF_TAKE2 };
+    (take3) => { $crate::__private::bindings::NGX_CONF_TAKE3 };
+    (take4) => { $crate::__private::bindings::NGX_CONF_TAKE4 };
+    (take5) => { $crate::__private::bindings::NGX_CONF_TAKE5 };
+    (take6) => { $crate::__private::bindings::NGX_CONF_TAKE6 };
+    (take7) => { $crate::__private::bindings::NGX_CONF_TAKE7 };
+    (take12) => { $crate::__private::bindings::NGX_CONF_TAKE1 | $crate::__private::bindings::NGX_CONF_TAKE2 };
+    (take13) => { $crate::__private::bindings::NGX_CONF_TAKE1 | $crate::__private::bindings::NGX_CONF_TAKE3 };
+    (take23) => { $crate::__private::bindings::NGX_CONF_TAKE2 | $crate::__private::bindings::NGX_CONF_TAKE3 };
+    (take123) => { $crate::__private::bindings::NGX_CONF_TAKE1 | $crate::__private::bindings::NGX_CONF_TAKE2 | $crate::__private::bindings::NGX_CONF_TAKE3 };
+    (take1234) => {
+        $crate::__private::bindings::NGX_CONF_TAKE1 | $crate::__private::bindings::NGX_CONF_TAKE2
+            | $crate::__private::bindings::NGX_CONF_TAKE3 | $crate::__private::bindings::NGX_CONF_TAKE4
+    };
+    (flag) => { $crate::__private::bindings::NGX_CONF_FLAG };
+    (more1) => { $crate::__private::bindings::NGX_CONF_1MORE };
+    (more2) => { $crate::__private::bindings::NGX_CONF_2MORE };
+    (any) => { $crate::__private::bindings::NGX_CONF_ANY };
+    (block) => { $crate::__private::bindings::NGX_CONF_BLOCK };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_command_conf_offset {
+    (main) => { $crate::__private::bindings::NGX_RS_HTTP_MAIN_CONF_OFFSET };
+    (srv) => { $crate::__private::bindings::NGX_RS_HTTP_SRV_CONF_OFFSET };
+    (loc) => { $crate::__private::bindings::NGX_RS_HTTP_LOC_CONF_OFFSET };
+    (stream_main) => { $crate::__private::bindings::NGX_RS_STREAM_MAIN_CONF_OFFSET };
+    (stream_srv) => { $crate::__private::bindings::NGX_RS_STREAM_SRV_CONF_OFFSET };
+    (mail_main) => { $crate::__private::bindings::NGX_RS_MAIL_MAIN_CONF_OFFSET };
+    (mail_srv) => { $crate::__private::bindings::NGX_RS_MAIL_SRV_CONF_OFFSET };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_command_conf_type {
+    ($module: ty, main) => { <$module as $crate::http::HTTPModule>::MainConf };
+    ($module: ty, srv) => { <$module as $crate::http::HTTPModule>::SrvConf };
+    ($module: ty, loc) => { <$module as $crate::http::HTTPModule>::LocConf };
+    ($module: ty, stream_main) => { <$module as $crate::stream::StreamModule>::MainConf };
+    ($module: ty, stream_srv) => { <$module as $crate::stream::StreamModule>::SrvConf };
+    ($module: ty, mail_main) => { <$module as $crate::mail::MailModule>::MainConf };
+    ($module: ty, mail_srv) => { <$module as $crate::mail::MailModule>::SrvConf };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_command_slot {
+    (str) => { $crate::http::set_str_slot };
+    (flag) => { $crate::http::set_flag_slot };
+    (num) => { $crate::http::set_num_slot };
+    (msec) => { $crate::http::set_msec_slot };
+    (sec) => { $crate::http::set_sec_slot };
+    (size) => { $crate::http::set_size_slot };
+    (path) => { $crate::http::set_path_slot };
+    (file) => { $crate::http::set_file_slot };
+}
+
+/// Arguments of the directive currently being parsed, including the directive name.
+pub unsafe fn conf_args<'a>(cf: *mut ngx_conf_t) -> &'a [ngx_str_t] {
+    let args = (*cf).args;
+    slice::from_raw_parts((*args).elts as *const ngx_str_t, (*args).nelts)
+}
+
+/// First argument of the directive currently being parsed.
+unsafe fn conf_value<'a>(cf: *mut ngx_conf_t) -> Result<&'a NgxStr, String> {
+    match conf_args(cf).get(1) {
+        Some(value) => Ok(NgxStr::from_ngx_str(*value)),
+        None => Err(String::from("requires an argument")),
+    }
+}
+
+// Fields set by directives so far, by address, kept in the pool of the cycle so each
+// configuration starts afresh.
+struct SetFields(HashSet<usize>);
+
+/// Record that the directive being parsed sets `field`, or return an error if a directive
+/// already set it in this configuration.
+///
+/// The directive setters call this, so a field is set once per configuration block, as with
+/// the `NGX_CONF_UNSET` check of the slots of Nginx.
+pub unsafe fn conf_set_once<T>(cf: *mut ngx_conf_t, field: &T) -> Result<(), String> {
+    let mut pool = Pool::from_ngx_pool((*cf).pool);
+    let mut fields = pool.get_local::<SetFields>();
+    if fields.is_null() {
+        fields = pool.insert_local(SetFields(HashSet::new()));
+        if fields.is_null() {
+            return Err(String::from("failed to allocate"));
+        }
+    }
+    if !(*fields).0.insert(field as *const T as usize) {
+        return Err(String::from("is duplicate"));
+    }
+    Ok(())
+}
+
+/// Convert the result of a directive setter into the value returned to Nginx, logging the error.
+#[doc(hidden)]
+pub unsafe fn conf_slot_result(cf: *mut ngx_conf_t, cmd: *mut ngx_command_t, result: Result<(), String>) -> *mut c_char {
+    match result {
+        Ok(()) => NGX_CONF_OK,
+        Err(message) => {
+            let name = NgxStr::from_ngx_str((*cmd).name);
+            let message = format!("\"{}\" directive {}", name, message);
+            let message = CString::new(message).unwrap_or_default();
+            let fmt = b"%s\0";
+            ngx_conf_log_error(NGX_LOG_EMERG as ngx_uint_t, cf, 0, fmt.as_ptr() as *const c_char, message.as_ptr());
+            NGX_CONF_ERROR
+        }
+    }
+}
+
+/// Warn that the directive being parsed is deprecated in favour of `replacement`.
+pub unsafe fn conf_warn_deprecated(cf: *mut ngx_conf_t, cmd: *mut ngx_command_t, replacement: &str) {
+    let name = NgxStr::from_ngx_str((*cmd).name);
+    let message = format!("the \"{}\" directive is deprecated, use the \"{}\" directive instead", name, replacement);
+    let message = CString::new(message).unwrap_or_default();
+    let fmt = b"%s\0";
+    ngx_conf_log_error(NGX_LOG_WARN as ngx_uint_t, cf, 0, fmt.as_ptr() as *const c_char, message.as_ptr());
+}
+
+fn invalid_value(value: &NgxStr) -> String {
+    format!("invalid value \"{}\"", value)
+}
+
+/// Directive setter for a string argument.
+pub unsafe fn set_str_slot<T: From<String>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    *field = T::from(value.to_string_lossy().into_owned());
+    Ok(())
+}
+
+/// Directive setter for an `on`/`off` argument.
+pub unsafe fn set_flag_slot<T: From<bool>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let flag = if value.as_bytes().eq_ignore_ascii_case(b"on") {
+        true
+    } else if value.as_bytes().eq_ignore_ascii_case(b"off") {
+        false
+    } else {
+        return Err(format!("{}, it must be \"on\" or \"off\"", invalid_value(value)));
+    };
+    *field = T::from(flag);
+    Ok(())
+}
+
+/// Directive setter for a non-negative integer argument.
+pub unsafe fn set_num_slot<T: From<usize>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let n = ngx_atoi(value.as_bytes().as_ptr() as *mut u_char, value.as_bytes().len());
+    if n == NGX_ERROR as ngx_int_t {
+        return Err(invalid_value(value));
+    }
+    *field = T::from(n as usize);
+    Ok(())
+}
+
+/// Directive setter for a time interval argument, in milliseconds.
+pub unsafe fn set_msec_slot<T: From<Msec>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let msec = value.to_str().ok().and_then(|value| Msec::parse(value).ok()).ok_or_else(|| invalid_value(value))?;
+    *field = T::from(msec);
+    Ok(())
+}
+
+/// Directive setter for a time interval argument, in seconds.
+pub unsafe fn set_sec_slot<T: From<Sec>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let sec = value.to_str().ok().and_then(|value| Sec::parse(value).ok()).ok_or_else(|| invalid_value(value))?;
+    *field = T::from(sec);
+    Ok(())
+}
+
+/// Directive setter for a size argument, in bytes.
+pub unsafe fn set_size_slot<T: From<ByteSize>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let size = value.to_str().ok().and_then(|value| ByteSize::parse(value).ok()).ok_or_else(|| invalid_value(value))?;
+    *field = T::from(size);
+    Ok(())
+}
+
+/// Directive setter for a file path argument.
+///
+/// Relative paths are resolved against the configuration prefix, see [`conf_full_name`].
+pub unsafe fn set_path_slot<T: From<PathBuf>>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let path = match conf_full_name(cf, value, true) {
+        Some(path) => path,
+        None => return Err(invalid_value(value)),
+    };
+    *field = T::from(PathBuf::from(OsStr::from_bytes(path.as_bytes())));
+    Ok(())
+}
+
+/// Directive setter for a structured configuration file, read into a serde type.
+///
+/// Relative paths are resolved against the configuration prefix. Errors point to the
+/// position in the file.
+#[cfg(feature = "serde")]
+pub unsafe fn set_file_slot<T: serde::de::DeserializeOwned>(cf: *mut ngx_conf_t, field: &mut T) -> Result<(), String> {
+    conf_set_once(cf, field)?;
+    let value = conf_value(cf)?;
+    let path = match conf_full_name(cf, value, true) {
+        Some(path) => PathBuf::from(OsStr::from_bytes(path.as_bytes())),
+        None => return Err(invalid_value(value)),
+    };
+    *field = load_conf_file(&path).map_err(|err| format!("failed to load {}", err))?;
+    Ok(())
+}
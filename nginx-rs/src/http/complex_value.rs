@@ -0,0 +1,69 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::mem;
+
+/// A compiled [complex value].
+///
+/// Complex values are compiled at configuration time, typically from a directive argument,
+/// and evaluated per request. The compiled value is allocated from the configuration pool, so
+/// the handle can be freely copied and stored in a module configuration.
+///
+/// [complex value]: https://nginx.org/en/docs/dev/development_guide.html#http_complex_values
+#[derive(Clone, Copy)]
+pub struct ComplexValue(*mut ngx_http_complex_value_t);
+
+impl ComplexValue {
+    /// Compile a complex value (`ngx_http_compile_complex_value`).
+    ///
+    /// Returns `None` if the value is invalid, for example when it references an unknown
+    /// variable. Nginx logs the reason as a configuration error.
+    pub unsafe fn compile(cf: *mut ngx_conf_t, value: &NgxStr) -> Option<ComplexValue> {
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+
+        let cv = pool.calloc_type::<ngx_http_complex_value_t>();
+        if cv.is_null() {
+            return None;
+        }
+
+        // Values without variables are used as is, so they must live as long as the configuration.
+        let mut value = NgxString::new_in(value, &mut pool)?.into_ngx_str();
+
+        let mut ccv: ngx_http_compile_complex_value_t = mem::zeroed();
+        ccv.cf = cf;
+        ccv.value = &mut value;
+        ccv.complex_value = cv;
+
+        if ngx_http_compile_complex_value(&mut ccv) != NGX_OK as ngx_int_t {
+            return None;
+        }
+
+        Some(ComplexValue(cv))
+    }
+
+    /// Create a [`ComplexValue`] from an already compiled [`ngx_http_complex_value_t`].
+    ///
+    /// [`ngx_http_complex_value_t`]: https://nginx.org/en/docs/dev/development_guide.html#http_complex_values
+    pub unsafe fn from_ngx_complex_value(cv: *mut ngx_http_complex_value_t) -> ComplexValue {
+        assert!(!cv.is_null());
+        ComplexValue(cv)
+    }
+
+    /// Pointer to the underlying [`ngx_http_complex_value_t`].
+    ///
+    /// [`ngx_http_complex_value_t`]: https://nginx.org/en/docs/dev/development_guide.html#http_complex_values
+    pub fn as_ngx_complex_value(&self) -> *mut ngx_http_complex_value_t {
+        self.0
+    }
+
+    /// Does the value contain no variables?
+    pub fn is_constant(&self) -> bool {
+        unsafe { (*self.0).lengths.is_null() }
+    }
+
+    /// Evaluate the value for a request.
+    pub fn evaluate<'a>(&self, request: &'a Request) -> Option<&'a NgxStr> {
+        request.get_complex_value(unsafe { &*self.0 })
+    }
+}
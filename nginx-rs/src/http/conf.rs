@@ -18,6 +18,16 @@ pub unsafe fn ngx_http_conf_get_module_loc_conf(cf: *mut ngx_conf_t, module: &ng
     *(*http_conf_ctx).loc_conf.add(module.ctx_index)
 }
 
+/// Set the content handler of the `location` block being configured, such as one defined
+/// with [`http_request_handler!`](crate::http_request_handler).
+///
+/// Call this from the handler of a directive allowed in `location` blocks, as `proxy_pass`
+/// or `return` do, or use the `content` setter of [`ngx_commands!`](crate::ngx_commands).
+pub unsafe fn ngx_http_conf_set_content_handler(cf: *mut ngx_conf_t, handler: unsafe extern "C" fn(*mut ngx_http_request_t) -> ngx_int_t) {
+    let clcf = ngx_http_conf_get_module_loc_conf(cf, &ngx_http_core_module) as *mut ngx_http_core_loc_conf_t;
+    (*clcf).handler = Some(handler);
+}
+
 pub unsafe fn ngx_cycle_conf_get_module_main_conf(cycle: *mut ngx_cycle_t, module: &ngx_module_t)  -> *mut c_void {
     let idx = ngx_http_module.index;
     let http_conf_ctx = *((*cycle).conf_ctx.add(idx)) as *mut ngx_http_conf_ctx_t ;
@@ -0,0 +1,379 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::command::conf_args;
+use crate::http::request::Request;
+
+use std::fmt;
+
+/// A parsed media type, as found in the `Content-Type` header.
+///
+/// The type and subtype, as well as parameter names, are lowercased; parameter values are
+/// unquoted.
+///
+/// ```ignore
+/// let content_type = ContentType::parse("multipart/form-data; boundary=\"abc\"").unwrap();
+/// assert_eq!(content_type.mime(), "multipart/form-data");
+/// assert_eq!(content_type.boundary(), Some("abc"));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContentType {
+    mime: String,
+    slash: usize,
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// Parse a media type such as `text/html; charset=utf-8`. Returns `None` if the value has
+    /// no valid `type/subtype`; malformed parameters are ignored.
+    pub fn parse(value: &str) -> Option<ContentType> {
+        let (mime, mut rest) = match value.find(';') {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => (value, ""),
+        };
+
+        let mime = mime.trim().to_ascii_lowercase();
+        let slash = mime.find('/')?;
+        if !is_token(&mime[..slash]) || !is_token(&mime[slash + 1..]) {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            let (param, next) = split_param(rest);
+            rest = next;
+            if let Some(eq) = param.find('=') {
+                let name = param[..eq].trim();
+                let value = unquote(param[eq + 1..].trim());
+                if is_token(name) {
+                    params.push((name.to_ascii_lowercase(), value));
+                }
+            }
+        }
+
+        Some(ContentType { mime, slash, params })
+    }
+
+    /// The type and subtype, such as `text/html`.
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    /// The top-level type, such as `text`.
+    pub fn main_type(&self) -> &str {
+        &self.mime[..self.slash]
+    }
+
+    /// The subtype, such as `html`.
+    pub fn subtype(&self) -> &str {
+        &self.mime[self.slash + 1..]
+    }
+
+    /// The value of the parameter `name`, compared case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All parameters, as lowercased name and value.
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The `charset` parameter.
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// The `boundary` parameter of a multipart type.
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary").filter(|boundary| !boundary.is_empty())
+    }
+
+    /// Is this a JSON type (`application/json` or a `+json` suffix)?
+    pub fn is_json(&self) -> bool {
+        self.mime == "application/json" || self.subtype().ends_with("+json")
+    }
+
+    /// Is this a multipart type?
+    pub fn is_multipart(&self) -> bool {
+        self.main_type() == "multipart"
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.mime)?;
+        for (name, value) in self.params.iter() {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Split the first parameter off `s`, ignoring separators in quoted strings.
+fn split_param(s: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, b) in s.bytes().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b';' if !quoted => return (&s[..i], &s[i + 1..]),
+            _ => {}
+        }
+    }
+    (s, "")
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return value.to_string();
+    }
+
+    let mut unquoted = String::with_capacity(value.len() - 2);
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// A set of media type patterns, such as the values of a `*_types` directive.
+///
+/// Patterns are `type/subtype`, `type/*` or `*` (any type), and match the type and subtype
+/// of a [`ContentType`] regardless of its parameters. Body filters use it to only process
+/// the configured types:
+///
+/// ```ignore
+/// http_body_filter!(ngx_http_rewrite_body_filter, |request: &mut Request, chain: Chain| {
+///     let conf = unsafe { &*(request.get_module_loc_conf(&ngx_http_rewrite_body_module) as *const LocConf) };
+///     if !conf.types.matches_response(request) {
+///         return NEXT_BODY_FILTER.call(request, chain);
+///     }
+///     // ...
+/// });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ContentTypeMatcher {
+    any: bool,
+    exact: Vec<String>,
+    main_types: Vec<String>,
+}
+
+impl ContentTypeMatcher {
+    /// An empty matcher, matching no type.
+    pub fn new() -> ContentTypeMatcher {
+        ContentTypeMatcher::default()
+    }
+
+    /// A matcher for the given patterns.
+    pub fn from_patterns<S: AsRef<str>>(patterns: &[S]) -> Result<ContentTypeMatcher, String> {
+        let mut matcher = ContentTypeMatcher::new();
+        for pattern in patterns {
+            matcher.add(pattern.as_ref())?;
+        }
+        Ok(matcher)
+    }
+
+    /// Add a pattern, in the form `type/subtype`, `type/*` or `*`.
+    pub fn add(&mut self, pattern: &str) -> Result<(), String> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        if pattern == "*" || pattern == "*/*" {
+            self.any = true;
+            return Ok(());
+        }
+
+        let invalid = || format!("invalid MIME type pattern \"{}\"", pattern);
+        let slash = pattern.find('/').ok_or_else(invalid)?;
+        let (main_type, subtype) = (&pattern[..slash], &pattern[slash + 1..]);
+        // `*` is a valid token, but would never match as part of a type.
+        let is_type = |s: &str| is_token(s) && !s.contains('*');
+        if !is_type(main_type) || (subtype != "*" && !is_type(subtype)) {
+            return Err(invalid());
+        }
+
+        if subtype == "*" {
+            self.main_types.push(main_type.to_string());
+        } else {
+            self.exact.push(pattern);
+        }
+        Ok(())
+    }
+
+    /// Add the patterns in the arguments of the directive being parsed, as done by a
+    /// `*_types` directive handler.
+    pub unsafe fn add_conf_args(&mut self, cf: *mut ngx_conf_t) -> Result<(), String> {
+        for arg in conf_args(cf).iter().skip(1) {
+            self.add(&NgxStr::from_ngx_str(*arg).to_string_lossy())?;
+        }
+        Ok(())
+    }
+
+    /// Does the matcher match no type?
+    pub fn is_empty(&self) -> bool {
+        !self.any && self.exact.is_empty() && self.main_types.is_empty()
+    }
+
+    /// Does a content type match one of the patterns?
+    pub fn matches(&self, content_type: &ContentType) -> bool {
+        self.any
+            || self.exact.iter().any(|mime| mime == content_type.mime())
+            || self.main_types.iter().any(|main_type| main_type == content_type.main_type())
+    }
+
+    /// Does the value of a `Content-Type` header match one of the patterns? A value that
+    /// can't be parsed only matches `*`.
+    pub fn matches_str(&self, value: &str) -> bool {
+        match ContentType::parse(value) {
+            Some(content_type) => self.matches(&content_type),
+            None => self.any,
+        }
+    }
+
+    /// Does the content type of the response match one of the patterns?
+    pub fn matches_response(&self, request: &Request) -> bool {
+        match request.response_content_type() {
+            Some(content_type) => self.matches(&content_type),
+            None => self.any,
+        }
+    }
+
+    /// Does the content type of the request body match one of the patterns?
+    pub fn matches_request(&self, request: &Request) -> bool {
+        match request.content_type() {
+            Some(content_type) => self.matches(&content_type),
+            None => self.any,
+        }
+    }
+}
+
+impl Request {
+    /// Content type of the request body (`Content-Type` request header).
+    pub fn content_type(&self) -> Option<ContentType> {
+        let r = self.as_ngx_http_request();
+        unsafe {
+            let h = (*r).headers_in.content_type;
+            if h.is_null() {
+                return None;
+            }
+            ContentType::parse(&NgxStr::from_ngx_str((*h).value).to_string_lossy())
+        }
+    }
+
+    /// Content type of the response, including the charset set by the `charset` directive.
+    pub fn response_content_type(&self) -> Option<ContentType> {
+        let r = self.as_ngx_http_request();
+        unsafe {
+            let headers = &(*r).headers_out;
+            if headers.content_type.len == 0 {
+                return None;
+            }
+            let mut content_type = ContentType::parse(&NgxStr::from_ngx_str(headers.content_type).to_string_lossy())?;
+            if headers.charset.len != 0 && content_type.charset().is_none() {
+                let charset = NgxStr::from_ngx_str(headers.charset).to_string_lossy().to_string();
+                content_type.params.push(("charset".to_string(), charset));
+            }
+            Some(content_type)
+        }
+    }
+
+    /// Test the response content type against a types hash built by
+    /// `ngx_http_types_slot` (`ngx_http_test_content_type`).
+    pub unsafe fn test_content_type(&mut self, types: &mut ngx_hash_t) -> bool {
+        !ngx_http_test_content_type(self.as_ngx_http_request_mut(), types).is_null()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let content_type = ContentType::parse("Text/HTML; Charset=UTF-8").unwrap();
+        assert_eq!(content_type.mime(), "text/html");
+        assert_eq!(content_type.main_type(), "text");
+        assert_eq!(content_type.subtype(), "html");
+        assert_eq!(content_type.charset(), Some("UTF-8"));
+        assert_eq!(content_type.param("CHARSET"), Some("UTF-8"));
+        assert_eq!(content_type.params().collect::<Vec<_>>(), vec![("charset", "UTF-8")]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for value in &["", "text", "text/", "/html", "text html", "te xt/html", "text/ht\"ml"] {
+            assert_eq!(ContentType::parse(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn parse_params() {
+        let content_type = ContentType::parse(" application/json ;charset=utf-8 ; ; invalid; x =1; b@d=2").unwrap();
+        assert_eq!(content_type.mime(), "application/json");
+        assert_eq!(content_type.params().collect::<Vec<_>>(), vec![("charset", "utf-8"), ("x", "1")]);
+        assert!(content_type.is_json());
+        assert!(ContentType::parse("application/vnd.api+json").unwrap().is_json());
+        assert!(!ContentType::parse("application/jsonx").unwrap().is_json());
+    }
+
+    #[test]
+    fn parse_quoted() {
+        let content_type = ContentType::parse(r#"multipart/form-data; boundary="a;b=\"c\\d\""; charset=utf-8"#).unwrap();
+        assert!(content_type.is_multipart());
+        assert_eq!(content_type.boundary(), Some(r#"a;b="c\d""#));
+        assert_eq!(content_type.charset(), Some("utf-8"));
+        assert_eq!(ContentType::parse("multipart/mixed; boundary=\"\"").unwrap().boundary(), None);
+    }
+
+    #[test]
+    fn display() {
+        let content_type = ContentType::parse(r#"TEXT/Plain; Format="flowed"; Title="a \"b\"""#).unwrap();
+        assert_eq!(content_type.to_string(), r#"text/plain; format=flowed; title="a \"b\"""#);
+        assert_eq!(ContentType::parse(&content_type.to_string()), Some(content_type));
+    }
+
+    #[test]
+    fn matcher() {
+        let matcher = ContentTypeMatcher::from_patterns(&["text/html", "Application/*"]).unwrap();
+        assert!(matcher.matches_str("text/html"));
+        assert!(matcher.matches_str("TEXT/HTML; charset=utf-8"));
+        assert!(matcher.matches_str("application/json"));
+        assert!(matcher.matches_str("application/vnd.api+json"));
+        assert!(!matcher.matches_str("text/plain"));
+        assert!(!matcher.matches_str("image/png"));
+        assert!(!matcher.matches_str("not a type"));
+    }
+
+    #[test]
+    fn matcher_any() {
+        assert!(ContentTypeMatcher::new().is_empty());
+        assert!(!ContentTypeMatcher::new().matches_str("text/html"));
+        for pattern in &["*", "*/*"] {
+            let matcher = ContentTypeMatcher::from_patterns(&[pattern]).unwrap();
+            assert!(!matcher.is_empty());
+            assert!(matcher.matches_str("image/png"));
+            assert!(matcher.matches_str("not a type"));
+        }
+    }
+
+    #[test]
+    fn matcher_invalid() {
+        for pattern in &["text", "text/", "*/html", "te xt/html", "text/**"] {
+            assert!(ContentTypeMatcher::new().add(pattern).is_err(), "{:?}", pattern);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::bindings::*;
+use crate::event::*;
+use crate::http::request::Request;
+
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default value of [`set_detach_limit`].
+pub const DEFAULT_DETACH_LIMIT: usize = 1024;
+
+static DETACH_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_DETACH_LIMIT);
+static DETACH_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the maximum number of detached tasks that may be pending in a worker process.
+///
+/// See [`Request::detach`].
+pub fn set_detach_limit(limit: usize) {
+    DETACH_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Number of detached tasks currently pending in this worker process.
+pub fn detach_pending() -> usize {
+    DETACH_PENDING.load(Ordering::Relaxed)
+}
+
+struct DetachedTask {
+    event: ngx_event_t,
+    work: Option<Box<dyn FnOnce()>>,
+}
+
+impl Request {
+    /// Detach work from the request lifecycle.
+    ///
+    /// `work` is called with `data` once the request has been freed, whether it completed
+    /// normally or the client went away, which makes it suitable for logging or exporting data
+    /// about the request. Anything it needs must be moved out of the request (and its pool)
+    /// into `data` beforehand, as none of it is valid by the time `work` runs.
+    ///
+    /// The work runs on the event loop of the worker, so it must not block. To bound the amount
+    /// of background work, at most [`set_detach_limit`] tasks may be pending at a time; beyond
+    /// that `data` is handed back as an error.
+    pub fn detach<T, F>(&mut self, data: T, work: F) -> Result<(), T>
+    where
+        T: 'static,
+        F: FnOnce(T) + 'static,
+    {
+        if DETACH_PENDING.load(Ordering::Relaxed) >= DETACH_LIMIT.load(Ordering::Relaxed) {
+            return Err(data);
+        }
+
+        unsafe {
+            let r = self.as_ngx_http_request_mut();
+            let cln = ngx_pool_cleanup_add((*r).pool, 0);
+            if cln.is_null() {
+                return Err(data);
+            }
+
+            // The task lives on the heap, as it outlives the request pool.
+            let task = Box::into_raw(Box::new(DetachedTask {
+                event: std::mem::zeroed(),
+                work: Some(Box::new(move || work(data))),
+            }));
+            (*task).event.data = task as *mut c_void;
+            (*task).event.handler = Some(ngx_http_rs_detached_handler);
+            (*task).event.log = (*ngx_cycle).log;
+
+            (*cln).handler = Some(ngx_http_rs_detach_cleanup);
+            (*cln).data = task as *mut c_void;
+        }
+
+        DETACH_PENDING.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn ngx_http_rs_detach_cleanup(data: *mut c_void) {
+    // Run the work once the current event has been processed, rather than in the middle of
+    // tearing down the request.
+    let task = data as *mut DetachedTask;
+    ngx_post_event(&mut (*task).event, ptr::addr_of_mut!(ngx_posted_events));
+}
+
+unsafe extern "C" fn ngx_http_rs_detached_handler(ev: *mut ngx_event_t) {
+    let mut task = Box::from_raw((*ev).data as *mut DetachedTask);
+    DETACH_PENDING.fetch_sub(1, Ordering::Relaxed);
+
+    if let Some(work) = task.work.take() {
+        work();
+    }
+}
@@ -0,0 +1,288 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::io::{self, Read};
+
+/// Version of the export schema written by the [`Codec`] of [`ExportRecord`].
+pub const EXPORT_SCHEMA_VERSION: u8 = 1;
+
+const TAG_REMOTE_ADDR: u8 = 1;
+const TAG_METHOD: u8 = 2;
+const TAG_URI: u8 = 3;
+const TAG_ARGS: u8 = 4;
+const TAG_HEADER: u8 = 5;
+const TAG_TLS_FINGERPRINT: u8 = 6;
+const TAG_START_MSEC: u8 = 7;
+const TAG_HEADER_TIME: u8 = 8;
+const TAG_BODY_TIME: u8 = 9;
+const TAG_STATUS: u8 = 10;
+const TAG_TRAFFIC_CLASS: u8 = 11;
+const TAG_TENANT: u8 = 12;
+
+/// Selected fields of a request, for shipping to an external analyzer.
+///
+/// Records are encoded with [`Codec`] in a compact binary format, so they can be batched by
+/// simply concatenating them:
+///
+/// ```text
+/// record = length:u32 version:u8 field*       (length counts version and fields)
+/// field  = tag:u8 length:u32 value
+/// header = name_length:u16 name value         (value of a header field)
+/// ```
+///
+/// Integers are little-endian; times are `u64` milliseconds. Readers skip fields with
+/// unknown tags, so new fields can be added without breaking existing readers; the version
+/// only changes when existing fields change meaning. [`ExportReader`] reads records back.
+///
+/// With the `zstd` feature, batches can be compressed with `Zstd::compress_records`, and read
+/// back with `Zstd::export_reader`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportRecord {
+    /// Client address and port as text, such as `192.0.2.1:51234` or `[2001:db8::1]:51234`,
+    /// or the address alone for UNIX-domain sockets.
+    pub remote_addr: Option<Vec<u8>>,
+    /// Request method.
+    pub method: Option<Vec<u8>>,
+    /// Request URI, without arguments.
+    pub uri: Option<Vec<u8>>,
+    /// Request arguments.
+    pub args: Option<Vec<u8>>,
+    /// Selected request headers, as name and value.
+    pub headers: Vec<(Vec<u8>, Vec<u8>)>,
+    /// TLS client fingerprint.
+    pub tls_fingerprint: Option<Vec<u8>>,
+    /// When the request started, in milliseconds since the Unix epoch.
+    pub start_msec: Option<u64>,
+    /// When the response header was sent, in milliseconds since the request started.
+    pub header_time: Option<u64>,
+    /// When the first part of the response body was sent, in milliseconds since the request
+    /// started.
+    pub body_time: Option<u64>,
+    /// Response status.
+    pub status: Option<u16>,
+    /// Traffic class of the request [classification](crate::http::Classification).
+    pub traffic_class: Option<Vec<u8>>,
+    /// Tenant of the request [classification](crate::http::Classification).
+    pub tenant: Option<Vec<u8>>,
+}
+
+impl ExportRecord {
+    /// Capture the fields of a request, including the request headers named in `headers`.
+    ///
+    /// The TLS fingerprint is not captured, as it depends on how the module computes it. The
+    /// classification is only captured if it was already computed for the request.
+    pub fn from_request(request: &Request, headers: &[&str]) -> ExportRecord {
+        let r = request.as_ngx_http_request();
+        let (method, start_msec, status) = unsafe {
+            let method = NgxStr::from_ngx_str((*r).method_name).as_bytes().to_vec();
+            let start_msec = (*r).start_sec as u64 * 1000 + (*r).start_msec as u64;
+            (method, start_msec, (*r).headers_out.status)
+        };
+        let timings = request.timings();
+        let classification = request.cached_classification().unwrap_or_default();
+
+        ExportRecord {
+            remote_addr: remote_addr(request),
+            method: Some(method).filter(|method| !method.is_empty()),
+            uri: request.uri().map(String::into_bytes),
+            args: request.args().map(String::into_bytes),
+            headers: headers.iter()
+                .filter_map(|&name| request.get_header(name).map(|value| (name.as_bytes().to_vec(), value.into_bytes())))
+                .collect(),
+            tls_fingerprint: None,
+            start_msec: Some(start_msec),
+            header_time: timings.header.map(|ms| ms as u64),
+            body_time: timings.first_body.map(|ms| ms as u64),
+            status: if status > 0 && status <= u16::MAX as ngx_uint_t { Some(status as u16) } else { None },
+            traffic_class: classification.class.map(String::into_bytes),
+            tenant: classification.tenant.map(String::into_bytes),
+        }
+    }
+
+    fn decode_body(mut fields: &[u8]) -> Result<ExportRecord, CodecError> {
+        let version = u8::decode(&mut fields)?;
+        if version != EXPORT_SCHEMA_VERSION {
+            return Err(CodecError::Invalid(format!("unsupported export schema version {}", version)));
+        }
+
+        let mut record = ExportRecord::default();
+        while !fields.is_empty() {
+            let (tag, value) = <(u8, Vec<u8>)>::decode(&mut fields)?;
+            match tag {
+                TAG_REMOTE_ADDR => record.remote_addr = Some(value),
+                TAG_METHOD => record.method = Some(value),
+                TAG_URI => record.uri = Some(value),
+                TAG_ARGS => record.args = Some(value),
+                TAG_HEADER => {
+                    let mut value = &value[..];
+                    let name_len = u16::decode(&mut value)? as usize;
+                    if value.len() < name_len {
+                        return Err(CodecError::Truncated);
+                    }
+                    let (name, value) = value.split_at(name_len);
+                    record.headers.push((name.to_vec(), value.to_vec()));
+                }
+                TAG_TLS_FINGERPRINT => record.tls_fingerprint = Some(value),
+                TAG_START_MSEC => record.start_msec = Some(u64::from_bytes(&value)?),
+                TAG_HEADER_TIME => record.header_time = Some(u64::from_bytes(&value)?),
+                TAG_BODY_TIME => record.body_time = Some(u64::from_bytes(&value)?),
+                TAG_STATUS => record.status = Some(u16::from_bytes(&value)?),
+                TAG_TRAFFIC_CLASS => record.traffic_class = Some(value),
+                TAG_TENANT => record.tenant = Some(value),
+                // Fields added by later versions of the schema.
+                _ => {}
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// Records are encoded with their length, so a batch is a concatenation of records.
+impl Codec for ExportRecord {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), CodecError> {
+        let start = out.len();
+        0u32.encode(out)?;
+        EXPORT_SCHEMA_VERSION.encode(out)?;
+
+        let bytes_fields = [
+            (TAG_REMOTE_ADDR, &self.remote_addr),
+            (TAG_METHOD, &self.method),
+            (TAG_URI, &self.uri),
+            (TAG_ARGS, &self.args),
+        ];
+        for &(tag, value) in bytes_fields.iter() {
+            if let Some(value) = value {
+                put_field(out, tag, value)?;
+            }
+        }
+
+        for (name, value) in self.headers.iter() {
+            let name = &name[..name.len().min(u16::MAX as usize)];
+            let mut field = Vec::with_capacity(2 + name.len() + value.len());
+            (name.len() as u16).encode(&mut field)?;
+            field.extend_from_slice(name);
+            field.extend_from_slice(value);
+            put_field(out, TAG_HEADER, &field)?;
+        }
+
+        if let Some(fingerprint) = &self.tls_fingerprint {
+            put_field(out, TAG_TLS_FINGERPRINT, fingerprint)?;
+        }
+
+        let time_fields = [
+            (TAG_START_MSEC, self.start_msec),
+            (TAG_HEADER_TIME, self.header_time),
+            (TAG_BODY_TIME, self.body_time),
+        ];
+        for &(tag, value) in time_fields.iter() {
+            if let Some(value) = value {
+                put_field(out, tag, &value.to_bytes()?)?;
+            }
+        }
+
+        if let Some(status) = self.status {
+            put_field(out, TAG_STATUS, &status.to_bytes()?)?;
+        }
+
+        let classification_fields = [(TAG_TRAFFIC_CLASS, &self.traffic_class), (TAG_TENANT, &self.tenant)];
+        for &(tag, value) in classification_fields.iter() {
+            if let Some(value) = value {
+                put_field(out, tag, value)?;
+            }
+        }
+
+        let len = out.len() - start - 4;
+        if len > u32::MAX as usize {
+            return Err(CodecError::Unencodable(format!("export record of {} bytes", len)));
+        }
+        out[start..start + 4].copy_from_slice(&(len as u32).to_le_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<ExportRecord, CodecError> {
+        let len = u32::decode(input)? as usize;
+        if input.len() < len {
+            return Err(CodecError::Truncated);
+        }
+        let (body, rest) = input.split_at(len);
+        *input = rest;
+        ExportRecord::decode_body(body)
+    }
+}
+
+// The client address with its port, as `ngx_sock_ntop` formats it.
+fn remote_addr(request: &Request) -> Option<Vec<u8>> {
+    match request.remote_addr() {
+        Some(addr) => Some(addr.to_string().into_bytes()),
+        None => Some(request.connection().remote_addr_text().as_bytes().to_vec()).filter(|addr| !addr.is_empty()),
+    }
+}
+
+// A field is its tag followed by its value as bytes, with their length.
+fn put_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(), CodecError> {
+    tag.encode(out)?;
+    if value.len() > u32::MAX as usize {
+        return Err(CodecError::Unencodable(format!("export field {} of {} bytes", tag, value.len())));
+    }
+    (value.len() as u32).encode(out)?;
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+/// Reads a stream of [`ExportRecord`]s, such as a batch received by an analyzer.
+///
+/// The reader doesn't call into Nginx, so it can also be used by tools built with this crate
+/// outside of a worker process, such as tests of an analyzer.
+pub struct ExportReader<R> {
+    inner: R,
+    max_record: usize,
+}
+
+impl<R: Read> ExportReader<R> {
+    /// Read records from `inner`, rejecting records larger than 1 MiB.
+    pub fn new(inner: R) -> ExportReader<R> {
+        ExportReader { inner, max_record: 1 << 20 }
+    }
+
+    /// Set the maximum size of a record.
+    pub fn set_max_record(&mut self, max_record: ByteSize) {
+        self.max_record = max_record.as_bytes();
+    }
+
+    /// Read the next record, or `None` at the end of the stream.
+    pub fn read_record(&mut self) -> io::Result<Option<ExportRecord>> {
+        // The stream may only end between records.
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.inner.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated export record")),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let len = u32::from_le_bytes(len) as usize;
+        if len > self.max_record {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "export record too large"));
+        }
+
+        let mut body = vec![0; len];
+        self.inner.read_exact(&mut body)?;
+        ExportRecord::decode_body(&body)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+impl<R: Read> Iterator for ExportReader<R> {
+    type Item = io::Result<ExportRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
@@ -0,0 +1,163 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::cell::UnsafeCell;
+
+/// Define a static [header filter].
+///
+/// Filters are expected to take a single [`Request`] argument and return a [`Status`],
+/// normally by passing the request on to the next filter with [`NextHeaderFilter::call`], or
+/// a `Result` whose error is logged and returned as `NGX_ERROR`, as for
+/// [`http_request_handler`](crate::http_request_handler).
+///
+/// ```ignore
+/// static NEXT_HEADER_FILTER: NextHeaderFilter = NextHeaderFilter::new();
+///
+/// http_header_filter!(ngx_http_server_header_filter, |request: &mut Request| {
+///     request.set_header("X-Served-By", "nginx-rs");
+///     NEXT_HEADER_FILTER.call(request)
+/// });
+///
+/// // In `postconfiguration`:
+/// NEXT_HEADER_FILTER.install(ngx_http_server_header_filter);
+/// ```
+///
+/// [header filter]: https://nginx.org/en/docs/dev/development_guide.html#http_header_filters
+#[macro_export]
+macro_rules! http_header_filter {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            $crate::core::catch_panic(log, stringify!($name), $crate::__private::bindings::NGX_ERROR as $crate::__private::bindings::ngx_int_t, || {
+                let status = $handler(unsafe { &mut $crate::http::Request::from_ngx_http_request(r) });
+                $crate::core::HandlerResult::into_status(status, log, $crate::core::ERROR).0
+            })
+        }
+    };
+}
+
+/// The header filter following a module's own filter in the chain.
+///
+/// This is the Rust equivalent of the `ngx_http_next_header_filter` static of a C filter
+/// module. It is typically a `static` of the module, installed from `postconfiguration`.
+pub struct NextHeaderFilter(UnsafeCell<ngx_http_output_header_filter_pt>);
+
+// SAFETY: Filters are installed while parsing the configuration and called from the event
+// loop, both of which run on a single thread.
+unsafe impl Sync for NextHeaderFilter {}
+
+impl NextHeaderFilter {
+    /// An empty link, calling it does nothing until a filter is installed.
+    pub const fn new() -> NextHeaderFilter {
+        NextHeaderFilter(UnsafeCell::new(None))
+    }
+
+    /// Put `filter` at the top of the header filter chain, saving the current top filter as
+    /// the next one.
+    ///
+    /// Call this from the `postconfiguration` handler of a module. Nginx rebuilds the chain
+    /// whenever the configuration is reloaded.
+    pub unsafe fn install(&self, filter: unsafe extern "C" fn(*mut ngx_http_request_t) -> ngx_int_t) {
+        *self.0.get() = ngx_http_top_header_filter;
+        ngx_http_top_header_filter = Some(filter);
+    }
+
+    /// Pass the request on to the next header filter.
+    pub fn call(&self, request: &mut Request) -> Status {
+        match unsafe { *self.0.get() } {
+            Some(next) => Status(unsafe { next(request.as_ngx_http_request_mut()) }),
+            None => OK,
+        }
+    }
+}
+
+impl Default for NextHeaderFilter {
+    fn default() -> NextHeaderFilter {
+        NextHeaderFilter::new()
+    }
+}
+
+/// Define a static [body filter].
+///
+/// Filters are expected to take a [`Request`] and the [`Chain`] of buffers being output, and
+/// return a [`Status`], normally by passing the (possibly modified) chain on to the next
+/// filter with [`NextBodyFilter::call`], or a `Result` whose error is logged and returned as
+/// `NGX_ERROR`. Buffers that are not
+/// [mutable](crate::core::ChainLink::is_mutable) must be copied before modification, see
+/// [`ChainLink::make_mutable`](crate::core::ChainLink::make_mutable).
+///
+/// ```ignore
+/// static NEXT_BODY_FILTER: NextBodyFilter = NextBodyFilter::new();
+///
+/// http_body_filter!(ngx_http_upper_body_filter, |request: &mut Request, mut chain: Chain| {
+///     let mut pool = request.pool();
+///     for link in chain.iter_mut() {
+///         if let Some(data) = link.make_mutable(&mut pool) {
+///             data.make_ascii_uppercase();
+///         }
+///     }
+///     NEXT_BODY_FILTER.call(request, chain)
+/// });
+/// ```
+///
+/// [body filter]: https://nginx.org/en/docs/dev/development_guide.html#http_body_filters
+#[macro_export]
+macro_rules! http_body_filter {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t, cl: *mut $crate::__private::bindings::ngx_chain_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            $crate::core::catch_panic(log, stringify!($name), $crate::__private::bindings::NGX_ERROR as $crate::__private::bindings::ngx_int_t, || {
+                let status = $handler(
+                    unsafe { &mut $crate::http::Request::from_ngx_http_request(r) },
+                    unsafe { $crate::core::Chain::from_ngx_chain(cl) },
+                );
+                $crate::core::HandlerResult::into_status(status, log, $crate::core::ERROR).0
+            })
+        }
+    };
+}
+
+/// The body filter following a module's own filter in the chain.
+///
+/// This is the Rust equivalent of the `ngx_http_next_body_filter` static of a C filter
+/// module, see [`NextHeaderFilter`].
+pub struct NextBodyFilter(UnsafeCell<ngx_http_output_body_filter_pt>);
+
+// SAFETY: See `NextHeaderFilter`.
+unsafe impl Sync for NextBodyFilter {}
+
+impl NextBodyFilter {
+    /// An empty link, calling it does nothing until a filter is installed.
+    pub const fn new() -> NextBodyFilter {
+        NextBodyFilter(UnsafeCell::new(None))
+    }
+
+    /// Put `filter` at the top of the body filter chain, saving the current top filter as the
+    /// next one.
+    ///
+    /// Call this from the `postconfiguration` handler of a module.
+    pub unsafe fn install(
+        &self,
+        filter: unsafe extern "C" fn(*mut ngx_http_request_t, *mut ngx_chain_t) -> ngx_int_t,
+    ) {
+        *self.0.get() = ngx_http_top_body_filter;
+        ngx_http_top_body_filter = Some(filter);
+    }
+
+    /// Pass the chain on to the next body filter.
+    pub fn call(&self, request: &mut Request, chain: Chain) -> Status {
+        match unsafe { *self.0.get() } {
+            Some(next) => Status(unsafe { next(request.as_ngx_http_request_mut(), chain.as_ngx_chain()) }),
+            None => OK,
+        }
+    }
+}
+
+impl Default for NextBodyFilter {
+    fn default() -> NextBodyFilter {
+        NextBodyFilter::new()
+    }
+}
@@ -0,0 +1,104 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::mem;
+
+impl Request {
+    /// Finalize the request with `status` (`ngx_http_finalize_request`): an HTTP status sends
+    /// the matching error page, [`OK`] or the status of the last output completes the
+    /// request, [`ERROR`] terminates it, and [`DONE`] only releases a reference.
+    ///
+    /// This is for handlers that don't return their status to Nginx, such as body read
+    /// handlers. The request may be freed by the call, so it must not be used afterwards
+    /// unless a reference to it is still held, see [`Request::hold`].
+    pub fn finalize(&mut self, status: Status) {
+        unsafe { ngx_http_finalize_request(self.as_ngx_http_request_mut(), status.0) };
+    }
+
+    /// Number of references to the main request (`r->main->count`), which is freed once
+    /// they are all released.
+    pub fn count(&self) -> u32 {
+        unsafe { (*(*self.as_ngx_http_request()).main).count() as u32 }
+    }
+
+    /// Take a reference to the request, keeping it alive until the returned handle is
+    /// finalized or dropped, even if the client goes away meanwhile: as for thread tasks,
+    /// the termination of the request waits for the reference to be released.
+    ///
+    /// This is how a handler keeps the request across asynchronous operations, such as
+    /// timers or subrequests to other services: it takes a reference, returns [`DONE`] to
+    /// Nginx, and finalizes the request from the completion of the operation.
+    ///
+    /// ```ignore
+    /// http_request_handler!(ngx_http_lookup_handler, |request: &mut Request| {
+    ///     let mut held = request.hold();
+    ///     Timer::once(Msec::from_msec(50), move || {
+    ///         let status = held.request().send_response(HTTP_OK, "text/plain", b"done");
+    ///         held.finalize(status);
+    ///     })
+    ///     .detach();
+    ///     DONE
+    /// });
+    /// ```
+    pub fn hold(&mut self) -> RequestRef {
+        let r = self.as_ngx_http_request_mut();
+        unsafe {
+            let main = (*r).main;
+            (*main).set_count((*main).count() + 1);
+            (*main).set_blocked((*main).blocked() + 1);
+        }
+        RequestRef(r)
+    }
+}
+
+/// A reference to a request taken with [`Request::hold`].
+///
+/// Dropping the reference releases it, as finalizing the request with [`DONE`] does, so the
+/// request is neither leaked nor finalized twice. Unlike [`RequestRef::finalize`], dropping
+/// doesn't run posted requests, as the reference may be dropped while the request is being
+/// processed.
+#[derive(Debug)]
+pub struct RequestRef(*mut ngx_http_request_t);
+
+impl RequestRef {
+    /// The request.
+    pub fn request(&mut self) -> &mut Request {
+        unsafe { Request::from_ngx_http_request(self.0) }
+    }
+
+    /// Finalize the request with `status`, see [`Request::finalize`], releasing the
+    /// reference.
+    ///
+    /// Subrequests and other requests posted meanwhile then run, as this is meant to be
+    /// called from event handlers outside of the processing of the request.
+    pub fn finalize(self, status: Status) {
+        let r = self.0;
+        mem::forget(self);
+        unsafe {
+            let c = (*r).connection;
+            unblock(r);
+            ngx_http_finalize_request(r, status.0);
+            ngx_http_run_posted_requests(c);
+        }
+    }
+
+    /// Release the reference without finalizing the request otherwise.
+    pub fn release(self) {
+        self.finalize(DONE);
+    }
+}
+
+impl Drop for RequestRef {
+    fn drop(&mut self) {
+        unsafe {
+            unblock(self.0);
+            ngx_http_finalize_request(self.0, NGX_DONE as ngx_int_t);
+        }
+    }
+}
+
+unsafe fn unblock(r: *mut ngx_http_request_t) {
+    let main = (*r).main;
+    (*main).set_blocked((*main).blocked() - 1);
+}
@@ -0,0 +1,234 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+#[cfg(feature = "stream")]
+use crate::stream::Session;
+
+use std::alloc::{self, Layout};
+
+/// Number of slots probed for a fingerprint before giving up.
+const PROBES: usize = 8;
+
+/// Limit of new connections or requests per TLS fingerprint.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FingerprintLimit {
+    /// Number of connections or requests allowed per window.
+    pub count: usize,
+    /// Length of the sliding window.
+    pub window: Msec,
+}
+
+#[repr(C)]
+struct Slot {
+    key: Atomic,
+    window: Atomic,
+    current: Atomic,
+    previous: Atomic,
+}
+
+/// Counters of recently seen fingerprints, for use by a [`FingerprintLimiter`].
+///
+/// Fingerprints are tracked in `N` slots with an approximate sliding window per slot, using
+/// atomic operations only. The table contains no pointers and zeroed memory is a valid empty
+/// table, so it can be placed as is in a shared memory zone for all worker processes to
+/// enforce the same limits. When too many fingerprints compete for the same slots, the
+/// fingerprints that don't fit are allowed and counted in [`FingerprintTable::overflows`].
+#[repr(C)]
+pub struct FingerprintTable<const N: usize> {
+    slots: [Slot; N],
+    overflows: Atomic,
+}
+
+impl<const N: usize> FingerprintTable<N> {
+    /// Allocate an empty table in the memory of the worker process, to enforce limits per
+    /// worker process.
+    pub fn boxed() -> Box<FingerprintTable<N>> {
+        let layout = Layout::new::<FingerprintTable<N>>();
+        // SAFETY: zeroed memory is a valid table, and the layout is that of the table.
+        unsafe {
+            let table = alloc::alloc_zeroed(layout) as *mut FingerprintTable<N>;
+            if table.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            Box::from_raw(table)
+        }
+    }
+
+    /// Count a hit of `fingerprint`, unless it is over `limit`. Returns `true` if the hit is
+    /// allowed.
+    ///
+    /// Rejected hits are not counted, so a client over the limit is allowed again as soon as
+    /// its rate falls below the limit.
+    pub fn hit(&self, fingerprint: &[u8], limit: FingerprintLimit) -> bool {
+        let window = (limit.window.as_msec() as ngx_atomic_uint_t).max(1);
+        let now = shared_msec();
+        let index = now / window;
+
+        let key = XxHash64::with_seed(0).hash64(fingerprint) as ngx_atomic_uint_t | 1;
+        let slot = match self.find(key, index) {
+            Some(slot) => slot,
+            None => {
+                self.overflows.fetch_add(1);
+                return true;
+            }
+        };
+
+        let current = slot.window.load();
+        if current != index && slot.window.cmp_set(current, index) {
+            // Hits counted by other workers while rolling the window over may be lost, which
+            // is acceptable for an approximate limit.
+            let previous = if index == current + 1 { slot.current.load() } else { 0 };
+            slot.previous.store(previous);
+            slot.current.store(0);
+        }
+
+        // Weight the previous window by how much of it is still within the sliding window.
+        let elapsed = now % window;
+        let estimate = slot.previous.load() * (window - elapsed) / window + slot.current.load();
+        if estimate >= limit.count as ngx_atomic_uint_t {
+            return false;
+        }
+
+        slot.current.fetch_add(1);
+        true
+    }
+
+    /// Number of hits allowed because the table had no slot left for their fingerprint.
+    pub fn overflows(&self) -> usize {
+        self.overflows.load() as usize
+    }
+
+    fn find(&self, key: ngx_atomic_uint_t, index: ngx_atomic_uint_t) -> Option<&Slot> {
+        let start = (key % N as ngx_atomic_uint_t) as usize;
+        let probes = || (0..PROBES.min(N)).map(|i| &self.slots[(start + i) % N]);
+
+        for slot in probes() {
+            let current = slot.key.load();
+            if current == key {
+                return Some(slot);
+            }
+            if current == 0 && slot.key.cmp_set(0, key) {
+                return Some(Self::reset(slot, index));
+            }
+        }
+
+        // Take over a slot that has seen no hit in the last two windows.
+        for slot in probes() {
+            let current = slot.key.load();
+            if slot.window.load() + 1 < index && slot.key.cmp_set(current, key) {
+                return Some(Self::reset(slot, index));
+            }
+        }
+
+        None
+    }
+
+    fn reset(slot: &Slot, index: ngx_atomic_uint_t) -> &Slot {
+        slot.window.store(index);
+        slot.previous.store(0);
+        slot.current.store(0);
+        slot
+    }
+}
+
+// Time in milliseconds shared by all worker processes, unlike `ngx_current_msec`.
+fn shared_msec() -> ngx_atomic_uint_t {
+    unsafe {
+        let tp = ngx_cached_time;
+        (*tp).sec as ngx_atomic_uint_t * 1000 + (*tp).msec as ngx_atomic_uint_t
+    }
+}
+
+/// Rate limiting of new connections or requests per TLS fingerprint.
+///
+/// Limiting per client address fails when many clients share an address behind carrier-grade
+/// NAT, and lets a single client with many addresses through. Limiting per fingerprint of
+/// the ClientHello (JA3) instead targets the TLS stack of a client, which is what automated
+/// clients have in common.
+///
+/// Limits are set per listening socket, with an optional default, and counted in a
+/// [`FingerprintTable`]. In a stream server with `ssl_preread` or the preread phase, new
+/// connections are limited by [`FingerprintLimiter::check_session`]:
+///
+/// ```ignore
+/// stream_preread_handler!(ngx_stream_fingerprint_limit_handler, |session: &mut Session| {
+///     let conf = unsafe { &*(session.get_module_main_conf(&my_module) as *const MainConf) };
+///     conf.limiter.check_session(conf.table(), session)
+/// });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FingerprintLimiter {
+    default: Option<FingerprintLimit>,
+    listeners: Vec<(String, FingerprintLimit)>,
+}
+
+impl FingerprintLimiter {
+    /// Create a limiter applying `default` to the listening sockets without a limit of their
+    /// own, or no limit if `None`.
+    pub fn new(default: Option<FingerprintLimit>) -> FingerprintLimiter {
+        FingerprintLimiter { default, listeners: Vec::new() }
+    }
+
+    /// Set the limit of a listening socket, by its address as in the `listen` directive, such
+    /// as `0.0.0.0:443` or `[::]:8443`.
+    pub fn set_listener_limit(&mut self, listen: &str, limit: FingerprintLimit) {
+        self.listeners.retain(|(address, _)| address != listen);
+        self.listeners.push((listen.to_string(), limit));
+    }
+
+    /// Limit of the listening socket a connection was accepted on.
+    pub fn limit(&self, c: *mut ngx_connection_t) -> Option<FingerprintLimit> {
+        let listening = unsafe { (*c).listening };
+        if listening.is_null() {
+            return self.default;
+        }
+        let address = unsafe { NgxStr::from_ngx_str((*listening).addr_text) };
+        self.listeners
+            .iter()
+            .find(|(listen, _)| listen.as_bytes() == address.as_bytes())
+            .map(|&(_, limit)| limit)
+            .or(self.default)
+    }
+
+    /// Count a hit of `fingerprint` on a connection. Returns `true` if it is allowed.
+    pub fn check<const N: usize>(&self, table: &FingerprintTable<N>, c: *mut ngx_connection_t, fingerprint: &[u8]) -> bool {
+        match self.limit(c) {
+            Some(limit) => table.hit(fingerprint, limit),
+            None => true,
+        }
+    }
+
+    /// Limit requests by the fingerprint of their connection.
+    ///
+    /// Returns [`DECLINED`] to let the request continue through the access phase, or
+    /// `HTTP_TOO_MANY_REQUESTS`.
+    pub fn check_request<const N: usize>(&self, table: &FingerprintTable<N>, request: &mut Request, fingerprint: &[u8]) -> Status {
+        if self.check(table, request.connection_mut().as_ngx_connection_mut(), fingerprint) {
+            DECLINED
+        } else {
+            HTTP_TOO_MANY_REQUESTS.into()
+        }
+    }
+
+    /// Limit new stream connections by the JA3 fingerprint of their ClientHello, from a
+    /// preread phase handler.
+    ///
+    /// Returns `AGAIN` until the ClientHello has been received, `DECLINED` to let the
+    /// connection continue (including connections that are not TLS), or
+    /// `NGX_STREAM_SERVICE_UNAVAILABLE` to close it, as done by `limit_conn`.
+    #[cfg(feature = "stream")]
+    pub fn check_session<const N: usize>(&self, table: &FingerprintTable<N>, session: &mut Session) -> Status {
+        let hello = match session.preread_client_hello() {
+            Ok(hello) => hello,
+            Err(ClientHelloError::Incomplete) => return AGAIN,
+            Err(_) => return DECLINED,
+        };
+
+        if self.check(table, session.connection(), hello.ja3_hash().as_bytes()) {
+            DECLINED
+        } else {
+            Status(NGX_STREAM_SERVICE_UNAVAILABLE as ngx_int_t)
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+use crate::ngx_log;
+
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+/// What to do with a request when a handler exceeds its [`Guardrails`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailPolicy {
+    /// Let the request continue as if the handler had not run (`NGX_DECLINED`).
+    Open,
+    /// Fail the request with `500 Internal Server Error`.
+    Closed,
+}
+
+/// A limit of [`Guardrails`] that was exceeded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exceeded {
+    /// Bytes allocated from the request pool since the guardrails started.
+    Memory(usize),
+    /// Milliseconds spent since the guardrails started.
+    Time(ngx_msec_t),
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exceeded::Memory(bytes) => write!(f, "memory limit exceeded: {} bytes allocated", bytes),
+            Exceeded::Time(msec) => write!(f, "time limit exceeded: {}ms spent", msec),
+        }
+    }
+}
+
+impl Error for Exceeded {}
+
+/// Limits on the work of a Rust handler, to protect the worker from a request that makes
+/// the handler allocate or compute far more than expected.
+///
+/// The limits are on the bytes allocated from the request pool (see [`Pool::allocated`]) and
+/// the wall time since the guardrails started for the request. They are not enforced
+/// preemptively, and nothing in the crate checks them on its own: [`Guardrails::run`] checks
+/// them once before the work, and the handler checks them again at its yield points with
+/// [`Guardrails::check`], such as once per loop iteration. The first check to fail aborts
+/// the work, and the request is handled according to the [`FailPolicy`], which is fail-open
+/// by default.
+///
+/// ```ignore
+/// http_request_handler!(access_handler, |request: &mut Request| {
+///     let conf = unsafe { &*(request.get_module_loc_conf(&my_module) as *const LocConf) };
+///     conf.guardrails.run(request, |request| {
+///         for rule in conf.rules.iter() {
+///             conf.guardrails.check(request)?;
+///             if rule.matches(request) {
+///                 return Ok(HTTP_FORBIDDEN.into());
+///             }
+///         }
+///         Ok(DECLINED)
+///     })
+/// });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Guardrails {
+    max_memory: Option<ByteSize>,
+    max_time: Option<Msec>,
+    policy: FailPolicy,
+}
+
+impl Default for Guardrails {
+    fn default() -> Guardrails {
+        Guardrails::new()
+    }
+}
+
+/// Start of the guarded work of a request, stored in its pool.
+struct GuardStart {
+    allocated: usize,
+    time: Instant,
+}
+
+impl Guardrails {
+    /// Guardrails without limits, failing open.
+    pub fn new() -> Guardrails {
+        Guardrails { max_memory: None, max_time: None, policy: FailPolicy::Open }
+    }
+
+    /// Limit the bytes allocated from the request pool.
+    pub fn set_max_memory(&mut self, size: ByteSize) {
+        self.max_memory = Some(size);
+    }
+
+    /// Limit the wall time of the work.
+    pub fn set_max_time(&mut self, time: Msec) {
+        self.max_time = Some(time);
+    }
+
+    /// Set what happens to a request once a limit is exceeded.
+    pub fn set_policy(&mut self, policy: FailPolicy) {
+        self.policy = policy;
+    }
+
+    /// Start the guarded work of a request.
+    ///
+    /// Only the first call for a request has an effect, so work resumed from another event
+    /// keeps counting from where it started.
+    pub fn start(&self, request: &mut Request) {
+        let mut pool = request.pool();
+        if pool.get_local::<GuardStart>().is_null() {
+            let allocated = pool.allocated();
+            pool.insert_local(GuardStart { allocated, time: Instant::now() });
+        }
+    }
+
+    /// Check the limits for a request, starting the guarded work if needed.
+    pub fn check(&self, request: &mut Request) -> Result<(), Exceeded> {
+        self.start(request);
+
+        let pool = request.pool();
+        let start = unsafe { &*pool.get_local::<GuardStart>() };
+
+        if let Some(max_memory) = self.max_memory {
+            let allocated = pool.allocated().saturating_sub(start.allocated);
+            if allocated > max_memory.as_bytes() {
+                return Err(Exceeded::Memory(allocated));
+            }
+        }
+        if let Some(max_time) = self.max_time {
+            let elapsed = start.time.elapsed().as_millis() as ngx_msec_t;
+            if elapsed > max_time.as_msec() {
+                return Err(Exceeded::Time(elapsed));
+            }
+        }
+        Ok(())
+    }
+
+    /// Status to return for a request whose work exceeded a limit, logging why.
+    pub fn fail(&self, request: &mut Request, exceeded: Exceeded) -> Status {
+        let log = request.connection().log();
+        ngx_log!(NGX_LOG_WARN, log, "handler aborted: {}", exceeded);
+
+        match self.policy {
+            FailPolicy::Open => DECLINED,
+            FailPolicy::Closed => HTTP_INTERNAL_SERVER_ERROR.into(),
+        }
+    }
+
+    /// Run guarded work for a request.
+    ///
+    /// The limits are checked before `work`, and by `work` itself with
+    /// [`Guardrails::check`]. Returns the status of `work`, or that of the [`FailPolicy`] if a
+    /// limit was exceeded.
+    pub fn run<F>(&self, request: &mut Request, work: F) -> Status
+    where
+        F: FnOnce(&mut Request) -> Result<Status, Exceeded>,
+    {
+        match self.check(request).and_then(|()| work(request)) {
+            Ok(status) => status,
+            Err(exceeded) => self.fail(request, exceeded),
+        }
+    }
+}
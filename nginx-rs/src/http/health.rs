@@ -0,0 +1,381 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::upstream::Peers;
+use crate::ngx_log;
+
+use std::mem;
+use std::ops::RangeInclusive;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::rc::Rc;
+
+/// Size of the buffer responses are read with.
+const READ_SIZE: usize = 4096;
+
+/// Outcome of evaluating the response to a health check probe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    /// The peer is healthy.
+    Pass,
+    /// The peer is unhealthy.
+    Fail,
+    /// More of the response is needed. At the end of the response, the check fails.
+    Incomplete,
+}
+
+/// Active health checks of the servers of an upstream block.
+///
+/// Each peer is probed every `interval`: a connection is opened, the probe sent, and the
+/// response evaluated by a closure as it is received, all within `timeout`. A peer is marked
+/// down after `fails` consecutive failed checks, and up again after `passes` consecutive
+/// successful ones. Peers marked `down` in the configuration are never checked.
+///
+/// When the upstream block has a `zone`, its peers are in shared memory: the first worker
+/// process checks them on behalf of all workers, which all see the same state. Otherwise each
+/// worker process checks its own copy of the peers.
+///
+/// ```ignore
+/// unsafe extern "C" fn init_process(cycle: *mut ngx_cycle_t) -> ngx_int_t {
+///     let check = HealthCheck::http("/healthz", "backend.example.com", 200..=299);
+///     match upstream::find(cycle, "backend").map(|us| check.start(us)) {
+///         Some(Ok(())) => NGX_OK as ngx_int_t,
+///         _ => NGX_ERROR as ngx_int_t,
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct HealthCheck {
+    probe: Vec<u8>,
+    evaluate: Rc<dyn Fn(&[u8]) -> Verdict>,
+    interval: Msec,
+    timeout: Msec,
+    passes: usize,
+    fails: usize,
+    max_response: ByteSize,
+}
+
+impl HealthCheck {
+    /// A check sending `probe` and evaluating the response received so far with `evaluate`.
+    ///
+    /// Checks run every 5 seconds with a timeout of 1 second, and a single failed or
+    /// successful check changes the state of a peer, as with the `health_check` directive of
+    /// Nginx Plus.
+    pub fn new<F>(probe: impl Into<Vec<u8>>, evaluate: F) -> HealthCheck
+    where
+        F: Fn(&[u8]) -> Verdict + 'static,
+    {
+        HealthCheck {
+            probe: probe.into(),
+            evaluate: Rc::new(evaluate),
+            interval: Msec::from_secs(5),
+            timeout: Msec::from_secs(1),
+            passes: 1,
+            fails: 1,
+            max_response: ByteSize::from_kib(16),
+        }
+    }
+
+    /// A check that only opens a TCP connection.
+    pub fn tcp() -> HealthCheck {
+        HealthCheck::new(Vec::new(), |_| Verdict::Pass)
+    }
+
+    /// A check sending an HTTP/1.0 `GET` request for `uri`, passing if the response status is
+    /// in `statuses`.
+    pub fn http(uri: &str, host: &str, statuses: RangeInclusive<u16>) -> HealthCheck {
+        let probe = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", uri, host);
+        HealthCheck::new(probe, move |response| match response_status(response) {
+            Some(Some(status)) if statuses.contains(&status) => Verdict::Pass,
+            Some(_) => Verdict::Fail,
+            None => Verdict::Incomplete,
+        })
+    }
+
+    /// Set the interval between two checks of a peer.
+    pub fn set_interval(&mut self, interval: Msec) {
+        self.interval = interval;
+    }
+
+    /// Set the time a check may take, from opening the connection to evaluating the response.
+    pub fn set_timeout(&mut self, timeout: Msec) {
+        self.timeout = timeout;
+    }
+
+    /// Set the number of consecutive successful checks after which a peer is marked up.
+    pub fn set_passes(&mut self, passes: usize) {
+        self.passes = passes.max(1);
+    }
+
+    /// Set the number of consecutive failed checks after which a peer is marked down.
+    pub fn set_fails(&mut self, fails: usize) {
+        self.fails = fails.max(1);
+    }
+
+    /// Set the size of the response after which a check fails without a verdict. The default
+    /// is 16k.
+    pub fn set_max_response(&mut self, size: ByteSize) {
+        self.max_response = size;
+    }
+
+    /// Start checking the peers of an upstream block, found with
+    /// [`upstream::find`](crate::http::upstream::find).
+    ///
+    /// Call this from the `init_process` handler of a module. The checks live as long as the
+    /// worker process, and stop when it begins shutting down.
+    pub unsafe fn start(&self, us: *mut ngx_http_upstream_srv_conf_t) -> Result<(), String> {
+        let peers = (*us).peer.data as *mut ngx_http_upstream_rr_peers_t;
+        let upstream = NgxStr::from_ngx_str((*us).host).to_string_lossy().into_owned();
+        if peers.is_null() {
+            return Err(format!("upstream \"{}\" has no peers", upstream));
+        }
+        if !(*peers).shpool.is_null() && worker_number() != Some(0) {
+            return Ok(());
+        }
+
+        for &list in [peers, (*peers).next].iter().filter(|list| !list.is_null()) {
+            for peer in Peers::from_ngx_peers(list).iter().filter(|peer| !peer.is_down()) {
+                // The probe lives as long as the worker process.
+                let probe: &'static mut Probe = Box::leak(Box::new(Probe {
+                    check: self.clone(),
+                    upstream: upstream.clone(),
+                    peers: list,
+                    peer: peer as *const _ as *mut ngx_http_upstream_rr_peer_t,
+                    pc: mem::zeroed(),
+                    timer: mem::zeroed(),
+                    sent: 0,
+                    reading: false,
+                    response: Vec::new(),
+                    passes: 0,
+                    fails: 0,
+                }));
+
+                probe.timer.handler = Some(ngx_rs_health_check_handler);
+                probe.timer.data = probe as *mut Probe as *mut c_void;
+                probe.timer.log = (*ngx_cycle).log;
+                probe.timer.set_cancelable(1);
+
+                // Spread the first checks over the interval.
+                let delay = (random_f64() * self.interval.as_msec() as f64) as ngx_msec_t;
+                ngx_add_timer(&mut probe.timer, delay + 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Status of an HTTP response: `None` until the status line is complete, `Some(None)` if it is
+// invalid.
+fn response_status(response: &[u8]) -> Option<Option<u16>> {
+    let end = response.iter().position(|&b| b == b'\n')?;
+    let line = String::from_utf8_lossy(&response[..end]);
+    let mut parts = line.split_whitespace();
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => status.parse().ok(),
+        _ => None,
+    };
+    Some(status)
+}
+
+struct Probe {
+    check: HealthCheck,
+    upstream: String,
+    peers: *mut ngx_http_upstream_rr_peers_t,
+    peer: *mut ngx_http_upstream_rr_peer_t,
+    pc: ngx_peer_connection_t,
+    // Schedules the next check, then enforces the timeout of the check in progress.
+    timer: ngx_event_t,
+    sent: usize,
+    reading: bool,
+    response: Vec<u8>,
+    passes: usize,
+    fails: usize,
+}
+
+impl Probe {
+    unsafe fn connect(&mut self) {
+        self.sent = 0;
+        self.reading = false;
+        self.response.clear();
+
+        self.pc = mem::zeroed();
+        self.pc.sockaddr = (*self.peer).sockaddr;
+        self.pc.socklen = (*self.peer).socklen;
+        self.pc.name = &mut (*self.peer).name;
+        self.pc.get = Some(ngx_event_get_peer);
+        self.pc.log = (*ngx_cycle).log;
+        self.pc.set_log_error(ngx_connection_log_error_e_NGX_ERROR_ERR as u32);
+
+        let rc = ngx_event_connect_peer(&mut self.pc);
+        if rc == NGX_ERROR as ngx_int_t || rc == NGX_BUSY as ngx_int_t || rc == NGX_DECLINED as ngx_int_t {
+            return self.finish(false);
+        }
+
+        let c = self.pc.connection;
+        (*c).data = self as *mut Probe as *mut c_void;
+        (*c).log = self.pc.log;
+        (*(*c).read).handler = Some(ngx_rs_health_check_read_handler);
+        (*(*c).read).log = (*c).log;
+        (*(*c).write).handler = Some(ngx_rs_health_check_write_handler);
+        (*(*c).write).log = (*c).log;
+
+        ngx_add_timer(&mut self.timer, self.check.timeout.as_msec());
+
+        if rc == NGX_OK as ngx_int_t {
+            self.send();
+        }
+    }
+
+    unsafe fn send(&mut self) {
+        let c = self.pc.connection;
+
+        if self.check.probe.is_empty() && !self.is_connected() {
+            return self.finish(false);
+        }
+
+        while self.sent < self.check.probe.len() {
+            let probe = &mut self.check.probe[self.sent..];
+            let n = (*c).send.unwrap()(c, probe.as_mut_ptr(), probe.len());
+            if n == NGX_ERROR as isize {
+                return self.finish(false);
+            }
+            if n == NGX_AGAIN as isize || n == 0 {
+                if ngx_handle_write_event((*c).write, 0) != NGX_OK as ngx_int_t {
+                    self.finish(false);
+                }
+                return;
+            }
+            self.sent += n as usize;
+        }
+
+        self.reading = true;
+        if self.check.probe.is_empty() {
+            // Checks without a probe may pass as soon as the connection is open.
+            match (self.check.evaluate)(&[]) {
+                Verdict::Incomplete => {}
+                verdict => return self.finish(verdict == Verdict::Pass),
+            }
+        }
+        self.receive();
+    }
+
+    unsafe fn receive(&mut self) {
+        let c = self.pc.connection;
+        let mut buf = [0u8; READ_SIZE];
+
+        loop {
+            let n = (*c).recv.unwrap()(c, buf.as_mut_ptr(), buf.len());
+            if n == NGX_AGAIN as isize {
+                if ngx_handle_read_event((*c).read, 0) != NGX_OK as ngx_int_t {
+                    self.finish(false);
+                }
+                return;
+            }
+            if n == NGX_ERROR as isize {
+                return self.finish(false);
+            }
+            if n == 0 {
+                let verdict = (self.check.evaluate)(&self.response);
+                return self.finish(verdict == Verdict::Pass);
+            }
+
+            self.response.extend_from_slice(&buf[..n as usize]);
+            if self.response.len() > self.check.max_response.as_bytes() {
+                return self.finish(false);
+            }
+            match (self.check.evaluate)(&self.response) {
+                Verdict::Pass => return self.finish(true),
+                Verdict::Fail => return self.finish(false),
+                Verdict::Incomplete => {}
+            }
+        }
+    }
+
+    // Check that a non-blocking connect succeeded, as done by `ngx_http_upstream_test_connect`.
+    unsafe fn is_connected(&self) -> bool {
+        let mut err: c_int = 0;
+        let mut len = mem::size_of::<c_int>() as socklen_t;
+        let rc = getsockopt(
+            (*self.pc.connection).fd,
+            SOL_SOCKET as c_int,
+            SO_ERROR as c_int,
+            &mut err as *mut c_int as *mut c_void,
+            &mut len,
+        );
+        rc == 0 && err == 0
+    }
+
+    unsafe fn finish(&mut self, passed: bool) {
+        if !self.pc.connection.is_null() {
+            ngx_close_connection(self.pc.connection);
+            self.pc.connection = ptr::null_mut();
+        }
+
+        self.update(passed);
+
+        if ngx_exiting != 0 || ngx_terminate != 0 || ngx_quit != 0 {
+            if self.timer.timer_set() != 0 {
+                ngx_del_timer(&mut self.timer);
+            }
+            return;
+        }
+        ngx_add_timer(&mut self.timer, self.check.interval.as_msec());
+    }
+
+    unsafe fn update(&mut self, passed: bool) {
+        if passed {
+            self.passes += 1;
+            self.fails = 0;
+        } else {
+            self.fails += 1;
+            self.passes = 0;
+        }
+
+        let peers = Peers::from_ngx_peers(self.peers);
+        let peer = self.peer;
+        peers.rlock();
+        peers.lock_peer(peer);
+
+        let name = NgxStr::from_ngx_str((*peer).name).to_string_lossy();
+        if (*peer).down != 0 && self.passes >= self.check.passes {
+            (*peer).down = 0;
+            (*peer).fails = 0;
+            ngx_log!(NGX_LOG_WARN, (*ngx_cycle).log, "upstream \"{}\" peer {} is up: health check passed", self.upstream, name);
+        } else if (*peer).down == 0 && self.fails >= self.check.fails {
+            (*peer).down = 1;
+            ngx_log!(NGX_LOG_WARN, (*ngx_cycle).log, "upstream \"{}\" peer {} is down: health check failed", self.upstream, name);
+        }
+
+        peers.unlock_peer(peer);
+        peers.unlock();
+    }
+}
+
+unsafe extern "C" fn ngx_rs_health_check_handler(ev: *mut ngx_event_t) {
+    let probe = &mut *((*ev).data as *mut Probe);
+
+    if !probe.pc.connection.is_null() {
+        // The check in progress timed out.
+        return probe.finish(false);
+    }
+    if ngx_exiting != 0 || ngx_terminate != 0 || ngx_quit != 0 {
+        return;
+    }
+    probe.connect();
+}
+
+unsafe extern "C" fn ngx_rs_health_check_write_handler(wev: *mut ngx_event_t) {
+    let c = (*wev).data as *mut ngx_connection_t;
+    let probe = &mut *((*c).data as *mut Probe);
+    if !probe.reading {
+        probe.send();
+    }
+}
+
+unsafe extern "C" fn ngx_rs_health_check_read_handler(rev: *mut ngx_event_t) {
+    let c = (*rev).data as *mut ngx_connection_t;
+    let probe = &mut *((*c).data as *mut Probe);
+    if probe.reading {
+        probe.receive();
+    }
+}
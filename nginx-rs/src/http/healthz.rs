@@ -0,0 +1,296 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::EventLoopLag;
+use crate::http::json_log::json_escape;
+use crate::http::request::Request;
+use crate::http::status::*;
+use crate::http::upstream::OutlierDetector;
+
+use std::cell::Cell;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// A health endpoint, such as `/healthz`, reporting the state of the worker process handling
+/// the request in JSON.
+///
+/// The report covers:
+/// - the worker: process ID and number, connections in use and the event loop lag, if
+///   [`EventLoopLag`] is running,
+/// - shared memory zones: size and free space,
+/// - background tasks: time since their last [`Heartbeat::beat`],
+/// - self-tests: the result of each function registered with [`Healthz::add_self_test`],
+/// - outlier detection: the statistics of the peers of each [`OutlierDetector`] added with
+///   [`Healthz::add_outlier_detector`], which are informational and never fail the check.
+///
+/// The response is `200 OK` if all checks pass, and `503 Service Unavailable` with
+/// `"status": "fail"` otherwise, so load balancers can use the endpoint directly. By default
+/// only stale tasks and failed self-tests fail the check; the event loop lag and zone free
+/// space fail it once limits are set.
+///
+/// A `Healthz` is local to the worker process. It is typically created while parsing the
+/// configuration, and [`Healthz::handle`] called from a content handler:
+///
+/// ```ignore
+/// http_request_handler!(healthz_handler, |request: &mut Request| {
+///     let conf = unsafe { &*(request.get_module_main_conf(&my_module) as *const MainConf) };
+///     conf.healthz.handle(request)
+/// });
+/// ```
+pub struct Healthz {
+    max_lag: Option<ngx_msec_t>,
+    min_zone_free: f64,
+    required_zones: Vec<String>,
+    tasks: Vec<(String, ngx_msec_t, Heartbeat)>,
+    tests: Vec<(String, Box<dyn Fn() -> Result<(), String>>)>,
+    outliers: Vec<&'static OutlierDetector>,
+}
+
+impl Healthz {
+    /// Create a health endpoint without tasks or self-tests.
+    pub fn new() -> Healthz {
+        Healthz { max_lag: None, min_zone_free: 0.0, required_zones: Vec::new(), tasks: Vec::new(), tests: Vec::new(), outliers: Vec::new() }
+    }
+
+    /// Fail the check while the smoothed event loop lag is above `max_lag`.
+    pub fn set_max_lag(&mut self, max_lag: Msec) {
+        self.max_lag = Some(max_lag.as_msec());
+    }
+
+    /// Fail the check while a shared memory zone has less than `fraction` (between `0.0` and
+    /// `1.0`) of its space free. The default is `0.0`, which never fails.
+    pub fn set_min_zone_free(&mut self, fraction: f64) {
+        self.min_zone_free = fraction.clamp(0.0, 1.0);
+    }
+
+    /// Fail the check if there is no shared memory zone named `name`.
+    pub fn require_zone(&mut self, name: &str) {
+        self.required_zones.push(name.to_string());
+    }
+
+    /// Add a background task, which is stale if it doesn't call [`Heartbeat::beat`] on the
+    /// returned handle at least every `max_age`.
+    ///
+    /// A task which never beat is reported as such without failing the check, as it may
+    /// not have run yet.
+    pub fn add_task(&mut self, name: &str, max_age: Msec) -> Heartbeat {
+        let heartbeat = Heartbeat(Rc::new(Cell::new(None)));
+        self.tasks.push((name.to_string(), max_age.as_msec(), heartbeat.clone()));
+        heartbeat
+    }
+
+    /// Add a self-test, run for each request, which returns an error message on failure.
+    ///
+    /// Self-tests should be fast and must not block, as they run in the event loop.
+    pub fn add_self_test<F>(&mut self, name: &str, test: F)
+    where
+        F: Fn() -> Result<(), String> + 'static,
+    {
+        self.tests.push((name.to_string(), Box::new(test)));
+    }
+
+    /// Report the peers tracked by an outlier detector.
+    pub fn add_outlier_detector(&mut self, detector: &'static OutlierDetector) {
+        self.outliers.push(detector);
+    }
+
+    /// Run the checks, returning whether they all passed and the JSON report.
+    pub fn report(&self) -> (bool, String) {
+        let mut healthy = true;
+        let now = unsafe { ngx_current_msec };
+        let mut json = String::from("{");
+
+        let lag = EventLoopLag::smoothed();
+        let lag_ok = match (lag, self.max_lag) {
+            (Some(lag), Some(max_lag)) => lag <= max_lag,
+            _ => true,
+        };
+        healthy &= lag_ok;
+        let (connections, free_connections) = unsafe { ((*ngx_cycle).connection_n, (*ngx_cycle).free_connection_n) };
+        let _ = write!(
+            json,
+            "\"worker\":{{\"pid\":{},\"number\":{},\"connections\":{},\"free_connections\":{},\"event_loop_lag\":{},\"status\":{}}}",
+            pid(),
+            json_option(worker_number()),
+            connections - free_connections,
+            free_connections,
+            json_option(lag),
+            json_status(lag_ok),
+        );
+
+        json.push_str(",\"zones\":[");
+        let zones = shm_zones();
+        for (i, zone) in zones.iter().enumerate() {
+            let ok = zone.size == 0 || zone.free as f64 >= zone.size as f64 * self.min_zone_free;
+            healthy &= ok;
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":{},\"size\":{},\"free\":{},\"status\":{}}}",
+                json_string(&zone.name),
+                zone.size,
+                zone.free,
+                json_status(ok),
+            );
+        }
+        for name in self.required_zones.iter() {
+            if zones.iter().any(|zone| zone.name == *name) {
+                continue;
+            }
+            healthy = false;
+            if !json.ends_with('[') {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"name\":{},\"status\":\"missing\"}}", json_string(name));
+        }
+
+        json.push_str("],\"tasks\":[");
+        for (i, (name, max_age, heartbeat)) in self.tasks.iter().enumerate() {
+            let age = heartbeat.0.get().map(|last| now.wrapping_sub(last));
+            let status = match age {
+                None => "\"pending\"",
+                Some(age) if age <= *max_age => "\"ok\"",
+                Some(_) => {
+                    healthy = false;
+                    "\"stale\""
+                }
+            };
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":{},\"age\":{},\"max_age\":{},\"status\":{}}}",
+                json_string(name),
+                json_option(age),
+                max_age,
+                status,
+            );
+        }
+
+        json.push_str("],\"tests\":[");
+        for (i, (name, test)) in self.tests.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            match test() {
+                Ok(()) => {
+                    let _ = write!(json, "{{\"name\":{},\"status\":\"ok\"}}", json_string(name));
+                }
+                Err(error) => {
+                    healthy = false;
+                    let _ = write!(
+                        json,
+                        "{{\"name\":{},\"status\":\"fail\",\"error\":{}}}",
+                        json_string(name),
+                        json_string(&error),
+                    );
+                }
+            }
+        }
+
+        json.push_str("],\"outliers\":[");
+        for (i, detector) in self.outliers.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"zone\":{},\"peers\":[", json_string(&detector.name().to_string_lossy()));
+            for (j, peer) in detector.stats().iter().enumerate() {
+                if j > 0 {
+                    json.push(',');
+                }
+                let _ = write!(
+                    json,
+                    "{{\"name\":{},\"requests\":{},\"failures\":{},\"consecutive_failures\":{},\"avg_latency\":{},\"ejected_for\":{},\"ejections\":{}}}",
+                    json_string(&peer.name),
+                    peer.requests,
+                    peer.failures,
+                    peer.consecutive_failures,
+                    json_option(peer.avg_latency),
+                    json_option(peer.ejected_for),
+                    peer.ejections,
+                );
+            }
+            json.push_str("]}");
+        }
+
+        let _ = write!(json, "],\"status\":{}}}", json_status(healthy));
+        json.push('\n');
+        (healthy, json)
+    }
+
+    /// Respond to a request with the report.
+    pub fn handle(&self, request: &mut Request) -> Status {
+        let (healthy, json) = self.report();
+        let status = if healthy { HTTP_OK } else { HTTP_SERVICE_UNAVAILABLE };
+        request.send_response(status, "application/json", json.as_bytes())
+    }
+}
+
+impl Default for Healthz {
+    fn default() -> Healthz {
+        Healthz::new()
+    }
+}
+
+/// Handle for a background task of a [`Healthz`] to report it is still running.
+#[derive(Clone)]
+pub struct Heartbeat(Rc<Cell<Option<ngx_msec_t>>>);
+
+impl Heartbeat {
+    /// Record that the task ran now.
+    pub fn beat(&self) {
+        self.0.set(Some(unsafe { ngx_current_msec }));
+    }
+}
+
+struct ZoneStatus {
+    name: String,
+    size: usize,
+    free: usize,
+}
+
+fn shm_zones() -> Vec<ZoneStatus> {
+    let mut zones = Vec::new();
+    unsafe {
+        let mut part: *mut ngx_list_part_t = &mut (*ngx_cycle).shared_memory.part;
+        while !part.is_null() {
+            let elts = (*part).elts as *const ngx_shm_zone_t;
+            for i in 0..(*part).nelts {
+                let zone = &*elts.add(i);
+                let name = NgxStr::from_ngx_str(zone.shm.name).to_string_lossy().into_owned();
+                // Nginx initializes a slab pool at the start of each zone.
+                let free = if zone.shm.addr.is_null() {
+                    0
+                } else {
+                    (*(zone.shm.addr as *const ngx_slab_pool_t)).pfree * ngx_pagesize
+                };
+                zones.push(ZoneStatus { name, size: zone.shm.size, free: free as usize });
+            }
+            part = (*part).next;
+        }
+    }
+    zones
+}
+
+fn json_status(ok: bool) -> &'static str {
+    if ok {
+        "\"ok\""
+    } else {
+        "\"fail\""
+    }
+}
+
+fn json_option<T: std::fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => String::from("null"),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    json_escape(&mut out, value);
+    out
+}
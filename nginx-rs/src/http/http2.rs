@@ -0,0 +1,55 @@
+use crate::bindings::*;
+use crate::http::request::Request;
+
+// Server push was removed in Nginx 1.25.1.
+const NGINX_WITHOUT_PUSH: u32 = 1_025_001;
+
+impl Request {
+    /// Did the request arrive over HTTP/2?
+    pub fn is_http2(&self) -> bool {
+        self.http_version() == NGX_HTTP_VERSION_20 as ngx_uint_t
+    }
+
+    /// Identifier of the HTTP/2 stream of the request, or `None` for other protocols.
+    ///
+    /// With the `http2` feature, which requires Nginx to be built with
+    /// `--with-http_v2_module`; otherwise this is always `None`.
+    pub fn http2_stream_id(&self) -> Option<u32> {
+        #[cfg(feature = "http2")]
+        unsafe {
+            let r = self.as_ngx_http_request();
+            if !(*r).stream.is_null() && !(*(*r).stream).node.is_null() {
+                return Some((*(*(*r).stream).node).id as u32);
+            }
+        }
+        None
+    }
+
+    /// Push `uri` to an HTTP/2 client along with the response, with a `Link` preload header
+    /// that Nginx turns into a push with `http2_push_preload on`. `attributes` are added to
+    /// the link, such as `("as", "style")`.
+    ///
+    /// Pushed requests copy the headers of the request. This must be called before
+    /// [`Request::send_header`]. It does nothing and returns `false` for other protocols,
+    /// subrequests, without the `http2` feature, and with Nginx 1.25.1 and later, which
+    /// dropped server push.
+    pub fn push(&mut self, uri: &str, attributes: &[(&str, &str)]) -> bool {
+        if !cfg!(feature = "http2") || nginx_version >= NGINX_WITHOUT_PUSH || !self.is_http2() || !self.is_main() {
+            return false;
+        }
+        unsafe {
+            if (*self.as_ngx_http_request()).header_sent() != 0 {
+                return false;
+            }
+        }
+
+        let mut link = format!("<{}>; rel=preload", uri);
+        for (name, value) in attributes {
+            link.push_str("; ");
+            link.push_str(name);
+            link.push('=');
+            link.push_str(value);
+        }
+        self.set_header("Link", &link)
+    }
+}
@@ -0,0 +1,19 @@
+use crate::bindings::*;
+#[cfg(feature = "http3")]
+use crate::core::Quic;
+use crate::http::request::Request;
+
+impl Request {
+    /// Did the request arrive over HTTP/3?
+    pub fn is_http3(&self) -> bool {
+        self.http_version() == NGX_HTTP_VERSION_30 as ngx_uint_t
+    }
+
+    /// The QUIC connection of an HTTP/3 request, see [`Connection::quic`].
+    ///
+    /// [`Connection::quic`]: crate::core::Connection::quic
+    #[cfg(feature = "http3")]
+    pub fn quic(&self) -> Option<Quic> {
+        self.connection().quic()
+    }
+}
@@ -0,0 +1,314 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::Timer;
+use crate::http::request::Request;
+use crate::ngx_log;
+
+use std::fmt::Write;
+use std::os::raw::c_void;
+use std::ptr;
+
+/// A field of a request written by [`JsonLine::request`], under the name of the matching
+/// access log variable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogField {
+    /// Client address (`remote_addr`).
+    RemoteAddr,
+    /// Request method (`request_method`).
+    Method,
+    /// Request URI, without arguments (`uri`).
+    Uri,
+    /// Request arguments (`args`).
+    Args,
+    /// Response status (`status`).
+    Status,
+    /// Bytes sent to the client (`bytes_sent`).
+    BytesSent,
+    /// Bytes of the response body sent to the client (`body_bytes_sent`).
+    BodyBytesSent,
+    /// Milliseconds since the request started (`request_time_ms`).
+    RequestTime,
+    /// Total response time of the upstream servers in milliseconds, `null` without upstream
+    /// (`upstream_response_time_ms`).
+    UpstreamResponseTime,
+    /// Any variable, such as `time_iso8601`, `request_id` or one defined by a module, under
+    /// its name, `null` if not found.
+    Variable(&'static str),
+}
+
+/// A JSON object written on a single line, for structured access logs.
+///
+/// ```ignore
+/// http_log_handler!(ngx_http_waf_log_handler, |request: &mut Request| {
+///     let mut line = JsonLine::new();
+///     line.request(request, &[LogField::Method, LogField::Uri, LogField::Status, LogField::RequestTime,
+///         LogField::Variable("request_id")]);
+///     line.str("verdict", verdict_of(request)).uint("score", score_of(request));
+///     conf.json_log.write(&line.finish());
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct JsonLine {
+    buf: String,
+}
+
+impl JsonLine {
+    /// An empty object.
+    pub fn new() -> JsonLine {
+        JsonLine { buf: String::from("{") }
+    }
+
+    fn key(&mut self, key: &str) -> &mut String {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        json_escape(&mut self.buf, key);
+        self.buf.push(':');
+        &mut self.buf
+    }
+
+    /// Add a string field.
+    pub fn str(&mut self, key: &str, value: &str) -> &mut JsonLine {
+        json_escape(self.key(key), value);
+        self
+    }
+
+    /// Add a string field from bytes, replacing invalid UTF-8.
+    pub fn bytes(&mut self, key: &str, value: &[u8]) -> &mut JsonLine {
+        self.str(key, &String::from_utf8_lossy(value))
+    }
+
+    /// Add a signed integer field.
+    pub fn int(&mut self, key: &str, value: i64) -> &mut JsonLine {
+        let _ = write!(self.key(key), "{}", value);
+        self
+    }
+
+    /// Add an unsigned integer field.
+    pub fn uint(&mut self, key: &str, value: u64) -> &mut JsonLine {
+        let _ = write!(self.key(key), "{}", value);
+        self
+    }
+
+    /// Add a number field. Infinite and NaN values are written as `null`.
+    pub fn float(&mut self, key: &str, value: f64) -> &mut JsonLine {
+        if value.is_finite() {
+            let _ = write!(self.key(key), "{}", value);
+        } else {
+            self.key(key).push_str("null");
+        }
+        self
+    }
+
+    /// Add a boolean field.
+    pub fn bool(&mut self, key: &str, value: bool) -> &mut JsonLine {
+        self.key(key).push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    /// Add a `null` field.
+    pub fn null(&mut self, key: &str) -> &mut JsonLine {
+        self.key(key).push_str("null");
+        self
+    }
+
+    /// Add the `fields` of `request`.
+    ///
+    /// This is meant for log phase handlers, once the response metadata is final.
+    pub fn request(&mut self, request: &mut Request, fields: &[LogField]) -> &mut JsonLine {
+        for field in fields {
+            match *field {
+                LogField::RemoteAddr => {
+                    let addr = request.connection().remote_addr_text().as_bytes().to_vec();
+                    self.bytes("remote_addr", &addr)
+                }
+                LogField::Method => {
+                    let method = unsafe { NgxStr::from_ngx_str((*request.as_ngx_http_request()).method_name) };
+                    self.bytes("request_method", method.as_bytes())
+                }
+                LogField::Uri => self.str("uri", &request.uri().unwrap_or_default()),
+                LogField::Args => self.str("args", &request.args().unwrap_or_default()),
+                LogField::Status => self.uint("status", request.response_status().0 as u64),
+                LogField::BytesSent => self.uint("bytes_sent", request.bytes_sent() as u64),
+                LogField::BodyBytesSent => self.uint("body_bytes_sent", request.body_bytes_sent() as u64),
+                LogField::RequestTime => self.uint("request_time_ms", request.request_time() as u64),
+                LogField::UpstreamResponseTime => match request.upstream_response_time() {
+                    Some(time) => self.uint("upstream_response_time_ms", time as u64),
+                    None => self.null("upstream_response_time_ms"),
+                },
+                LogField::Variable(name) => match request.variable(name) {
+                    Some(value) => {
+                        let value = value.as_bytes().to_vec();
+                        self.bytes(name, &value)
+                    }
+                    None => self.null(name),
+                },
+            };
+        }
+        self
+    }
+
+    /// The object, as a line without the trailing newline.
+    pub fn finish(mut self) -> String {
+        self.buf.push('}');
+        self.buf
+    }
+}
+
+impl Default for JsonLine {
+    fn default() -> JsonLine {
+        JsonLine::new()
+    }
+}
+
+// Append `value` to `out` as a JSON string, with its quotes.
+pub(crate) fn json_escape(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Lines buffered by a worker process, in the `data` of the file.
+struct JsonLogBuffer {
+    buf: Vec<u8>,
+    size: usize,
+    flush: Option<Msec>,
+    timer: Option<Timer>,
+    exit_flusher: bool,
+}
+
+/// A log file owned by a module, such as one for [`JsonLine`]s, opened with the other log
+/// files of the configuration.
+///
+/// Nginx reopens the file on `nginx -s reopen` (`SIGUSR1`), after flushing the buffered
+/// lines, so it works with log rotation as access logs do. Lines are buffered per worker up
+/// to the buffer size, and written once it is full, after the flush interval, on reopen, and
+/// when the worker exits. The path must not also be used by an `access_log` directive.
+#[derive(Clone, Copy, Debug)]
+pub struct JsonLog {
+    file: *mut ngx_open_file_t,
+    buffer: *mut JsonLogBuffer,
+}
+
+impl JsonLog {
+    /// Open the log file `path`, relative to the prefix of the server, while parsing the
+    /// configuration, such as from the handler of a directive naming it.
+    ///
+    /// Lines are buffered up to `buffer` bytes, `0` to write each line as it is logged, and
+    /// for at most `flush`.
+    pub unsafe fn open(cf: *mut ngx_conf_t, path: &str, buffer: usize, flush: Option<Msec>) -> Option<JsonLog> {
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        // The file keeps the name.
+        let mut name = NgxString::new_in(path, &mut pool)?.into_ngx_str();
+
+        let file = ngx_conf_open_file((*cf).cycle, &mut name);
+        if file.is_null() {
+            return None;
+        }
+        if buffer == 0 {
+            return Some(JsonLog { file, buffer: ptr::null_mut() });
+        }
+
+        let state: *mut JsonLogBuffer = match pool.alloc(JsonLogBuffer {
+            buf: Vec::with_capacity(buffer),
+            size: buffer,
+            flush,
+            timer: None,
+            exit_flusher: false,
+        }) {
+            Some(state) => state.as_ptr(),
+            None => return None,
+        };
+        // Flushed on reopen. A second log on the same path is only flushed by its own timer
+        // and on exit, which doesn't lose lines.
+        if (*file).data.is_null() {
+            (*file).data = state as *mut c_void;
+            (*file).flush = Some(ngx_rs_json_log_flush);
+        }
+        Some(JsonLog { file, buffer: state })
+    }
+
+    /// Pointer to the [`ngx_open_file_t`].
+    pub fn as_ngx_open_file(&self) -> *mut ngx_open_file_t {
+        self.file
+    }
+
+    /// Write `line`, followed by a newline.
+    pub fn write(&self, line: &str) {
+        unsafe {
+            if self.buffer.is_null() {
+                write_file(self.file, &[line.as_bytes(), b"\n"]);
+                return;
+            }
+            let buffer = &mut *self.buffer;
+
+            if buffer.buf.len() + line.len() + 1 > buffer.size {
+                self.flush();
+                if line.len() + 1 > buffer.size {
+                    write_file(self.file, &[line.as_bytes(), b"\n"]);
+                    return;
+                }
+            }
+            buffer.buf.extend_from_slice(line.as_bytes());
+            buffer.buf.push(b'\n');
+
+            if !buffer.exit_flusher {
+                buffer.exit_flusher = true;
+                let log = *self;
+                register_exit_flusher("json_log", move |_| log.flush());
+            }
+            if let (Some(flush), None) = (buffer.flush, buffer.timer.as_ref()) {
+                let log = *self;
+                buffer.timer = Some(Timer::once(flush, move || log.flush()));
+            }
+        }
+    }
+
+    /// Write the buffered lines.
+    pub fn flush(&self) {
+        unsafe { flush_buffer(self.file, self.buffer) }
+    }
+}
+
+unsafe extern "C" fn ngx_rs_json_log_flush(file: *mut ngx_open_file_t, _log: *mut ngx_log_t) {
+    flush_buffer(file, (*file).data as *mut JsonLogBuffer);
+}
+
+unsafe fn flush_buffer(file: *mut ngx_open_file_t, buffer: *mut JsonLogBuffer) {
+    if buffer.is_null() {
+        return;
+    }
+    let buffer = &mut *buffer;
+    // Cancelled, or done if this runs from the timer.
+    drop(buffer.timer.take());
+    if !buffer.buf.is_empty() {
+        write_file(file, &[&buffer.buf]);
+        buffer.buf.clear();
+    }
+}
+
+unsafe fn write_file(file: *mut ngx_open_file_t, parts: &[&[u8]]) {
+    if (*file).fd == NGX_INVALID_FILE {
+        return;
+    }
+    for part in parts {
+        let n = libc::write((*file).fd, part.as_ptr() as *const c_void, part.len());
+        if n != part.len() as isize {
+            let name = NgxStr::from_ngx_str((*file).name);
+            ngx_log!(NGX_LOG_ALERT, (*ngx_cycle).log, "failed to write to \"{}\"", name);
+            return;
+        }
+    }
+}
@@ -1,9 +1,85 @@
+mod async_handler;
+mod body_hash;
+#[cfg(feature = "ssl")]
+mod certificate;
+mod classify;
+mod client;
+#[cfg(feature = "ssl")]
+mod client_hello;
+mod command;
+mod complex_value;
+mod content_type;
 mod conf;
+mod detach;
+mod export;
+mod filter;
+mod finalize;
+mod fingerprint_limit;
+mod guardrail;
+mod health;
+mod healthz;
+mod http2;
+mod http3;
+mod json_log;
 mod status;
 mod module;
+#[cfg(feature = "ssl")]
+mod mtls;
+mod parse;
+mod phases;
 mod request;
+mod resolver;
+mod shed;
+mod signing;
+mod sse;
+mod slo;
+#[cfg(feature = "threads")]
+mod thread_pool;
+mod timing;
+pub mod upstream;
+mod variable;
+mod websocket;
+mod writer;
+#[cfg(feature = "zstd")]
+mod zstd;
 
+pub use async_handler::*;
+pub use body_hash::*;
+#[cfg(feature = "ssl")]
+pub use certificate::*;
+pub use classify::*;
+pub use client::*;
+#[cfg(feature = "ssl")]
+pub use client_hello::*;
+pub use command::*;
+pub use complex_value::*;
+pub use content_type::*;
 pub use conf::*;
+pub use detach::*;
+pub use export::*;
+pub use filter::*;
+pub use finalize::*;
+pub use fingerprint_limit::*;
+pub use guardrail::*;
+pub use health::*;
+pub use healthz::*;
+pub use json_log::*;
 pub use status::*;
 pub use module::*;
+pub use parse::*;
+pub use phases::*;
 pub use request::*;
+pub use resolver::*;
+pub use shed::*;
+pub use signing::*;
+pub use sse::*;
+pub use slo::*;
+pub use timing::*;
+pub use variable::*;
+pub use websocket::*;
+pub use writer::*;
+#[cfg(feature = "zstd")]
+pub use self::zstd::*;
+
+#[cfg(feature = "derive")]
+pub use nginx_rs_derive::NgxConf;
@@ -4,6 +4,136 @@ use crate::core::*;
 use std::os::raw::{c_void, c_char};
 use core::ptr;
 
+/// Declare a complete HTTP module.
+///
+/// Expands to the module context (`ngx_http_module_t`) and the `ngx_module_t` static. The
+/// `ngx_modules` exports required by dynamic modules are left to [`ngx_modules!`], which
+/// lists all the modules of the library once. The configuration handlers are those of the
+/// given [`HTTPModule`] implementation, and the directives are a table such as one defined
+/// with [`ngx_commands!`].
+///
+/// Phase handlers, such as those defined with [`http_request_handler!`], are registered
+/// before `postconfiguration` of the [`HTTPModule`] is called. The module's `exit_process`
+/// handler runs the [exit flushers](crate::core::register_exit_flusher). Phases are named
+/// `post_read`, `server_rewrite`, `rewrite`, `preaccess`, `access`, `precontent`, `content`
+/// and `log`.
+///
+/// The optional `init_master`, `init_module`, `init_process`, `exit_process` and
+/// `exit_master` parameters, in this order, name lifecycle handlers defined with
+/// [`ngx_init_master!`], [`ngx_init_module!`], [`ngx_init_process!`], [`ngx_exit_process!`]
+/// and [`ngx_exit_master!`], which keep starting the background jobs and running the exit
+/// flushers.
+///
+/// Libraries with this module only can end with `ngx_modules = true;` to also expand to its
+/// `ngx_modules` exports, instead of calling [`ngx_modules!`].
+///
+/// ```ignore
+/// http_module! {
+///     static ngx_http_hello_world_module: Module;
+///     commands = ngx_http_hello_world_commands;
+///     phases = [access => ngx_http_hello_world_access_handler];
+/// }
+///
+/// ngx_modules!(ngx_http_hello_world_module);
+/// ```
+///
+/// [`ngx_modules!`]: crate::ngx_modules
+/// [`ngx_commands!`]: crate::ngx_commands
+/// [`http_request_handler!`]: crate::http_request_handler
+/// [`ngx_init_master!`]: crate::ngx_init_master
+/// [`ngx_init_module!`]: crate::ngx_init_module
+/// [`ngx_init_process!`]: crate::ngx_init_process
+/// [`ngx_exit_process!`]: crate::ngx_exit_process
+/// [`ngx_exit_master!`]: crate::ngx_exit_master
+#[macro_export]
+macro_rules! http_module {
+    (
+        static $name: ident : $module: ty ;
+        commands = $commands: ident ;
+        $( phases = [ $( $phase: ident => $handler: ident ),* $(,)? ] ; )?
+        $( init_master = $init_master: ident ; )?
+        $( init_module = $init_module: ident ; )?
+        $( init_process = $init_process: ident ; )?
+        $( exit_process = $exit_process: ident ; )?
+        $( exit_master = $exit_master: ident ; )?
+        $( ngx_modules = $exports: ident ; )?
+    ) => {
+        #[no_mangle]
+        pub static mut $name: $crate::__private::bindings::ngx_module_t = $crate::__private::bindings::ngx_module_t {
+            ctx_index: $crate::__private::bindings::ngx_uint_t::MAX,
+            index: $crate::__private::bindings::ngx_uint_t::MAX,
+            name: ::std::ptr::null_mut(),
+            spare0: 0,
+            spare1: 0,
+            version: $crate::__private::bindings::nginx_version as $crate::__private::bindings::ngx_uint_t,
+            signature: $crate::__private::bindings::NGX_RS_MODULE_SIGNATURE.as_ptr() as *const ::std::os::raw::c_char,
+
+            ctx: {
+                unsafe extern "C" fn postconfiguration(cf: *mut $crate::__private::bindings::ngx_conf_t) -> $crate::__private::bindings::ngx_int_t {
+                    $( $(
+                        let status = $crate::http::Phases::from_conf(cf).add($crate::__http_phase!($phase), $handler);
+                        if status != $crate::core::OK {
+                            return status.into();
+                        }
+                    )* )?
+
+                    <$module as $crate::http::HTTPModule>::postconfiguration(cf)
+                }
+
+                static CTX: $crate::__private::bindings::ngx_http_module_t = $crate::__private::bindings::ngx_http_module_t {
+                    preconfiguration: Some(<$module as $crate::http::HTTPModule>::preconfiguration),
+                    postconfiguration: Some(postconfiguration),
+
+                    create_main_conf: Some(<$module as $crate::http::HTTPModule>::create_main_conf),
+                    init_main_conf: Some(<$module as $crate::http::HTTPModule>::init_main_conf),
+
+                    create_srv_conf: Some(<$module as $crate::http::HTTPModule>::create_srv_conf),
+                    merge_srv_conf: Some(<$module as $crate::http::HTTPModule>::merge_srv_conf),
+
+                    create_loc_conf: Some(<$module as $crate::http::HTTPModule>::create_loc_conf),
+                    merge_loc_conf: Some(<$module as $crate::http::HTTPModule>::merge_loc_conf),
+                };
+
+                &CTX as *const _ as *mut _
+            },
+            commands: ::std::ptr::addr_of_mut!($commands) as *mut $crate::__private::bindings::ngx_command_t,
+            type_: $crate::__private::bindings::NGX_HTTP_MODULE as $crate::__private::bindings::ngx_uint_t,
+
+            init_master: $crate::__ngx_module_hook!($($init_master)?; None),
+            init_module: $crate::__ngx_module_hook!($($init_module)?; None),
+            init_process: $crate::__ngx_module_hook!($($init_process)?; Some($crate::core::init_process_jobs)),
+            init_thread: None,
+            exit_thread: None,
+            exit_process: $crate::__ngx_module_hook!($($exit_process)?; Some($crate::core::exit_process_flush)),
+            exit_master: $crate::__ngx_module_hook!($($exit_master)?; None),
+
+            spare_hook0: 0,
+            spare_hook1: 0,
+            spare_hook2: 0,
+            spare_hook3: 0,
+            spare_hook4: 0,
+            spare_hook5: 0,
+            spare_hook6: 0,
+            spare_hook7: 0,
+        };
+
+        $crate::__ngx_module_exports!($($exports)?; $name);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __http_phase {
+    (post_read) => { $crate::http::Phase::PostRead };
+    (server_rewrite) => { $crate::http::Phase::ServerRewrite };
+    (rewrite) => { $crate::http::Phase::Rewrite };
+    (preaccess) => { $crate::http::Phase::PreAccess };
+    (access) => { $crate::http::Phase::Access };
+    (precontent) => { $crate::http::Phase::PreContent };
+    (content) => { $crate::http::Phase::Content };
+    (log) => { $crate::http::Phase::Log };
+}
+
 pub trait Merge {
     fn merge(&mut self, prev: &Self);
 }
@@ -27,7 +157,10 @@ pub trait HTTPModule {
 
     unsafe extern "C" fn create_main_conf(cf: *mut ngx_conf_t) -> *mut c_void {
         let mut pool = Pool::from_ngx_pool((*cf).pool);
-        pool.allocate::<Self::MainConf>(Default::default()) as *mut c_void
+        match pool.alloc::<Self::MainConf>(Default::default()) {
+            Some(conf) => conf.as_ptr() as *mut c_void,
+            None => ptr::null_mut(),
+        }
     }
 
     unsafe extern "C" fn init_main_conf(_cf: *mut ngx_conf_t, _conf: *mut c_void) -> *mut c_char {
@@ -36,7 +169,10 @@ pub trait HTTPModule {
 
     unsafe extern "C" fn create_srv_conf(cf: *mut ngx_conf_t) -> *mut c_void {
         let mut pool = Pool::from_ngx_pool((*cf).pool);
-        pool.allocate::<Self::SrvConf>(Default::default()) as *mut c_void
+        match pool.alloc::<Self::SrvConf>(Default::default()) {
+            Some(conf) => conf.as_ptr() as *mut c_void,
+            None => ptr::null_mut(),
+        }
     }
 
     unsafe extern "C" fn merge_srv_conf(_cf: *mut ngx_conf_t, prev: *mut c_void, conf: *mut c_void) -> *mut c_char {
@@ -48,7 +184,10 @@ pub trait HTTPModule {
 
     unsafe extern "C" fn create_loc_conf(cf: *mut ngx_conf_t) -> *mut c_void {
         let mut pool = Pool::from_ngx_pool((*cf).pool);
-        pool.allocate::<Self::LocConf>(Default::default()) as *mut c_void
+        match pool.alloc::<Self::LocConf>(Default::default()) {
+            Some(conf) => conf.as_ptr() as *mut c_void,
+            None => ptr::null_mut(),
+        }
     }
 
     unsafe extern "C" fn merge_loc_conf(_cf: *mut ngx_conf_t, prev: *mut c_void, conf: *mut c_void) -> *mut c_char {
@@ -0,0 +1,59 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::phases::Access;
+use crate::http::request::Request;
+use crate::http::status::*;
+
+impl Request {
+    /// The TLS session of the connection of the request, or `None` without TLS.
+    ///
+    /// Unlike with [`Connection::ssl`], values are allocated from the request pool, so they
+    /// are freed with the request rather than with its keepalive connection.
+    pub fn ssl(&self) -> Option<Ssl> {
+        let r = self.as_ngx_http_request();
+        unsafe { Ssl::new((*r).connection, (*r).pool) }
+    }
+
+    /// The certificate presented by the client over TLS, verified or not, see
+    /// [`Ssl::client_certificate`].
+    pub fn client_certificate(&self) -> Option<ClientCertificate> {
+        self.ssl()?.client_certificate()
+    }
+
+    /// Result of the verification of the client certificate, `ClientVerify::None` for
+    /// requests without TLS.
+    pub fn client_verify(&self) -> ClientVerify {
+        self.ssl().map_or(ClientVerify::None, |ssl| ssl.client_verify())
+    }
+
+    /// The verified client certificate, for access phase handlers requiring mutual TLS.
+    ///
+    /// Without a certificate, this denies the request with `HTTP_NO_CERT` (496), and with a
+    /// certificate that failed verification, with `HTTP_SSL_CERT_ERROR` (495), as
+    /// `ssl_verify_client on` does. Both are answered with 400 by Nginx. Use
+    /// `ssl_verify_client optional` so the handshake doesn't fail first.
+    ///
+    /// ```ignore
+    /// http_access_handler!(ngx_http_mtls_access_handler, |request: &mut Request| {
+    ///     let cert = match request.require_client_certificate() {
+    ///         Ok(cert) => cert,
+    ///         Err(access) => return access,
+    ///     };
+    ///     match cert.subject_attribute("O") {
+    ///         Some(org) if org == "Example" => Access::Allow,
+    ///         _ => Access::Deny(HTTP_FORBIDDEN),
+    ///     }
+    /// });
+    /// ```
+    pub fn require_client_certificate(&self) -> Result<ClientCertificate, Access> {
+        let ssl = self.ssl().ok_or(Access::Deny(HTTP_NO_CERT))?;
+        match ssl.client_verify() {
+            ClientVerify::Success => ssl.client_certificate().ok_or(Access::Deny(HTTP_NO_CERT)),
+            ClientVerify::Failed(reason) => {
+                ngx_log!(NGX_LOG_INFO, self.connection().log(), "client certificate verification failed: {}", reason);
+                Err(Access::Deny(HTTP_SSL_CERT_ERROR))
+            }
+            ClientVerify::None => Err(Access::Deny(HTTP_NO_CERT)),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::bindings::*;
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::slice;
+
+/// Error of one of the HTTP parsers of Nginx.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HttpParseError {
+    /// A header line is invalid.
+    InvalidHeader,
+    /// A status line is invalid.
+    InvalidStatusLine,
+    /// The chunked encoding is invalid.
+    InvalidChunked,
+    /// A URI is invalid, such as one with a `..` segment above the root.
+    InvalidUri,
+}
+
+impl fmt::Display for HttpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpParseError::InvalidHeader => write!(f, "invalid header line"),
+            HttpParseError::InvalidStatusLine => write!(f, "invalid status line"),
+            HttpParseError::InvalidChunked => write!(f, "invalid chunked encoding"),
+            HttpParseError::InvalidUri => write!(f, "invalid uri"),
+        }
+    }
+}
+
+impl Error for HttpParseError {}
+
+// The parsers keep their state in a request, and log through its connection: outside of a
+// request, they get a blank one, logging to the cycle log.
+struct ParserState {
+    r: ngx_http_request_t,
+    c: ngx_connection_t,
+}
+
+impl ParserState {
+    fn new() -> Box<ParserState> {
+        unsafe {
+            let mut state: Box<ParserState> = Box::new(mem::zeroed());
+            state.c.log = (*ngx_cycle).log;
+            state.r.connection = &mut state.c;
+            state
+        }
+    }
+
+    fn reset(&mut self) {
+        self.r.state = 0;
+    }
+}
+
+// A buffer over `data`, for the parsers to read from.
+fn buffer(data: &[u8]) -> ngx_buf_t {
+    let mut b: ngx_buf_t = unsafe { mem::zeroed() };
+    b.start = data.as_ptr() as *mut u_char;
+    b.pos = b.start;
+    b.last = unsafe { b.start.add(data.len()) };
+    b.end = b.last;
+    b.set_temporary(1);
+    b
+}
+
+// Bytes between two pointers into the same buffer.
+unsafe fn between<'a>(start: *const u_char, end: *const u_char) -> &'a [u8] {
+    if start.is_null() || end <= start {
+        return &[];
+    }
+    slice::from_raw_parts(start, end as usize - start as usize)
+}
+
+/// A header line parsed by [`HeaderParser`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HeaderLine<'a> {
+    /// A header, with its name as sent and its value without surrounding whitespace.
+    Header { name: &'a [u8], value: &'a [u8] },
+    /// The empty line ending the headers.
+    Done,
+}
+
+/// Parser of header lines, with `ngx_http_parse_header_line`, for protocols built on HTTP
+/// framing over a [`PeerConnection`](crate::event::PeerConnection).
+///
+/// ```ignore
+/// let mut parser = HeaderParser::new(false);
+/// loop {
+///     match parser.parse(&buf[pos..])? {
+///         Some((len, HeaderLine::Header { name, value })) => {
+///             headers.push((name.to_vec(), value.to_vec()));
+///             pos += len;
+///         }
+///         Some((len, HeaderLine::Done)) => {
+///             pos += len;
+///             break;
+///         }
+///         // Wait for more data and parse again from `pos`.
+///         None => return Ok(()),
+///     }
+/// }
+/// ```
+pub struct HeaderParser {
+    state: Box<ParserState>,
+    allow_underscores: bool,
+}
+
+impl HeaderParser {
+    /// Create a parser. Header names with underscores are invalid unless
+    /// `allow_underscores` is set, as with `underscores_in_headers`.
+    pub fn new(allow_underscores: bool) -> HeaderParser {
+        HeaderParser { state: ParserState::new(), allow_underscores }
+    }
+
+    /// Parse the header line at the start of `data`, returning its length (including the
+    /// line ending) and the line, or `None` if the line is incomplete.
+    ///
+    /// Lines with invalid characters in the header name are skipped, as Nginx does with
+    /// `ignore_invalid_headers on`.
+    pub fn parse<'a>(&mut self, data: &'a [u8]) -> Result<Option<(usize, HeaderLine<'a>)>, HttpParseError> {
+        let mut pos = 0;
+        loop {
+            let mut b = buffer(&data[pos..]);
+            let r = &mut self.state.r;
+            let rc = unsafe { ngx_http_parse_header_line(r, &mut b, self.allow_underscores as ngx_uint_t) };
+            let len = b.pos as usize - data.as_ptr() as usize;
+
+            match rc {
+                rc if rc == NGX_OK as ngx_int_t => {
+                    if r.invalid_header() != 0 {
+                        pos = len;
+                        continue;
+                    }
+                    let (name, value) = unsafe {
+                        (between(r.header_name_start, r.header_name_end), between(r.header_start, r.header_end))
+                    };
+                    return Ok(Some((len, HeaderLine::Header { name, value })));
+                }
+                rc if rc == NGX_HTTP_PARSE_HEADER_DONE as ngx_int_t => {
+                    return Ok(Some((len, HeaderLine::Done)));
+                }
+                rc if rc == NGX_AGAIN as ngx_int_t => {
+                    // The parser keeps pointers into the line: start over once the line is
+                    // complete.
+                    self.state.reset();
+                    return Ok(None);
+                }
+                _ => {
+                    self.state.reset();
+                    return Err(HttpParseError::InvalidHeader);
+                }
+            }
+        }
+    }
+}
+
+/// A status line parsed by [`parse_status_line`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StatusLine<'a> {
+    /// HTTP version, such as `1001` for HTTP/1.1 (`NGX_HTTP_VERSION_11`).
+    pub http_version: ngx_uint_t,
+    /// Status code.
+    pub code: ngx_uint_t,
+    /// Reason phrase, possibly empty.
+    pub reason: &'a [u8],
+}
+
+/// Parse the status line at the start of `data`, such as `HTTP/1.1 200 OK`, with
+/// `ngx_http_parse_status_line`.
+///
+/// Returns the length of the line (including the line ending) and the line, or `None` if
+/// the line is incomplete.
+pub fn parse_status_line(data: &[u8]) -> Result<Option<(usize, StatusLine)>, HttpParseError> {
+    let mut state = ParserState::new();
+    let mut b = buffer(data);
+    let mut status: ngx_http_status_t = unsafe { mem::zeroed() };
+
+    let rc = unsafe { ngx_http_parse_status_line(&mut state.r, &mut b, &mut status) };
+    if rc == NGX_AGAIN as ngx_int_t {
+        return Ok(None);
+    }
+    if rc != NGX_OK as ngx_int_t {
+        return Err(HttpParseError::InvalidStatusLine);
+    }
+
+    // `start` is the status code, followed by the reason phrase.
+    let text = unsafe { between(status.start, status.end) };
+    let reason = text.get(4..).unwrap_or(&[]);
+    let len = b.pos as usize - data.as_ptr() as usize;
+    Ok(Some((len, StatusLine { http_version: status.http_version, code: status.code, reason })))
+}
+
+/// Part of a chunked body decoded by [`ChunkedDecoder`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Chunk<'a> {
+    /// Data of the body.
+    Data(&'a [u8]),
+    /// The input was consumed without data: more input is needed.
+    Again,
+    /// The body is complete, trailers included (they are skipped).
+    Done,
+}
+
+/// Decoder of the chunked transfer encoding, with `ngx_http_parse_chunked`.
+///
+/// Unlike the other parsers, the decoder consumes its input as it goes, so input can be
+/// passed as it arrives.
+///
+/// ```ignore
+/// let mut input = &buf[..];
+/// loop {
+///     let (len, chunk) = decoder.decode(input)?;
+///     input = &input[len..];
+///     match chunk {
+///         Chunk::Data(data) => body.extend_from_slice(data),
+///         Chunk::Again => break,
+///         Chunk::Done => return Ok(body),
+///     }
+/// }
+/// ```
+pub struct ChunkedDecoder {
+    state: Box<ParserState>,
+    ctx: ngx_http_chunked_t,
+    done: bool,
+}
+
+impl ChunkedDecoder {
+    /// Start decoding a body.
+    pub fn new() -> ChunkedDecoder {
+        ChunkedDecoder { state: ParserState::new(), ctx: unsafe { mem::zeroed() }, done: false }
+    }
+
+    /// Decode the start of `input`, returning the number of bytes consumed and what they
+    /// decoded to.
+    pub fn decode<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Chunk<'a>), HttpParseError> {
+        if self.done {
+            return Ok((0, Chunk::Done));
+        }
+
+        let mut b = buffer(input);
+        let rc = unsafe { ngx_http_parse_chunked(&mut self.state.r, &mut b, &mut self.ctx) };
+        let mut len = b.pos as usize - input.as_ptr() as usize;
+
+        match rc {
+            rc if rc == NGX_OK as ngx_int_t => {
+                // The chunk has `ctx.size` bytes of data left, of which those in `input`
+                // are consumed now.
+                let size = (self.ctx.size as usize).min(input.len() - len);
+                let data = &input[len..len + size];
+                self.ctx.size -= size as off_t;
+                len += size;
+                Ok((len, if data.is_empty() { Chunk::Again } else { Chunk::Data(data) }))
+            }
+            rc if rc == NGX_DONE as ngx_int_t => {
+                self.done = true;
+                Ok((len, Chunk::Done))
+            }
+            rc if rc == NGX_AGAIN as ngx_int_t => Ok((len, Chunk::Again)),
+            _ => Err(HttpParseError::InvalidChunked),
+        }
+    }
+
+    /// Has the end of the body been decoded?
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl Default for ChunkedDecoder {
+    fn default() -> ChunkedDecoder {
+        ChunkedDecoder::new()
+    }
+}
+
+/// A request URI normalized by [`parse_uri`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParsedUri {
+    /// The path, decoded, with `.` and `..` segments resolved (`$uri`).
+    pub path: Vec<u8>,
+    /// The query string, as sent (`$args`).
+    pub args: Option<Vec<u8>>,
+}
+
+/// Normalize a request URI such as `/a/./b/../c%20d?x=1` the way Nginx does for `$uri`,
+/// with `ngx_http_parse_complex_uri`: percent-encoded characters are decoded, `.` and `..`
+/// segments resolved and, with `merge_slashes`, repeated slashes merged.
+///
+/// The URI must start with `/`. A `..` above the root is invalid.
+pub fn parse_uri(uri: &[u8], merge_slashes: bool) -> Result<ParsedUri, HttpParseError> {
+    if uri.first() != Some(&b'/') {
+        return Err(HttpParseError::InvalidUri);
+    }
+
+    let mut state = ParserState::new();
+    let mut path = vec![0u8; uri.len() + 1];
+    let r = &mut state.r;
+    r.uri_start = uri.as_ptr() as *mut u_char;
+    r.uri_end = unsafe { r.uri_start.add(uri.len()) };
+    r.uri.data = path.as_mut_ptr();
+
+    if unsafe { ngx_http_parse_complex_uri(r, merge_slashes as ngx_uint_t) } != NGX_OK as ngx_int_t {
+        return Err(HttpParseError::InvalidUri);
+    }
+
+    path.truncate(r.uri.len);
+    let args = if r.args_start.is_null() {
+        None
+    } else {
+        Some(unsafe { between(r.args_start, r.uri_end) }.to_vec())
+    };
+    Ok(ParsedUri { path, args })
+}
@@ -0,0 +1,232 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::conf::*;
+use crate::http::status::*;
+
+/// A [phase] of HTTP request processing that handlers can be added to.
+///
+/// [phase]: https://nginx.org/en/docs/dev/development_guide.html#http_phases
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Right after the request header has been read (`NGX_HTTP_POST_READ_PHASE`).
+    PostRead,
+    /// Rewrite directives of the server block (`NGX_HTTP_SERVER_REWRITE_PHASE`).
+    ServerRewrite,
+    /// Rewrite directives of the location (`NGX_HTTP_REWRITE_PHASE`).
+    Rewrite,
+    /// Access control not based on the client, such as rate limiting (`NGX_HTTP_PREACCESS_PHASE`).
+    PreAccess,
+    /// Access control of the client (`NGX_HTTP_ACCESS_PHASE`).
+    Access,
+    /// Before the content is generated, such as `try_files` (`NGX_HTTP_PRECONTENT_PHASE`).
+    PreContent,
+    /// Generation of the response (`NGX_HTTP_CONTENT_PHASE`), for handlers not bound to a
+    /// location.
+    Content,
+    /// Logging, after the response has been sent (`NGX_HTTP_LOG_PHASE`).
+    Log,
+}
+
+impl Phase {
+    fn as_ngx_http_phase(self) -> ngx_http_phases {
+        match self {
+            Phase::PostRead => ngx_http_phases_NGX_HTTP_POST_READ_PHASE,
+            Phase::ServerRewrite => ngx_http_phases_NGX_HTTP_SERVER_REWRITE_PHASE,
+            Phase::Rewrite => ngx_http_phases_NGX_HTTP_REWRITE_PHASE,
+            Phase::PreAccess => ngx_http_phases_NGX_HTTP_PREACCESS_PHASE,
+            Phase::Access => ngx_http_phases_NGX_HTTP_ACCESS_PHASE,
+            Phase::PreContent => ngx_http_phases_NGX_HTTP_PRECONTENT_PHASE,
+            Phase::Content => ngx_http_phases_NGX_HTTP_CONTENT_PHASE,
+            Phase::Log => ngx_http_phases_NGX_HTTP_LOG_PHASE,
+        }
+    }
+}
+
+/// Registration of [phase handlers].
+///
+/// Handlers must be added in the `postconfiguration` handler of a module, once the core
+/// module has created the phase handler arrays.
+///
+/// ```ignore
+/// unsafe extern "C" fn postconfiguration(cf: *mut ngx_conf_t) -> ngx_int_t {
+///     Phases::from_conf(cf).add(Phase::Access, ngx_http_hello_world_access_handler).into()
+/// }
+/// ```
+///
+/// [phase handlers]: https://nginx.org/en/docs/dev/development_guide.html#http_phases
+pub struct Phases {
+    cmcf: *mut ngx_http_core_main_conf_t,
+}
+
+impl Phases {
+    /// Phase handlers of the `http` block being configured.
+    pub unsafe fn from_conf(cf: *mut ngx_conf_t) -> Phases {
+        let cmcf = ngx_http_conf_get_module_main_conf(cf, &ngx_http_core_module) as *mut ngx_http_core_main_conf_t;
+        Phases { cmcf }
+    }
+
+    /// Add a handler to a phase, such as one defined with
+    /// [`http_request_handler!`](crate::http_request_handler).
+    ///
+    /// Handlers of a phase run in the reverse order of registration between modules, so the
+    /// order relative to other modules depends on the module load order; see
+    /// [`Phases::add_last`].
+    pub fn add(&mut self, phase: Phase, handler: unsafe extern "C" fn(*mut ngx_http_request_t) -> ngx_int_t) -> Status {
+        unsafe {
+            let phase = &mut (*self.cmcf).phases[phase.as_ngx_http_phase() as usize];
+            let h = ngx_array_push(&mut phase.handlers) as *mut ngx_http_handler_pt;
+            if h.is_null() {
+                return ERROR;
+            }
+            *h = Some(handler);
+        }
+        OK
+    }
+
+    /// Add a handler to a phase, to run after the handlers added so far by all modules.
+    ///
+    /// This is for handlers that depend on the work of other modules in the same phase, such
+    /// as handlers of the post-read or preaccess phases using the client address, which the
+    /// `realip` module replaces in these phases: with this, they see the real address
+    /// whatever the module load order. Handlers added later by other modules still run
+    /// before.
+    pub fn add_last(&mut self, phase: Phase, handler: unsafe extern "C" fn(*mut ngx_http_request_t) -> ngx_int_t) -> Status {
+        if self.add(phase, handler) != OK {
+            return ERROR;
+        }
+        unsafe {
+            // Handlers run from the last added to the first: move the handler to the front.
+            let handlers = &mut (*self.cmcf).phases[phase.as_ngx_http_phase() as usize].handlers;
+            let elts = std::slice::from_raw_parts_mut(handlers.elts as *mut ngx_http_handler_pt, handlers.nelts);
+            elts.rotate_right(1);
+        }
+        OK
+    }
+}
+
+/// Decision of an access phase handler, see [`http_access_handler!`](crate::http_access_handler).
+pub enum Access {
+    /// Allow the request (`NGX_OK`). With `satisfy any`, this grants access regardless of
+    /// other access modules.
+    Allow,
+    /// Leave the decision to other access modules (`NGX_DECLINED`).
+    Decline,
+    /// Deny the request, finalizing it with the given status, normally `HTTP_FORBIDDEN`.
+    Deny(HTTPStatus),
+    /// The decision is not known yet (`NGX_AGAIN`). The handler must resume request
+    /// processing with `ngx_http_core_run_phases` once it is.
+    Pending,
+}
+
+impl From<Access> for Status {
+    fn from(access: Access) -> Status {
+        match access {
+            Access::Allow => OK,
+            Access::Decline => DECLINED,
+            Access::Deny(status) => status.into(),
+            Access::Pending => AGAIN,
+        }
+    }
+}
+
+impl HandlerResult for Access {
+    fn into_status(self, _log: *mut ngx_log_t, _default: Status) -> Status {
+        self.into()
+    }
+}
+
+/// Define a static access phase handler.
+///
+/// Handlers are expected to take a single [`Request`](crate::http::Request) argument and
+/// return an [`Access`] decision, or a `Result<Access, NgxError>` (see
+/// [`NgxError`](crate::core::NgxError)). Register the handler for [`Phase::Access`].
+///
+/// ```ignore
+/// http_access_handler!(ngx_http_hello_world_access_handler, |request: &mut Request| {
+///     if request.user_agent().as_bytes().starts_with(b"curl") {
+///         return Access::Deny(HTTP_FORBIDDEN);
+///     }
+///     Access::Decline
+/// });
+/// ```
+#[macro_export]
+macro_rules! http_access_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            let internal_error = $crate::__private::bindings::NGX_HTTP_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_int_t;
+            $crate::core::catch_panic(log, stringify!($name), internal_error, || {
+                let access = $handler(unsafe { $crate::http::Request::from_ngx_http_request(r) });
+                $crate::core::HandlerResult::into_status(access, log, $crate::http::HTTP_INTERNAL_SERVER_ERROR.into()).0
+            })
+        }
+    };
+}
+
+/// Decision of a precontent phase handler, see
+/// [`http_precontent_handler!`](crate::http_precontent_handler).
+pub enum PreContent {
+    /// Run the next precontent handler (`NGX_DECLINED`).
+    Continue,
+    /// Skip the remaining precontent handlers and select the content handler (`NGX_OK`).
+    Content,
+    /// Stop phase processing (`NGX_DONE`): the request has been redirected with
+    /// [`Request::internal_redirect`](crate::http::Request::internal_redirect), or the handler
+    /// started an operation that takes a reference to the request and resumes processing
+    /// itself, such as reading the request body. The reference held for the handler is
+    /// released.
+    Done,
+    /// Finalize the request with the given status, such as an HTTP error.
+    Finalize(Status),
+}
+
+impl From<PreContent> for Status {
+    fn from(precontent: PreContent) -> Status {
+        match precontent {
+            PreContent::Continue => DECLINED,
+            PreContent::Content => OK,
+            PreContent::Done => DONE,
+            PreContent::Finalize(status) => status,
+        }
+    }
+}
+
+impl HandlerResult for PreContent {
+    fn into_status(self, _log: *mut ngx_log_t, _default: Status) -> Status {
+        self.into()
+    }
+}
+
+/// Define a static precontent phase handler.
+///
+/// Precontent handlers run just before the content handler is selected, which makes them the
+/// place to start background work such as mirroring (see
+/// [`Request::background_subrequest`](crate::http::Request::background_subrequest)) or to
+/// change the target of the request like `try_files`. Handlers are expected to take a single
+/// [`Request`](crate::http::Request) argument and return a [`PreContent`] decision, or a
+/// `Result<PreContent, NgxError>` whose error is logged and finalizes the request with its
+/// status, `500` by default. Register the handler for [`Phase::PreContent`].
+#[macro_export]
+macro_rules! http_precontent_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            let internal_error = $crate::__private::bindings::NGX_HTTP_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_int_t;
+            $crate::core::catch_panic(log, stringify!($name), internal_error, || {
+                let status = $crate::core::HandlerResult::into_status(
+                    $handler(unsafe { $crate::http::Request::from_ngx_http_request(r) }),
+                    log,
+                    $crate::core::Status(internal_error),
+                );
+                if status == $crate::core::DONE {
+                    // As `try_files` and `mirror` do: phase handlers are not finalized by the
+                    // generic phase checker.
+                    unsafe { $crate::__private::bindings::ngx_http_finalize_request(r, $crate::__private::bindings::NGX_DONE as $crate::__private::bindings::ngx_int_t) };
+                }
+                status.0
+            })
+        }
+    };
+}
@@ -2,18 +2,47 @@ use crate::{bindings::*, ngx_null_string};
 use crate::core::*;
 use crate::http::status::*;
 
+use std::net::SocketAddr;
 use std::os::raw::c_void;
+use std::ptr;
 
 /// Define a static request handler.
 ///
-/// Handlers are expected to take a single [`Request`] argument and return a [`Status`].
+/// Handlers are expected to take a single [`Request`] argument and return a [`Status`], or a
+/// `Result<Status, NgxError>` whose error is logged and finalizes the request (see
+/// [`NgxError`]). A panic of the handler is logged and finalizes the request with an internal
+/// server error, see [`catch_panic`].
 #[macro_export]
 macro_rules! http_request_handler {
     ( $name: ident, $handler: expr ) => {
         #[no_mangle]
-        extern "C" fn $name(r: *mut ngx_http_request_t) -> ngx_int_t {
-            let status: Status = $handler(unsafe { &mut $crate::http::Request::from_ngx_http_request(r) });
-            status.0
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            let internal_error = $crate::__private::bindings::NGX_HTTP_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_int_t;
+            $crate::core::catch_panic(log, stringify!($name), internal_error, || {
+                let status = $handler(unsafe { &mut $crate::http::Request::from_ngx_http_request(r) });
+                $crate::core::HandlerResult::into_status(status, log, $crate::http::HTTP_INTERNAL_SERVER_ERROR.into()).0
+            })
+        }
+    };
+}
+
+/// Define a static log phase handler.
+///
+/// Handlers are expected to take a single [`Request`] argument. They run once the response
+/// has been sent, so the response metadata ([`Request::response_status`],
+/// [`Request::bytes_sent`], [`Request::request_time`], ...) is final. Register the handler
+/// for [`Phase::Log`](crate::http::Phase::Log).
+#[macro_export]
+macro_rules! http_log_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            $crate::core::catch_panic(log, stringify!($name), (), || {
+                let () = $handler(unsafe { $crate::http::Request::from_ngx_http_request(r) });
+            });
+            $crate::__private::bindings::NGX_OK as $crate::__private::bindings::ngx_int_t
         }
     };
 }
@@ -31,6 +60,20 @@ impl Request {
         &mut *r.cast::<Request>()
     }
 
+    /// Pointer to the underlying [`ngx_http_request_t`].
+    ///
+    /// [`ngx_http_request_t`]: https://nginx.org/en/docs/dev/development_guide.html#http_request
+    pub fn as_ngx_http_request(&self) -> *const ngx_http_request_t {
+        &self.0
+    }
+
+    /// Mutable pointer to the underlying [`ngx_http_request_t`].
+    ///
+    /// [`ngx_http_request_t`]: https://nginx.org/en/docs/dev/development_guide.html#http_request
+    pub fn as_ngx_http_request_mut(&mut self) -> *mut ngx_http_request_t {
+        &mut self.0
+    }
+
     /// Is this the main request (as opposed to a subrequest)?
     pub fn is_main(&self) -> bool {
         let main = self.0.main.cast();
@@ -45,36 +88,57 @@ impl Request {
         }
     }
 
-    /// Pointer to a [`ngx_connection_t`] client connection object.
+    /// The client connection.
+    pub fn connection(&self) -> &Connection {
+        // SAFETY: The connection outlives the request.
+        unsafe { Connection::from_ngx_connection(self.0.connection) }
+    }
+
+    /// The client connection, mutably.
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        // SAFETY: The connection outlives the request, and borrowing the request mutably
+        // keeps other references to the connection from being taken through it.
+        unsafe { Connection::from_ngx_connection(self.0.connection) }
+    }
+
+    /// Address of the client (`$remote_addr` and `$remote_port`), or `None` for UNIX-domain
+    /// sockets.
     ///
-    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
-    pub fn connection(&self) -> *mut ngx_connection_t {
-        self.0.connection
+    /// With the `realip` module, this is the address taken from the `real_ip_header` once
+    /// its handler has run: in the access phase and later, or in the post-read and
+    /// preaccess phases for handlers added with [`Phases::add_last`](crate::http::Phases::add_last).
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.connection().remote_addr()
     }
 
-    pub fn remote_address(&self) -> Option<String> {
-        unsafe {
-            let connection = self.0.connection;
-            let sockaddr = (*connection).sockaddr;
-            let socklen = (*connection).socklen;
-            const IP_MAX_LEN: usize = 128;  // should be enough for ipv4 and ipv6
-            let p = ngx_pnalloc(self.0.pool, IP_MAX_LEN);
-            if p.is_null() {
-                None
-            } else {
-                let buf = p as *mut u8;
-                let len = ngx_sock_ntop(sockaddr, socklen, buf, IP_MAX_LEN, 1);
-                let s = std::slice::from_raw_parts(buf, len as usize);
-                let addr = String::from_utf8_lossy(s);
-                if addr.is_empty() {
-                    None
-                } else {
-                    Some(addr.to_string())
-                }
-            }
+    /// Address of the peer of the connection before the `realip` module replaced it
+    /// (`$realip_remote_addr` and `$realip_remote_port`), such as the load balancer the
+    /// request came through.
+    ///
+    /// This is the same as [`Request::remote_addr`] if the address was not replaced, or if
+    /// Nginx is built without the `realip` module.
+    pub fn original_remote_addr(&mut self) -> Option<SocketAddr> {
+        let addr = self.variable("realip_remote_addr").and_then(|addr| parse_addr(addr.to_str().ok()?));
+        let port = self.variable("realip_remote_port").and_then(|port| port.to_str().ok()?.parse::<u16>().ok());
+        match (addr, port) {
+            (Some(addr), Some(port)) => Some(SocketAddr::new(addr, port)),
+            _ => self.remote_addr(),
         }
     }
 
+    /// Has the `realip` module replaced the address of the client?
+    pub fn is_remote_addr_rewritten(&mut self) -> bool {
+        let original = self.original_remote_addr();
+        original.is_some() && original != self.remote_addr()
+    }
+
+    /// The [PROXY protocol](ProxyProtocol) header received on the client connection, if the
+    /// `listen` socket has the `proxy_protocol` parameter.
+    pub fn proxy_protocol(&self) -> Option<ProxyProtocol> {
+        // SAFETY: The connection outlives the request.
+        unsafe { ProxyProtocol::from_connection(self.0.connection) }
+    }
+
     /// Module location configuration.
     pub fn get_module_loc_conf(&self, module: &ngx_module_t) -> *mut c_void {
         unsafe {
@@ -106,6 +170,67 @@ impl Request {
         }
     }
 
+    /// Get the value of a [variable] by name.
+    ///
+    /// Returns `None` if the variable is unknown or not found for this request. Prefer
+    /// [`Request::variable_indexed`] on hot paths.
+    ///
+    /// [variable]: https://nginx.org/en/docs/dev/development_guide.html#http_variables
+    pub fn variable(&mut self, name: &str) -> Option<&NgxStr> {
+        unsafe {
+            // Variable names are hashed and stored in lowercase.
+            let lowcase = ngx_pnalloc(self.0.pool, name.len()) as *mut u_char;
+            if lowcase.is_null() {
+                return None;
+            }
+            let key = ngx_hash_strlow(lowcase, name.as_ptr() as *mut u_char, name.len());
+
+            let mut name = ngx_str_t { len: name.len(), data: lowcase };
+            Self::variable_value(ngx_http_get_variable(&mut self.0, &mut name, key))
+        }
+    }
+
+    /// Get the value of an indexed [variable].
+    ///
+    /// The index is obtained at configuration time with [`Variables::index`]. Non-cacheable
+    /// variables are re-evaluated.
+    ///
+    /// [variable]: https://nginx.org/en/docs/dev/development_guide.html#http_variables
+    /// [`Variables::index`]: crate::http::Variables::index
+    pub fn variable_indexed(&mut self, index: usize) -> Option<&NgxStr> {
+        unsafe {
+            Self::variable_value(ngx_http_get_flushed_variable(&mut self.0, index as ngx_uint_t))
+        }
+    }
+
+    /// Set the value of an indexed [variable] for this request.
+    ///
+    /// The value is copied into the request pool and stored in the per-request variable
+    /// storage, where it is seen by everything evaluating the variable afterwards (for example
+    /// `proxy_set_header`). The variable should be cacheable, as non-cacheable variables are
+    /// re-evaluated on access; variables added with [`Variables::add_settable`] are suitable.
+    ///
+    /// [variable]: https://nginx.org/en/docs/dev/development_guide.html#http_variables
+    /// [`Variables::add_settable`]: crate::http::Variables::add_settable
+    pub fn set_variable(&mut self, index: usize, value: &[u8]) -> Status {
+        unsafe {
+            let cmcf = *self.0.main_conf.add(ngx_http_core_module.ctx_index) as *mut ngx_http_core_main_conf_t;
+            if index >= (*cmcf).variables.nelts || self.0.variables.is_null() {
+                return ERROR;
+            }
+
+            let v = self.0.variables.add(index);
+            Status(crate::http::set_variable_value(&mut self.0, v, Some(value)))
+        }
+    }
+
+    unsafe fn variable_value<'a>(v: *mut ngx_http_variable_value_t) -> Option<&'a NgxStr> {
+        if v.is_null() || (*v).not_found() != 0 {
+            return None;
+        }
+        Some(NgxStr::from_ngx_str(ngx_str_t { len: (*v).len() as usize, data: (*v).data }))
+    }
+
     /// Discard (read and ignore) the [request body].
     ///
     /// [request body]: https://nginx.org/en/docs/dev/development_guide.html#http_request_body
@@ -143,6 +268,7 @@ impl Request {
             let mut part = headers.part;
             let mut h = part.elts as *mut ngx_table_elt_t;
             let mut i = 0;
+            let mut scanned = 0;
             let mut name = header_name.to_string();
             loop {
                 if i >= part.nelts {
@@ -154,14 +280,17 @@ impl Request {
                     i = 0;
                 }
                 let header = *h.add(i);
+                scanned += 1;
                 if ngx_strncasecmp(header.key.data, name.as_mut_ptr(), header.key.len) != 0 {
                     i += 1;
                     continue;
                 }
+                count_header_scan(scanned);
                 let s = std::slice::from_raw_parts(header.value.data, header.value.len as usize);
                 let name = String::from_utf8_lossy(s);
                 return Some(name.to_string());
             }
+            count_header_scan(scanned);
             None
         }
     }
@@ -219,24 +348,70 @@ impl Request {
         }
     }
 
-    pub fn set_header(&self, name: &str, value: &str) -> bool {
-        unsafe {
-            let mut pool = self.pool();
-            if let (Some(mut n), Some(mut v)) = (pool.create_buffer_from_str(name), pool.create_buffer_from_str(value)) {
-                let n_str = ngx_str_t { len: name.len(), data: n.as_bytes_mut().as_mut_ptr() };
-                let v_str = ngx_str_t { len: value.len(), data: v.as_bytes_mut().as_mut_ptr() };
+    /// Add a response header, copying the name and value into the request pool.
+    ///
+    /// This must be called before [`Request::send_header`], such as from a content handler or
+    /// a header filter. Returns `false` if allocation fails.
+    pub fn set_header(&mut self, name: &str, value: &str) -> bool {
+        let list = &mut self.0.headers_out.headers as *mut ngx_list_t;
+        unsafe { Self::push_header(&mut self.pool(), list, name, value) }
+    }
 
-                let mut headers = self.0.headers_out.headers;
-                let mut h = ngx_list_push(&mut headers as *mut ngx_list_t) as *mut ngx_table_elt_t;
+    /// Declare the [trailers] that will be sent with the response.
+    ///
+    /// This must be called before [`Request::send_header`]. It sets `r->expect_trailers`, which
+    /// forces chunked transfer encoding for HTTP/1.1, and announces the trailer names in the
+    /// [`Trailer`] response header.
+    ///
+    /// [trailers]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Trailer
+    /// [`Trailer`]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Trailer
+    pub fn declare_trailers(&mut self, names: &[&str]) -> bool {
+        self.0.set_expect_trailers(1);
+        if names.is_empty() {
+            return true;
+        }
 
-                (*h).hash = 1;
-                (*h).key = n_str;
-                (*h).value = v_str;
-                true
-            } else {
-                false
-            }
+        let list = &mut self.0.headers_out.headers as *mut ngx_list_t;
+        unsafe { Self::push_header(&mut self.pool(), list, "Trailer", &names.join(", ")) }
+    }
+
+    /// Add a response trailer.
+    ///
+    /// Trailers are emitted by Nginx together with the last buffer of the response body, so
+    /// this must be called before the last buffer is sent.
+    pub fn add_trailer(&mut self, name: &str, value: &str) -> bool {
+        let list = &mut self.0.headers_out.trailers as *mut ngx_list_t;
+        unsafe { Self::push_header(&mut self.pool(), list, name, value) }
+    }
+
+    /// Are response trailers expected (`r->expect_trailers`)?
+    pub fn expect_trailers(&self) -> bool {
+        self.0.expect_trailers() != 0
+    }
+
+    pub(crate) unsafe fn push_header(pool: &mut Pool, list: *mut ngx_list_t, name: &str, value: &str) -> bool {
+        !Self::push_header_elt(pool, list, name, value).is_null()
+    }
+
+    /// Like [`Request::push_header`], returning the new header or null on failure.
+    pub(crate) unsafe fn push_header_elt(pool: &mut Pool, list: *mut ngx_list_t, name: &str, value: &str) -> *mut ngx_table_elt_t {
+        let key = NgxString::new_in(name, pool).map(NgxString::into_ngx_str);
+        let value = NgxString::new_in(value, pool).map(NgxString::into_ngx_str);
+        let (key, value) = match (key, value) {
+            (Some(key), Some(value)) => (key, value),
+            _ => return ptr::null_mut(),
+        };
+
+        let h = ngx_list_push(list) as *mut ngx_table_elt_t;
+        if h.is_null() {
+            return ptr::null_mut();
         }
+
+        ptr::write_bytes(h, 0, 1);
+        (*h).hash = 1;
+        (*h).key = key;
+        (*h).value = value;
+        h
     }
 
     /// Set HTTP status of response.
@@ -244,6 +419,67 @@ impl Request {
         self.0.headers_out.status = status.into();
     }
 
+    /// Response status, as logged by `$status`.
+    ///
+    /// This is the status of the error page if one was sent, and `0` if no response has been
+    /// sent yet.
+    pub fn response_status(&self) -> HTTPStatus {
+        if self.0.err_status != 0 {
+            HTTPStatus(self.0.err_status)
+        } else {
+            HTTPStatus(self.0.headers_out.status)
+        }
+    }
+
+    /// Number of bytes sent to the client for this request, including the header
+    /// (`$bytes_sent`).
+    pub fn bytes_sent(&self) -> usize {
+        unsafe { (*self.0.connection).sent.max(0) as usize }
+    }
+
+    /// Number of bytes of the response body sent to the client (`$body_bytes_sent`).
+    pub fn body_bytes_sent(&self) -> usize {
+        let sent = unsafe { (*self.0.connection).sent } - self.0.header_size as off_t;
+        sent.max(0) as usize
+    }
+
+    /// Milliseconds since the request started (`$request_time`).
+    pub fn request_time(&self) -> ngx_msec_t {
+        self.elapsed_msec()
+    }
+
+    /// Response times of all upstream servers tried for this request, in milliseconds
+    /// (`$upstream_response_time`).
+    ///
+    /// Servers for which no time is known, such as ones that could not be connected to, are
+    /// `None`. The times of the upstreams of internal redirects follow those of the first one.
+    pub fn upstream_response_times(&self) -> Vec<Option<ngx_msec_t>> {
+        let states = self.0.upstream_states;
+        if states.is_null() {
+            return Vec::new();
+        }
+
+        unsafe {
+            let elts = (*states).elts as *const ngx_http_upstream_state_t;
+            (0..(*states).nelts)
+                .map(|i| &*elts.add(i))
+                // Internal redirects to another upstream are marked by a state without peer.
+                .filter(|state| !state.peer.is_null())
+                .map(|state| if state.response_time == ngx_msec_t::MAX { None } else { Some(state.response_time) })
+                .collect()
+        }
+    }
+
+    /// Total response time of the upstream servers tried for this request, in milliseconds,
+    /// or `None` if the request was not passed to an upstream.
+    pub fn upstream_response_time(&self) -> Option<ngx_msec_t> {
+        let times = self.upstream_response_times();
+        if times.is_empty() {
+            return None;
+        }
+        Some(times.into_iter().flatten().sum())
+    }
+
     /// Set response body [Content-Length].
     ///
     /// [Content-Length]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Length
@@ -251,15 +487,102 @@ impl Request {
         self.0.headers_out.content_length_n = n as off_t;
     }
 
+    /// Set the response `Content-Type`, such as `text/plain` or `application/json`.
+    pub fn set_content_type(&mut self, content_type: &str) -> bool {
+        match Self::copy_str(&mut self.pool(), content_type) {
+            Some(value) => {
+                self.0.headers_out.content_type = value;
+                self.0.headers_out.content_type_len = value.len;
+                self.0.headers_out.content_type_lowcase = ptr::null_mut();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Send a complete response with a body held in memory, such as a short status page, from
+    /// a content handler.
+    ///
+    /// Sets the status, `Content-Type` and `Content-Length`, then sends the header and the
+    /// body. Returns the status for the content handler to return.
+    pub fn send_response(&mut self, status: HTTPStatus, content_type: &str, body: &[u8]) -> Status {
+        self.set_status(status);
+        if !self.set_content_type(content_type) {
+            return HTTP_INTERNAL_SERVER_ERROR.into();
+        }
+        self.set_content_length_n(body.len());
+        if body.is_empty() {
+            self.0.set_header_only(1);
+        }
+
+        let rc = self.send_header();
+        if rc == ERROR || rc.0 > OK.0 || self.header_only() {
+            return rc;
+        }
+
+        let mut buf = match self.pool().create_buffer_from_slice(body) {
+            Some(buf) => buf,
+            None => return ERROR,
+        };
+        buf.set_last_buf(self.is_main());
+        buf.set_last_in_chain(true);
+
+        let mut out = ngx_chain_t { buf: buf.as_ngx_buf_mut(), next: ptr::null_mut() };
+        self.output_filter(&mut out)
+    }
+
     /// Send the output header.
     ///
     /// Do not call this function until all output headers are set.
     pub fn send_header(&mut self) -> Status {
+        self.record_header_sent();
         unsafe {
             Status(ngx_http_send_header(&mut self.0))
         }
     }
 
+    /// Send a [103 Early Hints] interim response with the given `Link` header values.
+    ///
+    /// This must be called before [`Request::send_header`]. Early hints are only sent to
+    /// HTTP/1.1 clients on the main request; in all other cases [`DECLINED`] is returned and
+    /// nothing is sent. Links with control characters, such as CR or LF which would inject
+    /// headers into the response, are rejected with [`ERROR`].
+    ///
+    /// [103 Early Hints]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/103
+    pub fn send_early_hints(&mut self, links: &[&str]) -> Status {
+        if self.0.header_sent() != 0 {
+            return ERROR;
+        }
+
+        if links.iter().any(|link| link.bytes().any(|b| (b < b' ' && b != b'\t') || b == 0x7f)) {
+            return ERROR;
+        }
+
+        if links.is_empty() || !self.is_main() || self.0.http_version != NGX_HTTP_VERSION_11 as ngx_uint_t {
+            return DECLINED;
+        }
+
+        let mut response = String::from("HTTP/1.1 103 Early Hints\r\n");
+        for link in links {
+            response.push_str("Link: ");
+            response.push_str(link);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+
+        let mut buf = match self.pool().create_buffer_from_str(&response) {
+            Some(buf) => buf,
+            None => return ERROR,
+        };
+        buf.set_flush(true);
+
+        // Bypass the header and body filters, which only deal with the final response.
+        let mut out = ngx_chain_t { buf: buf.as_ngx_buf_mut(), next: ptr::null_mut() };
+        unsafe {
+            Status(ngx_http_write_filter(&mut self.0, &mut out))
+        }
+    }
+
     /// Flag indicating that the output does not require a body.
     ///
     /// For example, this flag is used by `HTTP HEAD` requests.
@@ -274,6 +597,7 @@ impl Request {
     ///
     /// [response body]: https://nginx.org/en/docs/dev/development_guide.html#http_request_body
     pub fn output_filter(&mut self, body: &mut ngx_chain_t) -> Status {
+        self.record_body_sent();
         unsafe {
             Status(ngx_http_output_filter(&mut self.0, body))
         }
@@ -297,6 +621,26 @@ impl Request {
         }
     }
 
+    /// Value of the query string argument `name`, as `$arg_name` (`ngx_http_arg`).
+    ///
+    /// The value is not copied nor unescaped. Only the first argument of that name is
+    /// returned; names are case-insensitive. Names that are empty or contain `=` or `&`
+    /// match no argument.
+    pub fn arg(&mut self, name: &str) -> Option<&NgxStr> {
+        // `ngx_http_arg` assumes a non-empty name.
+        if name.is_empty() || name.contains(|c| c == '=' || c == '&') {
+            return None;
+        }
+        unsafe {
+            let mut value = ngx_null_string!();
+            let rc = ngx_http_arg(&mut self.0, name.as_ptr() as *mut u_char, name.len(), &mut value);
+            if rc != NGX_OK as ngx_int_t {
+                return None;
+            }
+            Some(NgxStr::from_ngx_str(value))
+        }
+    }
+
     pub fn addr(&self) -> Option<String> {
         let value = unsafe { NgxStr::from_ngx_str((*self.0.connection).addr_text).to_string_lossy().to_string() };
         if value.is_empty() {
@@ -309,4 +653,69 @@ impl Request {
     pub fn http_version(&self) -> ngx_uint_t {
         self.0.http_version
     }
+
+    /// Redirect the request internally to another URI (`ngx_http_internal_redirect`).
+    ///
+    /// Request processing restarts from the server rewrite phase with the new URI and
+    /// arguments. Returns [`DONE`] on success; a content handler should return this status
+    /// while a precontent handler returns
+    /// [`PreContent::Done`](crate::http::PreContent::Done).
+    pub fn internal_redirect(&mut self, uri: &str, args: Option<&str>) -> Status {
+        let mut pool = self.pool();
+        let (mut uri, mut args) = match (Self::copy_str(&mut pool, uri), Self::copy_str(&mut pool, args.unwrap_or(""))) {
+            (Some(uri), Some(args)) => (uri, args),
+            _ => return ERROR,
+        };
+
+        unsafe { Status(ngx_http_internal_redirect(&mut self.0, &mut uri, &mut args)) }
+    }
+
+    /// Resume processing of the request phases (`ngx_http_core_run_phases`).
+    ///
+    /// This is how a phase handler that returned `NGX_AGAIN`, such as
+    /// [`Access::Pending`](crate::http::Access::Pending), continues once its decision is known:
+    /// the handler runs again, and can now decide.
+    pub fn run_phases(&mut self) {
+        unsafe { ngx_http_core_run_phases(&mut self.0) }
+    }
+
+    /// Start a background subrequest to `uri`, as the `mirror` directive does.
+    ///
+    /// The subrequest uses the method of this request and its response is discarded. The
+    /// main request does not wait for it, but is not freed before it completes.
+    pub fn background_subrequest(&mut self, uri: &str, args: Option<&str>) -> Status {
+        let mut pool = self.pool();
+        let mut uri = match Self::copy_str(&mut pool, uri) {
+            Some(uri) => uri,
+            None => return ERROR,
+        };
+        let mut args = match args.map(|args| Self::copy_str(&mut pool, args)) {
+            Some(Some(args)) => Some(args),
+            Some(None) => return ERROR,
+            None => None,
+        };
+
+        unsafe {
+            let args = args.as_mut().map_or(ptr::null_mut(), |args| args as *mut ngx_str_t);
+            let mut sr: *mut ngx_http_request_t = ptr::null_mut();
+            let rc = ngx_http_subrequest(&mut self.0, &mut uri, args, &mut sr, ptr::null_mut(),
+                NGX_HTTP_SUBREQUEST_BACKGROUND as ngx_uint_t);
+            if rc != NGX_OK as ngx_int_t {
+                return Status(rc);
+            }
+
+            (*sr).set_header_only(1);
+            (*sr).method = self.0.method;
+            (*sr).method_name = self.0.method_name;
+        }
+
+        OK
+    }
+
+    fn copy_str(pool: &mut Pool, s: &str) -> Option<ngx_str_t> {
+        if s.is_empty() {
+            return Some(ngx_str_t { len: 0, data: ptr::null_mut() });
+        }
+        NgxString::new_in(s, pool).map(NgxString::into_ngx_str)
+    }
 }
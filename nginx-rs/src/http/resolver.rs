@@ -0,0 +1,309 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::oneshot;
+use crate::event::posted::*;
+use crate::http::request::Request;
+
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::os::raw::c_void;
+use std::ptr;
+
+/// Error of a DNS lookup with [`Request::resolve_name`] or [`Request::resolve_addr`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResolveError {
+    /// No `resolver` is configured for the location of the request.
+    NoResolver,
+    /// The name or address does not exist (`NXDOMAIN`), or has no record of the type looked
+    /// up.
+    NotFound,
+    /// The lookup took longer than `resolver_timeout`.
+    Timeout,
+    /// The lookup failed otherwise, such as with a server failure, with the `NGX_RESOLVE_*`
+    /// code of the failure.
+    Failed(ngx_int_t),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolveError::NoResolver => write!(f, "no resolver defined"),
+            ResolveError::NotFound => write!(f, "not found"),
+            ResolveError::Timeout => write!(f, "timed out"),
+            ResolveError::Failed(code) => write!(f, "lookup failed ({})", code),
+        }
+    }
+}
+
+impl Error for ResolveError {}
+
+impl ResolveError {
+    fn from_state(state: ngx_int_t) -> ResolveError {
+        match state as u32 {
+            NGX_RESOLVE_NXDOMAIN => ResolveError::NotFound,
+            NGX_RESOLVE_TIMEDOUT => ResolveError::Timeout,
+            _ => ResolveError::Failed(state),
+        }
+    }
+}
+
+enum Lookup {
+    Name(Box<dyn FnOnce(&mut Request, Result<Vec<IpAddr>, ResolveError>) -> Status>),
+    Addr(Box<dyn FnOnce(&mut Request, Result<String, ResolveError>) -> Status>),
+}
+
+enum Resolved {
+    Name(Result<Vec<IpAddr>, ResolveError>),
+    Addr(Result<String, ResolveError>),
+}
+
+impl Request {
+    /// Resolve `name` to its addresses with the `resolver` of the location, without blocking
+    /// the worker, and call `callback` with the request and the addresses.
+    ///
+    /// The request is held until then, and finalized with the status returned by `callback`,
+    /// as for [`Request::fetch`]. `callback` always runs from a later event, even if the
+    /// answer is cached. If the request is freed first, the lookup is cancelled and `callback`
+    /// is not called.
+    pub fn resolve_name<F>(&mut self, name: &str, callback: F) -> Result<(), ResolveError>
+    where
+        F: FnOnce(&mut Request, Result<Vec<IpAddr>, ResolveError>) -> Status + 'static,
+    {
+        unsafe {
+            let ctx = self.resolve_start()?;
+            (*ctx).name = match NgxString::new_in(name, &mut self.pool()) {
+                Some(name) => name.into_ngx_str(),
+                None => {
+                    ngx_resolve_name_done(ctx);
+                    return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+                }
+            };
+
+            let lookup = self.resolve_prepare(ctx, Lookup::Name(Box::new(callback)))?;
+            if ngx_resolve_name(ctx) != NGX_OK as ngx_int_t {
+                // The context is freed by the resolver.
+                (*lookup).ctx = ptr::null_mut();
+                (*lookup).cancel();
+                return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+            }
+            (*lookup).hold();
+        }
+        Ok(())
+    }
+
+    /// Resolve `addr` to its name (a `PTR` lookup) with the `resolver` of the location,
+    /// without blocking the worker, and call `callback` with the request and the name.
+    ///
+    /// See [`Request::resolve_name`].
+    pub fn resolve_addr<F>(&mut self, addr: IpAddr, callback: F) -> Result<(), ResolveError>
+    where
+        F: FnOnce(&mut Request, Result<String, ResolveError>) -> Status + 'static,
+    {
+        unsafe {
+            let ctx = self.resolve_start()?;
+            let (sockaddr, socklen) = sockaddr_from_std(&SocketAddr::new(addr, 0));
+            let sa: *mut ngx_sockaddr_t = match self.pool().alloc(sockaddr) {
+                Some(sa) => sa.as_ptr(),
+                None => {
+                    ngx_resolve_addr_done(ctx);
+                    return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+                }
+            };
+            (*ctx).addr.sockaddr = &mut (*sa).sockaddr;
+            (*ctx).addr.socklen = socklen;
+
+            let lookup = self.resolve_prepare(ctx, Lookup::Addr(Box::new(callback)))?;
+            if ngx_resolve_addr(ctx) != NGX_OK as ngx_int_t {
+                (*lookup).ctx = ptr::null_mut();
+                (*lookup).cancel();
+                return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+            }
+            (*lookup).hold();
+        }
+        Ok(())
+    }
+
+    /// Resolve `name` to its addresses, for use from a task of the async executor.
+    ///
+    /// See [`Request::resolve_name`].
+    pub fn resolve_name_async(&mut self, name: &str) -> impl Future<Output = Result<Vec<IpAddr>, ResolveError>> {
+        let (tx, rx) = oneshot();
+        let started = self.resolve_name(name, move |_: &mut Request, addrs| {
+            tx.send(addrs);
+            DONE
+        });
+        async move {
+            started?;
+            rx.await.unwrap_or(Err(ResolveError::Failed(NGX_ERROR as ngx_int_t)))
+        }
+    }
+
+    /// Resolve `addr` to its name, for use from a task of the async executor.
+    ///
+    /// See [`Request::resolve_addr`].
+    pub fn resolve_addr_async(&mut self, addr: IpAddr) -> impl Future<Output = Result<String, ResolveError>> {
+        let (tx, rx) = oneshot();
+        let started = self.resolve_addr(addr, move |_: &mut Request, name| {
+            tx.send(name);
+            DONE
+        });
+        async move {
+            started?;
+            rx.await.unwrap_or(Err(ResolveError::Failed(NGX_ERROR as ngx_int_t)))
+        }
+    }
+
+    unsafe fn resolve_start(&mut self) -> Result<*mut ngx_resolver_ctx_t, ResolveError> {
+        let r = self.as_ngx_http_request_mut();
+        let clcf = *(*r).loc_conf.add(ngx_http_core_module.ctx_index) as *mut ngx_http_core_loc_conf_t;
+
+        let ctx = ngx_resolve_start((*clcf).resolver, ptr::null_mut());
+        if ctx.is_null() {
+            return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+        }
+        // `NGX_NO_RESOLVER`
+        if ctx as isize == -1 {
+            return Err(ResolveError::NoResolver);
+        }
+        (*ctx).timeout = (*clcf).resolver_timeout;
+        Ok(ctx)
+    }
+
+    unsafe fn resolve_prepare(&mut self, ctx: *mut ngx_resolver_ctx_t, callback: Lookup) -> Result<*mut PendingLookup, ResolveError> {
+        let r = self.as_ngx_http_request_mut();
+        let cln = ngx_http_cleanup_add(r, 0);
+        if cln.is_null() {
+            match callback {
+                Lookup::Name(_) => ngx_resolve_name_done(ctx),
+                Lookup::Addr(_) => ngx_resolve_addr_done(ctx),
+            }
+            return Err(ResolveError::Failed(NGX_ERROR as ngx_int_t));
+        }
+
+        let lookup = Box::into_raw(Box::new(PendingLookup {
+            request: r,
+            cleanup: cln,
+            ctx,
+            event: mem::zeroed(),
+            write_event_handler: (*r).write_event_handler,
+            callback: Some(callback),
+            resolved: None,
+        }));
+        (*lookup).event.handler = Some(ngx_http_rs_resolved_handler);
+        (*lookup).event.data = lookup as *mut c_void;
+        (*lookup).event.log = (*(*r).connection).log;
+
+        (*ctx).handler = Some(ngx_http_rs_resolve_handler);
+        (*ctx).data = lookup as *mut c_void;
+        (*cln).handler = Some(ngx_http_rs_resolve_cleanup);
+        (*cln).data = lookup as *mut c_void;
+        Ok(lookup)
+    }
+}
+
+struct PendingLookup {
+    request: *mut ngx_http_request_t,
+    cleanup: *mut ngx_http_cleanup_t,
+    ctx: *mut ngx_resolver_ctx_t,
+    // Delivers the result from the posted events.
+    event: ngx_event_t,
+    write_event_handler: ngx_http_event_handler_pt,
+    callback: Option<Lookup>,
+    resolved: Option<Resolved>,
+}
+
+impl PendingLookup {
+    // Hold the request while the lookup is in progress.
+    unsafe fn hold(&mut self) {
+        let r = self.request;
+        // Events of the client connection must not run the phases again while waiting.
+        (*r).write_event_handler = Some(ngx_http_request_empty_handler);
+        (*(*r).main).count += 1;
+    }
+
+    // Cancel the lookup and free it. It must not be used afterwards.
+    unsafe fn cancel(&mut self) {
+        if !self.ctx.is_null() {
+            match self.callback {
+                Some(Lookup::Addr(_)) => ngx_resolve_addr_done(self.ctx),
+                _ => ngx_resolve_name_done(self.ctx),
+            }
+            self.ctx = ptr::null_mut();
+        }
+        if self.event.posted() != 0 {
+            ngx_delete_posted_event(&mut self.event);
+        }
+        (*self.cleanup).handler = None;
+        drop(Box::from_raw(self));
+    }
+
+    unsafe fn finish(&mut self) {
+        let mut lookup = Box::from_raw(self);
+        (*lookup.cleanup).handler = None;
+
+        let r = lookup.request;
+        let c = (*r).connection;
+        (*r).write_event_handler = lookup.write_event_handler;
+
+        let request = Request::from_ngx_http_request(r);
+        let rc = match (lookup.callback.take(), lookup.resolved.take()) {
+            (Some(Lookup::Name(callback)), Some(Resolved::Name(result))) => callback(request, result),
+            (Some(Lookup::Addr(callback)), Some(Resolved::Addr(result))) => callback(request, result),
+            _ => ERROR,
+        };
+        drop(lookup);
+
+        ngx_http_finalize_request(r, rc.0);
+        ngx_http_run_posted_requests(c);
+    }
+}
+
+unsafe extern "C" fn ngx_http_rs_resolve_handler(ctx: *mut ngx_resolver_ctx_t) {
+    let lookup = &mut *((*ctx).data as *mut PendingLookup);
+    let state = (*ctx).state;
+
+    let resolved = match lookup.callback {
+        Some(Lookup::Addr(_)) => {
+            let result = if state != NGX_OK as ngx_int_t {
+                Err(ResolveError::from_state(state))
+            } else {
+                Ok(NgxStr::from_ngx_str((*ctx).name).to_string_lossy().into_owned())
+            };
+            ngx_resolve_addr_done(ctx);
+            Resolved::Addr(result)
+        }
+        _ => {
+            let result = if state != NGX_OK as ngx_int_t {
+                Err(ResolveError::from_state(state))
+            } else {
+                let addrs = (0..(*ctx).naddrs)
+                    .map(|i| &*(*ctx).addrs.add(i))
+                    .filter_map(|addr| sockaddr_to_std(addr.sockaddr, addr.socklen))
+                    .map(|addr| addr.ip())
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() { Err(ResolveError::NotFound) } else { Ok(addrs) }
+            };
+            ngx_resolve_name_done(ctx);
+            Resolved::Name(result)
+        }
+    };
+
+    lookup.ctx = ptr::null_mut();
+    lookup.resolved = Some(resolved);
+    // The handler runs from within `ngx_resolve_name` for cached answers: deliver the result
+    // from the posted events, never from the call that started the lookup.
+    ngx_post_event(&mut lookup.event, ptr::addr_of_mut!(ngx_posted_events));
+}
+
+unsafe extern "C" fn ngx_http_rs_resolved_handler(ev: *mut ngx_event_t) {
+    let lookup = &mut *((*ev).data as *mut PendingLookup);
+    lookup.finish();
+}
+
+unsafe extern "C" fn ngx_http_rs_resolve_cleanup(data: *mut c_void) {
+    let lookup = &mut *(data as *mut PendingLookup);
+    lookup.cancel();
+}
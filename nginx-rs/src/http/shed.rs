@@ -0,0 +1,125 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::classify::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+
+use std::cell::Cell;
+
+/// Minimum interval between two updates of the shed fraction, in milliseconds.
+const UPDATE_INTERVAL: ngx_msec_t = 100;
+
+/// Weight of a new measurement in the smoothed shed fraction.
+const SMOOTHING: f64 = 0.2;
+
+/// Adaptive load shedding for the access phase.
+///
+/// The shedder watches a load signal, such as event loop lag or upstream latency, in
+/// milliseconds. While the signal is below `target` nothing is shed. Between `target` and
+/// `limit` a growing fraction of traffic is rejected with `503 Service Unavailable` and a
+/// `Retry-After` header: first low priority requests, then normal priority requests, until at
+/// `limit` all but critical requests are shed. The fraction is smoothed so short spikes of the
+/// signal don't cause bursts of rejections.
+///
+/// A shedder is local to the worker process. It is typically created while parsing the
+/// configuration, and [`LoadShedder::check`] called from an access phase handler:
+///
+/// ```ignore
+/// http_request_handler!(access_handler, |request: &mut Request| {
+///     let conf = unsafe { &*(request.get_module_main_conf(&my_module) as *const MainConf) };
+///     conf.shedder.check(request)
+/// });
+/// ```
+pub struct LoadShedder {
+    target: ngx_msec_t,
+    limit: ngx_msec_t,
+    retry_after: ngx_uint_t,
+    signal: Box<dyn Fn() -> Option<ngx_msec_t>>,
+    fraction: Cell<f64>,
+    updated: Cell<ngx_msec_t>,
+}
+
+impl LoadShedder {
+    /// Create a shedder driven by `signal`.
+    ///
+    /// `signal` returns `None` if there is no measurement yet, which counts as no load. The
+    /// priority of requests is that of their [`Classification`].
+    pub fn new<S>(target: Msec, limit: Msec, signal: S) -> LoadShedder
+    where
+        S: Fn() -> Option<ngx_msec_t> + 'static,
+    {
+        LoadShedder {
+            target: target.as_msec(),
+            limit: limit.as_msec().max(target.as_msec() + 1),
+            retry_after: 1,
+            signal: Box::new(signal),
+            fraction: Cell::new(0.0),
+            updated: Cell::new(0),
+        }
+    }
+
+    /// Set the `Retry-After` value sent with shed requests, in seconds. The default is 1.
+    pub fn set_retry_after(&mut self, seconds: ngx_uint_t) {
+        self.retry_after = seconds;
+    }
+
+    /// Current smoothed fraction of the load range, from `0.0` (nothing shed) to `1.0` (all
+    /// but critical traffic shed).
+    pub fn fraction(&self) -> f64 {
+        self.fraction.get()
+    }
+
+    /// Probability that a request of the given priority is currently shed.
+    pub fn shed_probability(&self, priority: Priority) -> f64 {
+        let fraction = self.fraction();
+        match priority {
+            Priority::Low => (fraction * 2.0).min(1.0),
+            Priority::Normal => (fraction * 2.0 - 1.0).max(0.0),
+            Priority::Critical => 0.0,
+        }
+    }
+
+    /// Decide whether to shed a request.
+    ///
+    /// Returns [`DECLINED`] to let the request continue through the access phase, or
+    /// `HTTP_SERVICE_UNAVAILABLE` with the `Retry-After` header set.
+    pub fn check(&self, request: &mut Request) -> Status {
+        self.update();
+
+        if self.fraction() == 0.0 {
+            return DECLINED;
+        }
+
+        let probability = self.shed_probability(request.classification().priority);
+        if probability == 0.0 || random_f64() >= probability {
+            return DECLINED;
+        }
+
+        let r = request.as_ngx_http_request_mut();
+        let retry_after = self.retry_after.to_string();
+        unsafe {
+            let list = &mut (*r).headers_out.headers as *mut ngx_list_t;
+            Request::push_header(&mut request.pool(), list, "Retry-After", &retry_after);
+        }
+
+        HTTP_SERVICE_UNAVAILABLE.into()
+    }
+
+    fn update(&self) {
+        let now = unsafe { ngx_current_msec };
+        if now.wrapping_sub(self.updated.get()) < UPDATE_INTERVAL {
+            return;
+        }
+        self.updated.set(now);
+
+        let load = match (self.signal)() {
+            Some(load) if load > self.target => load - self.target,
+            _ => 0,
+        };
+        let pressure = (load as f64 / (self.limit - self.target) as f64).min(1.0);
+
+        let fraction = self.fraction.get() + SMOOTHING * (pressure - self.fraction.get());
+        // Snap to zero, so an idle shedder doesn't keep rejecting a tiny fraction of requests.
+        self.fraction.set(if fraction < 0.001 { 0.0 } else { fraction });
+    }
+}
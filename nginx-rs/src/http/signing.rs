@@ -0,0 +1,285 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::error::Error;
+use std::fmt;
+
+/// Version of the signature format.
+const VERSION: &str = "v1";
+
+/// Signing of headers forwarded between tiers of proxies, such as an edge tier and the
+/// application tier behind it.
+///
+/// Headers set by the edge tier, like the client address or a bot score, can't be told apart
+/// from the same headers sent by a client reaching the inner tier directly. The edge tier
+/// signs them with [`HeaderSigner::sign`], HMAC-SHA-256 with a shared secret and a timestamp,
+/// and sends the signature in another header. The inner tier checks the signature with
+/// [`HeaderSigner::verify_request`] and reads the values with [`Request::trusted_header`].
+///
+/// Keys have an identifier sent with the signature, for rotation: add the new key to the
+/// inner tier with [`HeaderSigner::add_key`], switch the edge tier to it with
+/// [`HeaderSigner::rotate`], then remove the old key from the inner tier.
+///
+/// On the edge tier, the signature is typically a variable set with `proxy_set_header`, next
+/// to the signed headers, so that headers of the same names sent by clients are replaced:
+///
+/// ```ignore
+/// // proxy_set_header X-Client-IP $remote_addr;
+/// // proxy_set_header X-Edge-Signature $edge_signature;
+/// Variables::add(cf, "edge_signature", VariableFlags::NOCACHEABLE, move |request: &mut Request| {
+///     let client_ip = request.remote_addr()?.ip().to_string();
+///     Some(signer.sign(&[("x-client-ip", &client_ip)]))
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeaderSigner {
+    header: String,
+    keys: Vec<(String, Vec<u8>)>,
+    max_age: Sec,
+}
+
+impl HeaderSigner {
+    /// Create a signer with the key used to sign headers.
+    ///
+    /// Signatures are sent in the `X-Edge-Signature` header and are valid for 30 seconds.
+    /// Key identifiers must not contain `;`, `,` or `=`.
+    pub fn new(key_id: &str, secret: &[u8]) -> HeaderSigner {
+        HeaderSigner {
+            header: String::from("X-Edge-Signature"),
+            keys: vec![(key_id.to_string(), secret.to_vec())],
+            max_age: Sec::from_secs(30),
+        }
+    }
+
+    /// Set the name of the header carrying the signature.
+    pub fn set_header_name(&mut self, name: &str) {
+        self.header = name.to_string();
+    }
+
+    /// Name of the header carrying the signature.
+    pub fn header_name(&self) -> &str {
+        &self.header
+    }
+
+    /// Set how long a signature is valid. It should cover the clock difference between the
+    /// tiers, as signatures from the future are accepted within the same bound.
+    pub fn set_max_age(&mut self, max_age: Sec) {
+        self.max_age = max_age;
+    }
+
+    /// Add a key accepted when verifying signatures, without signing with it.
+    pub fn add_key(&mut self, key_id: &str, secret: &[u8]) {
+        self.remove_key(key_id);
+        self.keys.push((key_id.to_string(), secret.to_vec()));
+    }
+
+    /// Sign with a new key, keeping the previous keys for verification.
+    pub fn rotate(&mut self, key_id: &str, secret: &[u8]) {
+        self.remove_key(key_id);
+        self.keys.insert(0, (key_id.to_string(), secret.to_vec()));
+    }
+
+    /// Stop accepting a key. The signing key can't be removed.
+    pub fn remove_key(&mut self, key_id: &str) {
+        if let Some(index) = self.keys.iter().position(|(id, _)| id == key_id) {
+            if index > 0 {
+                self.keys.remove(index);
+            }
+        }
+    }
+
+    /// Signature of the given headers, as names and values, for the signature header.
+    ///
+    /// Header names are case-insensitive. The values must be forwarded unchanged.
+    pub fn sign(&self, headers: &[(&str, &str)]) -> String {
+        let (key_id, secret) = &self.keys[0];
+        let timestamp = now();
+        let names: Vec<String> = headers.iter().map(|(name, _)| name.to_ascii_lowercase()).collect();
+        let values: Vec<&str> = headers.iter().map(|&(_, value)| value).collect();
+
+        let mac = hmac_sha256(secret, &signed_data(key_id, timestamp, &names, &values));
+        format!("{};kid={};ts={};h={};sig={}", VERSION, key_id, timestamp, names.join(","), encode_base64url(&mac))
+    }
+
+    /// Verify the signature of the headers of a request.
+    pub fn verify(&self, request: &Request) -> Result<TrustedHeaders, SignatureError> {
+        let signature = request.get_header(&self.header).ok_or(SignatureError::Missing)?;
+        let signature = Signature::parse(&signature).ok_or(SignatureError::Malformed)?;
+
+        let secret = self
+            .keys
+            .iter()
+            .find(|(id, _)| *id == signature.key_id)
+            .map(|(_, secret)| secret)
+            .ok_or(SignatureError::UnknownKey)?;
+
+        if now().abs_diff(signature.timestamp) > self.max_age.as_secs().max(0) as u64 {
+            return Err(SignatureError::Expired);
+        }
+
+        let mut values = Vec::with_capacity(signature.names.len());
+        for name in signature.names.iter() {
+            values.push(request.get_header(name).ok_or(SignatureError::Invalid)?);
+        }
+        let value_refs: Vec<&str> = values.iter().map(String::as_str).collect();
+
+        let mac = hmac_sha256(secret, &signed_data(signature.key_id, signature.timestamp, &signature.names, &value_refs));
+        if !constant_time_eq(&mac, &signature.mac) {
+            return Err(SignatureError::Invalid);
+        }
+
+        Ok(TrustedHeaders {
+            key_id: signature.key_id.to_string(),
+            timestamp: signature.timestamp,
+            headers: signature.names.into_iter().zip(values).collect(),
+        })
+    }
+
+    /// Verify the signature of the headers of a request, making them available with
+    /// [`Request::trusted_header`].
+    pub fn verify_request(&self, request: &mut Request) -> Result<(), SignatureError> {
+        let trusted = self.verify(request)?;
+        let mut pool = request.pool();
+        let cached = pool.get_local::<TrustedHeaders>();
+        if cached.is_null() {
+            pool.insert_local(trusted);
+        } else {
+            unsafe { *cached = trusted };
+        }
+        Ok(())
+    }
+}
+
+/// Headers of a request whose signature was verified by a [`HeaderSigner`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustedHeaders {
+    /// Identifier of the key the headers were signed with.
+    pub key_id: String,
+    /// When the headers were signed, in seconds since the epoch.
+    pub timestamp: time_t,
+    headers: Vec<(String, String)>,
+}
+
+impl TrustedHeaders {
+    /// Value of a signed header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Signed headers, as lowercase names and values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl Request {
+    /// Headers whose signature was verified by [`HeaderSigner::verify_request`].
+    pub fn trusted_headers(&self) -> Option<&TrustedHeaders> {
+        unsafe { self.pool().get_local::<TrustedHeaders>().as_ref() }
+    }
+
+    /// Value of a header whose signature was verified by [`HeaderSigner::verify_request`],
+    /// or `None` if the header was not signed or the signature not verified.
+    pub fn trusted_header(&self, name: &str) -> Option<String> {
+        self.trusted_headers()?.get(name).map(String::from)
+    }
+}
+
+/// Error verifying a header signature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignatureError {
+    /// The request has no signature header.
+    Missing,
+    /// The signature header is not valid.
+    Malformed,
+    /// The signature uses a key that is not accepted.
+    UnknownKey,
+    /// The signature is too old, or too far in the future.
+    Expired,
+    /// The signature doesn't match the headers.
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "missing signature"),
+            SignatureError::Malformed => write!(f, "malformed signature"),
+            SignatureError::UnknownKey => write!(f, "unknown signature key"),
+            SignatureError::Expired => write!(f, "expired signature"),
+            SignatureError::Invalid => write!(f, "invalid signature"),
+        }
+    }
+}
+
+impl Error for SignatureError {}
+
+struct Signature<'a> {
+    key_id: &'a str,
+    timestamp: time_t,
+    names: Vec<String>,
+    mac: Vec<u8>,
+}
+
+impl<'a> Signature<'a> {
+    fn parse(value: &'a str) -> Option<Signature<'a>> {
+        let mut fields = value.trim().split(';');
+        if fields.next()? != VERSION {
+            return None;
+        }
+
+        let (mut key_id, mut timestamp, mut names, mut mac) = (None, None, None, None);
+        for field in fields {
+            let (name, value) = field.split_at(field.find('=')?);
+            let value = &value[1..];
+            match name {
+                "kid" => key_id = Some(value),
+                "ts" => timestamp = Some(value.parse().ok()?),
+                "h" => names = Some(value.split(',').filter(|name| !name.is_empty()).map(str::to_ascii_lowercase).collect()),
+                "sig" => mac = Some(decode_base64url(value)?),
+                _ => {}
+            }
+        }
+
+        Some(Signature { key_id: key_id?, timestamp: timestamp?, names: names?, mac: mac? })
+    }
+}
+
+fn signed_data(key_id: &str, timestamp: time_t, names: &[String], values: &[&str]) -> Vec<u8> {
+    let mut data = format!("{}\n{}\n{}\n", VERSION, key_id, timestamp);
+    for (name, value) in names.iter().zip(values.iter()) {
+        data.push_str(name);
+        data.push(':');
+        data.push_str(value);
+        data.push('\n');
+    }
+    data.into_bytes()
+}
+
+fn now() -> time_t {
+    unsafe { (*ngx_cached_time).sec }
+}
+
+fn encode_base64url(data: &[u8]) -> String {
+    let mut out = vec![0u8; (data.len() + 2) / 3 * 4];
+    let mut dst = ngx_str_t { len: 0, data: out.as_mut_ptr() };
+    let mut src = ngx_str_t { len: data.len(), data: data.as_ptr() as *mut u_char };
+    unsafe { ngx_encode_base64url(&mut dst, &mut src) };
+    out.truncate(dst.len);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+fn decode_base64url(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![0u8; (text.len() + 3) / 4 * 3];
+    let mut dst = ngx_str_t { len: 0, data: out.as_mut_ptr() };
+    let mut src = ngx_str_t { len: text.len(), data: text.as_ptr() as *mut u_char };
+    if unsafe { ngx_decode_base64url(&mut dst, &mut src) } != NGX_OK as ngx_int_t {
+        return None;
+    }
+    out.truncate(dst.len);
+    Some(out)
+}
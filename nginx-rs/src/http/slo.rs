@@ -0,0 +1,301 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::variable::*;
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Number of buckets each burn-rate window is divided into.
+const BUCKETS: usize = 60;
+
+/// A service level objective, evaluated over a short and a long window.
+///
+/// A request is bad if its response status is 5xx or, when `latency` is set, if the response
+/// header took longer than `latency` to send. The burn rate of a window is the ratio of bad
+/// requests divided by the error budget (`1 - target`): a burn rate of 1 spends exactly the
+/// budget over the SLO period. Alerting on both windows at once, as recommended by the
+/// [SRE workbook], reacts quickly to heavy burn while ignoring short blips.
+///
+/// [SRE workbook]: https://sre.google/workbook/alerting-on-slos/
+#[derive(Clone, Copy, Debug)]
+pub struct SloObjective {
+    /// Fraction of requests that must be good, such as `0.999`.
+    pub target: f64,
+    /// Latency above which a request is bad.
+    pub latency: Option<Msec>,
+    /// Length of the short window.
+    pub short_window: Msec,
+    /// Length of the long window.
+    pub long_window: Msec,
+    /// Burn rate over both windows from which the state is [`SloState::Warn`].
+    pub warn_burn: f64,
+    /// Burn rate over both windows from which the state is [`SloState::Critical`].
+    pub critical_burn: f64,
+}
+
+impl SloObjective {
+    /// A 99.9% availability objective with 5 minute and 1 hour windows, warning at a burn
+    /// rate of 6 and critical at 14.4 (2% and 5% of a 30 day budget within an hour).
+    pub const fn new() -> SloObjective {
+        SloObjective {
+            target: 0.999,
+            latency: None,
+            short_window: Msec::from_secs(5 * 60),
+            long_window: Msec::from_secs(60 * 60),
+            warn_burn: 6.0,
+            critical_burn: 14.4,
+        }
+    }
+
+    /// Does the request count against the objective?
+    pub fn is_bad(&self, request: &Request) -> bool {
+        let status = unsafe { (*request.as_ngx_http_request()).headers_out.status };
+        if status >= NGX_HTTP_INTERNAL_SERVER_ERROR as ngx_uint_t && status < 600 {
+            return true;
+        }
+
+        match self.latency {
+            Some(latency) => request.timings().ttfb().unwrap_or_else(|| request.elapsed_msec()) > latency.as_msec(),
+            None => false,
+        }
+    }
+
+    fn burn_rate(&self, (total, bad): (usize, usize)) -> f64 {
+        if total == 0 {
+            return 0.0;
+        }
+        let budget = (1.0 - self.target).max(f64::EPSILON);
+        (bad as f64 / total as f64) / budget
+    }
+}
+
+impl Default for SloObjective {
+    fn default() -> SloObjective {
+        SloObjective::new()
+    }
+}
+
+/// State of a service level objective.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum SloState {
+    /// The error budget is not burning faster than the warning threshold.
+    Ok,
+    /// Both windows burn faster than the warning threshold.
+    Warn,
+    /// Both windows burn faster than the critical threshold.
+    Critical,
+}
+
+impl SloState {
+    /// Name of the state: `ok`, `warn` or `critical`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SloState::Ok => "ok",
+            SloState::Warn => "warn",
+            SloState::Critical => "critical",
+        }
+    }
+}
+
+impl fmt::Display for SloState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Burn rates of the two windows of an objective.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BurnRates {
+    /// Burn rate over the short window.
+    pub short: f64,
+    /// Burn rate over the long window.
+    pub long: f64,
+}
+
+impl fmt::Display for BurnRates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3} {:.3}", self.short, self.long)
+    }
+}
+
+#[repr(C)]
+struct Bucket {
+    epoch: Atomic,
+    total: Atomic,
+    bad: Atomic,
+}
+
+#[repr(C)]
+struct Window {
+    buckets: [Bucket; BUCKETS],
+}
+
+impl Window {
+    fn record(&self, epoch: usize, bad: bool) {
+        let bucket = &self.buckets[epoch % BUCKETS];
+        let epoch = epoch as ngx_atomic_uint_t;
+        let current = bucket.epoch.load();
+        if current != epoch && bucket.epoch.cmp_set(current, epoch) {
+            // Requests counted concurrently with the reset may be lost, which is fine for
+            // an approximate rate.
+            bucket.total.store(0);
+            bucket.bad.store(0);
+        }
+
+        bucket.total.fetch_add(1);
+        if bad {
+            bucket.bad.fetch_add(1);
+        }
+    }
+
+    fn counts(&self, epoch: usize) -> (usize, usize) {
+        let mut counts = (0, 0);
+        for bucket in self.buckets.iter() {
+            if epoch.wrapping_sub(bucket.epoch.load() as usize) < BUCKETS {
+                counts.0 += bucket.total.load() as usize;
+                counts.1 += bucket.bad.load() as usize;
+            }
+        }
+        counts
+    }
+}
+
+/// Request counters of an objective, shared by all worker processes.
+///
+/// The tracker only contains atomics, so it can be placed in shared memory: zeroed memory is
+/// a valid empty tracker. Each window is divided into 60 buckets, so rates are approximate
+/// and a window forgets requests one bucket at a time.
+#[repr(C)]
+pub struct SloTracker {
+    short: Window,
+    long: Window,
+}
+
+impl SloTracker {
+    /// Initialize an empty tracker in place, such as in a shared memory zone.
+    pub unsafe fn init(tracker: *mut SloTracker) {
+        ptr::write_bytes(tracker, 0, 1);
+    }
+
+    /// Count a request, using the current time.
+    pub fn record(&self, objective: &SloObjective, bad: bool) {
+        let now = current_msec();
+        self.short.record(epoch(now, objective.short_window), bad);
+        self.long.record(epoch(now, objective.long_window), bad);
+    }
+
+    /// Current burn rates of both windows.
+    pub fn burn_rates(&self, objective: &SloObjective) -> BurnRates {
+        let now = current_msec();
+        BurnRates {
+            short: objective.burn_rate(self.short.counts(epoch(now, objective.short_window))),
+            long: objective.burn_rate(self.long.counts(epoch(now, objective.long_window))),
+        }
+    }
+
+    /// Current state of the objective.
+    pub fn state(&self, objective: &SloObjective) -> SloState {
+        let rates = self.burn_rates(objective);
+        let burn = rates.short.min(rates.long);
+        if burn >= objective.critical_burn {
+            SloState::Critical
+        } else if burn >= objective.warn_burn {
+            SloState::Warn
+        } else {
+            SloState::Ok
+        }
+    }
+}
+
+impl Default for SloTracker {
+    fn default() -> SloTracker {
+        // SAFETY: All fields are atomics, for which zero is a valid value.
+        unsafe { mem::zeroed() }
+    }
+}
+
+fn current_msec() -> ngx_msec_t {
+    // The monotonic Nginx time, comparable between worker processes.
+    unsafe { ngx_current_msec }
+}
+
+fn epoch(now: ngx_msec_t, window: Msec) -> usize {
+    let width = (window.as_msec() / BUCKETS as ngx_msec_t).max(1);
+    (now / width) as usize
+}
+
+/// A service level objective with the tracker it is evaluated against.
+///
+/// An `Slo` is typically a `static` of the module. Until a tracker is attached (once the
+/// shared memory holding it is available), requests are not counted and the state is
+/// [`SloState::Ok`]. The state can drive load-shedding decisions in request handlers, and is
+/// available to the configuration as a variable.
+pub struct Slo {
+    objective: SloObjective,
+    tracker: AtomicPtr<SloTracker>,
+}
+
+impl Slo {
+    /// Create an objective with no tracker attached.
+    pub const fn new(objective: SloObjective) -> Slo {
+        Slo { objective, tracker: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    /// The objective.
+    pub fn objective(&self) -> &SloObjective {
+        &self.objective
+    }
+
+    /// Attach the tracker counting requests, such as one in a shared memory zone.
+    ///
+    /// The tracker must remain valid for as long as the `Slo` is used.
+    pub unsafe fn attach(&self, tracker: *mut SloTracker) {
+        self.tracker.store(tracker, Ordering::Release);
+    }
+
+    /// The attached tracker, if any.
+    pub fn tracker(&self) -> Option<&SloTracker> {
+        unsafe { self.tracker.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Count a request, normally from the log phase once the response has been sent.
+    pub fn record(&self, request: &Request) {
+        if let Some(tracker) = self.tracker() {
+            tracker.record(&self.objective, self.objective.is_bad(request));
+        }
+    }
+
+    /// Current burn rates, or `None` if no tracker is attached.
+    pub fn burn_rates(&self) -> Option<BurnRates> {
+        self.tracker().map(|tracker| tracker.burn_rates(&self.objective))
+    }
+
+    /// Current state of the objective.
+    pub fn state(&self) -> SloState {
+        match self.tracker() {
+            Some(tracker) => tracker.state(&self.objective),
+            None => SloState::Ok,
+        }
+    }
+
+    /// Register a variable with the current state (`ok`, `warn` or `critical`), and a
+    /// `<name>_burn` variable with the short and long burn rates.
+    ///
+    /// Call this from the `preconfiguration` handler of a module.
+    pub unsafe fn add_variables(&'static self, cf: *mut ngx_conf_t, name: &str) -> Status {
+        let status = Variables::add(cf, name, VariableFlags::NOCACHEABLE, move |_: &mut Request| {
+            Some(self.state().as_str())
+        });
+        if status != OK {
+            return status;
+        }
+
+        let burn = format!("{}_burn", name);
+        Variables::add(cf, &burn, VariableFlags::NOCACHEABLE, move |_: &mut Request| {
+            self.burn_rates().map(|rates| rates.to_string())
+        })
+    }
+}
@@ -0,0 +1,298 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+use crate::http::writer::*;
+
+use std::cell::RefCell;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+
+/// An event of a [`SseStream`].
+///
+/// ```ignore
+/// handle.send(&SseEvent::new(&json).event("price").id(&seq.to_string()));
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SseEvent<'a> {
+    event: Option<&'a str>,
+    data: &'a str,
+    id: Option<&'a str>,
+    retry: Option<Msec>,
+}
+
+impl<'a> SseEvent<'a> {
+    /// An event with data, which may span several lines.
+    pub fn new(data: &'a str) -> SseEvent<'a> {
+        SseEvent { data, ..Default::default() }
+    }
+
+    /// Set the event type, dispatched to listeners of that type by the client
+    /// (`message` otherwise).
+    pub fn event(mut self, event: &'a str) -> SseEvent<'a> {
+        self.event = Some(event);
+        self
+    }
+
+    /// Set the event id, sent back by the client in `Last-Event-ID` when it reconnects.
+    pub fn id(mut self, id: &'a str) -> SseEvent<'a> {
+        self.id = Some(id);
+        self
+    }
+
+    /// Set the time the client waits before reconnecting.
+    pub fn retry(mut self, retry: Msec) -> SseEvent<'a> {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Append the event in the `text/event-stream` format to `out`.
+    ///
+    /// Line breaks in the fields other than the data would end the fields early, so they
+    /// are replaced by spaces.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut field = |name: &str, value: &str| {
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend(value.bytes().map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }));
+            out.push(b'\n');
+        };
+        if let Some(event) = self.event {
+            field("event", event);
+        }
+        if let Some(id) = self.id {
+            field("id", id);
+        }
+        if let Some(retry) = self.retry {
+            field("retry", &retry.as_msec().to_string());
+        }
+        // Clients break lines at CRLF, LF or a lone CR alike.
+        for line in self.data.split('\n') {
+            for line in line.strip_suffix('\r').unwrap_or(line).split('\r') {
+                field("data", line);
+            }
+        }
+        out.push(b'\n');
+    }
+}
+
+struct SseState {
+    request: *mut ngx_http_request_t,
+    queue: Vec<u8>,
+    // Was anything sent since the last keep-alive?
+    active: bool,
+    done: bool,
+    on_close: Option<Box<dyn FnOnce()>>,
+}
+
+// Kept in the request pool: the stream ends when it is destroyed.
+struct SseLocal {
+    keepalive: Option<Msec>,
+    timer: ngx_event_t,
+    state: Rc<RefCell<SseState>>,
+}
+
+impl Drop for SseLocal {
+    fn drop(&mut self) {
+        unsafe {
+            if self.timer.timer_set() != 0 {
+                ngx_del_timer(&mut self.timer);
+            }
+        }
+        let on_close = {
+            let mut state = self.state.borrow_mut();
+            state.request = ptr::null_mut();
+            state.on_close.take()
+        };
+        if let Some(on_close) = on_close {
+            on_close();
+        }
+    }
+}
+
+/// A [Server-Sent Events] response, streaming events to the client with a
+/// [`ResponseWriter`].
+///
+/// Events are sent through the [`SseHandle`] returned by [`SseStream::start`], from timers
+/// or other connections. The stream ends when the client disconnects, or with
+/// [`SseHandle::finish`].
+///
+/// ```ignore
+/// http_request_handler!(ngx_http_events_handler, |request: &mut Request| {
+///     match SseStream::start(request, Some(Msec::from_secs(15))) {
+///         Ok(handle) => {
+///             SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().push(handle));
+///             DONE
+///         }
+///         Err(status) => status,
+///     }
+/// });
+/// ```
+///
+/// [Server-Sent Events]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+pub struct SseStream;
+
+impl SseStream {
+    /// Send the header of an event stream and start streaming, sending a comment every
+    /// `keepalive` while no events are sent, so proxies and clients don't time the
+    /// connection out.
+    ///
+    /// The content handler must then return [`DONE`].
+    pub fn start(request: &mut Request, keepalive: Option<Msec>) -> Result<SseHandle, Status> {
+        request.set_status(HTTP_OK);
+        if !request.set_content_type("text/event-stream")
+            || !request.set_header("Cache-Control", "no-cache")
+            || !request.set_header("X-Accel-Buffering", "no")
+        {
+            return Err(ERROR);
+        }
+
+        let r = request.as_ngx_http_request_mut();
+        unsafe { (*r).headers_out.content_length_n = -1 };
+        let rc = request.send_header();
+        if rc == ERROR || rc.0 > NGX_OK as ngx_int_t || request.header_only() {
+            return Err(rc);
+        }
+
+        let state = Rc::new(RefCell::new(SseState { request: r, queue: Vec::new(), active: false, done: false, on_close: None }));
+        let local = request.pool().insert_local(SseLocal {
+            keepalive,
+            timer: unsafe { mem::zeroed() },
+            state: state.clone(),
+        });
+        if local.is_null() {
+            return Err(ERROR);
+        }
+
+        unsafe {
+            // Finalize the request when the client disconnects.
+            (*r).read_event_handler = Some(ngx_http_test_reading);
+
+            if let Some(keepalive) = keepalive {
+                let timer = &mut (*local).timer;
+                timer.handler = Some(ngx_http_rs_sse_keepalive_handler);
+                timer.data = local as *mut c_void;
+                timer.log = (*(*r).connection).log;
+                timer.set_cancelable(1);
+                ngx_add_timer(timer, keepalive.as_msec());
+            }
+        }
+
+        let producer_state = state.clone();
+        let rc = ResponseWriter::start(request, move |_: &mut Request, writer: &mut ResponseWriter| {
+            let (data, done) = {
+                let mut state = producer_state.borrow_mut();
+                (mem::take(&mut state.queue), state.done)
+            };
+            if !data.is_empty() {
+                if writer.write(&data) == ERROR {
+                    return Next::Error(ERROR);
+                }
+                return Next::Continue;
+            }
+            if done {
+                Next::Done
+            } else {
+                Next::Wait
+            }
+        });
+        if rc == ERROR {
+            return Err(rc);
+        }
+        Ok(SseHandle(state))
+    }
+}
+
+/// Handle to send the events of a [`SseStream`].
+///
+/// The handle does not keep the stream open: once the client is gone, sending does nothing.
+#[derive(Clone)]
+pub struct SseHandle(Rc<RefCell<SseState>>);
+
+impl SseHandle {
+    /// Send an event.
+    ///
+    /// Returns `false` if the stream is closed. Events are buffered while the client is slow
+    /// to read them: check [`SseHandle::pending`] to drop events for slow clients.
+    pub fn send(&self, event: &SseEvent) -> bool {
+        let mut data = Vec::new();
+        event.encode(&mut data);
+        self.send_raw(&data)
+    }
+
+    /// Send a comment, ignored by clients.
+    pub fn comment(&self, comment: &str) -> bool {
+        let mut data = Vec::with_capacity(comment.len() + 3);
+        data.extend_from_slice(b": ");
+        data.extend(comment.bytes().map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }));
+        data.extend_from_slice(b"\n\n");
+        self.send_raw(&data)
+    }
+
+    /// End the stream once the events sent so far are sent.
+    pub fn finish(&self) {
+        if !self.is_open() {
+            return;
+        }
+        self.0.borrow_mut().done = true;
+        self.resume();
+    }
+
+    /// Call `f` once the stream is closed, by either side.
+    pub fn on_close<F: FnOnce() + 'static>(&self, f: F) {
+        self.0.borrow_mut().on_close = Some(Box::new(f));
+    }
+
+    /// Is the stream still open?
+    pub fn is_open(&self) -> bool {
+        let state = self.0.borrow();
+        !state.request.is_null() && !state.done
+    }
+
+    /// Number of bytes of events waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.0.borrow().queue.len()
+    }
+
+    fn send_raw(&self, data: &[u8]) -> bool {
+        if !self.is_open() {
+            return false;
+        }
+        {
+            let mut state = self.0.borrow_mut();
+            state.queue.extend_from_slice(data);
+            state.active = true;
+        }
+        self.resume();
+        true
+    }
+
+    fn resume(&self) {
+        let r = self.0.borrow().request;
+        if !r.is_null() {
+            ResponseWriter::resume(unsafe { Request::from_ngx_http_request(r) });
+        }
+    }
+}
+
+unsafe extern "C" fn ngx_http_rs_sse_keepalive_handler(ev: *mut ngx_event_t) {
+    let local = (*ev).data as *mut SseLocal;
+    let handle = SseHandle((*local).state.clone());
+    let keepalive = (*local).keepalive;
+
+    // Only idle streams need a keep-alive.
+    let active = mem::replace(&mut handle.0.borrow_mut().active, false);
+    if !active {
+        handle.comment("");
+        handle.0.borrow_mut().active = false;
+    }
+    // The request, and with it `local`, may be gone.
+    if handle.is_open() {
+        if let Some(keepalive) = keepalive {
+            ngx_add_timer(ev, keepalive.as_msec());
+        }
+    }
+}
@@ -1,11 +1,154 @@
 use crate::bindings::*;
-use crate::core::Status;
+use crate::core::{NgxError, Status};
 
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// An HTTP response status code.
+///
+/// Constants cover the statuses Nginx knows, including its own codes (`444`, `494` to `499`),
+/// which are never sent to clients.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct HTTPStatus(pub ngx_uint_t);
 
-impl Into<Status> for HTTPStatus {
-    fn into(self) -> Status {
-        Status(self.0 as ngx_int_t)
+impl HTTPStatus {
+    /// The status code.
+    pub fn as_u16(&self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Canonical reason phrase of the status, such as `Not Found` for `404`, or `None` for
+    /// unknown statuses and Nginx's own codes.
+    pub fn reason_phrase(&self) -> Option<&'static str> {
+        Some(match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            102 => "Processing",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            207 => "Multi-Status",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            507 => "Insufficient Storage",
+            508 => "Loop Detected",
+            511 => "Network Authentication Required",
+            _ => return None,
+        })
+    }
+
+    /// Is this an informational status (`1xx`)?
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.0)
+    }
+
+    /// Is this a success status (`2xx`)?
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    /// Is this a redirection status (`3xx`)?
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    /// Is this a client error status (`4xx`)?
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    /// Is this a server error status (`5xx`)?
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+impl fmt::Display for HTTPStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason_phrase() {
+            Some(reason) => write!(f, "{} {}", self.0, reason),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Error of converting a number that is not a status code (`100` to `599`) to an
+/// [`HTTPStatus`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidHTTPStatus(pub u16);
+
+impl fmt::Display for InvalidHTTPStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid HTTP status {}", self.0)
+    }
+}
+
+impl Error for InvalidHTTPStatus {}
+
+impl TryFrom<u16> for HTTPStatus {
+    type Error = InvalidHTTPStatus;
+
+    fn try_from(code: u16) -> Result<HTTPStatus, InvalidHTTPStatus> {
+        if (100..600).contains(&code) {
+            Ok(HTTPStatus(code as ngx_uint_t))
+        } else {
+            Err(InvalidHTTPStatus(code))
+        }
+    }
+}
+
+impl From<HTTPStatus> for Status {
+    fn from(status: HTTPStatus) -> Status {
+        Status(status.0 as ngx_int_t)
+    }
+}
+
+impl From<HTTPStatus> for NgxError {
+    fn from(status: HTTPStatus) -> NgxError {
+        NgxError::from(Status::from(status))
     }
 }
 
@@ -15,7 +158,55 @@ impl Into<ngx_uint_t> for HTTPStatus {
     }
 }
 
+pub const HTTP_CONTINUE: HTTPStatus = HTTPStatus(NGX_HTTP_CONTINUE as ngx_uint_t);
+pub const HTTP_SWITCHING_PROTOCOLS: HTTPStatus = HTTPStatus(NGX_HTTP_SWITCHING_PROTOCOLS as ngx_uint_t);
+pub const HTTP_PROCESSING: HTTPStatus = HTTPStatus(NGX_HTTP_PROCESSING as ngx_uint_t);
+pub const HTTP_EARLY_HINTS: HTTPStatus = HTTPStatus(103);
+
 pub const HTTP_OK: HTTPStatus = HTTPStatus(NGX_HTTP_OK as ngx_uint_t);
+pub const HTTP_CREATED: HTTPStatus = HTTPStatus(NGX_HTTP_CREATED as ngx_uint_t);
+pub const HTTP_ACCEPTED: HTTPStatus = HTTPStatus(NGX_HTTP_ACCEPTED as ngx_uint_t);
 pub const HTTP_NO_CONTENT: HTTPStatus = HTTPStatus(NGX_HTTP_NO_CONTENT as ngx_uint_t);
-pub const HTTP_INTERNAL_SERVER_ERROR: HTTPStatus = HTTPStatus(NGX_HTTP_INTERNAL_SERVER_ERROR as ngx_uint_t);
+pub const HTTP_PARTIAL_CONTENT: HTTPStatus = HTTPStatus(NGX_HTTP_PARTIAL_CONTENT as ngx_uint_t);
+
+pub const HTTP_SPECIAL_RESPONSE: HTTPStatus = HTTPStatus(NGX_HTTP_SPECIAL_RESPONSE as ngx_uint_t);
+pub const HTTP_MOVED_PERMANENTLY: HTTPStatus = HTTPStatus(NGX_HTTP_MOVED_PERMANENTLY as ngx_uint_t);
+pub const HTTP_MOVED_TEMPORARILY: HTTPStatus = HTTPStatus(NGX_HTTP_MOVED_TEMPORARILY as ngx_uint_t);
+pub const HTTP_SEE_OTHER: HTTPStatus = HTTPStatus(NGX_HTTP_SEE_OTHER as ngx_uint_t);
+pub const HTTP_NOT_MODIFIED: HTTPStatus = HTTPStatus(NGX_HTTP_NOT_MODIFIED as ngx_uint_t);
+pub const HTTP_TEMPORARY_REDIRECT: HTTPStatus = HTTPStatus(NGX_HTTP_TEMPORARY_REDIRECT as ngx_uint_t);
+pub const HTTP_PERMANENT_REDIRECT: HTTPStatus = HTTPStatus(NGX_HTTP_PERMANENT_REDIRECT as ngx_uint_t);
+
+pub const HTTP_BAD_REQUEST: HTTPStatus = HTTPStatus(NGX_HTTP_BAD_REQUEST as ngx_uint_t);
+pub const HTTP_UNAUTHORIZED: HTTPStatus = HTTPStatus(NGX_HTTP_UNAUTHORIZED as ngx_uint_t);
 pub const HTTP_FORBIDDEN: HTTPStatus = HTTPStatus(NGX_HTTP_FORBIDDEN as ngx_uint_t);
+pub const HTTP_NOT_FOUND: HTTPStatus = HTTPStatus(NGX_HTTP_NOT_FOUND as ngx_uint_t);
+pub const HTTP_NOT_ALLOWED: HTTPStatus = HTTPStatus(NGX_HTTP_NOT_ALLOWED as ngx_uint_t);
+pub const HTTP_REQUEST_TIME_OUT: HTTPStatus = HTTPStatus(NGX_HTTP_REQUEST_TIME_OUT as ngx_uint_t);
+pub const HTTP_CONFLICT: HTTPStatus = HTTPStatus(NGX_HTTP_CONFLICT as ngx_uint_t);
+pub const HTTP_LENGTH_REQUIRED: HTTPStatus = HTTPStatus(NGX_HTTP_LENGTH_REQUIRED as ngx_uint_t);
+pub const HTTP_PRECONDITION_FAILED: HTTPStatus = HTTPStatus(NGX_HTTP_PRECONDITION_FAILED as ngx_uint_t);
+pub const HTTP_REQUEST_ENTITY_TOO_LARGE: HTTPStatus = HTTPStatus(NGX_HTTP_REQUEST_ENTITY_TOO_LARGE as ngx_uint_t);
+pub const HTTP_REQUEST_URI_TOO_LARGE: HTTPStatus = HTTPStatus(NGX_HTTP_REQUEST_URI_TOO_LARGE as ngx_uint_t);
+pub const HTTP_UNSUPPORTED_MEDIA_TYPE: HTTPStatus = HTTPStatus(NGX_HTTP_UNSUPPORTED_MEDIA_TYPE as ngx_uint_t);
+pub const HTTP_RANGE_NOT_SATISFIABLE: HTTPStatus = HTTPStatus(NGX_HTTP_RANGE_NOT_SATISFIABLE as ngx_uint_t);
+pub const HTTP_MISDIRECTED_REQUEST: HTTPStatus = HTTPStatus(NGX_HTTP_MISDIRECTED_REQUEST as ngx_uint_t);
+pub const HTTP_TOO_MANY_REQUESTS: HTTPStatus = HTTPStatus(NGX_HTTP_TOO_MANY_REQUESTS as ngx_uint_t);
+pub const HTTP_UNAVAILABLE_FOR_LEGAL_REASONS: HTTPStatus = HTTPStatus(451);
+
+/// Close the connection without sending a response.
+pub const HTTP_CLOSE: HTTPStatus = HTTPStatus(NGX_HTTP_CLOSE as ngx_uint_t);
+pub const HTTP_REQUEST_HEADER_TOO_LARGE: HTTPStatus = HTTPStatus(NGX_HTTP_REQUEST_HEADER_TOO_LARGE as ngx_uint_t);
+pub const HTTP_SSL_CERT_ERROR: HTTPStatus = HTTPStatus(NGX_HTTPS_CERT_ERROR as ngx_uint_t);
+pub const HTTP_NO_CERT: HTTPStatus = HTTPStatus(NGX_HTTPS_NO_CERT as ngx_uint_t);
+pub const HTTP_TO_HTTPS: HTTPStatus = HTTPStatus(NGX_HTTP_TO_HTTPS as ngx_uint_t);
+/// The client closed the connection before the response was sent, as logged.
+pub const HTTP_CLIENT_CLOSED_REQUEST: HTTPStatus = HTTPStatus(NGX_HTTP_CLIENT_CLOSED_REQUEST as ngx_uint_t);
+
+pub const HTTP_INTERNAL_SERVER_ERROR: HTTPStatus = HTTPStatus(NGX_HTTP_INTERNAL_SERVER_ERROR as ngx_uint_t);
+pub const HTTP_NOT_IMPLEMENTED: HTTPStatus = HTTPStatus(NGX_HTTP_NOT_IMPLEMENTED as ngx_uint_t);
+pub const HTTP_BAD_GATEWAY: HTTPStatus = HTTPStatus(NGX_HTTP_BAD_GATEWAY as ngx_uint_t);
+pub const HTTP_SERVICE_UNAVAILABLE: HTTPStatus = HTTPStatus(NGX_HTTP_SERVICE_UNAVAILABLE as ngx_uint_t);
+pub const HTTP_GATEWAY_TIME_OUT: HTTPStatus = HTTPStatus(NGX_HTTP_GATEWAY_TIME_OUT as ngx_uint_t);
+pub const HTTP_VERSION_NOT_SUPPORTED: HTTPStatus = HTTPStatus(NGX_HTTP_VERSION_NOT_SUPPORTED as ngx_uint_t);
+pub const HTTP_INSUFFICIENT_STORAGE: HTTPStatus = HTTPStatus(NGX_HTTP_INSUFFICIENT_STORAGE as ngx_uint_t);
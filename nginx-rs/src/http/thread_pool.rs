@@ -0,0 +1,53 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+impl Request {
+    /// Run `task` on a thread of `pool`, then `completion` on the event loop with the request
+    /// and the result of the task, or `None` if it panicked.
+    ///
+    /// The request is kept alive until `completion` returns, even if the client goes away
+    /// meanwhile, and is then finalized with the status `completion` returns. The handler
+    /// must return the status returned by this function: [`DONE`], or [`ERROR`] if the task
+    /// couldn't be queued.
+    ///
+    /// ```ignore
+    /// http_request_handler!(score_handler, |request: &mut Request| {
+    ///     let features = extract_features(request);
+    ///     request.spawn_blocking(&conf.pool, move || MODEL.score(&features), |request, score| {
+    ///         match score {
+    ///             Some(score) => request.send_response(HTTP_OK, "text/plain", score.to_string().as_bytes()),
+    ///             None => HTTP_INTERNAL_SERVER_ERROR.into(),
+    ///         }
+    ///     })
+    /// });
+    /// ```
+    pub fn spawn_blocking<R, T, C>(&mut self, pool: &ThreadPool, task: T, completion: C) -> Status
+    where
+        R: Send + 'static,
+        T: FnOnce() -> R + Send + 'static,
+        C: FnOnce(&mut Request, Option<R>) -> Status + 'static,
+    {
+        let r = self.as_ngx_http_request_mut();
+        let rc = pool.spawn(task, move |result: Option<R>| unsafe {
+            let c = (*r).connection;
+            let main = (*r).main;
+            (*main).set_blocked((*main).blocked() - 1);
+            let rc = completion(Request::from_ngx_http_request(r), result);
+            ngx_http_finalize_request(r, rc.0);
+            ngx_http_run_posted_requests(c);
+        });
+        if rc == ERROR {
+            return ERROR;
+        }
+
+        unsafe {
+            // Keep the request until the task completes, and hold off its termination, as
+            // for thread I/O.
+            let main = (*r).main;
+            (*main).set_count((*main).count() + 1);
+            (*main).set_blocked((*main).blocked() + 1);
+        }
+        DONE
+    }
+}
@@ -0,0 +1,97 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::variable::*;
+
+/// Response timing of a request, in milliseconds since the request started.
+///
+/// Timings are recorded automatically when the response header and body are sent through
+/// this crate ([`Request::send_header`], [`Request::output_filter`] and
+/// [`ResponseWriter`](crate::http::ResponseWriter)). They use the cached Nginx time, which is
+/// updated once per event loop iteration unless `timer_resolution` is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResponseTimings {
+    /// When the response header was sent.
+    pub header: Option<ngx_msec_t>,
+    /// When the first part of the response body was sent.
+    pub first_body: Option<ngx_msec_t>,
+}
+
+impl ResponseTimings {
+    /// Time to first byte: when the first part of the response (normally the header) was sent.
+    pub fn ttfb(&self) -> Option<ngx_msec_t> {
+        match (self.header, self.first_body) {
+            (Some(header), Some(body)) => Some(header.min(body)),
+            (header, body) => header.or(body),
+        }
+    }
+
+    /// Register the `$rs_header_time`, `$rs_body_time` and `$rs_ttfb` variables.
+    ///
+    /// Values are in seconds with millisecond resolution, like `$request_time`. Call this from
+    /// the `preconfiguration` handler of a module.
+    pub unsafe fn add_variables(cf: *mut ngx_conf_t) -> Status {
+        let timings: [(&str, fn(&ResponseTimings) -> Option<ngx_msec_t>); 3] = [
+            ("rs_header_time", |t| t.header),
+            ("rs_body_time", |t| t.first_body),
+            ("rs_ttfb", ResponseTimings::ttfb),
+        ];
+
+        for &(name, timing) in timings.iter() {
+            let status = Variables::add(cf, name, VariableFlags::NOCACHEABLE, move |request: &mut Request| {
+                timing(&request.timings()).map(|ms| format!("{}.{:03}", ms / 1000, ms % 1000))
+            });
+            if status != OK {
+                return status;
+            }
+        }
+
+        OK
+    }
+}
+
+impl Request {
+    /// Response timings recorded for this request so far.
+    pub fn timings(&self) -> ResponseTimings {
+        let timings = self.pool().get_local::<ResponseTimings>();
+        if timings.is_null() {
+            ResponseTimings::default()
+        } else {
+            unsafe { *timings }
+        }
+    }
+
+    pub(crate) fn record_header_sent(&mut self) {
+        let now = self.elapsed_msec();
+        if let Some(timings) = self.timings_mut() {
+            timings.header.get_or_insert(now);
+        }
+    }
+
+    pub(crate) fn record_body_sent(&mut self) {
+        let now = self.elapsed_msec();
+        if let Some(timings) = self.timings_mut() {
+            timings.first_body.get_or_insert(now);
+        }
+    }
+
+    /// Milliseconds since the request started (`$request_time`).
+    pub(crate) fn elapsed_msec(&self) -> ngx_msec_t {
+        unsafe {
+            let tp = ngx_cached_time;
+            let r = self.as_ngx_http_request();
+            let ms = ((*tp).sec - (*r).start_sec) as ngx_msec_int_t * 1000
+                + ((*tp).msec as ngx_msec_int_t - (*r).start_msec as ngx_msec_int_t);
+            ms.max(0) as ngx_msec_t
+        }
+    }
+
+    fn timings_mut(&mut self) -> Option<&mut ResponseTimings> {
+        let mut pool = self.pool();
+        let mut timings = pool.get_local::<ResponseTimings>();
+        if timings.is_null() {
+            timings = pool.insert_local(ResponseTimings::default());
+        }
+        unsafe { timings.as_mut() }
+    }
+}
@@ -0,0 +1,546 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::core::shm::{SlabPool, DYNAMIC_UPSTREAM_ZONE_TAG};
+use crate::http::conf::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+use crate::ngx_log;
+
+use std::fmt::Write;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+
+/// Maximum length of the address of a server, as text.
+const NAME_LEN: usize = 64;
+
+const SOCKADDR_LEN: usize = mem::size_of::<ngx_sockaddr_t>();
+
+/// A server of a [`DynamicUpstream`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UpstreamServer {
+    /// Identifier of the server, unique within the upstream.
+    pub id: u64,
+    /// Address of the server, such as `10.0.0.1:8080` or `[2001:db8::1]:80`.
+    pub address: String,
+    /// Weight of the server.
+    pub weight: u32,
+    /// Is the server marked as unavailable?
+    pub down: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Server {
+    id: u64,
+    weight: u32,
+    down: u32,
+    socklen: u32,
+    name_len: u32,
+    sockaddr: [u8; SOCKADDR_LEN],
+    name: [u8; NAME_LEN],
+}
+
+impl Server {
+    fn name(&self) -> &[u8] {
+        // The length is bounded as readers may see a server while it is being written.
+        &self.name[..(self.name_len as usize).min(NAME_LEN)]
+    }
+
+    fn info(&self) -> UpstreamServer {
+        UpstreamServer {
+            id: self.id,
+            address: String::from_utf8_lossy(self.name()).into_owned(),
+            weight: self.weight,
+            down: self.down != 0,
+        }
+    }
+
+    unsafe fn from_addr(sockaddr: *const sockaddr, socklen: socklen_t, name: &[u8], weight: u32) -> Option<Server> {
+        if socklen as usize > SOCKADDR_LEN || name.len() > NAME_LEN {
+            return None;
+        }
+        let mut server: Server = mem::zeroed();
+        server.weight = weight;
+        server.socklen = socklen as u32;
+        ptr::copy_nonoverlapping(sockaddr as *const u8, server.sockaddr.as_mut_ptr(), socklen as usize);
+        server.name[..name.len()].copy_from_slice(name);
+        server.name_len = name.len() as u32;
+        Some(server)
+    }
+}
+
+// The server list: `capacity` servers follow the header. It is only accessed under the
+// mutex of the slab pool of the zone, which the master process releases if a worker dies
+// while holding it.
+#[repr(C)]
+struct Table {
+    len: usize,
+    next_id: u64,
+    capacity: usize,
+}
+
+impl Table {
+    fn size(capacity: usize) -> usize {
+        mem::size_of::<Table>() + capacity * mem::size_of::<Server>()
+    }
+
+    unsafe fn list(&self) -> *mut Server {
+        (self as *const Table as *mut u8).add(mem::size_of::<Table>()) as *mut Server
+    }
+}
+
+/// An upstream block balancing over a list of servers that can be changed at runtime.
+///
+/// The servers of the `upstream` block are the initial list. The list is kept in a shared
+/// memory zone, so all worker processes see changes at once, and it is kept across
+/// configuration reloads as long as the zone is not resized. Requests and updates hold the
+/// lock of the list only to read or copy it.
+///
+/// Servers are chosen at random according to their weight, skipping servers marked down
+/// and those already tried for the request. Passive failure accounting (`max_fails`) and TLS
+/// session reuse are not supported, nor is the `zone` directive of the upstream block.
+///
+/// ```ignore
+/// // upstream backend { dynamic backend_zone 64; server 10.0.0.1:80; }
+/// unsafe extern "C" fn ngx_http_dynamic(cf: *mut ngx_conf_t, _cmd: *mut ngx_command_t, conf: *mut c_void) -> *mut c_char {
+///     let conf = &mut *(conf as *mut SrvConf);
+///     let args = conf_args(cf);
+///     match DynamicUpstream::add(cf, &args[1], args[2].parse().unwrap_or(0)) {
+///         Ok(upstream) => {
+///             conf.upstream = Some(upstream);
+///             NGX_CONF_OK
+///         }
+///         Err(_) => NGX_CONF_ERROR,
+///     }
+/// }
+/// ```
+pub struct DynamicUpstream {
+    table: *mut Table,
+    shpool: *mut ngx_slab_pool_t,
+    upstream: *mut ngx_http_upstream_srv_conf_t,
+    capacity: usize,
+}
+
+impl DynamicUpstream {
+    /// Make the `upstream` block being configured dynamic, with a shared memory zone `name`
+    /// holding up to `capacity` servers.
+    ///
+    /// Call this from the handler of a directive allowed in `upstream` blocks (the `ups`
+    /// context). The upstream lives as long as the configuration.
+    pub unsafe fn add(cf: *mut ngx_conf_t, name: &str, capacity: usize) -> Result<&'static DynamicUpstream, String> {
+        if capacity == 0 {
+            return Err(String::from("invalid capacity"));
+        }
+
+        let uscf = ngx_http_conf_get_module_srv_conf(cf, &ngx_http_upstream_module) as *mut ngx_http_upstream_srv_conf_t;
+        if uscf.is_null() {
+            return Err(String::from("is not allowed here"));
+        }
+
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        let mut zone_name = match NgxString::new_in(name, &mut pool) {
+            Some(name) => name.into_ngx_str(),
+            None => return Err(String::from("allocation failed")),
+        };
+
+        // Leave room for the bookkeeping of the zone's slab allocator.
+        let size = Table::size(capacity);
+        let zone_size = size + size / 8 + 8 * ngx_pagesize as usize;
+        let zone = ngx_shared_memory_add(cf, &mut zone_name, zone_size, &DYNAMIC_UPSTREAM_ZONE_TAG as *const u8 as *mut c_void);
+        if zone.is_null() {
+            return Err(format!("failed to add shared memory zone \"{}\"", name));
+        }
+        if !(*zone).data.is_null() {
+            return Err(format!("duplicate zone \"{}\"", name));
+        }
+
+        let upstream: *mut DynamicUpstream = match pool.alloc(DynamicUpstream { table: ptr::null_mut(), shpool: ptr::null_mut(), upstream: uscf, capacity }) {
+            Some(upstream) => upstream.as_ptr(),
+            None => return Err(String::from("allocation failed")),
+        };
+        (*zone).init = Some(ngx_rs_dynamic_upstream_init_zone);
+        (*zone).data = upstream as *mut c_void;
+
+        (*uscf).peer.init_upstream = Some(ngx_rs_dynamic_upstream_init);
+        (*uscf).peer.data = upstream as *mut c_void;
+        (*uscf).flags = (NGX_HTTP_UPSTREAM_CREATE | NGX_HTTP_UPSTREAM_WEIGHT | NGX_HTTP_UPSTREAM_DOWN) as ngx_uint_t;
+
+        Ok(&*upstream)
+    }
+
+    /// Name of the upstream block.
+    pub fn name(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str((*self.upstream).host) }
+    }
+
+    /// Maximum number of servers.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Current servers, in order.
+    pub fn servers(&self) -> Vec<UpstreamServer> {
+        self.read(|servers| servers.iter().map(Server::info).collect())
+    }
+
+    /// Add a server, by IP address and port. Returns the identifier of the new server.
+    pub fn add_server(&self, address: &str, weight: u32) -> Result<u64, String> {
+        let (addr, name) = parse_address(address)?;
+        let mut server = unsafe {
+            let mut sa: ngx_sockaddr_t = mem::zeroed();
+            let socklen = to_sockaddr(&addr, &mut sa);
+            Server::from_addr(&sa as *const ngx_sockaddr_t as *const sockaddr, socklen, name.as_bytes(), weight)
+        }
+        .ok_or_else(|| format!("invalid address \"{}\"", address))?;
+
+        self.update(|servers, next_id| {
+            if servers.iter().any(|s| s.name() == name.as_bytes()) {
+                return Err(format!("server \"{}\" already exists", name));
+            }
+            server.id = *next_id;
+            *next_id += 1;
+            servers.push(server);
+            Ok(server.id)
+        })
+    }
+
+    /// Remove a server. Requests already sent to the server are not affected.
+    pub fn remove_server(&self, address: &str) -> Result<(), String> {
+        let (_, name) = parse_address(address)?;
+        self.update(|servers, _| {
+            let len = servers.len();
+            servers.retain(|s| s.name() != name.as_bytes());
+            if servers.len() == len {
+                return Err(format!("server \"{}\" not found", name));
+            }
+            Ok(())
+        })
+    }
+
+    /// Change the weight of a server.
+    pub fn set_weight(&self, address: &str, weight: u32) -> Result<(), String> {
+        self.modify(address, |server| server.weight = weight)
+    }
+
+    /// Mark a server as unavailable, or available again.
+    pub fn set_down(&self, address: &str, down: bool) -> Result<(), String> {
+        self.modify(address, |server| server.down = down as u32)
+    }
+
+    /// Handle a request to the admin interface of the upstream, from a content handler.
+    ///
+    /// The servers are changed by `POST` or `PUT` requests according to the query string, such
+    /// as `?add=10.0.0.2:80`, `?add=10.0.0.2:80&weight=5`, `?remove=10.0.0.2:80`, `?down=...`,
+    /// `?up=...` or `?server=10.0.0.2:80&weight=2`, and the resulting list of servers is sent
+    /// as text. Other methods only get the list, so that links and prefetching can't change
+    /// it. Access to the location should be restricted, such as with `allow` and `deny`.
+    pub fn handle_admin(&self, request: &mut Request) -> Status {
+        let params = parse_args(&request.args().unwrap_or_default());
+        let param = |key: &str| params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+        let changes = ["add", "remove", "down", "up", "server", "weight"].iter().any(|key| param(key).is_some());
+        if changes {
+            let method = unsafe { (*request.as_ngx_http_request()).method };
+            if method & (NGX_HTTP_POST | NGX_HTTP_PUT) as ngx_uint_t == 0 {
+                return request.send_response(HTTP_NOT_ALLOWED, "text/plain", b"changes need POST or PUT\n");
+            }
+            // The changes are in the query string; the body is not used.
+            let rc = request.discard_request_body();
+            if !rc.is_ok() {
+                return rc;
+            }
+        }
+
+        let weight = match param("weight").map(str::parse::<u32>) {
+            Some(Ok(weight)) => Some(weight),
+            Some(Err(_)) => return request.send_response(HTTP_BAD_REQUEST, "text/plain", b"invalid weight\n"),
+            None => None,
+        };
+
+        let result = if let Some(address) = param("add") {
+            self.add_server(address, weight.unwrap_or(1)).map(|_| ())
+        } else if let Some(address) = param("remove") {
+            self.remove_server(address)
+        } else if let Some(address) = param("down") {
+            self.set_down(address, true)
+        } else if let Some(address) = param("up") {
+            self.set_down(address, false)
+        } else if let (Some(address), Some(weight)) = (param("server"), weight) {
+            self.set_weight(address, weight)
+        } else {
+            Ok(())
+        };
+
+        if let Err(message) = result {
+            return request.send_response(HTTP_BAD_REQUEST, "text/plain", format!("{}\n", message).as_bytes());
+        }
+
+        let mut body = String::new();
+        for server in self.servers() {
+            let _ = write!(body, "id={} address={} weight={}", server.id, server.address, server.weight);
+            body.push_str(if server.down { " down\n" } else { "\n" });
+        }
+        request.send_response(HTTP_OK, "text/plain", body.as_bytes())
+    }
+
+    fn modify<F: FnOnce(&mut Server)>(&self, address: &str, f: F) -> Result<(), String> {
+        let (_, name) = parse_address(address)?;
+        self.update(|servers, _| match servers.iter_mut().find(|s| s.name() == name.as_bytes()) {
+            Some(server) => {
+                f(server);
+                Ok(())
+            }
+            None => Err(format!("server \"{}\" not found", name)),
+        })
+    }
+
+    fn read<R, F: FnOnce(&[Server]) -> R>(&self, f: F) -> R {
+        let table = match unsafe { self.table.as_ref() } {
+            Some(table) => table,
+            None => return f(&[]),
+        };
+
+        let shpool = unsafe { SlabPool::from_ngx_slab_pool(self.shpool) };
+        let _guard = shpool.lock();
+        let len = table.len.min(self.capacity);
+        f(unsafe { slice::from_raw_parts(table.list(), len) })
+    }
+
+    fn update<R, F>(&self, f: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut Vec<Server>, &mut u64) -> Result<R, String>,
+    {
+        let table = match unsafe { self.table.as_mut() } {
+            Some(table) => table,
+            None => return Err(String::from("upstream is not initialized")),
+        };
+
+        let shpool = unsafe { SlabPool::from_ngx_slab_pool(self.shpool) };
+        let _guard = shpool.lock();
+
+        let len = table.len.min(self.capacity);
+        let mut servers = unsafe { slice::from_raw_parts(table.list(), len) }.to_vec();
+        let mut next_id = table.next_id;
+
+        let result = f(&mut servers, &mut next_id)?;
+        if servers.len() > self.capacity {
+            return Err(format!("too many servers, the capacity is {}", self.capacity));
+        }
+        unsafe { ptr::copy_nonoverlapping(servers.as_ptr(), table.list(), servers.len()) };
+        table.len = servers.len();
+        table.next_id = next_id;
+        Ok(result)
+    }
+
+    // Fill the new table with the servers of the upstream block.
+    unsafe fn seed(&self, table: &mut Table) -> ngx_int_t {
+        let mut servers = Vec::new();
+        let array = (*self.upstream).servers;
+        if !array.is_null() {
+            let elts = slice::from_raw_parts((*array).elts as *const ngx_http_upstream_server_t, (*array).nelts);
+            for us in elts {
+                let addrs = slice::from_raw_parts(us.addrs, us.naddrs);
+                for addr in addrs {
+                    let name = NgxStr::from_ngx_str(addr.name).as_bytes();
+                    match Server::from_addr(addr.sockaddr, addr.socklen, name, us.weight as u32) {
+                        Some(mut server) => {
+                            server.down = us.down as u32;
+                            server.id = servers.len() as u64 + 1;
+                            servers.push(server);
+                        }
+                        None => return NGX_ERROR as ngx_int_t,
+                    }
+                }
+            }
+        }
+
+        if servers.len() > self.capacity {
+            let log = (*ngx_cycle).log;
+            ngx_log!(NGX_LOG_EMERG, log, "upstream \"{}\" has more than {} servers", self.name(), self.capacity);
+            return NGX_ERROR as ngx_int_t;
+        }
+
+        table.capacity = self.capacity;
+        ptr::copy_nonoverlapping(servers.as_ptr(), table.list(), servers.len());
+        table.len = servers.len();
+        table.next_id = servers.len() as u64 + 1;
+        NGX_OK as ngx_int_t
+    }
+}
+
+fn parse_address(address: &str) -> Result<(SocketAddr, String), String> {
+    let addr: SocketAddr = address.parse().map_err(|_| format!("invalid address \"{}\"", address))?;
+    Ok((addr, addr.to_string()))
+}
+
+unsafe fn to_sockaddr(addr: &SocketAddr, sa: &mut ngx_sockaddr_t) -> socklen_t {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sin = sa as *mut ngx_sockaddr_t as *mut sockaddr_in;
+            (*sin).sin_family = AF_INET as _;
+            (*sin).sin_port = addr.port().to_be();
+            (*sin).sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            mem::size_of::<sockaddr_in>() as socklen_t
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = sa as *mut ngx_sockaddr_t as *mut sockaddr_in6;
+            (*sin6).sin6_family = AF_INET6 as _;
+            (*sin6).sin6_port = addr.port().to_be();
+            (*sin6).sin6_flowinfo = addr.flowinfo().to_be();
+            (*sin6).sin6_scope_id = addr.scope_id();
+            ptr::copy_nonoverlapping(addr.ip().octets().as_ptr(), &mut (*sin6).sin6_addr as *mut in6_addr as *mut u8, 16);
+            mem::size_of::<sockaddr_in6>() as socklen_t
+        }
+    }
+}
+
+fn parse_args(args: &str) -> Vec<(String, String)> {
+    args.split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = match param.find('=') {
+                Some(i) => (&param[..i], &param[i + 1..]),
+                None => (param, ""),
+            };
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+unsafe extern "C" fn ngx_rs_dynamic_upstream_init_zone(zone: *mut ngx_shm_zone_t, data: *mut c_void) -> ngx_int_t {
+    let upstream = &mut *((*zone).data as *mut DynamicUpstream);
+    let shpool = (*zone).shm.addr as *mut ngx_slab_pool_t;
+    upstream.shpool = shpool;
+
+    // Keep the servers of the previous configuration on reload.
+    if let Some(previous) = (data as *const DynamicUpstream).as_ref() {
+        upstream.table = previous.table;
+        return NGX_OK as ngx_int_t;
+    }
+
+    if (*zone).shm.exists != 0 {
+        upstream.table = (*shpool).data as *mut Table;
+        return NGX_OK as ngx_int_t;
+    }
+
+    let table = ngx_slab_calloc(shpool, Table::size(upstream.capacity)) as *mut Table;
+    if table.is_null() {
+        return NGX_ERROR as ngx_int_t;
+    }
+    (*shpool).data = table as *mut c_void;
+    upstream.table = table;
+
+    upstream.seed(&mut *table)
+}
+
+struct PeerData {
+    upstream: *const DynamicUpstream,
+    tried: Vec<u64>,
+    sockaddr: ngx_sockaddr_t,
+    name: [u8; NAME_LEN],
+    name_str: ngx_str_t,
+}
+
+unsafe extern "C" fn ngx_rs_dynamic_upstream_init(_cf: *mut ngx_conf_t, us: *mut ngx_http_upstream_srv_conf_t) -> ngx_int_t {
+    (*us).peer.init = Some(ngx_rs_dynamic_upstream_init_peer);
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn ngx_rs_dynamic_upstream_init_peer(r: *mut ngx_http_request_t, us: *mut ngx_http_upstream_srv_conf_t) -> ngx_int_t {
+    let upstream = (*us).peer.data as *const DynamicUpstream;
+
+    let mut pool = Pool::from_ngx_pool((*r).pool);
+    let pd: *mut PeerData = match pool.alloc(PeerData {
+        upstream,
+        tried: Vec::new(),
+        sockaddr: mem::zeroed(),
+        name: [0; NAME_LEN],
+        name_str: (*us).host,
+    }) {
+        Some(pd) => pd.as_ptr(),
+        None => return NGX_ERROR as ngx_int_t,
+    };
+
+    let u = (*r).upstream;
+    (*u).peer.data = pd as *mut c_void;
+    (*u).peer.get = Some(ngx_rs_dynamic_upstream_get_peer);
+    (*u).peer.free = Some(ngx_rs_dynamic_upstream_free_peer);
+    (*u).peer.tries = (*upstream).read(|servers| servers.len()).max(1) as ngx_uint_t;
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn ngx_rs_dynamic_upstream_get_peer(pc: *mut ngx_peer_connection_t, data: *mut c_void) -> ngx_int_t {
+    let pd = &mut *(data as *mut PeerData);
+    let tried = &pd.tried;
+
+    let chosen = (*pd.upstream).read(|servers| {
+        let eligible = || servers.iter().filter(|s| s.down == 0 && s.weight > 0 && !tried.contains(&s.id));
+        let total: u64 = eligible().map(|s| s.weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = random_u64() % total;
+        for server in eligible() {
+            if point < server.weight as u64 {
+                return Some(*server);
+            }
+            point -= server.weight as u64;
+        }
+        None
+    });
+
+    let server = match chosen {
+        Some(server) => server,
+        None => {
+            pd.name_str = (*(*pd.upstream).upstream).host;
+            (*pc).name = &mut pd.name_str;
+            return NGX_BUSY as ngx_int_t;
+        }
+    };
+
+    // The server is copied, as the list may change while the request uses it.
+    pd.tried.push(server.id);
+    let socklen = (server.socklen as usize).min(SOCKADDR_LEN);
+    ptr::copy_nonoverlapping(server.sockaddr.as_ptr(), &mut pd.sockaddr as *mut ngx_sockaddr_t as *mut u8, socklen);
+    let name = server.name();
+    pd.name[..name.len()].copy_from_slice(name);
+    pd.name_str = ngx_str_t { len: name.len(), data: pd.name.as_mut_ptr() };
+
+    (*pc).sockaddr = &mut pd.sockaddr as *mut ngx_sockaddr_t as *mut sockaddr;
+    (*pc).socklen = socklen as socklen_t;
+    (*pc).name = &mut pd.name_str;
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn ngx_rs_dynamic_upstream_free_peer(pc: *mut ngx_peer_connection_t, _data: *mut c_void, _state: ngx_uint_t) {
+    if (*pc).tries != 0 {
+        (*pc).tries -= 1;
+    }
+}
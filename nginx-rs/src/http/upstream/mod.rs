@@ -0,0 +1,455 @@
+//! Custom [upstream] load balancers.
+//!
+//! A balancer builds on the round-robin peers Nginx creates for an `upstream` block: it
+//! picks which server each request goes to, while Nginx keeps handling weights, failure
+//! accounting (`max_fails`, `fail_timeout`), `max_conns` and TLS sessions.
+//!
+//! A [`DynamicUpstream`] instead balances over a server list kept in shared memory, which can
+//! be changed at runtime. An [`OutlierDetector`] lets balancers skip peers that keep
+//! failing.
+//!
+//! [upstream]: https://nginx.org/en/docs/http/ngx_http_upstream_module.html
+
+mod dynamic;
+mod outlier;
+
+pub use dynamic::*;
+pub use outlier::*;
+
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::ConnectionPool;
+use crate::http::conf::*;
+use crate::http::request::Request;
+
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+/// Number of [`Balancer::get_peer`] attempts before falling back to round-robin, as done by
+/// the `hash` module.
+const MAX_ATTEMPTS: usize = 20;
+
+/// Define the `init_upstream` handler of a [`Balancer`].
+///
+/// The balancer is the upstream server configuration of `module` (its `SrvConf`, which is
+/// created for each `upstream` block), and the handler is installed with [`set_balancer`]
+/// from a directive of the upstream block.
+///
+/// ```ignore
+/// http_upstream_balancer!(ngx_http_upstream_init_key_hash, SrvConf, ngx_http_key_hash_module);
+///
+/// unsafe extern "C" fn ngx_http_key_hash(cf: *mut ngx_conf_t, _cmd: *mut ngx_command_t, _conf: *mut c_void) -> *mut c_char {
+///     match set_balancer(cf, ngx_http_upstream_init_key_hash) {
+///         Ok(()) => NGX_CONF_OK,
+///         Err(_) => NGX_CONF_ERROR,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! http_upstream_balancer {
+    ( $name: ident, $balancer: ty, $module: ident ) => {
+        unsafe extern "C" fn $name(cf: *mut $crate::__private::bindings::ngx_conf_t, us: *mut $crate::__private::bindings::ngx_http_upstream_srv_conf_t) -> $crate::__private::bindings::ngx_int_t {
+            unsafe extern "C" fn init_peer(r: *mut $crate::__private::bindings::ngx_http_request_t, us: *mut $crate::__private::bindings::ngx_http_upstream_srv_conf_t) -> $crate::__private::bindings::ngx_int_t {
+                let balancer = $crate::http::upstream::ngx_http_conf_upstream_srv_conf(us, &*::std::ptr::addr_of!($module)) as *const $balancer;
+                $crate::http::upstream::init_peer::<$balancer>(r, us, balancer)
+            }
+
+            let balancer = $crate::http::upstream::ngx_http_conf_upstream_srv_conf(us, &*::std::ptr::addr_of!($module)) as *mut $balancer;
+            $crate::http::upstream::init_upstream::<$balancer>(cf, us, balancer, init_peer)
+        }
+    };
+}
+
+/// A load balancing algorithm for an `upstream` block.
+///
+/// ```ignore
+/// impl Balancer for SrvConf {
+///     type Peer = u64;
+///
+///     fn init_peer(&self, request: &mut Request) -> Result<u64, Status> {
+///         let key = request.get_header("x-session-id").unwrap_or_default();
+///         Ok(XxHash64::with_seed(0).hash64(key.as_bytes()))
+///     }
+///
+///     fn get_peer(&self, hash: &mut u64, peers: &Peers) -> Option<usize> {
+///         let choice = (*hash % peers.len() as u64) as usize;
+///         // Rehash for the next attempt, if the peer is unavailable.
+///         *hash = XxHash64::with_seed(*hash).hash64(b"");
+///         Some(choice)
+///     }
+/// }
+/// ```
+pub trait Balancer: 'static {
+    /// State of the balancer for a request, such as the hash of its key.
+    type Peer: 'static;
+
+    /// Prepare the balancer for an upstream block, once its servers are known.
+    ///
+    /// Called while parsing the configuration, after the round-robin peers have been created.
+    fn init_upstream(&mut self, _cf: *mut ngx_conf_t, _peers: &Peers) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Create the state of a request. An error status finalizes the request.
+    fn init_peer(&self, request: &mut Request) -> Result<Self::Peer, Status>;
+
+    /// Choose the index of the peer to try next, or `None` if no peer can be used.
+    ///
+    /// Peers that are down, failed, at their connection limit or already tried for the
+    /// request are skipped and the balancer is asked again; after a few attempts, the peer
+    /// is chosen by round-robin.
+    fn get_peer(&self, state: &mut Self::Peer, peers: &Peers) -> Option<usize>;
+
+    /// Called once the request is done with a peer, with whether the attempt failed.
+    fn free_peer(&self, _state: &mut Self::Peer, _peer: &Peer, _failed: bool) {}
+
+    /// Pool of idle connections to reuse, if any.
+    ///
+    /// For HTTP upstreams, connections are only reusable with `proxy_http_version 1.1` and
+    /// `proxy_set_header Connection ""`, as with the `keepalive` directive.
+    fn keepalive(&self) -> Option<&ConnectionPool> {
+        None
+    }
+}
+
+/// Install the `init_upstream` handler of a balancer, such as one defined with
+/// [`http_upstream_balancer!`](crate::http_upstream_balancer), for the `upstream` block being
+/// configured.
+///
+/// Call this from the handler of a directive allowed in `upstream` blocks (the `ups`
+/// context). Backup servers are not supported.
+pub unsafe fn set_balancer(cf: *mut ngx_conf_t, init: unsafe extern "C" fn(*mut ngx_conf_t, *mut ngx_http_upstream_srv_conf_t) -> ngx_int_t) -> Result<(), String> {
+    let uscf = ngx_http_conf_get_module_srv_conf(cf, &ngx_http_upstream_module) as *mut ngx_http_upstream_srv_conf_t;
+    if uscf.is_null() {
+        return Err(String::from("is not allowed here"));
+    }
+
+    if (*uscf).peer.init_upstream.is_some() {
+        let fmt = b"%s\0";
+        let message = CString::new("load balancing method redefined").unwrap_or_default();
+        ngx_conf_log_error(NGX_LOG_WARN as ngx_uint_t, cf, 0, fmt.as_ptr() as *const c_char, message.as_ptr());
+    }
+
+    (*uscf).peer.init_upstream = Some(init);
+    (*uscf).flags = (NGX_HTTP_UPSTREAM_CREATE
+        | NGX_HTTP_UPSTREAM_WEIGHT
+        | NGX_HTTP_UPSTREAM_MAX_CONNS
+        | NGX_HTTP_UPSTREAM_MAX_FAILS
+        | NGX_HTTP_UPSTREAM_FAIL_TIMEOUT
+        | NGX_HTTP_UPSTREAM_DOWN) as ngx_uint_t;
+    Ok(())
+}
+
+/// Find the configuration of the upstream block named `name` in `cycle`.
+///
+/// Implicit upstreams, created for the host of a `proxy_pass` URL, are found as well.
+pub unsafe fn find(cycle: *mut ngx_cycle_t, name: &str) -> Option<*mut ngx_http_upstream_srv_conf_t> {
+    let umcf = ngx_cycle_conf_get_module_main_conf(cycle, &ngx_http_upstream_module) as *mut ngx_http_upstream_main_conf_t;
+    if umcf.is_null() {
+        return None;
+    }
+
+    let upstreams = &(*umcf).upstreams;
+    let elts = upstreams.elts as *mut *mut ngx_http_upstream_srv_conf_t;
+    (0..upstreams.nelts)
+        .map(|i| *elts.add(i))
+        .find(|&uscf| NgxStr::from_ngx_str((*uscf).host).as_bytes() == name.as_bytes())
+}
+
+pub unsafe fn ngx_http_conf_upstream_srv_conf(us: *mut ngx_http_upstream_srv_conf_t, module: &ngx_module_t) -> *mut c_void {
+    *(*us).srv_conf.add(module.ctx_index)
+}
+
+/// A server of an upstream block, as seen by a [`Balancer`].
+#[repr(transparent)]
+pub struct Peer(ngx_http_upstream_rr_peer_t);
+
+impl Peer {
+    /// Name of the peer, its address as text.
+    pub fn name(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.name) }
+    }
+
+    /// Name of the `server` directive the peer comes from, which may resolve to several peers.
+    pub fn server(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.server) }
+    }
+
+    /// Address of the peer.
+    pub fn sockaddr(&self) -> *const sockaddr {
+        self.0.sockaddr
+    }
+
+    /// Weight of the peer (`weight=`).
+    pub fn weight(&self) -> ngx_int_t {
+        self.0.weight
+    }
+
+    /// Is the peer marked as permanently unavailable (`down`)?
+    pub fn is_down(&self) -> bool {
+        self.0.down != 0
+    }
+
+    /// Number of failures in the current `fail_timeout` period.
+    pub fn fails(&self) -> ngx_uint_t {
+        self.0.fails
+    }
+
+    /// Number of active connections to the peer, in this worker process or, with `zone`,
+    /// in all of them.
+    pub fn conns(&self) -> ngx_uint_t {
+        self.0.conns
+    }
+
+    fn is_available(&self, now: time_t) -> bool {
+        let peer = &self.0;
+        if peer.down != 0 {
+            return false;
+        }
+        if peer.max_fails != 0 && peer.fails >= peer.max_fails && now - peer.checked <= peer.fail_timeout {
+            return false;
+        }
+        !(peer.max_conns != 0 && peer.conns >= peer.max_conns)
+    }
+}
+
+/// The servers of an upstream block, passed to a [`Balancer`].
+pub struct Peers<'a> {
+    peers: *mut ngx_http_upstream_rr_peers_t,
+    tried: *mut uintptr_t,
+    _marker: PhantomData<&'a Peer>,
+}
+
+impl<'a> Peers<'a> {
+    /// The round-robin peers of an upstream block, once its `init_upstream` handler has run.
+    pub unsafe fn from_upstream(us: *mut ngx_http_upstream_srv_conf_t) -> Peers<'a> {
+        Peers::from_ngx_peers((*us).peer.data as *mut ngx_http_upstream_rr_peers_t)
+    }
+
+    pub(crate) unsafe fn from_ngx_peers(peers: *mut ngx_http_upstream_rr_peers_t) -> Peers<'a> {
+        Peers { peers, tried: ptr::null_mut(), _marker: PhantomData }
+    }
+
+    /// The backup servers of the upstream block, if any.
+    pub fn backup(&self) -> Option<Peers<'a>> {
+        let backup = unsafe { (*self.peers).next };
+        if backup.is_null() {
+            return None;
+        }
+        Some(Peers { peers: backup, tried: ptr::null_mut(), _marker: PhantomData })
+    }
+
+    /// Number of peers.
+    pub fn len(&self) -> usize {
+        unsafe { (*self.peers).number }
+    }
+
+    /// Is the upstream empty? Upstream blocks always have at least one peer.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sum of the weights of the peers.
+    pub fn total_weight(&self) -> ngx_uint_t {
+        unsafe { (*self.peers).total_weight }
+    }
+
+    /// The peer at `index`, in configuration order.
+    pub fn get(&self, index: usize) -> Option<&'a Peer> {
+        self.iter().nth(index)
+    }
+
+    /// Iterate over the peers, in configuration order.
+    pub fn iter(&self) -> impl Iterator<Item = &'a Peer> {
+        let mut peer = unsafe { (*self.peers).peer };
+        std::iter::from_fn(move || unsafe {
+            let current = (peer as *const Peer).as_ref()?;
+            peer = (*peer).next;
+            Some(current)
+        })
+    }
+
+    /// Has the peer at `index` already been tried for the current request?
+    pub fn is_tried(&self, index: usize) -> bool {
+        if self.tried.is_null() {
+            return false;
+        }
+        let bits = 8 * mem::size_of::<uintptr_t>();
+        unsafe { *self.tried.add(index / bits) & (1 << (index % bits)) != 0 }
+    }
+
+    fn set_tried(&self, index: usize) {
+        let bits = 8 * mem::size_of::<uintptr_t>();
+        unsafe { *self.tried.add(index / bits) |= 1 << (index % bits) };
+    }
+
+    // Locks mirror the `ngx_http_upstream_rr_peers_*lock` macros, which only lock peers in
+    // a shared memory zone.
+    pub(crate) unsafe fn rlock(&self) {
+        if !(*self.peers).shpool.is_null() {
+            ngx_rwlock_rlock(&mut (*self.peers).rwlock);
+        }
+    }
+
+    pub(crate) unsafe fn unlock(&self) {
+        if !(*self.peers).shpool.is_null() {
+            ngx_rwlock_unlock(&mut (*self.peers).rwlock);
+        }
+    }
+
+    pub(crate) unsafe fn lock_peer(&self, peer: *mut ngx_http_upstream_rr_peer_t) {
+        if !(*self.peers).shpool.is_null() {
+            ngx_rwlock_wlock(&mut (*peer).lock);
+        }
+    }
+
+    pub(crate) unsafe fn unlock_peer(&self, peer: *mut ngx_http_upstream_rr_peer_t) {
+        if !(*self.peers).shpool.is_null() {
+            ngx_rwlock_unlock(&mut (*peer).lock);
+        }
+    }
+}
+
+// The round-robin data must come first: Nginx fills it in place and its own handlers (TLS
+// sessions, round-robin fallback) use the peer data as round-robin data.
+#[repr(C)]
+struct PeerData<B: Balancer> {
+    rrp: ngx_http_upstream_rr_peer_data_t,
+    upstream: *mut ngx_http_upstream_t,
+    balancer: *const B,
+    state: B::Peer,
+}
+
+#[doc(hidden)]
+pub unsafe fn init_upstream<B: Balancer>(
+    cf: *mut ngx_conf_t,
+    us: *mut ngx_http_upstream_srv_conf_t,
+    balancer: *mut B,
+    init_peer: unsafe extern "C" fn(*mut ngx_http_request_t, *mut ngx_http_upstream_srv_conf_t) -> ngx_int_t,
+) -> ngx_int_t {
+    if ngx_http_upstream_init_round_robin(cf, us) != NGX_OK as ngx_int_t {
+        return NGX_ERROR as ngx_int_t;
+    }
+    (*us).peer.init = Some(init_peer);
+
+    let peers = Peers::from_upstream(us);
+    match (*balancer).init_upstream(cf, &peers) {
+        Ok(()) => NGX_OK as ngx_int_t,
+        Err(message) => {
+            let fmt = b"%s\0";
+            let message = CString::new(message).unwrap_or_default();
+            ngx_conf_log_error(NGX_LOG_EMERG as ngx_uint_t, cf, 0, fmt.as_ptr() as *const c_char, message.as_ptr());
+            NGX_ERROR as ngx_int_t
+        }
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn init_peer<B: Balancer>(r: *mut ngx_http_request_t, us: *mut ngx_http_upstream_srv_conf_t, balancer: *const B) -> ngx_int_t {
+    let state = match (*balancer).init_peer(Request::from_ngx_http_request(r)) {
+        Ok(state) => state,
+        Err(status) => return status.0,
+    };
+
+    let u = (*r).upstream;
+    let mut pool = Pool::from_ngx_pool((*r).pool);
+    let pd: *mut PeerData<B> = match pool.alloc(PeerData::<B> { rrp: mem::zeroed(), upstream: u, balancer, state }) {
+        Some(pd) => pd.as_ptr(),
+        None => return NGX_ERROR as ngx_int_t,
+    };
+
+    (*u).peer.data = pd as *mut c_void;
+    if ngx_http_upstream_init_round_robin_peer(r, us) != NGX_OK as ngx_int_t {
+        return NGX_ERROR as ngx_int_t;
+    }
+
+    (*u).peer.get = Some(get_peer::<B>);
+    (*u).peer.free = Some(free_peer::<B>);
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn get_peer<B: Balancer>(pc: *mut ngx_peer_connection_t, data: *mut c_void) -> ngx_int_t {
+    let rc = choose_peer::<B>(pc, data);
+    if rc != NGX_OK as ngx_int_t {
+        return rc;
+    }
+
+    let pd = &*(data as *mut PeerData<B>);
+    match (*pd.balancer).keepalive() {
+        Some(pool) if pool.get(pc) => NGX_DONE as ngx_int_t,
+        _ => rc,
+    }
+}
+
+unsafe fn choose_peer<B: Balancer>(pc: *mut ngx_peer_connection_t, data: *mut c_void) -> ngx_int_t {
+    let pd = &mut *(data as *mut PeerData<B>);
+    let balancer = &*pd.balancer;
+    let peers = Peers { peers: pd.rrp.peers, tried: pd.rrp.tried, _marker: PhantomData };
+    let now = (*ngx_cached_time).sec;
+
+    peers.rlock();
+
+    let mut chosen = None;
+    for _ in 0..MAX_ATTEMPTS {
+        let index = match balancer.get_peer(&mut pd.state, &peers) {
+            Some(index) => index,
+            None => {
+                peers.unlock();
+                (*pc).name = (*peers.peers).name;
+                return NGX_BUSY as ngx_int_t;
+            }
+        };
+        if peers.is_tried(index) {
+            continue;
+        }
+        if let Some(peer) = peers.get(index) {
+            let peer = peer as *const Peer as *mut ngx_http_upstream_rr_peer_t;
+            peers.lock_peer(peer);
+            if (*(peer as *const Peer)).is_available(now) {
+                chosen = Some((index, peer));
+                break;
+            }
+            peers.unlock_peer(peer);
+        }
+    }
+
+    let (index, peer) = match chosen {
+        Some(chosen) => chosen,
+        None => {
+            peers.unlock();
+            return ngx_http_upstream_get_round_robin_peer(pc, &mut pd.rrp as *mut _ as *mut c_void);
+        }
+    };
+
+    // As done by `ngx_http_upstream_get_round_robin_peer` once a peer is chosen.
+    pd.rrp.current = peer;
+    (*pc).sockaddr = (*peer).sockaddr;
+    (*pc).socklen = (*peer).socklen;
+    (*pc).name = &mut (*peer).name;
+    (*peer).conns += 1;
+    if now - (*peer).checked > (*peer).fail_timeout {
+        (*peer).checked = now;
+    }
+    peers.set_tried(index);
+
+    peers.unlock_peer(peer);
+    peers.unlock();
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn free_peer<B: Balancer>(pc: *mut ngx_peer_connection_t, data: *mut c_void, state: ngx_uint_t) {
+    let pd = &mut *(data as *mut PeerData<B>);
+    if !pd.rrp.current.is_null() {
+        let failed = state & NGX_PEER_FAILED as ngx_uint_t != 0;
+        (*pd.balancer).free_peer(&mut pd.state, &*(pd.rrp.current as *const Peer), failed);
+    }
+
+    if let Some(pool) = (*pd.balancer).keepalive() {
+        let u = pd.upstream;
+        pool.release(pc, state, (*u).keepalive() != 0 && (*u).request_body_sent() != 0);
+    }
+    ngx_http_upstream_free_round_robin_peer(pc, &mut pd.rrp as *mut _ as *mut c_void, state);
+}
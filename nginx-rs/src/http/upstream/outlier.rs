@@ -0,0 +1,437 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::core::shm::{SlabPool, OUTLIER_ZONE_TAG};
+use crate::http::upstream::Peer;
+use crate::ngx_log;
+
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+
+/// Maximum length of the name of a peer tracked by an [`OutlierDetector`].
+const NAME_LEN: usize = 64;
+
+/// When an [`OutlierDetector`] ejects a peer.
+#[derive(Clone, Copy, Debug)]
+pub struct OutlierPolicy {
+    consecutive_failures: u32,
+    max_error_rate: Option<f64>,
+    min_requests: u32,
+    max_latency: Option<ngx_msec_t>,
+    interval: ngx_msec_t,
+    base_ejection_time: ngx_msec_t,
+    max_ejection_time: ngx_msec_t,
+    max_ejection_percent: u32,
+}
+
+impl Default for OutlierPolicy {
+    fn default() -> OutlierPolicy {
+        OutlierPolicy::new()
+    }
+}
+
+impl OutlierPolicy {
+    /// Eject peers after 5 consecutive failures, for 30 seconds times the number of times
+    /// they were ejected, up to 5 minutes, with at most 10% of the peers ejected at once.
+    pub fn new() -> OutlierPolicy {
+        OutlierPolicy {
+            consecutive_failures: 5,
+            max_error_rate: None,
+            min_requests: 20,
+            max_latency: None,
+            interval: 10_000,
+            base_ejection_time: 30_000,
+            max_ejection_time: 300_000,
+            max_ejection_percent: 10,
+        }
+    }
+
+    /// Eject a peer after `failures` consecutive failures, or never with `0`.
+    pub fn set_consecutive_failures(&mut self, failures: u32) {
+        self.consecutive_failures = failures;
+    }
+
+    /// Eject a peer whose share of failures (between `0.0` and `1.0`) exceeds `rate` within
+    /// an interval, once it had at least `min_requests` requests in the interval.
+    pub fn set_max_error_rate(&mut self, rate: f64, min_requests: u32) {
+        self.max_error_rate = Some(rate.clamp(0.0, 1.0));
+        self.min_requests = min_requests.max(1);
+    }
+
+    /// Count responses slower than `latency` as failures.
+    pub fn set_max_latency(&mut self, latency: Msec) {
+        self.max_latency = Some(latency.as_msec());
+    }
+
+    /// Length of the intervals over which error rates are computed.
+    pub fn set_interval(&mut self, interval: Msec) {
+        self.interval = interval.as_msec().max(1);
+    }
+
+    /// Time a peer is ejected the first time; each further ejection lasts one more such
+    /// period, up to `max`.
+    pub fn set_ejection_time(&mut self, base: Msec, max: Msec) {
+        self.base_ejection_time = base.as_msec();
+        self.max_ejection_time = max.as_msec().max(self.base_ejection_time);
+    }
+
+    /// Limit the share of the peers ejected at once, in percent. One peer can always be
+    /// ejected, so that a single failing peer of a small upstream is.
+    pub fn set_max_ejection_percent(&mut self, percent: u32) {
+        self.max_ejection_percent = percent.min(100);
+    }
+}
+
+/// Statistics of a peer tracked by an [`OutlierDetector`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutlierStats {
+    /// Name of the peer, its address as text.
+    pub name: String,
+    /// Requests in the current interval.
+    pub requests: u64,
+    /// Failures in the current interval.
+    pub failures: u64,
+    /// Failures since the last success.
+    pub consecutive_failures: u32,
+    /// Average response time in the current interval, in milliseconds.
+    pub avg_latency: Option<ngx_msec_t>,
+    /// Milliseconds until the peer is used again, if it is ejected.
+    pub ejected_for: Option<ngx_msec_t>,
+    /// Number of times the peer was ejected.
+    pub ejections: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Entry {
+    name_len: u32,
+    consecutive_failures: u32,
+    ejections: u32,
+    requests: u64,
+    failures: u64,
+    latency_sum: u64,
+    interval_start: ngx_msec_t,
+    ejected_until: ngx_msec_t,
+    last_used: ngx_msec_t,
+    name: [u8; NAME_LEN],
+}
+
+impl Entry {
+    fn name(&self) -> &[u8] {
+        &self.name[..(self.name_len as usize).min(NAME_LEN)]
+    }
+
+    fn is_ejected(&self, now: ngx_msec_t) -> bool {
+        self.ejected_until != 0 && (self.ejected_until.wrapping_sub(now) as ngx_msec_int_t) > 0
+    }
+}
+
+// Entries are only accessed with the mutex of the slab pool held, which the master process
+// releases if a worker dies while holding it.
+#[repr(C)]
+struct Table {
+    len: usize,
+}
+
+impl Table {
+    fn size(capacity: usize) -> usize {
+        mem::size_of::<Table>() + capacity * mem::size_of::<Entry>()
+    }
+
+    unsafe fn entries(&self, capacity: usize) -> &mut [Entry] {
+        let entries = (self as *const Table as *mut u8).add(mem::size_of::<Table>()) as *mut Entry;
+        slice::from_raw_parts_mut(entries, self.len.min(capacity))
+    }
+}
+
+/// Passive outlier detection for the peers of an upstream block, in the manner of Envoy.
+///
+/// A [`Balancer`](crate::http::upstream::Balancer) reports the outcome of each attempt with
+/// [`OutlierDetector::record`] from its `free_peer`, and skips peers for which
+/// [`OutlierDetector::is_ejected`] is `true` in its `get_peer`. A peer is ejected for a
+/// while after too many consecutive failures, or too high an error rate in an interval (see
+/// [`OutlierPolicy`]); it is used again once the ejection expires. Statistics are kept in a
+/// shared memory zone, so all worker processes share them, and they are kept across
+/// configuration reloads. When the zone is full, a new peer replaces the peer that was used
+/// least recently and is not ejected, such as a peer removed from the upstream.
+///
+/// Unlike `max_fails`, which only counts connection errors and the responses selected by
+/// `proxy_next_upstream`, the balancer decides what a failure is, and slow responses can
+/// count as failures.
+///
+/// ```ignore
+/// impl Balancer for SrvConf {
+///     type Peer = ngx_msec_t;
+///
+///     fn init_peer(&self, _request: &mut Request) -> Result<ngx_msec_t, Status> {
+///         Ok(unsafe { ngx_current_msec })
+///     }
+///
+///     fn get_peer(&self, _start: &mut ngx_msec_t, peers: &Peers) -> Option<usize> {
+///         let healthy: Vec<usize> = peers.iter().enumerate()
+///             .filter(|(_, peer)| !self.outliers.is_ejected(peer))
+///             .map(|(i, _)| i)
+///             .collect();
+///         if healthy.is_empty() {
+///             return None;
+///         }
+///         Some(healthy[random_u64() as usize % healthy.len()])
+///     }
+///
+///     fn free_peer(&self, start: &mut ngx_msec_t, peer: &Peer, failed: bool) {
+///         let latency = unsafe { ngx_current_msec }.wrapping_sub(*start);
+///         self.outliers.record(peer, failed, latency);
+///     }
+/// }
+/// ```
+pub struct OutlierDetector {
+    table: *mut Table,
+    shpool: *mut ngx_slab_pool_t,
+    name: ngx_str_t,
+    capacity: usize,
+    policy: OutlierPolicy,
+}
+
+impl OutlierDetector {
+    /// Create an outlier detector with a shared memory zone `name` tracking up to `capacity`
+    /// peers.
+    ///
+    /// Call this while parsing the configuration, typically from a directive of an
+    /// `upstream` block. The detector lives as long as the configuration.
+    pub unsafe fn add(cf: *mut ngx_conf_t, name: &str, capacity: usize, policy: OutlierPolicy) -> Result<&'static OutlierDetector, String> {
+        if capacity == 0 {
+            return Err(String::from("invalid capacity"));
+        }
+
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        let mut zone_name = match NgxString::new_in(name, &mut pool) {
+            Some(name) => name.into_ngx_str(),
+            None => return Err(String::from("allocation failed")),
+        };
+
+        let size = Table::size(capacity);
+        let zone_size = size + size / 8 + 8 * ngx_pagesize as usize;
+        let zone = ngx_shared_memory_add(cf, &mut zone_name, zone_size, &OUTLIER_ZONE_TAG as *const u8 as *mut c_void);
+        if zone.is_null() {
+            return Err(format!("failed to add shared memory zone \"{}\"", name));
+        }
+        if !(*zone).data.is_null() {
+            return Err(format!("duplicate zone \"{}\"", name));
+        }
+
+        let detector: *mut OutlierDetector = match pool.alloc(OutlierDetector { table: ptr::null_mut(), shpool: ptr::null_mut(), name: zone_name, capacity, policy }) {
+            Some(detector) => detector.as_ptr(),
+            None => return Err(String::from("allocation failed")),
+        };
+        (*zone).init = Some(ngx_rs_outlier_init_zone);
+        (*zone).data = detector as *mut c_void;
+
+        Ok(&*detector)
+    }
+
+    /// Name of the shared memory zone.
+    pub fn name(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.name) }
+    }
+
+    /// The ejection policy.
+    pub fn policy(&self) -> &OutlierPolicy {
+        &self.policy
+    }
+
+    /// Is `peer` currently ejected?
+    pub fn is_ejected(&self, peer: &Peer) -> bool {
+        let now = unsafe { ngx_current_msec };
+        self.with_entry(peer.name().as_bytes(), false, |entry, _| entry.map_or(false, |entry| entry.is_ejected(now)))
+    }
+
+    /// Record the outcome of an attempt to use `peer`, which took `latency` milliseconds, and
+    /// eject the peer if it is now an outlier.
+    ///
+    /// Returns `true` if the peer was ejected by this call.
+    pub fn record(&self, peer: &Peer, failed: bool, latency: ngx_msec_t) -> bool {
+        let policy = &self.policy;
+        let now = unsafe { ngx_current_msec };
+        let failed = failed || policy.max_latency.map_or(false, |max| latency > max);
+
+        let ejected = self.with_entry(peer.name().as_bytes(), true, |entry, ejected_count| {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => return None,
+            };
+
+            if now.wrapping_sub(entry.interval_start) >= policy.interval {
+                entry.interval_start = now;
+                entry.requests = 0;
+                entry.failures = 0;
+                entry.latency_sum = 0;
+            }
+            entry.last_used = now;
+            entry.requests += 1;
+            entry.latency_sum += latency as u64;
+            if failed {
+                entry.failures += 1;
+                entry.consecutive_failures += 1;
+            } else {
+                entry.consecutive_failures = 0;
+            }
+
+            if !failed || entry.is_ejected(now) {
+                return None;
+            }
+            let consecutive = policy.consecutive_failures != 0 && entry.consecutive_failures >= policy.consecutive_failures;
+            let error_rate = policy.max_error_rate.map_or(false, |max| {
+                entry.requests >= policy.min_requests as u64 && entry.failures as f64 / entry.requests as f64 > max
+            });
+            if !(consecutive || error_rate) || !ejected_count.allows_one_more(policy) {
+                return None;
+            }
+
+            entry.ejections += 1;
+            let time = (policy.base_ejection_time * entry.ejections as ngx_msec_t).min(policy.max_ejection_time);
+            // Zero means not ejected.
+            entry.ejected_until = now.wrapping_add(time).max(1);
+            entry.consecutive_failures = 0;
+            Some(time)
+        });
+
+        match ejected {
+            Some(time) => {
+                let log = unsafe { (*ngx_cycle).log };
+                ngx_log!(NGX_LOG_WARN, log, "outlier detection: peer {} ejected for {}ms", peer.name(), time);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Statistics of the tracked peers, in the order they were first seen.
+    pub fn stats(&self) -> Vec<OutlierStats> {
+        let now = unsafe { ngx_current_msec };
+        let interval = self.policy.interval;
+        self.with_entries(|entries| {
+            entries
+                .iter()
+                .map(|entry| {
+                    let current = now.wrapping_sub(entry.interval_start) < interval;
+                    let (requests, failures) = if current { (entry.requests, entry.failures) } else { (0, 0) };
+                    OutlierStats {
+                        name: String::from_utf8_lossy(entry.name()).into_owned(),
+                        requests,
+                        failures,
+                        consecutive_failures: entry.consecutive_failures,
+                        avg_latency: if requests > 0 { Some((entry.latency_sum / requests) as ngx_msec_t) } else { None },
+                        ejected_for: if entry.is_ejected(now) { Some(entry.ejected_until.wrapping_sub(now)) } else { None },
+                        ejections: entry.ejections,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    fn with_entries<R, F: FnOnce(&mut [Entry]) -> R>(&self, f: F) -> R {
+        let table = match unsafe { self.table.as_ref() } {
+            Some(table) => table,
+            None => return f(&mut []),
+        };
+        let shpool = unsafe { SlabPool::from_ngx_slab_pool(self.shpool) };
+        let _guard = shpool.lock();
+        f(unsafe { table.entries(self.capacity) })
+    }
+
+    // Run `f` with the entry of the peer `name`, added if `create` is set, and the number of
+    // ejected peers. A new entry takes the place of the least recently used one that is not
+    // ejected if the table is full.
+    fn with_entry<R, F>(&self, name: &[u8], create: bool, f: F) -> R
+    where
+        F: FnOnce(Option<&mut Entry>, Ejected) -> R,
+    {
+        let table = match unsafe { self.table.as_mut() } {
+            Some(table) => table,
+            None => return f(None, Ejected { ejected: 0, peers: 0 }),
+        };
+        let now = unsafe { ngx_current_msec };
+
+        let shpool = unsafe { SlabPool::from_ngx_slab_pool(self.shpool) };
+        let _guard = shpool.lock();
+
+        let mut entries = unsafe { table.entries(self.capacity) };
+        let index = match entries.iter().position(|entry| entry.name() == name) {
+            Some(index) => Some(index),
+            None if create && name.len() <= NAME_LEN => {
+                let index = if entries.len() < self.capacity {
+                    table.len = entries.len() + 1;
+                    entries = unsafe { table.entries(self.capacity) };
+                    Some(entries.len() - 1)
+                } else {
+                    // The entry used the longest ago, with wrapping times.
+                    entries
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, entry)| !entry.is_ejected(now))
+                        .max_by_key(|(_, entry)| now.wrapping_sub(entry.last_used))
+                        .map(|(index, _)| index)
+                };
+                if let Some(index) = index {
+                    let entry = &mut entries[index];
+                    *entry = unsafe { mem::zeroed() };
+                    entry.name[..name.len()].copy_from_slice(name);
+                    entry.name_len = name.len() as u32;
+                    entry.interval_start = now;
+                    entry.last_used = now;
+                }
+                index
+            }
+            None => None,
+        };
+
+        let ejected = Ejected {
+            ejected: entries.iter().filter(|entry| entry.is_ejected(now)).count(),
+            peers: entries.len(),
+        };
+        f(index.map(move |index| &mut entries[index]), ejected)
+    }
+}
+
+// Number of ejected peers, to enforce the maximum ejection percentage.
+#[derive(Clone, Copy)]
+struct Ejected {
+    ejected: usize,
+    peers: usize,
+}
+
+impl Ejected {
+    fn allows_one_more(&self, policy: &OutlierPolicy) -> bool {
+        let max = (self.peers * policy.max_ejection_percent as usize / 100).max(1);
+        self.ejected < max
+    }
+}
+
+unsafe extern "C" fn ngx_rs_outlier_init_zone(zone: *mut ngx_shm_zone_t, data: *mut c_void) -> ngx_int_t {
+    let detector = &mut *((*zone).data as *mut OutlierDetector);
+    let shpool = (*zone).shm.addr as *mut ngx_slab_pool_t;
+    detector.shpool = shpool;
+
+    // Keep the statistics of the previous configuration on reload, if the zone was not
+    // resized.
+    if let Some(previous) = (data as *const OutlierDetector).as_ref() {
+        if previous.capacity == detector.capacity {
+            detector.table = previous.table;
+            return NGX_OK as ngx_int_t;
+        }
+    }
+
+    if (*zone).shm.exists != 0 {
+        detector.table = (*shpool).data as *mut Table;
+        return NGX_OK as ngx_int_t;
+    }
+
+    let table = ngx_slab_calloc(shpool, Table::size(detector.capacity)) as *mut Table;
+    if table.is_null() {
+        return NGX_ERROR as ngx_int_t;
+    }
+    (*shpool).data = table as *mut c_void;
+    detector.table = table;
+    NGX_OK as ngx_int_t
+}
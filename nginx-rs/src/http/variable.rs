@@ -0,0 +1,195 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+
+use std::ops::BitOr;
+
+/// Define a static [variable] getter.
+///
+/// Getters are expected to take a single [`Request`] argument and return an `Option` of
+/// anything that can be viewed as bytes (`String`, `Vec<u8>`, `&str`, ...), where `None` means
+/// the variable is not found. The value is copied into the request pool.
+///
+/// Register the getter with [`Variables::add_handler`].
+///
+/// [variable]: https://nginx.org/en/docs/dev/development_guide.html#http_variables
+#[macro_export]
+macro_rules! http_variable {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(r: *mut $crate::__private::bindings::ngx_http_request_t, v: *mut $crate::__private::bindings::ngx_http_variable_value_t, _data: $crate::__private::bindings::uintptr_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*r).connection).log };
+            $crate::core::catch_panic(log, stringify!($name), $crate::__private::bindings::NGX_ERROR as $crate::__private::bindings::ngx_int_t, || {
+                let value = $handler(unsafe { $crate::http::Request::from_ngx_http_request(r) });
+                unsafe { $crate::http::set_variable_value(r, v, value) }
+            })
+        }
+    };
+}
+
+/// Flags of a registered variable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VariableFlags(pub ngx_uint_t);
+
+impl VariableFlags {
+    /// No flags.
+    pub const NONE: VariableFlags = VariableFlags(0);
+    /// The variable may be redefined by other modules (or the `set` directive).
+    pub const CHANGEABLE: VariableFlags = VariableFlags(NGX_HTTP_VAR_CHANGEABLE as ngx_uint_t);
+    /// The value is not cached, the getter is called every time the variable is evaluated.
+    pub const NOCACHEABLE: VariableFlags = VariableFlags(NGX_HTTP_VAR_NOCACHEABLE as ngx_uint_t);
+    /// The variable is only accessible by index, not by name.
+    pub const NOHASH: VariableFlags = VariableFlags(NGX_HTTP_VAR_NOHASH as ngx_uint_t);
+    /// A prefix variable, matching every variable whose name starts with the name (like `$http_`).
+    pub const PREFIX: VariableFlags = VariableFlags(NGX_HTTP_VAR_PREFIX as ngx_uint_t);
+}
+
+impl BitOr for VariableFlags {
+    type Output = VariableFlags;
+
+    fn bitor(self, rhs: VariableFlags) -> VariableFlags {
+        VariableFlags(self.0 | rhs.0)
+    }
+}
+
+type Getter = Box<dyn Fn(&mut Request, *mut ngx_http_variable_value_t) -> ngx_int_t>;
+
+/// Registration of [variables].
+///
+/// Variables must be added in the `preconfiguration` handler of a module, before Nginx
+/// resolves the variables used by the configuration.
+///
+/// [variables]: https://nginx.org/en/docs/dev/development_guide.html#http_variables
+pub struct Variables;
+
+impl Variables {
+    /// Add a variable whose value is computed by a Rust closure.
+    ///
+    /// The closure returns `None` if the variable is not found for a request. Its value is
+    /// copied into the request pool.
+    pub unsafe fn add<F, V>(cf: *mut ngx_conf_t, name: &str, flags: VariableFlags, getter: F) -> Status
+    where
+        F: Fn(&mut Request) -> Option<V> + 'static,
+        V: AsRef<[u8]>,
+    {
+        let getter: Getter = Box::new(move |request: &mut Request, v: *mut ngx_http_variable_value_t| {
+            let value = getter(request);
+            set_variable_value(request.as_ngx_http_request_mut(), v, value)
+        });
+
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        let getter: *mut Getter = match pool.alloc(getter) {
+            Some(getter) => getter.as_ptr(),
+            None => return ERROR,
+        };
+
+        Self::add_handler(cf, name, flags, Some(ngx_http_rs_variable_getter), getter as uintptr_t)
+    }
+
+    /// Add a variable with a raw getter, such as one defined with [`http_variable!`].
+    ///
+    /// `data` is passed to the getter as is.
+    pub unsafe fn add_handler(
+        cf: *mut ngx_conf_t,
+        name: &str,
+        flags: VariableFlags,
+        getter: ngx_http_get_variable_pt,
+        data: uintptr_t,
+    ) -> Status {
+        let v = Self::add_variable(cf, name, flags);
+        if v.is_null() {
+            return ERROR;
+        }
+
+        (*v).get_handler = getter;
+        (*v).data = data;
+        OK
+    }
+
+    /// Add a variable whose value is set by the module with [`Request::set_variable`].
+    ///
+    /// The variable is not found until a value is set for a request. Returns the index of the
+    /// variable for use with [`Request::set_variable`], or `None` on failure.
+    pub unsafe fn add_settable(cf: *mut ngx_conf_t, name: &str, flags: VariableFlags) -> Option<usize> {
+        if Self::add_handler(cf, name, flags, Some(ngx_http_rs_variable_not_found), 0) != OK {
+            return None;
+        }
+        Self::index(cf, name)
+    }
+
+    /// Get the index of a variable, for use with [`Request::variable_indexed`].
+    ///
+    /// Indexing a variable that is never defined is a configuration error reported by Nginx
+    /// at the end of configuration parsing.
+    pub unsafe fn index(cf: *mut ngx_conf_t, name: &str) -> Option<usize> {
+        let mut name = ngx_str_t { len: name.len(), data: name.as_ptr() as *mut u_char };
+        let index = ngx_http_get_variable_index(cf, &mut name);
+        if index == NGX_ERROR as ngx_int_t {
+            None
+        } else {
+            Some(index as usize)
+        }
+    }
+
+    unsafe fn add_variable(cf: *mut ngx_conf_t, name: &str, flags: VariableFlags) -> *mut ngx_http_variable_t {
+        let mut name = ngx_str_t { len: name.len(), data: name.as_ptr() as *mut u_char };
+        // `ngx_http_add_variable` copies the name into the configuration pool.
+        ngx_http_add_variable(cf, &mut name, flags.0)
+    }
+}
+
+/// Store the value returned by a variable getter.
+///
+/// Used by [`http_variable!`]. The value is copied into the request pool; `None` marks the
+/// variable as not found.
+#[doc(hidden)]
+pub unsafe fn set_variable_value<V: AsRef<[u8]>>(
+    r: *mut ngx_http_request_t,
+    v: *mut ngx_http_variable_value_t,
+    value: Option<V>,
+) -> ngx_int_t {
+    let value = match value {
+        Some(ref value) => value.as_ref(),
+        None => {
+            (*v).set_not_found(1);
+            return NGX_OK as ngx_int_t;
+        }
+    };
+
+    // The value length is a 28-bit field.
+    if value.len() >= 1 << 28 {
+        return NGX_ERROR as ngx_int_t;
+    }
+
+    let mut pool = Pool::from_ngx_pool((*r).pool);
+    let value = match NgxString::new_in(value, &mut pool) {
+        Some(value) => value.into_ngx_str(),
+        None => return NGX_ERROR as ngx_int_t,
+    };
+
+    (*v).data = value.data;
+    (*v).set_len(value.len as _);
+    (*v).set_valid(1);
+    (*v).set_no_cacheable(0);
+    (*v).set_not_found(0);
+
+    NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn ngx_http_rs_variable_getter(
+    r: *mut ngx_http_request_t,
+    v: *mut ngx_http_variable_value_t,
+    data: uintptr_t,
+) -> ngx_int_t {
+    let getter = &*(data as *const Getter);
+    getter(Request::from_ngx_http_request(r), v)
+}
+
+unsafe extern "C" fn ngx_http_rs_variable_not_found(
+    _r: *mut ngx_http_request_t,
+    v: *mut ngx_http_variable_value_t,
+    _data: uintptr_t,
+) -> ngx_int_t {
+    (*v).set_not_found(1);
+    NGX_OK as ngx_int_t
+}
@@ -0,0 +1,1030 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::request::Request;
+use crate::http::status::*;
+
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::rc::Rc;
+
+/// Default maximum size of a message received, see [`WebSocket::set_max_message_size`].
+pub const WEBSOCKET_DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+// Appended to the key of the client to compute `Sec-WebSocket-Accept` (RFC 6455).
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Size of the buffer data is read with.
+const READ_SIZE: usize = 4096;
+
+/// Time to wait for the client to answer a close frame.
+const CLOSE_TIMEOUT: Msec = Msec::from_secs(5);
+
+/// Close code of a normal closure.
+pub const WEBSOCKET_CLOSE_NORMAL: u16 = 1000;
+/// Close code of an endpoint going away, such as on timeout.
+pub const WEBSOCKET_CLOSE_GOING_AWAY: u16 = 1001;
+/// Close code of a protocol error.
+pub const WEBSOCKET_CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// Close code of a text message that is not UTF-8.
+pub const WEBSOCKET_CLOSE_INVALID_DATA: u16 = 1007;
+/// Close code of a message too large to process.
+pub const WEBSOCKET_CLOSE_TOO_BIG: u16 = 1009;
+
+/// Opcode of a WebSocket [`Frame`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Opcode {
+    /// Continuation of a fragmented message.
+    Continuation,
+    /// Text message.
+    Text,
+    /// Binary message.
+    Binary,
+    /// Close the connection.
+    Close,
+    /// Ping, answered with a pong.
+    Ping,
+    /// Pong.
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(opcode: u8) -> Option<Opcode> {
+        match opcode {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    /// Is this a control frame (close, ping or pong)?
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A WebSocket frame ([RFC 6455]).
+///
+/// [RFC 6455]: https://datatracker.ietf.org/doc/html/rfc6455#section-5.2
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// Is this the last frame of its message?
+    pub fin: bool,
+    /// Opcode of the frame.
+    pub opcode: Opcode,
+    /// Was the frame masked? Frames sent by clients must be.
+    pub masked: bool,
+    /// Payload, unmasked.
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// An unfragmented frame.
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Frame {
+        Frame { fin: true, opcode, masked: false, payload }
+    }
+
+    /// Append the encoded frame to `out`, masked with `mask` if given, as frames sent by
+    /// clients must be.
+    pub fn encode(&self, mask: Option<[u8; 4]>, out: &mut Vec<u8>) {
+        out.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        let len = self.payload.len();
+        if len < 126 {
+            out.push(mask_bit | len as u8);
+        } else if len <= u16::MAX as usize {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        match mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                out.extend(self.payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+    }
+
+    /// Decode the frame at the start of `data`, returning its length and the frame, or `None`
+    /// if the frame is incomplete.
+    ///
+    /// Frames with a payload larger than `max_payload` are rejected before they are complete.
+    pub fn decode(data: &[u8], max_payload: usize) -> Result<Option<(usize, Frame)>, WebSocketError> {
+        if data.len() < 2 {
+            return Ok(None);
+        }
+        let fin = data[0] & 0x80 != 0;
+        // No extensions are negotiated, so the reserved bits must be clear.
+        if data[0] & 0x70 != 0 {
+            return Err(WebSocketError::Protocol);
+        }
+        let opcode = Opcode::from_u8(data[0] & 0x0f).ok_or(WebSocketError::Protocol)?;
+        let masked = data[1] & 0x80 != 0;
+
+        let (len, mut pos) = match data[1] & 0x7f {
+            126 if data.len() < 4 => return Ok(None),
+            126 => (u16::from_be_bytes([data[2], data[3]]) as u64, 4),
+            127 if data.len() < 10 => return Ok(None),
+            127 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data[2..10]);
+                (u64::from_be_bytes(bytes), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(WebSocketError::Protocol);
+        }
+        if len > max_payload as u64 {
+            return Err(WebSocketError::TooLarge);
+        }
+        let len = len as usize;
+
+        let mask = if masked {
+            if data.len() < pos + 4 {
+                return Ok(None);
+            }
+            let mask = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+            pos += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        if data.len() < pos + len {
+            return Ok(None);
+        }
+
+        let mut payload = data[pos..pos + len].to_vec();
+        if let Some(mask) = mask {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= mask[i % 4];
+            }
+        }
+        Ok(Some((pos + len, Frame { fin, opcode, masked, payload })))
+    }
+}
+
+/// A WebSocket message, made of one or more frames.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Message {
+    /// Text message.
+    Text(String),
+    /// Binary message.
+    Binary(Vec<u8>),
+    /// Ping, with its payload. Pings received are answered automatically.
+    Ping(Vec<u8>),
+    /// Pong, with its payload.
+    Pong(Vec<u8>),
+    /// Close, with the code and reason, if any. Close frames received are answered
+    /// automatically.
+    Close(Option<(u16, String)>),
+}
+
+impl Message {
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data),
+            Message::Ping(data) => Frame::new(Opcode::Ping, data),
+            Message::Pong(data) => Frame::new(Opcode::Pong, data),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+            Message::Close(Some((code, reason))) => {
+                // Control frames are limited to 125 bytes, and the reason must stay valid
+                // UTF-8, so it is cut at a character boundary.
+                let mut len = reason.len().min(123);
+                while !reason.is_char_boundary(len) {
+                    len -= 1;
+                }
+                let mut payload = code.to_be_bytes().to_vec();
+                payload.extend_from_slice(&reason.as_bytes()[..len]);
+                Frame::new(Opcode::Close, payload)
+            }
+        }
+    }
+}
+
+/// Event of a [`WebSocket`], passed to its handler.
+#[derive(Debug)]
+pub enum WebSocketEvent {
+    /// A message was received.
+    Message(Message),
+    /// The connection is closed: after a close handshake (`Ok`), or because of an error. This
+    /// is the last event of a WebSocket.
+    Closed(Result<(), WebSocketError>),
+}
+
+/// Error of a [`WebSocket`] connection.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WebSocketError {
+    /// The client broke the protocol, such as with an unmasked frame.
+    Protocol,
+    /// A text message is not valid UTF-8.
+    InvalidUtf8,
+    /// A message is larger than the maximum message size.
+    TooLarge,
+    /// Sending or receiving failed, or the client closed the connection without a close
+    /// frame.
+    Io,
+    /// Nothing was received for longer than the idle timeout.
+    Timeout,
+}
+
+impl WebSocketError {
+    /// Close code sent to the client for the error.
+    pub fn close_code(&self) -> u16 {
+        match self {
+            WebSocketError::Protocol => WEBSOCKET_CLOSE_PROTOCOL_ERROR,
+            WebSocketError::InvalidUtf8 => WEBSOCKET_CLOSE_INVALID_DATA,
+            WebSocketError::TooLarge => WEBSOCKET_CLOSE_TOO_BIG,
+            WebSocketError::Io | WebSocketError::Timeout => WEBSOCKET_CLOSE_GOING_AWAY,
+        }
+    }
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebSocketError::Protocol => write!(f, "protocol error"),
+            WebSocketError::InvalidUtf8 => write!(f, "invalid UTF-8 in text message"),
+            WebSocketError::TooLarge => write!(f, "message too large"),
+            WebSocketError::Io => write!(f, "connection error"),
+            WebSocketError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl Error for WebSocketError {}
+
+/// Error of a WebSocket handshake, see [`WebSocket::accept`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HandshakeError {
+    /// The request is not a `GET` request over HTTP/1.1.
+    Method,
+    /// The request doesn't ask for an upgrade to WebSocket with `Upgrade` and `Connection`.
+    NotUpgrade,
+    /// `Sec-WebSocket-Version` is not 13.
+    Version,
+    /// `Sec-WebSocket-Key` is missing or invalid.
+    Key,
+    /// The response could not be sent.
+    Internal,
+}
+
+impl HandshakeError {
+    /// Status to finalize the request with.
+    pub fn status(&self) -> HTTPStatus {
+        match self {
+            HandshakeError::Internal => HTTP_INTERNAL_SERVER_ERROR,
+            _ => HTTP_BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Method => write!(f, "not a GET request over HTTP/1.1"),
+            HandshakeError::NotUpgrade => write!(f, "not a WebSocket upgrade"),
+            HandshakeError::Version => write!(f, "unsupported WebSocket version"),
+            HandshakeError::Key => write!(f, "invalid Sec-WebSocket-Key"),
+            HandshakeError::Internal => write!(f, "failed to send the handshake response"),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+type Handler = Box<dyn FnMut(&mut WebSocket, WebSocketEvent)>;
+
+/// A WebSocket connection ([RFC 6455]) terminated by the module, upgraded from a request.
+///
+/// The connection is driven by a handler receiving its [`WebSocketEvent`]s with the
+/// connection, on which it sends messages or closes the connection, as with a
+/// [`PeerConnection`](crate::event::PeerConnection). Pings and close frames are answered
+/// automatically. Messages sent are buffered, and sent as soon as the client can accept
+/// them.
+///
+/// ```ignore
+/// http_request_handler!(ngx_http_control_handler, |request: &mut Request| {
+///     match WebSocket::accept(request, None, |ws: &mut WebSocket, event| {
+///         if let WebSocketEvent::Message(Message::Text(command)) = event {
+///             ws.send_text(&run(&command));
+///         }
+///     }) {
+///         Ok(_) => DONE,
+///         Err(err) => err.status().into(),
+///     }
+/// });
+/// ```
+///
+/// [RFC 6455]: https://datatracker.ietf.org/doc/html/rfc6455
+pub struct WebSocket {
+    request: *mut ngx_http_request_t,
+    input: Vec<u8>,
+    out: Vec<u8>,
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message_size: usize,
+    idle_timeout: Option<Msec>,
+    close_sent: bool,
+    close_received: bool,
+    closing: bool,
+    closed: bool,
+    finalized: bool,
+    dispatching: bool,
+    rc: ngx_int_t,
+    handle: Rc<Cell<*mut WebSocket>>,
+    handler: Option<Handler>,
+}
+
+/// Handle to a [`WebSocket`], to send messages outside of its handler, such as from a
+/// timer.
+///
+/// The handle does not keep the connection open: once the connection is closed, the handle
+/// is empty.
+#[derive(Clone)]
+pub struct WebSocketHandle(Rc<Cell<*mut WebSocket>>);
+
+impl WebSocketHandle {
+    /// Call `f` with the connection, or return `None` if it is closed.
+    ///
+    /// `None` is also returned while the handler of the connection runs, such as when it is
+    /// called from the handler, which has the connection already.
+    pub fn with<R>(&self, f: impl FnOnce(&mut WebSocket) -> R) -> Option<R> {
+        let ws = self.0.get();
+        if ws.is_null() || unsafe { (*ws).dispatching } {
+            return None;
+        }
+
+        unsafe {
+            let c = (*(*ws).request).connection;
+            let result = f(&mut *ws);
+            WebSocket::release(ws);
+            ngx_http_run_posted_requests(c);
+            Some(result)
+        }
+    }
+
+    /// Is the connection still open?
+    pub fn is_open(&self) -> bool {
+        !self.0.get().is_null()
+    }
+}
+
+impl WebSocket {
+    /// Validate the WebSocket handshake of a request, send the `101 Switching Protocols`
+    /// response and hand the connection over to `handler`.
+    ///
+    /// `protocol` is the subprotocol selected, if any, from those the client offered in
+    /// `Sec-WebSocket-Protocol`. On success, the content handler must return [`DONE`]; on
+    /// error, it should finalize the request with [`HandshakeError::status`].
+    ///
+    /// Only HTTP/1.1 is supported, not WebSockets over HTTP/2 ([RFC 8441]).
+    ///
+    /// [RFC 8441]: https://datatracker.ietf.org/doc/html/rfc8441
+    pub fn accept<F>(request: &mut Request, protocol: Option<&str>, handler: F) -> Result<WebSocketHandle, HandshakeError>
+    where
+        F: FnMut(&mut WebSocket, WebSocketEvent) + 'static,
+    {
+        let accept = handshake_accept(request)?;
+
+        let mut pool = request.pool();
+        if !pool.get_local::<WebSocket>().is_null() {
+            return Err(HandshakeError::Internal);
+        }
+        if !request.set_header("Upgrade", "websocket") || !request.set_header("Sec-WebSocket-Accept", &accept) {
+            return Err(HandshakeError::Internal);
+        }
+        if let Some(protocol) = protocol {
+            if !request.set_header("Sec-WebSocket-Protocol", protocol) {
+                return Err(HandshakeError::Internal);
+            }
+        }
+
+        let r = request.as_ngx_http_request_mut();
+        unsafe {
+            // The header filter adds `Connection: upgrade` to 101 responses.
+            (*r).headers_out.status = NGX_HTTP_SWITCHING_PROTOCOLS as ngx_uint_t;
+            (*r).headers_out.content_length_n = -1;
+            (*r).set_keepalive(0);
+
+            let rc = ngx_http_send_header(r);
+            if rc == NGX_ERROR as ngx_int_t || rc > NGX_OK as ngx_int_t {
+                return Err(HandshakeError::Internal);
+            }
+            if ngx_http_send_special(r, NGX_HTTP_FLUSH as ngx_uint_t) == NGX_ERROR as ngx_int_t {
+                return Err(HandshakeError::Internal);
+            }
+        }
+
+        let ws = pool.insert_local(WebSocket {
+            request: r,
+            input: Vec::new(),
+            out: Vec::new(),
+            fragments: None,
+            max_message_size: WEBSOCKET_DEFAULT_MAX_MESSAGE_SIZE,
+            idle_timeout: None,
+            close_sent: false,
+            close_received: false,
+            closing: false,
+            closed: false,
+            finalized: false,
+            dispatching: false,
+            rc: NGX_OK as ngx_int_t,
+            handle: Rc::new(Cell::new(ptr::null_mut())),
+            handler: Some(Box::new(handler)),
+        });
+        if ws.is_null() {
+            return Err(HandshakeError::Internal);
+        }
+
+        unsafe {
+            (*ws).handle.set(ws);
+            let handle = WebSocketHandle((*ws).handle.clone());
+
+            // Frames sent along with the request.
+            let header_in = (*r).header_in;
+            if !header_in.is_null() && (*header_in).pos < (*header_in).last {
+                let len = (*header_in).last as usize - (*header_in).pos as usize;
+                (*ws).input.extend_from_slice(std::slice::from_raw_parts((*header_in).pos, len));
+                (*header_in).pos = (*header_in).last;
+            }
+
+            let main = (*r).main;
+            (*main).set_count((*main).count() + 1);
+            (*r).read_event_handler = Some(ngx_http_rs_websocket_read_handler);
+            (*r).write_event_handler = Some(ngx_http_rs_websocket_write_handler);
+
+            // Events are not dispatched before `accept` returns, as the caller does not
+            // expect its handler to run yet; they are posted instead.
+            let rev = (*(*r).connection).read;
+            if (*rev).timer_set() != 0 {
+                ngx_del_timer(rev);
+            }
+            ngx_post_event(rev, ptr::addr_of_mut!(ngx_posted_events));
+
+            Ok(handle)
+        }
+    }
+
+    /// Set the maximum size of a message received, defaulting to
+    /// [`WEBSOCKET_DEFAULT_MAX_MESSAGE_SIZE`]. Larger messages close the connection with
+    /// [`WebSocketError::TooLarge`].
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Close the connection with [`WebSocketError::Timeout`] when nothing is received for
+    /// `timeout`, or never with `None`, the default. Clients can be kept active by sending
+    /// them pings.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Msec>) {
+        self.idle_timeout = timeout;
+        unsafe {
+            let rev = (*(*self.request).connection).read;
+            match timeout {
+                Some(timeout) => ngx_add_timer(rev, timeout.as_msec()),
+                None if (*rev).timer_set() != 0 => ngx_del_timer(rev),
+                None => {}
+            }
+        }
+    }
+
+    /// The request the connection was upgraded from.
+    pub fn request(&mut self) -> &mut Request {
+        unsafe { Request::from_ngx_http_request(self.request) }
+    }
+
+    /// Is the connection open, and not closing?
+    pub fn is_open(&self) -> bool {
+        !self.closed && !self.close_sent
+    }
+
+    /// Number of bytes written but not sent yet.
+    pub fn pending(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Send a message. Nothing is sent once a close frame was sent.
+    pub fn send(&mut self, message: Message) {
+        if self.closed || self.close_sent {
+            return;
+        }
+        if let Message::Close(_) = message {
+            self.close_sent = true;
+        }
+        message.into_frame().encode(None, &mut self.out);
+        unsafe { self.flush() };
+    }
+
+    /// Send a text message.
+    pub fn send_text(&mut self, text: &str) {
+        self.send(Message::Text(String::from(text)));
+    }
+
+    /// Send a binary message.
+    pub fn send_binary(&mut self, data: &[u8]) {
+        self.send(Message::Binary(data.to_vec()));
+    }
+
+    /// Send a ping, to keep the connection active or measure latency.
+    pub fn ping(&mut self, data: &[u8]) {
+        self.send(Message::Ping(data.to_vec()));
+    }
+
+    /// Start the close handshake with a code, such as [`WEBSOCKET_CLOSE_NORMAL`], and a
+    /// reason. The connection is closed once the client answers, or after a few seconds.
+    pub fn close(&mut self, code: u16, reason: &str) {
+        if self.closed || self.close_sent {
+            return;
+        }
+        self.send(Message::Close(Some((code, String::from(reason)))));
+        unsafe {
+            if self.close_received {
+                self.closing = true;
+                self.flush();
+            } else if !self.closed {
+                ngx_add_timer((*(*self.request).connection).read, CLOSE_TIMEOUT.as_msec());
+            }
+        }
+    }
+
+    // Finalize the request of a closed connection, unless one of its events is being handled.
+    // The connection, which is in the request pool, may be freed.
+    unsafe fn release(ws: *mut WebSocket) {
+        if (*ws).closed && !(*ws).dispatching && !(*ws).finalized {
+            (*ws).finalized = true;
+            let (r, rc) = ((*ws).request, (*ws).rc);
+            ngx_http_finalize_request(r, rc);
+        }
+    }
+
+    unsafe fn dispatch(&mut self, event: WebSocketEvent) {
+        if let Some(mut handler) = self.handler.take() {
+            let dispatching = mem::replace(&mut self.dispatching, true);
+            handler(self, event);
+            self.dispatching = dispatching;
+            self.handler = Some(handler);
+        }
+    }
+
+    // Close with a final event for the handler.
+    unsafe fn finish(&mut self, result: Result<(), WebSocketError>) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        self.handle.set(ptr::null_mut());
+        if result.is_err() {
+            self.rc = NGX_ERROR as ngx_int_t;
+        }
+        self.dispatch(WebSocketEvent::Closed(result));
+    }
+
+    // Fail because of the client: tell it why, without waiting for an answer.
+    unsafe fn fail(&mut self, err: WebSocketError) {
+        if !self.close_sent && err != WebSocketError::Io {
+            self.close_sent = true;
+            Message::Close(Some((err.close_code(), err.to_string()))).into_frame().encode(None, &mut self.out);
+            self.send_out();
+        }
+        self.finish(Err(err));
+    }
+
+    unsafe fn on_read(&mut self) {
+        let c = (*self.request).connection;
+        let rev = (*c).read;
+        if (*rev).timedout() != 0 {
+            (*c).set_timedout(1);
+            return if self.close_sent { self.finish(Ok(())) } else { self.fail(WebSocketError::Timeout) };
+        }
+
+        let mut buf = [0u8; READ_SIZE];
+        loop {
+            // Data received before the upgrade is processed first.
+            if !self.input.is_empty() {
+                self.process_input();
+                if self.closed {
+                    return;
+                }
+            }
+
+            let n = (*c).recv.unwrap()(c, buf.as_mut_ptr(), buf.len());
+            if n == NGX_AGAIN as isize {
+                break;
+            }
+            if n == NGX_ERROR as isize || n == 0 {
+                (*c).set_error(1);
+                return self.finish(if self.close_received { Ok(()) } else { Err(WebSocketError::Io) });
+            }
+
+            if let Some(timeout) = self.idle_timeout {
+                if !self.close_sent {
+                    ngx_add_timer(rev, timeout.as_msec());
+                }
+            }
+            // Nothing is processed after a close frame: data still arriving is discarded
+            // rather than buffered until the connection closes.
+            if !self.close_received {
+                self.input.extend_from_slice(&buf[..n as usize]);
+            }
+        }
+
+        if ngx_handle_read_event(rev, 0) != NGX_OK as ngx_int_t {
+            self.finish(Err(WebSocketError::Io));
+        }
+    }
+
+    unsafe fn process_input(&mut self) {
+        let mut pos = 0;
+        while !self.closed && !self.close_received {
+            let frame = match Frame::decode(&self.input[pos..], self.max_message_size) {
+                Ok(Some((len, frame))) => {
+                    pos += len;
+                    frame
+                }
+                Ok(None) => break,
+                Err(err) => return self.fail(err),
+            };
+            if !frame.masked {
+                return self.fail(WebSocketError::Protocol);
+            }
+            self.on_frame(frame);
+        }
+        if self.close_received {
+            self.input.clear();
+        } else if !self.closed {
+            self.input.drain(..pos);
+        }
+    }
+
+    unsafe fn on_frame(&mut self, frame: Frame) {
+        let (opcode, payload) = match frame.opcode {
+            Opcode::Ping => {
+                if !self.close_sent {
+                    Frame::new(Opcode::Pong, frame.payload.clone()).encode(None, &mut self.out);
+                    self.flush();
+                }
+                return self.dispatch(WebSocketEvent::Message(Message::Ping(frame.payload)));
+            }
+            Opcode::Pong => return self.dispatch(WebSocketEvent::Message(Message::Pong(frame.payload))),
+            Opcode::Close => return self.on_close(frame.payload),
+            Opcode::Text | Opcode::Binary if self.fragments.is_some() => return self.fail(WebSocketError::Protocol),
+            Opcode::Text | Opcode::Binary if !frame.fin => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                return;
+            }
+            Opcode::Text | Opcode::Binary => (frame.opcode, frame.payload),
+            Opcode::Continuation => {
+                let (opcode, mut payload) = match self.fragments.take() {
+                    Some(fragments) => fragments,
+                    None => return self.fail(WebSocketError::Protocol),
+                };
+                if payload.len() + frame.payload.len() > self.max_message_size {
+                    return self.fail(WebSocketError::TooLarge);
+                }
+                payload.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    self.fragments = Some((opcode, payload));
+                    return;
+                }
+                (opcode, payload)
+            }
+        };
+
+        let message = match opcode {
+            Opcode::Text => match String::from_utf8(payload) {
+                Ok(text) => Message::Text(text),
+                Err(_) => return self.fail(WebSocketError::InvalidUtf8),
+            },
+            _ => Message::Binary(payload),
+        };
+        self.dispatch(WebSocketEvent::Message(message));
+    }
+
+    unsafe fn on_close(&mut self, payload: Vec<u8>) {
+        let close = match payload.len() {
+            0 => None,
+            1 => return self.fail(WebSocketError::Protocol),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !is_valid_close_code(code) {
+                    return self.fail(WebSocketError::Protocol);
+                }
+                match String::from_utf8(payload[2..].to_vec()) {
+                    Ok(reason) => Some((code, reason)),
+                    Err(_) => return self.fail(WebSocketError::InvalidUtf8),
+                }
+            }
+        };
+        self.close_received = true;
+
+        self.dispatch(WebSocketEvent::Message(Message::Close(close.clone())));
+        if self.closed {
+            return;
+        }
+        if !self.close_sent {
+            // Echo the code, as recommended.
+            self.close_sent = true;
+            let code = close.map_or(WEBSOCKET_CLOSE_NORMAL, |(code, _)| code);
+            Message::Close(Some((code, String::new()))).into_frame().encode(None, &mut self.out);
+        }
+        self.closing = true;
+        self.flush();
+    }
+
+    unsafe fn on_write(&mut self) {
+        let c = (*self.request).connection;
+        if (*(*c).write).timedout() != 0 {
+            (*c).set_timedout(1);
+            return self.finish(Err(WebSocketError::Timeout));
+        }
+        self.flush();
+    }
+
+    // Send the data written, once the response header is sent.
+    unsafe fn flush(&mut self) {
+        let r = self.request;
+        if !(*r).out.is_null() {
+            let rc = ngx_http_output_filter(r, ptr::null_mut());
+            if rc == NGX_ERROR as ngx_int_t {
+                return self.finish(Err(WebSocketError::Io));
+            }
+            if !(*r).out.is_null() {
+                return self.wait_writable();
+            }
+        }
+
+        if !self.send_out() {
+            return self.finish(Err(WebSocketError::Io));
+        }
+        if !self.out.is_empty() {
+            return self.wait_writable();
+        }
+
+        let wev = (*(*r).connection).write;
+        if (*wev).timer_set() != 0 {
+            ngx_del_timer(wev);
+        }
+        if self.closing {
+            self.finish(Ok(()));
+        }
+    }
+
+    // Send as much data as the connection accepts, returning false on error.
+    unsafe fn send_out(&mut self) -> bool {
+        let c = (*self.request).connection;
+        let mut sent = 0;
+        while sent < self.out.len() {
+            let out = &mut self.out[sent..];
+            let n = (*c).send.unwrap()(c, out.as_mut_ptr(), out.len());
+            if n == NGX_ERROR as isize {
+                (*c).set_error(1);
+                return false;
+            }
+            if n == NGX_AGAIN as isize || n == 0 {
+                break;
+            }
+            sent += n as usize;
+        }
+        self.out.drain(..sent);
+        true
+    }
+
+    unsafe fn wait_writable(&mut self) {
+        let r = self.request;
+        let wev = (*(*r).connection).write;
+        let clcf = *(*r).loc_conf.add(ngx_http_core_module.ctx_index) as *mut ngx_http_core_loc_conf_t;
+
+        if (*wev).timer_set() == 0 {
+            ngx_add_timer(wev, (*clcf).send_timeout);
+        }
+        if ngx_handle_write_event(wev, (*clcf).send_lowat) != NGX_OK as ngx_int_t {
+            self.finish(Err(WebSocketError::Io));
+        }
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        self.handle.set(ptr::null_mut());
+    }
+}
+
+// Can a close frame carry `code` (RFC 6455, section 7.4)? Codes such as 1005 and 1006 only
+// stand for a missing code or a lost connection, and the others are reserved.
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+// `Sec-WebSocket-Accept` of a valid handshake request.
+fn handshake_accept(request: &Request) -> Result<String, HandshakeError> {
+    unsafe {
+        let r = request.as_ngx_http_request();
+        if (*r).method != NGX_HTTP_GET as ngx_uint_t
+            || (*r).http_version != NGX_HTTP_VERSION_11 as ngx_uint_t
+            || (*r).header_sent() != 0
+            || !request.is_main()
+        {
+            return Err(HandshakeError::Method);
+        }
+    }
+
+    let has_token = |header: &str, token: &str| {
+        request
+            .get_header(header)
+            .map_or(false, |value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    };
+    if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+        return Err(HandshakeError::NotUpgrade);
+    }
+    if request.get_header("sec-websocket-version").as_deref().map(str::trim) != Some("13") {
+        return Err(HandshakeError::Version);
+    }
+
+    let key = request.get_header("sec-websocket-key").ok_or(HandshakeError::Key)?;
+    let key = key.trim();
+    if decode_base64(key).map_or(true, |nonce| nonce.len() != 16) {
+        return Err(HandshakeError::Key);
+    }
+
+    let mut digest = [0u8; 20];
+    unsafe {
+        let mut sha1: ngx_sha1_t = mem::zeroed();
+        ngx_sha1_init(&mut sha1);
+        ngx_sha1_update(&mut sha1, key.as_ptr() as *const c_void, key.len());
+        ngx_sha1_update(&mut sha1, ACCEPT_GUID.as_ptr() as *const c_void, ACCEPT_GUID.len());
+        ngx_sha1_final(digest.as_mut_ptr(), &mut sha1);
+    }
+    Ok(encode_base64(&digest))
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = vec![0u8; (data.len() + 2) / 3 * 4];
+    let mut dst = ngx_str_t { len: 0, data: out.as_mut_ptr() };
+    let mut src = ngx_str_t { len: data.len(), data: data.as_ptr() as *mut u_char };
+    unsafe { ngx_encode_base64(&mut dst, &mut src) };
+    out.truncate(dst.len);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![0u8; (text.len() + 3) / 4 * 3];
+    let mut dst = ngx_str_t { len: 0, data: out.as_mut_ptr() };
+    let mut src = ngx_str_t { len: text.len(), data: text.as_ptr() as *mut u_char };
+    if unsafe { ngx_decode_base64(&mut dst, &mut src) } != NGX_OK as ngx_int_t {
+        return None;
+    }
+    out.truncate(dst.len);
+    Some(out)
+}
+
+unsafe extern "C" fn ngx_http_rs_websocket_read_handler(r: *mut ngx_http_request_t) {
+    let ws = Request::from_ngx_http_request(r).pool().get_local::<WebSocket>();
+    if ws.is_null() || (*ws).closed {
+        return;
+    }
+    (*ws).on_read();
+    WebSocket::release(ws);
+}
+
+unsafe extern "C" fn ngx_http_rs_websocket_write_handler(r: *mut ngx_http_request_t) {
+    let ws = Request::from_ngx_http_request(r).pool().get_local::<WebSocket>();
+    if ws.is_null() || (*ws).closed {
+        return;
+    }
+    (*ws).on_write();
+    WebSocket::release(ws);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: usize = 1 << 20;
+
+    fn round_trip(frame: &Frame, mask: Option<[u8; 4]>) -> Frame {
+        let mut data = Vec::new();
+        frame.encode(mask, &mut data);
+        let (len, decoded) = Frame::decode(&data, MAX).unwrap().unwrap();
+        assert_eq!(len, data.len());
+        decoded
+    }
+
+    #[test]
+    fn encode_decode() {
+        let frame = Frame::new(Opcode::Text, b"hello".to_vec());
+        let mut data = Vec::new();
+        frame.encode(None, &mut data);
+        assert_eq!(data, b"\x81\x05hello");
+        assert_eq!(round_trip(&frame, None), frame);
+
+        let fragment = Frame { fin: false, opcode: Opcode::Binary, masked: false, payload: vec![1, 2, 3] };
+        assert_eq!(round_trip(&fragment, None), fragment);
+    }
+
+    #[test]
+    fn extended_lengths() {
+        for &len in &[125, 126, 65535, 65536] {
+            let frame = Frame::new(Opcode::Binary, vec![7; len]);
+            let mut data = Vec::new();
+            frame.encode(None, &mut data);
+            let header = match len {
+                0..=125 => 2,
+                126..=65535 => 4,
+                _ => 10,
+            };
+            assert_eq!(data.len(), header + len);
+            assert_eq!(round_trip(&frame, None), frame);
+        }
+    }
+
+    #[test]
+    fn masking() {
+        let frame = Frame::new(Opcode::Text, b"Hello".to_vec());
+        let mut data = Vec::new();
+        frame.encode(Some([0x37, 0xfa, 0x21, 0x3d]), &mut data);
+        // The masked example of RFC 6455, section 5.7.
+        assert_eq!(data, [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+
+        let decoded = round_trip(&frame, Some([0x37, 0xfa, 0x21, 0x3d]));
+        assert!(decoded.masked);
+        assert_eq!(decoded.payload, b"Hello");
+    }
+
+    #[test]
+    fn incomplete() {
+        let mut data = Vec::new();
+        Frame::new(Opcode::Binary, vec![0; 300]).encode(Some([1, 2, 3, 4]), &mut data);
+        for len in &[0, 1, 3, 5, 7, data.len() - 1] {
+            assert_eq!(Frame::decode(&data[..*len], MAX).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn control_frames() {
+        let mut data = Vec::new();
+        Frame::new(Opcode::Ping, vec![0; 126]).encode(None, &mut data);
+        assert_eq!(Frame::decode(&data, MAX), Err(WebSocketError::Protocol));
+
+        let fragmented = Frame { fin: false, opcode: Opcode::Pong, masked: false, payload: Vec::new() };
+        let mut data = Vec::new();
+        fragmented.encode(None, &mut data);
+        assert_eq!(Frame::decode(&data, MAX), Err(WebSocketError::Protocol));
+
+        let close = Message::Close(Some((WEBSOCKET_CLOSE_NORMAL, "x".repeat(200)))).into_frame();
+        assert_eq!(close.payload.len(), 125);
+
+        // Two-byte characters: the reason is cut before the one crossing the limit.
+        let close = Message::Close(Some((WEBSOCKET_CLOSE_NORMAL, "é".repeat(100)))).into_frame();
+        assert_eq!(close.payload.len(), 124);
+        assert!(std::str::from_utf8(&close.payload[2..]).is_ok());
+    }
+
+    #[test]
+    fn reserved_bits_and_opcodes() {
+        assert_eq!(Frame::decode(b"\xc1\x00", MAX), Err(WebSocketError::Protocol));
+        assert_eq!(Frame::decode(b"\x83\x00", MAX), Err(WebSocketError::Protocol));
+    }
+
+    #[test]
+    fn max_payload() {
+        let mut data = Vec::new();
+        Frame::new(Opcode::Binary, vec![0; 100]).encode(None, &mut data);
+        assert_eq!(Frame::decode(&data, 99), Err(WebSocketError::TooLarge));
+        // Rejected from the header alone.
+        assert_eq!(Frame::decode(&data[..2], 99), Err(WebSocketError::TooLarge));
+        assert!(Frame::decode(&data, 100).unwrap().is_some());
+    }
+
+    #[test]
+    fn close_codes() {
+        for &code in &[1000, 1001, 1002, 1003, 1007, 1011, 3000, 4999] {
+            assert!(is_valid_close_code(code), "{}", code);
+        }
+        for &code in &[0, 999, 1004, 1005, 1006, 1015, 2999, 5000] {
+            assert!(!is_valid_close_code(code), "{}", code);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::event::*;
+use crate::http::request::Request;
+
+use std::os::raw::c_void;
+use std::ptr;
+
+/// What a [`ResponseWriter`] producer wants to happen next.
+pub enum Next {
+    /// Call the producer again as soon as the client is able to accept more data.
+    Continue,
+    /// Nothing to write right now. The producer will be resumed by [`ResponseWriter::resume`].
+    Wait,
+    /// The response is complete. The last buffer is sent if the producer did not already do so.
+    Done,
+    /// Abort the response, finalizing the request with the given status.
+    Error(Status),
+}
+
+type Producer = Box<dyn FnMut(&mut Request, &mut ResponseWriter) -> Next>;
+
+/// Streaming writer for the [response body].
+///
+/// The writer repeatedly calls a producer closure which writes chunks of the body. When the
+/// client can't keep up (the write filter returns `NGX_AGAIN`) the producer is not called again
+/// until the connection becomes writable, so buffered output never grows without bound.
+///
+/// [response body]: https://nginx.org/en/docs/dev/development_guide.html#http_response_body
+pub struct ResponseWriter {
+    request: *mut ngx_http_request_t,
+    blocked: bool,
+    finished: bool,
+    last_rc: ngx_int_t,
+    // Buffers sent in full, to reuse, and buffers still being sent.
+    free: *mut ngx_chain_t,
+    busy: *mut ngx_chain_t,
+}
+
+struct WriterState {
+    writer: ResponseWriter,
+    producer: Producer,
+    running: bool,
+}
+
+enum Action {
+    Wait,
+    Block,
+    Finalize(ngx_int_t),
+}
+
+impl ResponseWriter {
+    /// Start streaming the response body of a request.
+    ///
+    /// The response header must already have been sent. The request is kept alive until the
+    /// producer returns [`Next::Done`] or [`Next::Error`]; the content handler should return the
+    /// resulting status (normally [`DONE`]) to Nginx.
+    pub fn start<F>(request: &mut Request, producer: F) -> Status
+    where
+        F: FnMut(&mut Request, &mut ResponseWriter) -> Next + 'static,
+    {
+        let mut pool = request.pool();
+        if !pool.get_local::<WriterState>().is_null() {
+            return ERROR;
+        }
+
+        let r = request.as_ngx_http_request_mut();
+        let state = pool.insert_local(WriterState {
+            writer: ResponseWriter {
+                request: r,
+                blocked: false,
+                finished: false,
+                last_rc: NGX_OK as ngx_int_t,
+                free: ptr::null_mut(),
+                busy: ptr::null_mut(),
+            },
+            producer: Box::new(producer),
+            running: false,
+        });
+        if state.is_null() {
+            return ERROR;
+        }
+
+        unsafe {
+            let main = (*r).main;
+            (*main).set_count((*main).count() + 1);
+            (*r).write_event_handler = Some(ngx_http_rs_writer_handler);
+
+            drive(state);
+        }
+
+        DONE
+    }
+
+    /// Resume a producer that previously returned [`Next::Wait`].
+    ///
+    /// This is intended to be called from outside the request's own event handlers (for example
+    /// from a timer), so posted subrequests are run afterwards.
+    pub fn resume(request: &mut Request) {
+        let state = request.pool().get_local::<WriterState>();
+        if state.is_null() {
+            return;
+        }
+
+        unsafe {
+            let c = (*request.as_ngx_http_request()).connection;
+            drive(state);
+            ngx_http_run_posted_requests(c);
+        }
+    }
+
+    /// Write a chunk of the response body.
+    ///
+    /// The data is copied into a buffer of the request pool and flushed to the client.
+    /// Buffers are reused once sent, so a long response doesn't grow the pool with each chunk.
+    /// Returns [`AGAIN`] if the client could not accept all the data yet, in which case the
+    /// producer should return [`Next::Continue`] and wait to be called again.
+    pub fn write(&mut self, data: &[u8]) -> Status {
+        if self.finished {
+            return ERROR;
+        }
+
+        unsafe {
+            let pool = (*self.request).pool;
+            let cl = ngx_chain_get_free_buf(pool, &mut self.free);
+            if cl.is_null() {
+                return ERROR;
+            }
+
+            let b = (*cl).buf;
+            if (*b).start.is_null() || ((*b).end as usize - (*b).start as usize) < data.len() {
+                // A free buffer too small for the data is given new memory, as the gzip filter
+                // does; the old memory is freed with the pool.
+                let start = ngx_palloc(pool, data.len()) as *mut u_char;
+                if start.is_null() {
+                    return ERROR;
+                }
+                (*b).start = start;
+                (*b).end = start.add(data.len());
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), (*b).start, data.len());
+            (*b).pos = (*b).start;
+            (*b).last = (*b).start.add(data.len());
+            (*b).set_temporary(1);
+            (*b).set_flush(1);
+            (*b).tag = WRITER_TAG as ngx_buf_tag_t;
+
+            self.send(cl)
+        }
+    }
+
+    /// Finish the response body by sending the last buffer.
+    pub fn finish(&mut self) -> Status {
+        if self.finished {
+            return Status(self.last_rc);
+        }
+
+        self.finished = true;
+        let rc = unsafe { ngx_http_send_special(self.request, NGX_HTTP_LAST as ngx_uint_t) };
+        self.update(rc)
+    }
+
+    /// Is output currently blocked waiting for the client?
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Has the last buffer been sent?
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn send(&mut self, mut out: *mut ngx_chain_t) -> Status {
+        if !out.is_null() {
+            unsafe { Request::from_ngx_http_request(self.request) }.record_body_sent();
+        }
+
+        let rc = unsafe {
+            let rc = ngx_http_output_filter(self.request, out);
+            // Sent buffers move from busy to free.
+            ngx_chain_update_chains((*self.request).pool, &mut self.free, &mut self.busy, &mut out, WRITER_TAG as ngx_buf_tag_t);
+            rc
+        };
+        self.update(rc)
+    }
+
+    fn update(&mut self, rc: ngx_int_t) -> Status {
+        self.last_rc = rc;
+        if rc == NGX_AGAIN as ngx_int_t {
+            self.blocked = true;
+        }
+        Status(rc)
+    }
+}
+
+// Tag of the buffers of writers, telling them apart from other buffers when reusing them.
+const WRITER_TAG: *const c_void = ngx_http_rs_writer_handler as *const c_void;
+
+unsafe fn drive(state: *mut WriterState) {
+    if (*state).running {
+        // The producer resumed itself; the outer loop will carry on.
+        return;
+    }
+    (*state).running = true;
+
+    let r = (*state).writer.request;
+    let action = loop {
+        let WriterState { writer, producer, .. } = &mut *state;
+
+        if writer.blocked {
+            if writer.send(ptr::null_mut()) == AGAIN {
+                break Action::Block;
+            }
+            if writer.last_rc == NGX_ERROR as ngx_int_t {
+                break Action::Finalize(writer.last_rc);
+            }
+
+            writer.blocked = false;
+            let wev = (*(*r).connection).write;
+            if (*wev).timer_set() != 0 {
+                ngx_del_timer(wev);
+            }
+        }
+
+        if writer.finished {
+            break Action::Finalize(writer.last_rc);
+        }
+
+        match producer(Request::from_ngx_http_request(r), writer) {
+            Next::Continue => {}
+            Next::Wait => break Action::Wait,
+            Next::Done => {
+                writer.finish();
+            }
+            Next::Error(status) => break Action::Finalize(status.0),
+        }
+
+        if writer.last_rc == NGX_ERROR as ngx_int_t {
+            break Action::Finalize(writer.last_rc);
+        }
+        if writer.blocked {
+            break Action::Block;
+        }
+    };
+
+    (*state).running = false;
+
+    match action {
+        Action::Wait => {}
+        Action::Block => wait_writable(r),
+        // The request (and with it `state`) may be freed by finalization.
+        Action::Finalize(rc) => ngx_http_finalize_request(r, rc),
+    }
+}
+
+unsafe fn wait_writable(r: *mut ngx_http_request_t) {
+    let wev = (*(*r).connection).write;
+    let clcf = *(*r).loc_conf.add(ngx_http_core_module.ctx_index) as *mut ngx_http_core_loc_conf_t;
+
+    if (*wev).delayed() == 0 {
+        ngx_add_timer(wev, (*clcf).send_timeout);
+    }
+
+    if ngx_handle_write_event(wev, (*clcf).send_lowat) != NGX_OK as ngx_int_t {
+        ngx_http_finalize_request(r, NGX_ERROR as ngx_int_t);
+    }
+}
+
+unsafe extern "C" fn ngx_http_rs_writer_handler(r: *mut ngx_http_request_t) {
+    let c = (*r).connection;
+    let wev = (*c).write;
+
+    if (*wev).timedout() != 0 {
+        (*c).set_timedout(1);
+        ngx_http_finalize_request(r, NGX_HTTP_REQUEST_TIME_OUT as ngx_int_t);
+        return;
+    }
+
+    if (*wev).delayed() != 0 || (*r).aio() != 0 {
+        wait_writable(r);
+        return;
+    }
+
+    let state = Request::from_ngx_http_request(r).pool().get_local::<WriterState>();
+    if state.is_null() {
+        return;
+    }
+
+    drive(state);
+}
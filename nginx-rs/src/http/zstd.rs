@@ -0,0 +1,174 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::export::{ExportReader, ExportRecord};
+use crate::http::request::Request;
+
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Default compression level of a [`Zstd`] compressor.
+pub const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+/// A [zstd] dictionary, improving the compression of small, similar payloads such as export
+/// records.
+///
+/// [zstd]: https://facebook.github.io/zstd/
+#[derive(Clone, Debug)]
+pub struct ZstdDictionary(Vec<u8>);
+
+impl ZstdDictionary {
+    /// Load a dictionary trained with `zstd --train`.
+    ///
+    /// This reads the file synchronously, so call it while parsing the configuration or from
+    /// `init_process`, not while processing requests.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ZstdDictionary> {
+        fs::read(path).map(ZstdDictionary)
+    }
+
+    /// A dictionary from bytes.
+    pub fn from_bytes(bytes: Vec<u8>) -> ZstdDictionary {
+        ZstdDictionary(bytes)
+    }
+
+    /// The dictionary content.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A [zstd] compressor, optionally using a dictionary.
+///
+/// Used for batches of exported records, see [`Zstd::compress_records`], as well as module
+/// responses, see [`Zstd::encode_response`].
+///
+/// [zstd]: https://facebook.github.io/zstd/
+#[derive(Clone, Debug)]
+pub struct Zstd {
+    level: i32,
+    dictionary: Option<ZstdDictionary>,
+}
+
+impl Zstd {
+    /// A compressor with the given level (1 to 22, or negative for faster levels).
+    pub fn new(level: i32) -> Zstd {
+        Zstd { level, dictionary: None }
+    }
+
+    /// Compress with a dictionary. Data must be decompressed with the same dictionary.
+    pub fn with_dictionary(level: i32, dictionary: ZstdDictionary) -> Zstd {
+        Zstd { level, dictionary: Some(dictionary) }
+    }
+
+    /// Compress `data` into a single zstd frame.
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match &self.dictionary {
+            Some(dictionary) => zstd::bulk::Compressor::with_dictionary(self.level, dictionary.as_bytes())?.compress(data),
+            None => zstd::bulk::compress(data, self.level),
+        }
+    }
+
+    /// Decompress data compressed with [`Zstd::compress`] (or any zstd encoder using the same
+    /// dictionary), rejecting output larger than `limit`.
+    pub fn decompress(&self, data: &[u8], limit: ByteSize) -> io::Result<Vec<u8>> {
+        let limit = limit.as_bytes();
+        let mut out = Vec::new();
+        let read = match &self.dictionary {
+            Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(data, dictionary.as_bytes())?
+                .take(limit as u64 + 1)
+                .read_to_end(&mut out)?,
+            None => zstd::stream::read::Decoder::new(data)?.take(limit as u64 + 1).read_to_end(&mut out)?,
+        };
+        if read > limit {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "decompressed data exceeds limit"));
+        }
+        Ok(out)
+    }
+
+    /// Compress a batch of export records into a single zstd frame, for shipping to an
+    /// analyzer, which reads it back with [`Zstd::export_reader`].
+    pub fn compress_records(&self, records: &[ExportRecord]) -> io::Result<Vec<u8>> {
+        let mut batch = Vec::new();
+        for record in records {
+            record.encode(&mut batch).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        }
+        self.compress(&batch)
+    }
+
+    /// Read the records of batches compressed with [`Zstd::compress_records`], possibly
+    /// concatenated, using the dictionary of this compressor.
+    pub fn export_reader<R: Read>(&self, inner: R) -> io::Result<ExportReader<zstd::stream::read::Decoder<'static, BufReader<R>>>> {
+        let decoder = match &self.dictionary {
+            Some(dictionary) => zstd::stream::read::Decoder::with_dictionary(BufReader::new(inner), dictionary.as_bytes())?,
+            None => zstd::stream::read::Decoder::new(inner)?,
+        };
+        Ok(ExportReader::new(decoder))
+    }
+
+    /// Compress a response body if the client accepts zstd.
+    ///
+    /// Call this before [`Request::send_header`]. If the client accepts zstd, the body is
+    /// compressed, the `Content-Encoding` and `Vary` headers are set and the compressed body
+    /// is returned; its length should be used as the content length. Otherwise `None` is
+    /// returned, without setting any header, and the body should be sent as is.
+    ///
+    /// Compressors with a dictionary never encode responses: their output can't be decoded
+    /// as plain zstd by clients, which don't have the dictionary.
+    pub fn encode_response(&self, request: &mut Request, body: &[u8]) -> Option<Vec<u8>> {
+        if self.dictionary.is_some() || !accepts_zstd(request) {
+            return None;
+        }
+
+        let compressed = self.compress(body).ok()?;
+
+        let mut pool = request.pool();
+        let r = request.as_ngx_http_request_mut();
+        unsafe {
+            let list = &mut (*r).headers_out.headers as *mut ngx_list_t;
+            let h = Request::push_header_elt(&mut pool, list, "Content-Encoding", "zstd");
+            if h.is_null() {
+                return None;
+            }
+            if !Request::push_header(&mut pool, list, "Vary", "Accept-Encoding") {
+                // Headers can't be removed from the list: disable it instead, as Nginx does.
+                (*h).hash = 0;
+                return None;
+            }
+            // Lets other filters, such as gzip, know the response is already encoded.
+            (*r).headers_out.content_encoding = h;
+        }
+
+        Some(compressed)
+    }
+}
+
+impl Default for Zstd {
+    fn default() -> Zstd {
+        Zstd::new(ZSTD_DEFAULT_LEVEL)
+    }
+}
+
+/// Does the client accept zstd encoded responses (`Accept-Encoding`)?
+pub fn accepts_zstd(request: &Request) -> bool {
+    let accept = match request.get_header("accept-encoding") {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    accept.split(',').any(|coding| {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or("").trim();
+        if !name.eq_ignore_ascii_case("zstd") {
+            return false;
+        }
+
+        // An explicit `q=0` means not acceptable.
+        !params.any(|param| {
+            let param = param.trim();
+            match (param.get(..2), param.get(2..)) {
+                (Some(key), Some(q)) if key.eq_ignore_ascii_case("q=") => q.trim().parse::<f32>().map(|q| q == 0.0).unwrap_or(false),
+                _ => false,
+            }
+        })
+    })
+}
@@ -1,37 +1,76 @@
 pub mod http;
-pub mod bindings;
+pub(crate) mod bindings;
 pub mod core;
+pub mod event;
 pub mod log;
+pub mod prelude;
+#[cfg(feature = "mail")]
+pub mod mail;
+#[cfg(feature = "stream")]
+pub mod stream;
+
+/// Raw Nginx types, constants and functions (`ngx_*`), as generated by bindgen.
+///
+/// These follow the Nginx sources and may change with them, which is why they are behind the
+/// `raw` feature. The [`prelude`] covers what typical modules need.
+#[cfg(feature = "raw")]
+pub mod raw {
+    pub use crate::bindings::*;
+}
+
+// Paths used by the expansions of the macros of this crate, whatever its features.
+#[doc(hidden)]
+pub mod __private {
+    pub mod bindings {
+        pub use crate::bindings::*;
+    }
+}
 
 /// Define modules exported by this library.
 ///
 /// These are normally generated by the Nginx module system, but need to be
-/// defined when building modules outside of it.
+/// defined when building modules outside of it. List every module of the library, in the
+/// order Nginx should initialize them, in a single call:
+///
+/// ```ignore
+/// ngx_modules!(ngx_http_waf_module, ngx_stream_waf_module);
+/// ```
+///
+/// Libraries with a single module can instead pass `ngx_modules = true;` to the macro
+/// declaring it, such as [`http_module!`].
 #[macro_export]
 macro_rules! ngx_modules {
     ($( $mod:ident ),+) => {
         #[no_mangle]
-        pub static mut ngx_modules: [*const ngx_module_t; $crate::count!($( $mod, )+) + 1] = [
-            $( unsafe { &$mod } as *const ngx_module_t, )+
-            ptr::null()
+        pub static mut ngx_modules: [*const $crate::__private::bindings::ngx_module_t; $crate::count!($( $mod, )+) + 1] = [
+            $( ::std::ptr::addr_of!($mod), )+
+            ::std::ptr::null()
         ];
 
         #[no_mangle]
-        pub static mut ngx_module_names: [*const c_char; $crate::count!($( $mod, )+) + 1] = [
-            $( concat!(stringify!($mod), "\0").as_ptr() as *const i8, )+
-            ptr::null()
+        pub static mut ngx_module_names: [*const ::std::os::raw::c_char; $crate::count!($( $mod, )+) + 1] = [
+            $( concat!(stringify!($mod), "\0").as_ptr() as *const ::std::os::raw::c_char, )+
+            ::std::ptr::null()
         ];
 
         #[no_mangle]
-        pub static mut ngx_module_order: [*const c_char; 1] = [
-            ptr::null()
+        pub static mut ngx_module_order: [*const ::std::os::raw::c_char; 1] = [
+            ::std::ptr::null()
         ];
     };
 }
 
+/// The `ngx_modules` exports of a module macro, if requested with `ngx_modules = true`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ngx_module_exports {
+    ( true ; $name: ident ) => { $crate::ngx_modules!($name); };
+    ( $( false )? ; $name: ident ) => {};
+}
+
 /// Count number of arguments
 #[macro_export]
 macro_rules! count {
     () => { 0usize };
-    ($x:tt, $( $xs:tt ),*) => { 1usize + $crate::count!($( $xs, )*) };
+    ($x:tt, $( $xs:tt ),* $(,)?) => { 1usize + $crate::count!($( $xs, )*) };
 }
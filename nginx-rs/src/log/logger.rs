@@ -0,0 +1,85 @@
+use crate::bindings::*;
+use crate::log::{current_log, log_error};
+
+use ::log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use std::cell::Cell;
+
+/// A [`log`](https://docs.rs/log) backend writing to the Nginx error log.
+///
+/// Records from the `log` macros of any crate (`error!`, `warn!`, `info!`, ...) go to the
+/// error log of the cycle, or to that of a connection within [`with_log`](crate::log::with_log), tagged with their
+/// target. Levels map to `NGX_LOG_ERR`, `NGX_LOG_WARN`, `NGX_LOG_INFO` and `NGX_LOG_DEBUG`,
+/// `trace!` included, and records below the level of the `error_log` directive are
+/// discarded.
+///
+/// The Nginx logger is not thread-safe, so records from other threads, such as those of a
+/// [`ThreadPool`](crate::core::ThreadPool), are discarded.
+pub struct NgxLogger;
+
+static LOGGER: NgxLogger = NgxLogger;
+
+thread_local! {
+    // Only the thread the logger was installed from logs.
+    static INSTALLED: Cell<bool> = Cell::new(false);
+}
+
+/// Install [`NgxLogger`] as the logger of the `log` crate, with the maximum level of the
+/// error log of the current cycle.
+///
+/// Call this from the `init_process` handler of the module. Fails if another logger is
+/// already installed, such as by another module of the same binary.
+///
+/// ```ignore
+/// ngx_init_process!(ngx_http_feeds_init_process, |_cycle: &Cycle| {
+///     let _ = nginx_rs::log::install_logger();
+///     OK
+/// });
+/// ```
+pub fn install_logger() -> Result<(), SetLoggerError> {
+    ::log::set_logger(&LOGGER)?;
+    INSTALLED.with(|installed| installed.set(true));
+    ::log::set_max_level(max_level(unsafe { (*ngx_cycle).log }));
+    Ok(())
+}
+
+fn ngx_level(level: Level) -> ngx_uint_t {
+    (match level {
+        Level::Error => NGX_LOG_ERR,
+        Level::Warn => NGX_LOG_WARN,
+        Level::Info => NGX_LOG_INFO,
+        Level::Debug | Level::Trace => NGX_LOG_DEBUG,
+    }) as ngx_uint_t
+}
+
+fn max_level(log: *mut ngx_log_t) -> LevelFilter {
+    if log.is_null() {
+        return LevelFilter::Off;
+    }
+    match unsafe { (*log).log_level } as u32 {
+        level if level >= NGX_LOG_DEBUG => LevelFilter::Trace,
+        level if level >= NGX_LOG_INFO => LevelFilter::Info,
+        level if level >= NGX_LOG_WARN => LevelFilter::Warn,
+        level if level >= NGX_LOG_ERR => LevelFilter::Error,
+        _ => LevelFilter::Off,
+    }
+}
+
+impl Log for NgxLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if !INSTALLED.with(Cell::get) {
+            return false;
+        }
+        let log = current_log();
+        !log.is_null() && unsafe { (*log).log_level } >= ngx_level(metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        unsafe { log_error(ngx_level(record.level()), current_log(), record.target(), *record.args()) };
+    }
+
+    fn flush(&self) {}
+}
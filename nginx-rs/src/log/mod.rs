@@ -0,0 +1,171 @@
+use crate::bindings::*;
+
+use std::cell::Cell;
+use std::fmt;
+use std::os::raw::c_char;
+use std::ptr;
+
+#[cfg(feature = "log")]
+mod logger;
+#[cfg(feature = "tracing")]
+mod subscriber;
+
+#[cfg(feature = "log")]
+pub use logger::*;
+#[cfg(feature = "tracing")]
+pub use subscriber::*;
+
+/// Write to logger at a specified level.
+///
+/// See [Logging](https://nginx.org/en/docs/dev/development_guide.html#logging)
+/// for available log levels.
+#[macro_export]
+macro_rules! ngx_log_debug {
+    ( $level:expr, $log:expr, $($arg:tt)* ) => {
+        let log_level = unsafe { (*$log).log_level };
+        if log_level & $level as usize != 0 {
+            let level = $crate::__private::bindings::NGX_LOG_DEBUG as $crate::__private::bindings::ngx_uint_t;
+            let fmt = ::std::ffi::CString::new("%s").unwrap();
+            let c_message = ::std::ffi::CString::new(format!($($arg)*)).unwrap();
+            unsafe {
+                $crate::__private::bindings::ngx_log_error_core(level, $log, 0, fmt.as_ptr(), c_message.as_ptr());
+            }
+        }
+    }
+}
+
+
+/// Log to request connection log at level [`NGX_LOG_DEBUG_HTTP`].
+///
+/// [`NGX_LOG_DEBUG_HTTP`]: https://nginx.org/en/docs/dev/development_guide.html#logging
+#[macro_export]
+macro_rules! ngx_log_debug_http {
+    ( $request:expr, $($arg:tt)* ) => {
+        let log = $request.connection().log();
+        $crate::ngx_log_debug!($crate::__private::bindings::NGX_LOG_DEBUG_HTTP, log, $($arg)*);
+    }
+}
+
+#[macro_export]
+macro_rules! ngx_log {
+    ( $level:expr, $log:expr, $($arg:tt)* ) => {
+        let log_level = unsafe { (*$log).log_level } as $crate::__private::bindings::ngx_uint_t;
+        let level = $level as $crate::__private::bindings::ngx_uint_t;
+        if log_level >= level {
+            let fmt = ::std::ffi::CString::new("%s").unwrap();
+            let c_message = ::std::ffi::CString::new(format!($($arg)*)).unwrap();
+            unsafe {
+                $crate::__private::bindings::ngx_log_error_core(level, $log, 0, fmt.as_ptr(), c_message.as_ptr());
+            }
+        }
+    }
+}
+
+/// Maximum length of a message logged with [`ngx_log_error!`](crate::ngx_log_error), as
+/// `NGX_MAX_ERROR_STR`. Longer messages are truncated.
+pub const LOG_MESSAGE_MAX: usize = 2048;
+
+/// Log to `log` at `level` (`NGX_LOG_EMERG` to `NGX_LOG_DEBUG`), as `ngx_log_error`.
+///
+/// The message is formatted into a stack buffer, without allocating, and prefixed with the
+/// path of the calling Rust module, so messages can be traced to their module. Nothing is
+/// formatted if the level is disabled. `log` is a `*mut ngx_log_t`, such as
+/// [`Cycle::log`](crate::core::Cycle::log) or [`Connection::log`](crate::core::Connection::log).
+///
+/// ```ignore
+/// ngx_log_error!(NGX_LOG_WARN, cycle.log(), "feed {} is stale, last update {}s ago", name, age);
+/// ```
+#[macro_export]
+macro_rules! ngx_log_error {
+    ( $level: expr, $log: expr, $($arg: tt)+ ) => {{
+        let log: *mut $crate::__private::bindings::ngx_log_t = $log;
+        unsafe {
+            $crate::log::log_error($level as $crate::__private::bindings::ngx_uint_t, log, module_path!(), format_args!($($arg)+))
+        }
+    }};
+}
+
+/// Log to the connection log of an HTTP request at `level`, see
+/// [`ngx_log_error!`](crate::ngx_log_error).
+///
+/// The log of the connection adds the client address, server and request line to the
+/// message.
+///
+/// ```ignore
+/// ngx_log_http!(request, NGX_LOG_INFO, "blocked by rule {}", rule.id);
+/// ```
+#[macro_export]
+macro_rules! ngx_log_http {
+    ( $request: expr, $level: expr, $($arg: tt)+ ) => {
+        $crate::ngx_log_error!($level, $request.connection().log(), $($arg)+)
+    };
+}
+
+/// Log a message formatted from `args`, tagged with `module`, see
+/// [`ngx_log_error!`](crate::ngx_log_error).
+#[doc(hidden)]
+pub unsafe fn log_error(level: ngx_uint_t, log: *mut ngx_log_t, module: &str, args: fmt::Arguments) {
+    if log.is_null() || (*log).log_level < level {
+        return;
+    }
+
+    let mut message = LogBuffer { buf: [0; LOG_MESSAGE_MAX], len: 0 };
+    let _ = fmt::write(&mut message, format_args!("{}: {}", module, args));
+    ngx_log_error_core(
+        level,
+        log,
+        0,
+        b"%*s\0".as_ptr() as *const c_char,
+        message.len,
+        message.buf.as_ptr(),
+    );
+}
+
+// A message truncated to its capacity.
+struct LogBuffer {
+    buf: [u8; LOG_MESSAGE_MAX],
+    len: usize,
+}
+
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+thread_local! {
+    static CURRENT_LOG: Cell<*mut ngx_log_t> = Cell::new(ptr::null_mut());
+}
+
+/// Run `f` with the records of the `log` and `tracing` backends going to `log`, such as the
+/// log of a connection, which adds the client address and request to messages.
+///
+/// ```ignore
+/// let verdict = with_log(request.connection().log(), || engine.evaluate(&input));
+/// ```
+pub fn with_log<R, F: FnOnce() -> R>(log: *mut ngx_log_t, f: F) -> R {
+    struct Restore(*mut ngx_log_t);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_LOG.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT_LOG.with(|current| current.replace(log)));
+    f()
+}
+
+// The log set by `with_log`, or the log of the cycle.
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub(crate) fn current_log() -> *mut ngx_log_t {
+    let log = CURRENT_LOG.with(Cell::get);
+    if log.is_null() {
+        unsafe { (*ngx_cycle).log }
+    } else {
+        log
+    }
+}
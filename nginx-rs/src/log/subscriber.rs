@@ -0,0 +1,165 @@
+use crate::bindings::*;
+use crate::http::Request;
+use crate::log::{current_log, log_error};
+
+use ::tracing::field::{Field, Visit};
+use ::tracing::span::{Attributes, Id, Record};
+use ::tracing::subscriber::{self, SetGlobalDefaultError};
+use ::tracing::{Event, Level, Span, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{self, LookupSpan};
+
+use std::fmt::{self, Write};
+use std::thread::{self, ThreadId};
+
+/// A [`tracing-subscriber`](https://docs.rs/tracing-subscriber) layer writing events to the
+/// Nginx error log.
+///
+/// Events go to the error log of the cycle, or to that of a connection within
+/// [`with_log`](crate::log::with_log), tagged with their target and prefixed with the spans
+/// they occur in and their fields, as `request{connection=12 request_id=f3..}: message
+/// key=value`. Levels map to `NGX_LOG_ERR`, `NGX_LOG_WARN`, `NGX_LOG_INFO` and
+/// `NGX_LOG_DEBUG`, and events below the level of the `error_log` directive are discarded.
+///
+/// The Nginx logger is not thread-safe, so events from threads other than the one the layer
+/// was created on, such as those of a [`ThreadPool`](crate::core::ThreadPool), are
+/// discarded.
+pub struct NgxLayer {
+    thread: ThreadId,
+}
+
+impl NgxLayer {
+    /// A layer logging the events of the current thread, the event loop of the worker
+    /// process when created from `init_process`.
+    pub fn new() -> NgxLayer {
+        NgxLayer { thread: thread::current().id() }
+    }
+}
+
+impl Default for NgxLayer {
+    fn default() -> NgxLayer {
+        NgxLayer::new()
+    }
+}
+
+/// Install a subscriber made of an [`NgxLayer`] as the global default of `tracing`.
+///
+/// Call this from the `init_process` handler of the module. Modules combining the layer with
+/// others build their own subscriber instead. Fails if another subscriber is already
+/// installed, such as by another module of the same binary.
+///
+/// ```ignore
+/// ngx_init_process!(ngx_http_feeds_init_process, |_cycle: &Cycle| {
+///     let _ = nginx_rs::log::install_tracing();
+///     OK
+/// });
+/// ```
+pub fn install_tracing() -> Result<(), SetGlobalDefaultError> {
+    subscriber::set_global_default(registry::Registry::default().with(NgxLayer::new()))
+}
+
+/// A span for the processing of `request`, with the number of its connection and its
+/// `$request_id` as fields, to enter around instrumented code.
+///
+/// ```ignore
+/// let span = request_span(request);
+/// let verdict = span.in_scope(|| engine.evaluate(&input));
+/// ```
+pub fn request_span(request: &mut Request) -> Span {
+    let connection = request.connection().number() as u64;
+    let request_id = request.variable("request_id").map(|id| id.to_string_lossy().into_owned()).unwrap_or_default();
+    ::tracing::info_span!("request", connection, request_id = %request_id)
+}
+
+// The formatted fields of a span, in its extensions.
+struct SpanFields(String);
+
+// Formats fields as ` key=value`, with the message of events apart.
+struct FieldVisitor<'a> {
+    fields: &'a mut String,
+    message: Option<&'a mut String>,
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match self.message.as_mut() {
+            Some(message) if field.name() == "message" => {
+                let _ = write!(message, "{:?}", value);
+            }
+            _ => {
+                let _ = write!(self.fields, " {}={:?}", field.name(), value);
+            }
+        }
+    }
+}
+
+fn ngx_level(level: &Level) -> ngx_uint_t {
+    (match *level {
+        Level::ERROR => NGX_LOG_ERR,
+        Level::WARN => NGX_LOG_WARN,
+        Level::INFO => NGX_LOG_INFO,
+        _ => NGX_LOG_DEBUG,
+    }) as ngx_uint_t
+}
+
+impl<S> Layer<S> for NgxLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = String::new();
+        attrs.record(&mut FieldVisitor { fields: &mut fields, message: None });
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut FieldVisitor { fields, message: None });
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        if thread::current().id() != self.thread {
+            return;
+        }
+        let level = ngx_level(event.metadata().level());
+        let log = current_log();
+        if log.is_null() || unsafe { (*log).log_level } < level {
+            return;
+        }
+
+        let mut line = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                line.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(line, "{{{}}}", &fields[1..]);
+                    }
+                }
+                line.push(':');
+            }
+            line.push(' ');
+        }
+        let mut message = String::new();
+        let mut fields = String::new();
+        event.record(&mut FieldVisitor { fields: &mut fields, message: Some(&mut message) });
+        line.push_str(&message);
+        line.push_str(&fields);
+
+        unsafe { log_error(level, log, event.metadata().target(), format_args!("{}", line)) };
+    }
+}
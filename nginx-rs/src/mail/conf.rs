@@ -0,0 +1,24 @@
+use crate::bindings::*;
+
+use std::os::raw::c_void;
+use core::ptr;
+
+pub unsafe fn ngx_mail_conf_get_module_main_conf(cf: *mut ngx_conf_t, module: &ngx_module_t) -> *mut c_void {
+    let mail_conf_ctx = (*cf).ctx as *mut ngx_mail_conf_ctx_t;
+    *(*mail_conf_ctx).main_conf.add(module.ctx_index)
+}
+
+pub unsafe fn ngx_mail_conf_get_module_srv_conf(cf: *mut ngx_conf_t, module: &ngx_module_t) -> *mut c_void {
+    let mail_conf_ctx = (*cf).ctx as *mut ngx_mail_conf_ctx_t;
+    *(*mail_conf_ctx).srv_conf.add(module.ctx_index)
+}
+
+pub unsafe fn ngx_mail_cycle_get_module_main_conf(cycle: *mut ngx_cycle_t, module: &ngx_module_t) -> *mut c_void {
+    let idx = ngx_mail_module.index;
+    let mail_conf_ctx = *((*cycle).conf_ctx.add(idx)) as *mut ngx_mail_conf_ctx_t;
+    if mail_conf_ctx.is_null() {
+        ptr::null_mut()
+    } else {
+        *(*mail_conf_ctx).main_conf.add(module.ctx_index)
+    }
+}
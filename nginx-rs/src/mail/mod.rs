@@ -0,0 +1,13 @@
+//! [Mail proxy] (SMTP, IMAP and POP3) modules.
+//!
+//! Requires the `mail` feature and Nginx configured with `--with-mail`.
+//!
+//! [Mail proxy]: https://nginx.org/en/docs/mail/ngx_mail_core_module.html
+
+mod conf;
+mod module;
+mod session;
+
+pub use conf::*;
+pub use module::*;
+pub use session::*;
@@ -0,0 +1,180 @@
+use crate::bindings::*;
+use crate::core::*;
+
+use std::os::raw::c_void;
+
+/// Mail protocol of a [`Session`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Pop3,
+    Imap,
+    Smtp,
+    /// A protocol unknown to this crate.
+    Unknown(ngx_uint_t),
+}
+
+/// Authentication method used by the client of a [`Session`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthMethod {
+    Plain,
+    Login,
+    LoginUsername,
+    Apop,
+    CramMd5,
+    External,
+    None,
+    /// A method unknown to this crate.
+    Unknown(ngx_uint_t),
+}
+
+/// Wrapper struct for an [`ngx_mail_session_t`] pointer, providing methods for working with
+/// mail proxy sessions.
+///
+/// [`ngx_mail_session_t`]: https://nginx.org/en/docs/mail/ngx_mail_core_module.html
+#[repr(transparent)]
+pub struct Session(ngx_mail_session_t);
+
+impl Session {
+    /// Create a [`Session`] from an [`ngx_mail_session_t`].
+    ///
+    /// [`ngx_mail_session_t`]: https://nginx.org/en/docs/mail/ngx_mail_core_module.html
+    pub unsafe fn from_ngx_mail_session<'a>(s: *mut ngx_mail_session_t) -> &'a mut Session {
+        // SAFETY: The caller has provided a valid non-null pointer to a valid `ngx_mail_session_t`
+        // which shares the same representation as `Session`.
+        &mut *s.cast::<Session>()
+    }
+
+    /// Pointer to the underlying [`ngx_mail_session_t`].
+    ///
+    /// [`ngx_mail_session_t`]: https://nginx.org/en/docs/mail/ngx_mail_core_module.html
+    pub fn as_ngx_mail_session(&self) -> *const ngx_mail_session_t {
+        &self.0
+    }
+
+    /// Mutable pointer to the underlying [`ngx_mail_session_t`].
+    ///
+    /// [`ngx_mail_session_t`]: https://nginx.org/en/docs/mail/ngx_mail_core_module.html
+    pub fn as_ngx_mail_session_mut(&mut self) -> *mut ngx_mail_session_t {
+        &mut self.0
+    }
+
+    /// Pointer to the [`ngx_connection_t`] client connection object.
+    ///
+    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+    pub fn connection(&self) -> *mut ngx_connection_t {
+        self.0.connection
+    }
+
+    /// Session pool, which is the pool of the client connection.
+    pub fn pool(&self) -> Pool {
+        // SAFETY: The session is allocated from the connection pool, thus it must be valid.
+        unsafe {
+            Pool::from_ngx_pool((*self.0.connection).pool)
+        }
+    }
+
+    /// Mail protocol of the session.
+    pub fn protocol(&self) -> Protocol {
+        match self.0.protocol() as u32 {
+            NGX_MAIL_POP3_PROTOCOL => Protocol::Pop3,
+            NGX_MAIL_IMAP_PROTOCOL => Protocol::Imap,
+            NGX_MAIL_SMTP_PROTOCOL => Protocol::Smtp,
+            protocol => Protocol::Unknown(protocol as ngx_uint_t),
+        }
+    }
+
+    /// Authentication method used by the client.
+    pub fn auth_method(&self) -> AuthMethod {
+        match self.0.auth_method() as u32 {
+            NGX_MAIL_AUTH_PLAIN => AuthMethod::Plain,
+            NGX_MAIL_AUTH_LOGIN => AuthMethod::Login,
+            NGX_MAIL_AUTH_LOGIN_USERNAME => AuthMethod::LoginUsername,
+            NGX_MAIL_AUTH_APOP => AuthMethod::Apop,
+            NGX_MAIL_AUTH_CRAM_MD5 => AuthMethod::CramMd5,
+            NGX_MAIL_AUTH_EXTERNAL => AuthMethod::External,
+            NGX_MAIL_AUTH_NONE => AuthMethod::None,
+            method => AuthMethod::Unknown(method as ngx_uint_t),
+        }
+    }
+
+    /// Client address, as text.
+    pub fn addr_text(&self) -> &NgxStr {
+        unsafe {
+            if self.0.addr_text.is_null() {
+                NgxStr::from_ngx_str((*self.0.connection).addr_text)
+            } else {
+                NgxStr::from_ngx_str(*self.0.addr_text)
+            }
+        }
+    }
+
+    /// Client host name, resolved from its address if `resolver` is configured.
+    pub fn host(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.host) }
+    }
+
+    /// User name sent by the client.
+    pub fn login(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.login) }
+    }
+
+    /// Password sent by the client.
+    pub fn passwd(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.passwd) }
+    }
+
+    /// Challenge sent to the client for the APOP and CRAM-MD5 methods.
+    pub fn salt(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.salt) }
+    }
+
+    /// Argument of the SMTP `HELO` or `EHLO` command.
+    pub fn smtp_helo(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.smtp_helo) }
+    }
+
+    /// Argument of the SMTP `MAIL FROM` command.
+    pub fn smtp_from(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.smtp_from) }
+    }
+
+    /// Argument of the SMTP `RCPT TO` command.
+    pub fn smtp_to(&self) -> &NgxStr {
+        unsafe { NgxStr::from_ngx_str(self.0.smtp_to) }
+    }
+
+    /// Module main configuration.
+    pub fn get_module_main_conf(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.main_conf.add(module.ctx_index)
+        }
+    }
+
+    /// Module server configuration.
+    pub fn get_module_srv_conf(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.srv_conf.add(module.ctx_index)
+        }
+    }
+
+    /// Module context of the session, null if not set.
+    pub fn get_module_ctx(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.ctx.add(module.ctx_index)
+        }
+    }
+
+    /// Set the module context of the session.
+    pub fn set_module_ctx(&mut self, module: &ngx_module_t, ctx: *mut c_void) {
+        unsafe {
+            *self.0.ctx.add(module.ctx_index) = ctx;
+        }
+    }
+
+    /// Close the session, as done after a fatal error.
+    pub fn close(&mut self) {
+        unsafe {
+            ngx_mail_close_connection(self.0.connection);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Types and macros used by most modules.
+//!
+//! ```ignore
+//! use nginx_rs::prelude::*;
+//! ```
+//!
+//! Raw Nginx types and functions (`ngx_*`) are not part of the prelude. Modules needing them
+//! enable the `raw` feature and import them from [`raw`](crate::raw).
+
+pub use crate::core::{Buffer, Cycle, MutableBuffer, NgxError, NgxStr, Pool, Status};
+pub use crate::core::{ABORT, AGAIN, BUSY, DECLINED, DONE, ERROR, OK};
+pub use crate::core::{ByteSize, Msec, Sec};
+pub use crate::http::{Access, HTTPModule, HTTPStatus, Merge, Phase, Phases, PreContent, Request};
+pub use crate::http::{
+    HTTP_BAD_GATEWAY, HTTP_BAD_REQUEST, HTTP_CLOSE, HTTP_CREATED, HTTP_FORBIDDEN, HTTP_GATEWAY_TIME_OUT,
+    HTTP_INTERNAL_SERVER_ERROR, HTTP_MOVED_PERMANENTLY, HTTP_MOVED_TEMPORARILY, HTTP_NOT_ALLOWED, HTTP_NOT_FOUND,
+    HTTP_NOT_IMPLEMENTED, HTTP_NOT_MODIFIED, HTTP_NO_CERT, HTTP_NO_CONTENT, HTTP_OK, HTTP_PERMANENT_REDIRECT,
+    HTTP_REQUEST_ENTITY_TOO_LARGE, HTTP_SEE_OTHER, HTTP_SERVICE_UNAVAILABLE, HTTP_SSL_CERT_ERROR, HTTP_TEMPORARY_REDIRECT,
+    HTTP_TOO_MANY_REQUESTS, HTTP_UNAUTHORIZED,
+};
+
+pub use crate::{
+    http_access_handler, http_async_request_handler, http_log_handler, http_module, http_precontent_handler, http_request_handler,
+};
+pub use crate::{
+    ngx_commands, ngx_exit_master, ngx_exit_process, ngx_init_master, ngx_init_module, ngx_init_process, ngx_log, ngx_log_debug,
+    ngx_log_debug_http, ngx_log_error, ngx_log_http, ngx_modules, ngx_null_string, ngx_str, ngx_string,
+};
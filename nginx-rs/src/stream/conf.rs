@@ -0,0 +1,34 @@
+use crate::bindings::*;
+
+use std::os::raw::c_void;
+use core::ptr;
+
+pub unsafe fn ngx_stream_conf_get_module_main_conf(cf: *mut ngx_conf_t, module: &ngx_module_t) -> *mut c_void {
+    let stream_conf_ctx = (*cf).ctx as *mut ngx_stream_conf_ctx_t;
+    *(*stream_conf_ctx).main_conf.add(module.ctx_index)
+}
+
+pub unsafe fn ngx_stream_conf_get_module_srv_conf(cf: *mut ngx_conf_t, module: &ngx_module_t) -> *mut c_void {
+    let stream_conf_ctx = (*cf).ctx as *mut ngx_stream_conf_ctx_t;
+    *(*stream_conf_ctx).srv_conf.add(module.ctx_index)
+}
+
+pub unsafe fn ngx_stream_cycle_get_module_main_conf(cycle: *mut ngx_cycle_t, module: &ngx_module_t) -> *mut c_void {
+    let idx = ngx_stream_module.index;
+    let stream_conf_ctx = *((*cycle).conf_ctx.add(idx)) as *mut ngx_stream_conf_ctx_t;
+    if stream_conf_ctx.is_null() {
+        ptr::null_mut()
+    } else {
+        *(*stream_conf_ctx).main_conf.add(module.ctx_index)
+    }
+}
+
+/// Set the content handler of the `server` block being configured, such as one defined with
+/// [`stream_handler!`](crate::stream_handler).
+///
+/// Call this from the handler of a directive allowed in `server` blocks, as `proxy_pass` or
+/// `return` do.
+pub unsafe fn ngx_stream_conf_set_content_handler(cf: *mut ngx_conf_t, handler: unsafe extern "C" fn(*mut ngx_stream_session_t)) {
+    let cscf = ngx_stream_conf_get_module_srv_conf(cf, &ngx_stream_core_module) as *mut ngx_stream_core_srv_conf_t;
+    (*cscf).handler = Some(handler);
+}
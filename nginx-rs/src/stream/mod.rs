@@ -0,0 +1,15 @@
+//! [Stream] (TCP and UDP) modules.
+//!
+//! Requires the `stream` feature and Nginx configured with `--with-stream`.
+//!
+//! [Stream]: https://nginx.org/en/docs/stream/ngx_stream_core_module.html
+
+mod conf;
+mod module;
+mod phases;
+mod session;
+
+pub use conf::*;
+pub use module::*;
+pub use phases::*;
+pub use session::*;
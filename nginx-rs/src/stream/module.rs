@@ -0,0 +1,154 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::http::Merge;
+
+use std::os::raw::{c_void, c_char};
+use core::ptr;
+
+/// Declare a complete stream module.
+///
+/// The stream counterpart of [`http_module!`]: expands to the module context
+/// (`ngx_stream_module_t`) and the `ngx_module_t` static, to export with [`ngx_modules!`]. The
+/// configuration handlers are those of the given [`StreamModule`] implementation. Phases are
+/// named `post_accept`, `preaccess`, `access`, `ssl`, `preread` and `log`.
+///
+/// ```ignore
+/// stream_module! {
+///     static ngx_stream_sni_filter_module: Module;
+///     commands = ngx_stream_sni_filter_commands;
+///     phases = [preread => ngx_stream_sni_filter_handler];
+/// }
+///
+/// ngx_modules!(ngx_stream_sni_filter_module);
+/// ```
+///
+/// As with [`http_module!`], `ngx_modules = true;` may end the parameters to also expand to
+/// the `ngx_modules` exports of a library with this module only.
+///
+/// [`http_module!`]: crate::http_module
+/// [`ngx_modules!`]: crate::ngx_modules
+#[macro_export]
+macro_rules! stream_module {
+    (
+        static $name: ident : $module: ty ;
+        commands = $commands: ident ;
+        $( phases = [ $( $phase: ident => $handler: ident ),* $(,)? ] ; )?
+        $( init_master = $init_master: ident ; )?
+        $( init_module = $init_module: ident ; )?
+        $( init_process = $init_process: ident ; )?
+        $( exit_process = $exit_process: ident ; )?
+        $( exit_master = $exit_master: ident ; )?
+        $( ngx_modules = $exports: ident ; )?
+    ) => {
+        #[no_mangle]
+        pub static mut $name: $crate::__private::bindings::ngx_module_t = $crate::__private::bindings::ngx_module_t {
+            ctx_index: $crate::__private::bindings::ngx_uint_t::MAX,
+            index: $crate::__private::bindings::ngx_uint_t::MAX,
+            name: ::std::ptr::null_mut(),
+            spare0: 0,
+            spare1: 0,
+            version: $crate::__private::bindings::nginx_version as $crate::__private::bindings::ngx_uint_t,
+            signature: $crate::__private::bindings::NGX_RS_MODULE_SIGNATURE.as_ptr() as *const ::std::os::raw::c_char,
+
+            ctx: {
+                unsafe extern "C" fn postconfiguration(cf: *mut $crate::__private::bindings::ngx_conf_t) -> $crate::__private::bindings::ngx_int_t {
+                    $( $(
+                        let status = $crate::stream::Phases::from_conf(cf).add($crate::__stream_phase!($phase), $handler);
+                        if status != $crate::core::OK {
+                            return status.into();
+                        }
+                    )* )?
+
+                    <$module as $crate::stream::StreamModule>::postconfiguration(cf)
+                }
+
+                static CTX: $crate::__private::bindings::ngx_stream_module_t = $crate::__private::bindings::ngx_stream_module_t {
+                    preconfiguration: Some(<$module as $crate::stream::StreamModule>::preconfiguration),
+                    postconfiguration: Some(postconfiguration),
+
+                    create_main_conf: Some(<$module as $crate::stream::StreamModule>::create_main_conf),
+                    init_main_conf: Some(<$module as $crate::stream::StreamModule>::init_main_conf),
+
+                    create_srv_conf: Some(<$module as $crate::stream::StreamModule>::create_srv_conf),
+                    merge_srv_conf: Some(<$module as $crate::stream::StreamModule>::merge_srv_conf),
+                };
+
+                &CTX as *const _ as *mut _
+            },
+            commands: ::std::ptr::addr_of_mut!($commands) as *mut $crate::__private::bindings::ngx_command_t,
+            type_: $crate::__private::bindings::NGX_STREAM_MODULE as $crate::__private::bindings::ngx_uint_t,
+
+            init_master: $crate::__ngx_module_hook!($($init_master)?; None),
+            init_module: $crate::__ngx_module_hook!($($init_module)?; None),
+            init_process: $crate::__ngx_module_hook!($($init_process)?; Some($crate::core::init_process_jobs)),
+            init_thread: None,
+            exit_thread: None,
+            exit_process: $crate::__ngx_module_hook!($($exit_process)?; Some($crate::core::exit_process_flush)),
+            exit_master: $crate::__ngx_module_hook!($($exit_master)?; None),
+
+            spare_hook0: 0,
+            spare_hook1: 0,
+            spare_hook2: 0,
+            spare_hook3: 0,
+            spare_hook4: 0,
+            spare_hook5: 0,
+            spare_hook6: 0,
+            spare_hook7: 0,
+        };
+
+        $crate::__ngx_module_exports!($($exports)?; $name);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __stream_phase {
+    (post_accept) => { $crate::stream::Phase::PostAccept };
+    (preaccess) => { $crate::stream::Phase::PreAccess };
+    (access) => { $crate::stream::Phase::Access };
+    (ssl) => { $crate::stream::Phase::Ssl };
+    (preread) => { $crate::stream::Phase::Preread };
+    (log) => { $crate::stream::Phase::Log };
+}
+
+/// Configuration handlers of a stream module, the counterpart of
+/// [`HTTPModule`](crate::http::HTTPModule).
+pub trait StreamModule {
+    type MainConf: Merge + Default;
+    type SrvConf: Merge + Default;
+
+    unsafe extern "C" fn preconfiguration(_cf: *mut ngx_conf_t) -> ngx_int_t {
+        OK.into()
+    }
+
+    unsafe extern "C" fn postconfiguration(_cf: *mut ngx_conf_t) -> ngx_int_t {
+        OK.into()
+    }
+
+    unsafe extern "C" fn create_main_conf(cf: *mut ngx_conf_t) -> *mut c_void {
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        match pool.alloc::<Self::MainConf>(Default::default()) {
+            Some(conf) => conf.as_ptr() as *mut c_void,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn init_main_conf(_cf: *mut ngx_conf_t, _conf: *mut c_void) -> *mut c_char {
+        ptr::null_mut()
+    }
+
+    unsafe extern "C" fn create_srv_conf(cf: *mut ngx_conf_t) -> *mut c_void {
+        let mut pool = Pool::from_ngx_pool((*cf).pool);
+        match pool.alloc::<Self::SrvConf>(Default::default()) {
+            Some(conf) => conf.as_ptr() as *mut c_void,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn merge_srv_conf(_cf: *mut ngx_conf_t, prev: *mut c_void, conf: *mut c_void) -> *mut c_char {
+        let prev = &mut *(prev as *mut Self::SrvConf);
+        let conf = &mut *(conf as *mut Self::SrvConf);
+        conf.merge(prev);
+        ptr::null_mut()
+    }
+}
@@ -0,0 +1,65 @@
+use crate::bindings::*;
+use crate::core::*;
+use crate::stream::conf::*;
+
+/// A [phase] of stream session processing that handlers can be added to.
+///
+/// [phase]: https://nginx.org/en/docs/stream/stream_processing.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// Right after the connection has been accepted, such as `realip` (`NGX_STREAM_POST_ACCEPT_PHASE`).
+    PostAccept,
+    /// Access control not based on the client, such as connection limiting (`NGX_STREAM_PREACCESS_PHASE`).
+    PreAccess,
+    /// Access control of the client (`NGX_STREAM_ACCESS_PHASE`).
+    Access,
+    /// TLS termination (`NGX_STREAM_SSL_PHASE`).
+    Ssl,
+    /// Reading the initial bytes sent by the client, without consuming them (`NGX_STREAM_PREREAD_PHASE`).
+    Preread,
+    /// Logging, once the session is finalized (`NGX_STREAM_LOG_PHASE`).
+    Log,
+}
+
+impl Phase {
+    fn as_ngx_stream_phase(self) -> ngx_stream_phases {
+        match self {
+            Phase::PostAccept => ngx_stream_phases_NGX_STREAM_POST_ACCEPT_PHASE,
+            Phase::PreAccess => ngx_stream_phases_NGX_STREAM_PREACCESS_PHASE,
+            Phase::Access => ngx_stream_phases_NGX_STREAM_ACCESS_PHASE,
+            Phase::Ssl => ngx_stream_phases_NGX_STREAM_SSL_PHASE,
+            Phase::Preread => ngx_stream_phases_NGX_STREAM_PREREAD_PHASE,
+            Phase::Log => ngx_stream_phases_NGX_STREAM_LOG_PHASE,
+        }
+    }
+}
+
+/// Registration of stream phase handlers.
+///
+/// Handlers must be added in the `postconfiguration` handler of a module, once the core
+/// module has created the phase handler arrays.
+pub struct Phases {
+    cmcf: *mut ngx_stream_core_main_conf_t,
+}
+
+impl Phases {
+    /// Phase handlers of the `stream` block being configured.
+    pub unsafe fn from_conf(cf: *mut ngx_conf_t) -> Phases {
+        let cmcf = ngx_stream_conf_get_module_main_conf(cf, &ngx_stream_core_module) as *mut ngx_stream_core_main_conf_t;
+        Phases { cmcf }
+    }
+
+    /// Add a handler to a phase, such as one defined with
+    /// [`stream_preread_handler!`](crate::stream_preread_handler).
+    pub fn add(&mut self, phase: Phase, handler: unsafe extern "C" fn(*mut ngx_stream_session_t) -> ngx_int_t) -> Status {
+        unsafe {
+            let phase = &mut (*self.cmcf).phases[phase.as_ngx_stream_phase() as usize];
+            let h = ngx_array_push(&mut phase.handlers) as *mut ngx_stream_handler_pt;
+            if h.is_null() {
+                return ERROR;
+            }
+            *h = Some(handler);
+        }
+        OK
+    }
+}
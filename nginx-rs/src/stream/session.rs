@@ -0,0 +1,207 @@
+use crate::bindings::*;
+use crate::core::*;
+
+use std::os::raw::c_void;
+use std::slice;
+
+/// Define a static stream content handler.
+///
+/// Handlers are expected to take a single [`Session`] argument and are responsible for
+/// eventually finalizing it with [`Session::finalize`]. Set the handler of a `server` block
+/// with [`ngx_stream_conf_set_content_handler`](crate::stream::ngx_stream_conf_set_content_handler).
+///
+/// ```ignore
+/// stream_handler!(ngx_stream_deny_handler, |session: &mut Session| {
+///     session.finalize(NGX_STREAM_FORBIDDEN);
+/// });
+/// ```
+#[macro_export]
+macro_rules! stream_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(s: *mut $crate::__private::bindings::ngx_stream_session_t) {
+            let log = unsafe { (*(*s).connection).log };
+            let completed = $crate::core::catch_panic(log, stringify!($name), false, || {
+                $handler(unsafe { $crate::stream::Session::from_ngx_stream_session(s) });
+                true
+            });
+            if !completed {
+                let internal_error = $crate::__private::bindings::NGX_STREAM_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_uint_t;
+                unsafe { $crate::__private::bindings::ngx_stream_finalize_session(s, internal_error) };
+            }
+        }
+    };
+}
+
+/// Define a static stream phase handler, typically for the preread phase.
+///
+/// Handlers are expected to take a single [`Session`] argument and return a [`Status`]:
+/// `DECLINED` to run the next handler, `OK` to skip to the next phase, `AGAIN` (preread
+/// phase only) to wait for more data from the client, or an `NGX_STREAM_*` status code to
+/// finalize the session. A `Result<Status, NgxError>` may be returned instead: the error is
+/// logged and finalizes the session with its status, `500` by default. Register the handler
+/// with [`Phases::add`](crate::stream::Phases::add).
+#[macro_export]
+macro_rules! stream_preread_handler {
+    ( $name: ident, $handler: expr ) => {
+        #[no_mangle]
+        extern "C" fn $name(s: *mut $crate::__private::bindings::ngx_stream_session_t) -> $crate::__private::bindings::ngx_int_t {
+            let log = unsafe { (*(*s).connection).log };
+            let internal_error = $crate::__private::bindings::NGX_STREAM_INTERNAL_SERVER_ERROR as $crate::__private::bindings::ngx_int_t;
+            $crate::core::catch_panic(log, stringify!($name), internal_error, || {
+                $crate::core::HandlerResult::into_status(
+                    $handler(unsafe { $crate::stream::Session::from_ngx_stream_session(s) }),
+                    log,
+                    $crate::core::Status(internal_error),
+                )
+                .0
+            })
+        }
+    };
+}
+
+/// Wrapper struct for an [`ngx_stream_session_t`] pointer, providing methods for working with
+/// stream sessions.
+///
+/// [`ngx_stream_session_t`]: https://nginx.org/en/docs/dev/development_guide.html#stream
+#[repr(transparent)]
+pub struct Session(ngx_stream_session_t);
+
+impl Session {
+    /// Create a [`Session`] from an [`ngx_stream_session_t`].
+    ///
+    /// [`ngx_stream_session_t`]: https://nginx.org/en/docs/dev/development_guide.html#stream
+    pub unsafe fn from_ngx_stream_session<'a>(s: *mut ngx_stream_session_t) -> &'a mut Session {
+        // SAFETY: The caller has provided a valid non-null pointer to a valid `ngx_stream_session_t`
+        // which shares the same representation as `Session`.
+        &mut *s.cast::<Session>()
+    }
+
+    /// Pointer to the underlying [`ngx_stream_session_t`].
+    ///
+    /// [`ngx_stream_session_t`]: https://nginx.org/en/docs/dev/development_guide.html#stream
+    pub fn as_ngx_stream_session(&self) -> *const ngx_stream_session_t {
+        &self.0
+    }
+
+    /// Mutable pointer to the underlying [`ngx_stream_session_t`].
+    ///
+    /// [`ngx_stream_session_t`]: https://nginx.org/en/docs/dev/development_guide.html#stream
+    pub fn as_ngx_stream_session_mut(&mut self) -> *mut ngx_stream_session_t {
+        &mut self.0
+    }
+
+    /// Pointer to the [`ngx_connection_t`] client connection object.
+    ///
+    /// [`ngx_connection_t`]: https://nginx.org/en/docs/dev/development_guide.html#connection
+    pub fn connection(&self) -> *mut ngx_connection_t {
+        self.0.connection
+    }
+
+    /// Session pool, which is the pool of the client connection.
+    pub fn pool(&self) -> Pool {
+        // SAFETY: The session is allocated from the connection pool, thus it must be valid.
+        unsafe {
+            Pool::from_ngx_pool((*self.0.connection).pool)
+        }
+    }
+
+    /// Client address, as text.
+    pub fn remote_address(&self) -> Option<String> {
+        unsafe {
+            let addr = NgxStr::from_ngx_str((*self.0.connection).addr_text);
+            if addr.is_empty() {
+                None
+            } else {
+                Some(addr.to_string_lossy().to_string())
+            }
+        }
+    }
+
+    /// The [PROXY protocol](ProxyProtocol) header received on the client connection, if the
+    /// `listen` socket has the `proxy_protocol` parameter.
+    pub fn proxy_protocol(&self) -> Option<ProxyProtocol> {
+        // SAFETY: The session is allocated from the connection pool, so it does not outlive it.
+        unsafe { ProxyProtocol::from_connection(self.0.connection) }
+    }
+
+    /// Is the session using UDP?
+    pub fn is_udp(&self) -> bool {
+        unsafe { (*self.0.connection).type_ == libc::SOCK_DGRAM }
+    }
+
+    /// Number of bytes received from the client so far.
+    pub fn received(&self) -> off_t {
+        self.0.received
+    }
+
+    /// Data read from the client and not consumed yet.
+    ///
+    /// In the preread phase, this is what the client has sent so far: a handler returning
+    /// `AGAIN` is called again once more data has been read, up to `preread_buffer_size`
+    /// bytes or `preread_timeout`. The data is then passed on to the content handler, such
+    /// as `proxy_pass`. Use [`ClientHello::parse`] to get the server name of TLS connections.
+    pub fn preread_buffer(&self) -> &[u8] {
+        unsafe {
+            let b = (*self.0.connection).buffer;
+            if b.is_null() || (*b).pos.is_null() || (*b).last <= (*b).pos {
+                return &[];
+            }
+            slice::from_raw_parts((*b).pos, (*b).last as usize - (*b).pos as usize)
+        }
+    }
+
+    /// Parse the TLS ClientHello at the start of the preread buffer.
+    pub fn preread_client_hello(&self) -> Result<ClientHello, ClientHelloError> {
+        ClientHello::parse(self.preread_buffer())
+    }
+
+    /// Server name requested by a TLS client (SNI), parsed from the preread buffer.
+    ///
+    /// Returns `Err(ClientHelloError::Incomplete)` while the ClientHello has not been fully
+    /// received, and `Ok(None)` if the client did not send a server name.
+    pub fn preread_server_name(&self) -> Result<Option<String>, ClientHelloError> {
+        self.preread_client_hello().map(|hello| hello.server_name().map(String::from))
+    }
+
+    /// Status of the session, as logged by `$status`.
+    pub fn status(&self) -> ngx_uint_t {
+        self.0.status
+    }
+
+    /// Module main configuration.
+    pub fn get_module_main_conf(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.main_conf.add(module.ctx_index)
+        }
+    }
+
+    /// Module server configuration.
+    pub fn get_module_srv_conf(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.srv_conf.add(module.ctx_index)
+        }
+    }
+
+    /// Module context of the session, null if not set.
+    pub fn get_module_ctx(&self, module: &ngx_module_t) -> *mut c_void {
+        unsafe {
+            *self.0.ctx.add(module.ctx_index)
+        }
+    }
+
+    /// Set the module context of the session.
+    pub fn set_module_ctx(&mut self, module: &ngx_module_t, ctx: *mut c_void) {
+        unsafe {
+            *self.0.ctx.add(module.ctx_index) = ctx;
+        }
+    }
+
+    /// Finalize the session with a status such as `NGX_STREAM_OK` or `NGX_STREAM_FORBIDDEN`,
+    /// closing the connection.
+    pub fn finalize(&mut self, status: u32) {
+        unsafe {
+            ngx_stream_finalize_session(&mut self.0, status as ngx_uint_t);
+        }
+    }
+}
@@ -1,7 +1,31 @@
 #include <ngx_http.h>
+#ifdef NGX_RS_STREAM
+#include <ngx_stream.h>
+#endif
+#ifdef NGX_RS_MAIL
+#include <ngx_mail.h>
+#endif
+#ifdef NGX_RS_HTTP3
+#include <ngx_event_quic_connection.h>
+#endif
+
+// The crate follows the internal structures and functions of these versions.
+#if nginx_version < 1027004
+#error "nginx-rs requires Nginx 1.27.4 or later"
+#endif
 
 // Define as constants since bindgen can't parse these values
 const size_t NGX_RS_HTTP_LOC_CONF_OFFSET = NGX_HTTP_LOC_CONF_OFFSET;
 const size_t NGX_RS_HTTP_MAIN_CONF_OFFSET = NGX_HTTP_MAIN_CONF_OFFSET;
+const size_t NGX_RS_HTTP_SRV_CONF_OFFSET = NGX_HTTP_SRV_CONF_OFFSET;
 const char* NGX_RS_MODULE_SIGNATURE = NGX_MODULE_SIGNATURE;
 
+#ifdef NGX_RS_STREAM
+const size_t NGX_RS_STREAM_MAIN_CONF_OFFSET = NGX_STREAM_MAIN_CONF_OFFSET;
+const size_t NGX_RS_STREAM_SRV_CONF_OFFSET = NGX_STREAM_SRV_CONF_OFFSET;
+#endif
+
+#ifdef NGX_RS_MAIL
+const size_t NGX_RS_MAIL_MAIN_CONF_OFFSET = NGX_MAIL_MAIN_CONF_OFFSET;
+const size_t NGX_RS_MAIL_SRV_CONF_OFFSET = NGX_MAIL_SRV_CONF_OFFSET;
+#endif